@@ -0,0 +1,17 @@
+#![no_main]
+#![allow(dead_code)]
+
+// tapir is a binary, so the parser and what it needs are
+// pulled in by path.
+#[path = "../../src/error.rs"]
+mod error;
+#[path = "../../src/i18n.rs"]
+mod i18n;
+#[path = "../../src/signal.rs"]
+mod signal;
+#[path = "../../src/sse.rs"]
+mod sse;
+#[path = "../../src/types.rs"]
+mod types;
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| sse::fuzz(data));
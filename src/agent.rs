@@ -1,25 +1,37 @@
+use std::collections::HashMap;
 use std::fmt::Write as FmtWrite;
 use std::fs::{self, OpenOptions};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use serde_json::json;
 
 use crate::api;
-use crate::config::Config;
+use crate::checkpoint::Checkpoints;
+use crate::config::{Config, ModelInfo};
+use crate::cost;
 use crate::display::ToolOutputLog;
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::events;
+use crate::i18n::tr;
+use crate::lsp::LspManager;
+use crate::notify;
+use crate::plan::Plan;
 use crate::readline::Editor;
 use crate::session;
 use crate::signal;
+use crate::skill;
 use crate::sse::{Delta, SseEvent};
 use crate::stream;
+use crate::tokens;
 use crate::tool;
+use crate::trash::Trash;
 use crate::types::{
-    Content, ContentBlock, Message, Request, Role, StopReason, SystemBlock,
+    Content, ContentBlock, Message, Note, Request, Role, StopReason,
+    SystemBlock, Usage,
 };
-use crate::util::truncate;
-
-const COMPACT_THRESHOLD: u32 = 160_000;
-const KEEP_RECENT_TOKENS: u32 = 40_000;
+use crate::util::{floor_char_boundary, truncate};
 
 /// Mutable state shared across the session, passed to
 /// command handlers to avoid excessive parameters.
@@ -27,16 +39,251 @@ pub(crate) struct Session {
     pub(crate) entry: session::SessionEntry,
     pub(crate) file: PathBuf,
     pub(crate) messages: Vec<Message>,
+    /// Annotations between messages, from `/note`.
+    pub(crate) notes: Vec<Note>,
     pub(crate) token_pct: Option<u32>,
     pub(crate) total_input_tokens: u64,
     pub(crate) total_output_tokens: u64,
+    /// Dollars spent this session, cache pricing included.
+    pub(crate) cost: f64,
+    /// Start of today and what was spent since, read from the
+    /// ledger once a day and added to after each request.
+    pub(crate) spent_today: Option<(i64, f64)>,
+    /// Spend allowed past the budget limits so far.
+    pub(crate) approved: cost::Approved,
+    /// Response timing across the session's turns.
+    pub(crate) latency: stream::LatencyStats,
+    /// Prompt size of the last request, for compaction.
+    pub(crate) last_input_tokens: u32,
+    /// Ratio of actual to locally estimated prompt tokens,
+    /// learned from the last response.
+    pub(crate) token_scale: Option<f64>,
+    /// Files the model has read or written this session.
+    pub(crate) files: tool::FileTracker,
+    /// Checkpoint position at each user turn, for /rewind.
+    pub(crate) turns: Vec<Turn>,
+    /// Directory `!` escapes run in, changed with `!cd`.
+    pub(crate) shell_dir: PathBuf,
+    /// The last shell escape line, re-run by a bare `!!`.
+    pub(crate) last_escape: Option<String>,
+    /// Whether an automatic name was already asked for.
+    pub(crate) name_tried: bool,
+    /// Send the next request without tools (`/ask`).
+    pub(crate) answer_only: bool,
+    /// Values for `{{name}}` in outgoing messages, from `/set`.
+    pub(crate) vars: std::collections::BTreeMap<String, String>,
+    /// Model and info to return to after an `@name:` turn.
+    pub(crate) default_model: Option<(String, Option<ModelInfo>)>,
+    /// History length when this turn fell back to
+    /// `fallback_model`; thinking before it is signed by the
+    /// model it left.
+    pub(crate) fell_back_at: Option<usize>,
+    /// The `/plan-refactor` plan being worked through.
+    pub(crate) plan: Option<Plan>,
+    /// The last reply stopped at max_tokens.
+    pub(crate) cut_off: bool,
+    /// `/lsp restart` was asked for; done before the next turn.
+    pub(crate) restart_lsp: bool,
+}
+
+/// Where a user turn started: its message index and the last
+/// file snapshot taken before it.
+pub(crate) struct Turn {
+    pub(crate) message_index: usize,
+    pub(crate) checkpoint: u32,
 }
 
 impl Session {
+    /// A session with no messages yet, stored where `entry`
+    /// says.
+    fn new(config: &Config, entry: session::SessionEntry) -> Session {
+        let file = session::session_path(&entry);
+        let files = file_tracker(config, &entry.session_id);
+        Session {
+            entry,
+            file,
+            messages: Vec::new(),
+            notes: Vec::new(),
+            token_pct: None,
+            total_input_tokens: 0,
+            total_output_tokens: 0,
+            cost: 0.0,
+            spent_today: None,
+            approved: cost::Approved::default(),
+            latency: stream::LatencyStats::default(),
+            files,
+            turns: Vec::new(),
+            shell_dir: config.working_dir.clone(),
+            last_escape: None,
+            name_tried: false,
+            answer_only: false,
+            default_model: None,
+            fell_back_at: None,
+            plan: None,
+            cut_off: false,
+            restart_lsp: false,
+            vars: Default::default(),
+            last_input_tokens: 0,
+            token_scale: None,
+        }
+    }
+
     pub(crate) fn push_message(&mut self, msg: Message) {
         save_message(&self.file, &msg);
         self.messages.push(msg);
     }
+
+    /// Add the user's `text`. Tool results the last turn left
+    /// without a reply, as when it ran out of time, share its
+    /// message, since the API wants them answered there.
+    pub(crate) fn push_user_text(&mut self, text: &str) {
+        if let Some(Message {
+            role: Role::User,
+            content: Content::Blocks(blocks),
+        }) = self.messages.last_mut()
+            && blocks
+                .iter()
+                .all(|b| matches!(b, ContentBlock::ToolResult { .. }))
+        {
+            blocks.push(ContentBlock::Text {
+                text: text.to_string(),
+            });
+            if let Err(e) = self.rewrite_file() {
+                eprintln!("* warning: failed to write message: {e}");
+            }
+            return;
+        }
+        self.push_message(Message {
+            role: Role::User,
+            content: Content::Text(text.to_string()),
+        });
+    }
+
+    /// Drop messages from `index` on and rewrite the session
+    /// file to match.
+    pub(crate) fn truncate_messages(&mut self, index: usize) -> Result<()> {
+        self.messages.truncate(index);
+        self.turns.retain(|t| t.message_index < index);
+        self.notes.retain(|n| n.at <= index);
+        self.rewrite_file()
+    }
+
+    /// Record `text` after the current last message.
+    pub(crate) fn add_note(&mut self, text: &str) {
+        let note = Note {
+            at: self.messages.len(),
+            text: text.to_string(),
+            time: session::iso_now(),
+        };
+        if let Err(e) = append_line(&self.file, &note_line(&note)) {
+            eprintln!("* warning: failed to write note: {e}");
+        }
+        self.notes.push(note);
+    }
+
+    /// Swap in a rebuilt conversation, keeping notes within
+    /// it.
+    pub(crate) fn replace_messages(
+        &mut self,
+        msgs: Vec<Message>,
+    ) -> Result<()> {
+        self.messages = msgs;
+        let len = self.messages.len();
+        self.reindex(|at| at.min(len));
+        self.rewrite_file()
+    }
+
+    /// After `compact` summarized the first `cut` messages:
+    /// whatever pointed into them now points at the summary.
+    pub(crate) fn compacted(&mut self, cut: usize) {
+        if cut == 0 {
+            return;
+        }
+        let summary = 2; // summary_messages
+        self.reindex(|at| {
+            if at >= cut {
+                at - cut + summary
+            } else {
+                at.min(summary)
+            }
+        });
+    }
+
+    /// Move note positions and the plan's start with `map`
+    /// after messages were rebuilt. Turn boundaries no longer
+    /// line up, so /rewind starts over.
+    fn reindex(&mut self, map: impl Fn(usize) -> usize) {
+        self.turns.clear();
+        for note in &mut self.notes {
+            note.at = map(note.at);
+        }
+        if let Some(plan) = &mut self.plan {
+            plan.since = map(plan.since);
+            plan.save(&self.file);
+        }
+    }
+
+    /// Context use before the API has reported any: the next
+    /// request's size estimated locally.
+    pub(crate) fn estimated_pct(&self, config: &mut Config) -> u32 {
+        let tokens = self.estimate_tokens(config, &self.messages);
+        (tokens as f64 / config.context_window() as f64 * 100.0) as u32
+    }
+
+    /// Refuse a user message that can't fit the context window
+    /// even on its own, instead of spending a request on the
+    /// API's 400.
+    pub(crate) fn check_fits(
+        &self,
+        config: &mut Config,
+        text: &str,
+    ) -> Result<()> {
+        let msg = [Message {
+            role: Role::User,
+            content: Content::Text(text.to_string()),
+        }];
+        let tokens = self.estimate_tokens(config, &msg);
+        let window = config.context_window();
+        if tokens <= window {
+            return Ok(());
+        }
+        Err(Error::ContextOverflow { tokens, window })
+    }
+
+    /// Locally estimated size of a request for `messages`,
+    /// scaled by what the last response showed, or else by the
+    /// model's `token_scale`.
+    fn estimate_tokens(
+        &self,
+        config: &mut Config,
+        messages: &[Message],
+    ) -> u32 {
+        config.ensure_full_prompt();
+        let scale = self
+            .token_scale
+            .or(config.model_info.as_ref().and_then(|m| m.token_scale))
+            .unwrap_or(1.0);
+        let tools = tool::definitions(config.memory);
+        let request = turn_request(config, messages, &tools);
+        tokens::estimate_request(&request, scale)
+    }
+
+    fn rewrite_file(&self) -> Result<()> {
+        let mut out = session_header();
+        let mut notes = self.notes.iter().peekable();
+        for (i, msg) in self.messages.iter().enumerate() {
+            while let Some(note) = notes.next_if(|n| n.at <= i) {
+                out.push_str(&format!("{}\n", note_line(note)));
+            }
+            out.push_str(&serde_json::to_string(msg)?);
+            out.push('\n');
+        }
+        for note in notes {
+            out.push_str(&format!("{}\n", note_line(note)));
+        }
+        fs::write(&self.file, out)?;
+        Ok(())
+    }
 }
 
 // ----------------------------------------------------------
@@ -45,27 +292,59 @@ impl Session {
 
 use crate::command::{self, InputResult};
 
-pub fn run(config: &mut Config) -> Result<()> {
+/// How tapir was started, from the command line.
+#[derive(Default)]
+pub struct Launch {
+    /// Resume the most recent session of this project.
+    pub continue_last: bool,
+    /// Run this prompt without the line editor, then exit.
+    pub prompt: Option<String>,
+}
+
+pub fn run(config: &mut Config, launch: Launch) -> Result<()> {
     fs::create_dir_all(&config.session_dir)?;
+    crate::telemetry::init(config.telemetry.as_ref());
+    // A bad theme is warned about at load and left dark
+    if let Ok(theme) = config.color_theme() {
+        crate::theme::set(theme.for_terminal());
+    }
+    if launch.prompt.is_none() {
+        notify::configure(config.title, config.notify);
+    }
+    let pruned = session::prune(&config.session_dir, &config.retention);
+    if pruned > 0 {
+        eprintln!("* pruned {pruned} old sessions");
+    }
 
-    let tools = tool::definitions();
-    let mut editor = Editor::new()?;
+    let tools = tool::definitions(config.memory);
+    let mut editor = Editor::new(config.dirs.state.join("history"))?;
+    // A bad keymap is warned about at load and left default
+    editor.set_keymap(config.keymap().unwrap_or_default());
+    let lsp = LspManager::new(config.lsp.clone(), &config.working_dir);
+    let headless = launch.prompt.is_some();
+    if headless {
+        // Nobody to answer; anything that would ask is refused
+        config.policy.confirm = |_| false;
+    }
+    let mut launch = Some(launch);
 
     // Outer loop: each iteration is one full session.
     // /new restarts this loop.
     loop {
+        config.prompt_override = None;
+        config.refresh_environment();
         let entry =
-            session::create_entry(&config.session_dir, &config.working_dir);
-        let file = session::session_path(&entry);
-
-        let mut session = Session {
-            entry,
-            file,
-            messages: Vec::new(),
-            token_pct: None,
-            total_input_tokens: 0,
-            total_output_tokens: 0,
-        };
+            session::create_entry(&config.session_dir, &config.working_dir)?;
+        let mut session = Session::new(config, entry);
+        events::open(&session.file);
+        events::emit(
+            "session_start",
+            json!({
+                "session_id": session.entry.session_id,
+                "model": config.model,
+                "cwd": config.working_dir,
+            }),
+        );
 
         if !config.context_files.is_empty() {
             eprintln!("context:");
@@ -80,35 +359,69 @@ pub fn run(config: &mut Config) -> Result<()> {
         if !config.skills.is_empty() {
             eprintln!("skills:");
             let limit = 5;
-            for skill in config.skills.iter().take(limit) {
+            let active = config.skills.iter().filter(|s| !s.shadowed);
+            for skill in active.take(limit) {
                 eprintln!("  - {}", skill.name);
             }
             let total = config.skills.len();
             if total > limit {
                 eprintln!("  ({total} total, use /skills to see all)");
             }
+            let broken = config
+                .skills
+                .iter()
+                .filter(|s| !s.problems.is_empty())
+                .count();
+            if broken > 0 {
+                eprintln!("  ({broken} with problems, see /skills)");
+            }
             eprintln!();
         }
+        // Command-line options apply to the first session only
+        let Launch {
+            continue_last,
+            prompt,
+        } = launch.take().unwrap_or_default();
+        if continue_last {
+            match session::recent_entries(&config.session_dir)
+                .into_iter()
+                .next()
+            {
+                Some(entry) => command::resume(config, &mut session, entry),
+                None => {
+                    eprintln!("* no session to continue, starting a new one")
+                }
+            }
+        }
+
         eprintln!("cwd:     {}", config.working_dir.display());
         eprintln!("session: {}", session.entry.session_id);
         eprintln!();
 
-        // Initial input (supports /resume, /help, etc.)
-        let mut empty_log = ToolOutputLog::new();
-        match command::read_input(
-            &mut editor,
-            config,
-            &mut session,
-            &mut empty_log,
-            true,
-        )? {
-            InputResult::Quit => {
-                eprintln!("bye");
-                return Ok(());
+        if let Some(prompt) = prompt {
+            if session.entry.first_prompt == "No prompt" {
+                session.entry.first_prompt = truncate(&prompt, 100);
+            }
+            session.check_fits(config, &prompt)?;
+            command::add_user_message(&mut session, &prompt);
+        } else {
+            // Initial input (supports /resume, /help, etc.)
+            let mut empty_log = ToolOutputLog::new();
+            match read_input(
+                &mut editor,
+                config,
+                &mut session,
+                &mut empty_log,
+                &lsp,
+            )? {
+                InputResult::Quit => {
+                    eprintln!("bye");
+                    return Ok(());
+                }
+                InputResult::New => continue,
+                InputResult::Continue | InputResult::Idle => unreachable!(),
+                InputResult::Ready => {}
             }
-            InputResult::New => continue,
-            InputResult::Continue => unreachable!(),
-            InputResult::Ready => {}
         }
 
         // Persist new entry in index (or updated after
@@ -117,7 +430,14 @@ pub fn run(config: &mut Config) -> Result<()> {
         session::update_entry(&config.session_dir, &session.entry);
 
         // Conversation loop for this session
-        if run_session(config, &tools, &mut editor, &mut session)? {
+        if run_session(
+            config,
+            &tools,
+            &mut editor,
+            &mut session,
+            &lsp,
+            headless,
+        )? {
             // /new was requested — loop to create fresh
             // session
             continue;
@@ -127,57 +447,329 @@ pub fn run(config: &mut Config) -> Result<()> {
     }
 }
 
-/// Drive a single session until quit or /new.
-/// Returns `true` if /new was requested.
+/// Tracker for a session's file reads and writes, with
+/// checkpoints and trash under the session id.
+pub(crate) fn file_tracker(
+    config: &Config,
+    session_id: &str,
+) -> tool::FileTracker {
+    tool::FileTracker::with_checkpoints(Checkpoints::new(
+        &config.working_dir,
+        session_id,
+    ))
+    .with_trash(Trash::new(
+        &config.dirs.data,
+        session_id,
+        config.trash_quota,
+    ))
+}
+
+/// The request for one turn of the conversation. Call
+/// `config.ensure_full_prompt()` first.
+pub(crate) fn turn_request<'a>(
+    config: &'a Config,
+    messages: &'a [Message],
+    tools: &'a [crate::types::ToolDef],
+) -> Request<'a> {
+    let thinking = if config.thinking_budget > 0 {
+        Some(crate::types::ThinkingConfig {
+            kind: "enabled",
+            budget_tokens: config.thinking_budget,
+        })
+    } else {
+        None
+    };
+    // The API refuses sampling settings alongside thinking
+    let sampling = thinking.is_none();
+    Request {
+        model: &config.model,
+        max_tokens: config.max_tokens,
+        thinking,
+        system: vec![SystemBlock::cached_text(config.full_prompt())],
+        messages,
+        temperature: config.temperature.filter(|_| sampling),
+        top_p: config.top_p.filter(|_| sampling),
+        stop_sequences: &config.stop_sequences,
+        tools,
+        tool_choice: None,
+        stream: true,
+        context_management: config
+            .context_editing
+            .as_ref()
+            .map(|c| c.request()),
+    }
+}
+
+/// The model to retry a turn on after `err`: the configured
+/// fallback, when the current model stayed overloaded through
+/// every retry and isn't the fallback already.
+fn fallback_model(config: &Config, err: &Error) -> Option<String> {
+    if !matches!(err, Error::Api { status: 529, .. }) {
+        return None;
+    }
+    let name = config.fallback_model.as_deref()?;
+    let model = config.resolve_model(name).unwrap_or(name);
+    (model != config.model).then(|| model.to_string())
+}
+
+/// The history as sent to the fallback model: thinking from
+/// before the switch at `at` is dropped, since only the model
+/// that wrote it accepts its signatures, as in `/migrate`.
+fn fallback_history(messages: &[Message], at: usize) -> Vec<Message> {
+    let at = at.min(messages.len());
+    let mut out = crate::export::migrate(&messages[..at], false);
+    out.extend_from_slice(&messages[at..]);
+    out
+}
+
+/// Shrink `max_tokens` and the thinking budget to what `info`
+/// allows. The settings were sized for the model the turn
+/// fell back from.
+fn fit_to_model(request: &mut Request<'_>, info: Option<&ModelInfo>) {
+    let Some(info) = info else {
+        return;
+    };
+    request.max_tokens = request.max_tokens.min(info.max_output);
+    if let Some(thinking) = &mut request.thinking {
+        if thinking.budget_tokens >= request.max_tokens {
+            thinking.budget_tokens = request.max_tokens / 2;
+        }
+        if !info.extended_thinking
+            || thinking.budget_tokens < MIN_THINKING_BUDGET
+        {
+            request.thinking = None;
+        }
+    }
+}
+
+/// Smallest thinking budget the API accepts.
+const MIN_THINKING_BUDGET: u32 = 1024;
+
+/// Keep the model from calling tools on this request. The
+/// API refuses tool blocks in the history without tool
+/// definitions, so those keep them and forbid their use.
+fn without_tools(request: &mut Request<'_>) {
+    let used = request.messages.iter().any(|m| match &m.content {
+        Content::Blocks(blocks) => blocks.iter().any(|b| {
+            matches!(
+                b,
+                ContentBlock::ToolUse { .. } | ContentBlock::ToolResult { .. }
+            )
+        }),
+        Content::Text(_) => false,
+    });
+    if used {
+        request.tool_choice = Some(json!({"type": "none"}));
+    } else {
+        request.tools = &[];
+    }
+}
+
+/// Drive a single session until quit or /new, or until the
+/// first answer when `headless`. Returns `true` if /new was
+/// requested.
 fn run_session(
     config: &mut Config,
     tools: &[crate::types::ToolDef],
     editor: &mut Editor,
     session: &mut Session,
+    lsp: &LspManager,
+    headless: bool,
 ) -> Result<bool> {
-    let mut last_input_tokens: u32 = 0;
     let mut tool_log = ToolOutputLog::new();
+    let mut turn_started = Instant::now();
+    let mut tools_run = 0;
 
     loop {
+        // The terminal is gone; messages are already saved
+        if crate::signal::hung_up() {
+            session.entry.message_count = session.messages.len() as u32;
+            session.entry.modified = session::iso_now();
+            session::update_entry(&config.session_dir, &session.entry);
+            return Ok(false);
+        }
         tool_log.clear();
-        if last_input_tokens > COMPACT_THRESHOLD {
-            compact(config, &mut session.messages, last_input_tokens)?;
+        let (threshold, keep_tokens) = config.compaction_limits();
+        let mut tokens = session.last_input_tokens;
+        // A large tool result or message can overflow the
+        // window even though the last prompt was well inside it
+        let estimated = session.estimate_tokens(config, &session.messages);
+        if estimated > config.context_window() {
+            eprintln!(
+                "* next request would overflow the context window \
+                 (~{estimated} tokens)"
+            );
+            tokens = tokens.max(estimated);
+        }
+        if tokens > threshold {
+            let keep = keep_tokens as f64 / tokens as f64;
+            // Pruning old tool output is free; summarize only
+            // if that isn't enough
+            let freed = prune_tool_results(&mut session.messages, keep);
+            let remaining = tokens.saturating_sub(freed);
+            if freed > 0 && remaining < threshold {
+                session.last_input_tokens = remaining;
+            } else {
+                let cut =
+                    compact(config, &mut session.messages, tokens, keep, "")?;
+                session.compacted(cut);
+            }
+            // Neither touches the newest messages, where one
+            // huge result can still overflow the window alone
+            let estimated = session.estimate_tokens(config, &session.messages);
+            if estimated > config.context_window() {
+                let freed = shrink_tool_results(
+                    &mut session.messages,
+                    estimated - threshold.min(estimated),
+                );
+                eprintln!("* cut large tool results (~{freed} tokens)");
+            }
         }
 
-        let thinking = if config.thinking_budget > 0 {
-            Some(crate::types::ThinkingConfig {
-                kind: "enabled",
-                budget_tokens: config.thinking_budget,
-            })
-        } else {
-            None
-        };
+        let repairs = crate::validate::repair(&mut session.messages);
+        if !repairs.is_empty() {
+            for note in &repairs {
+                eprintln!("* repaired {note}");
+            }
+            let len = session.messages.len();
+            session.reindex(|at| at.min(len));
+            events::emit("messages_repaired", json!({"repairs": repairs}));
+        }
+
+        if !within_budget(config, session, headless)?
+            || !preflight(config, session, tools, headless)
+        {
+            if let Some(leave) = next_input(
+                config,
+                editor,
+                session,
+                &mut tool_log,
+                lsp,
+                headless,
+            )? {
+                return Ok(leave);
+            }
+            (turn_started, tools_run) = (Instant::now(), 0);
+            continue;
+        }
 
         config.ensure_full_prompt();
-        let request = Request {
-            model: &config.model,
-            max_tokens: config.max_tokens,
-            thinking,
-            system: vec![SystemBlock::cached_text(config.full_prompt())],
-            messages: &session.messages,
-            tools,
-            stream: true,
+        notify::title("thinking");
+        let answer_only = std::mem::take(&mut session.answer_only);
+        // Nothing changes before the user approves the plan
+        let drafting = session.plan.as_ref().is_some_and(|p| p.drafting);
+        let look_only: Vec<crate::types::ToolDef>;
+        let tools = if drafting {
+            look_only = tools
+                .iter()
+                .filter(|t| tool::READ_ONLY.contains(&t.name.as_str()))
+                .cloned()
+                .collect();
+            &look_only[..]
+        } else {
+            tools
         };
+        let started = Instant::now();
+        let (result, estimated) = loop {
+            let stripped;
+            let messages = match session.fell_back_at {
+                Some(at) => {
+                    stripped = fallback_history(&session.messages, at);
+                    &stripped[..]
+                }
+                None => &session.messages[..],
+            };
+            let mut request = turn_request(config, messages, tools);
+            if session.fell_back_at.is_some() {
+                fit_to_model(&mut request, config.model_info.as_ref());
+            }
+            if answer_only {
+                without_tools(&mut request);
+            }
+            let estimated = tokens::estimate_request(&request, 1.0);
+            events::emit(
+                "turn_start",
+                json!({
+                    "model": config.model,
+                    "messages": session.messages.len(),
+                }),
+            );
+            let err = match stream::stream_response(config, &request) {
+                Ok(result) => break (result, estimated),
+                Err(e) => e,
+            };
+            let Some(fallback) = fallback_model(config, &err) else {
+                return Err(err);
+            };
+            eprintln!(
+                "* {} is overloaded, using {fallback} for this turn",
+                config.model
+            );
+            events::emit(
+                "model_fallback",
+                json!({"from": config.model, "to": fallback}),
+            );
+            let info = config.models.get(&fallback).cloned();
+            let model = std::mem::replace(&mut config.model, fallback);
+            let info = std::mem::replace(&mut config.model_info, info);
+            // Back to the primary model at the next prompt
+            session.default_model.get_or_insert((model, info));
+            session.fell_back_at.get_or_insert(session.messages.len());
+        };
+        let cost = cost::cost(config.model_info.as_ref(), &result.usage);
+        events::emit(
+            "turn_end",
+            json!({
+                "duration_ms": started.elapsed().as_millis() as u64,
+                "model": config.model,
+                "cost": cost,
+                "stop_reason": result.stop_reason,
+                "interrupted": result.interrupted,
+                "input_tokens": result.usage.input_tokens,
+                "output_tokens": result.usage.output_tokens,
+                "cache_read_tokens": result.usage.cache_read_input_tokens,
+                "cache_write_tokens": result.usage.cache_creation_input_tokens,
+                "ttft_ms": result
+                    .timing
+                    .first_token
+                    .map(|t| t.as_millis() as u64),
+                "tokens_per_sec": result
+                    .timing
+                    .tokens_per_sec(result.usage.output_tokens),
+            }),
+        );
 
-        let result = stream::stream_response(config, &request)?;
+        session.cut_off = result.stop_reason == StopReason::MaxTokens;
 
         // Accumulate usage
         let u = &result.usage;
-        last_input_tokens = u.input_tokens;
+        session.last_input_tokens = u.prompt_tokens();
         session.total_input_tokens += u.input_tokens as u64;
         session.total_output_tokens += u.output_tokens as u64;
-        let context_window = config
-            .model_info
-            .as_ref()
-            .map(|m| m.context)
-            .unwrap_or(200_000);
-        let pct =
-            (u.input_tokens as f64 / context_window as f64 * 100.0) as u32;
+        session.cost += cost;
+        if let Some((_, today)) = &mut session.spent_today {
+            *today += cost;
+        }
+        cost::record(
+            &cost::ledger_path(&config.dirs.data),
+            &cost::Entry {
+                time: session::now_secs(),
+                session_id: session.entry.session_id.clone(),
+                project: config.working_dir.clone(),
+                model: config.model.clone(),
+                input_tokens: u.input_tokens,
+                output_tokens: u.output_tokens,
+                cache_read_tokens: u.cache_read_input_tokens,
+                cache_write_tokens: u.cache_creation_input_tokens,
+                cost,
+            },
+        );
+        if u.prompt_tokens() > 0 {
+            session.token_scale =
+                Some(tokens::calibrate(u.prompt_tokens(), estimated));
+        }
+        let pct = (u.prompt_tokens() as f64 / config.context_window() as f64
+            * 100.0) as u32;
         session.token_pct = Some(pct);
         save_token_pct(&session.file, pct);
         eprint!(
@@ -190,7 +782,22 @@ fn run_session(
         if u.cache_read_input_tokens > 0 {
             eprint!(" cache_read={}", u.cache_read_input_tokens);
         }
-        eprintln!();
+        eprintln!(" {}", result.timing.describe(u.output_tokens));
+        session.latency.add(&result.timing, u.output_tokens);
+
+        if let Some(editing) = config
+            .context_editing
+            .as_ref()
+            .filter(|_| result.cleared_tool_uses > 0)
+        {
+            let n = clear_tool_results(
+                &mut session.messages,
+                editing.keep_tool_uses as usize,
+                &editing.exclude_tools,
+            );
+            eprintln!("* server cleared {n} old tool results");
+            events::emit("context_cleared", json!({"tool_uses": n}));
+        }
 
         // Handle empty interrupted response
         if result.interrupted && result.content.is_empty() {
@@ -210,14 +817,40 @@ fn run_session(
                 content: Content::Blocks(result.content),
             });
 
-            if !result.interrupted && result.stop_reason == StopReason::ToolUse
-            {
+            let wants_tools = !result.interrupted
+                && result.stop_reason == StopReason::ToolUse;
+            if wants_tools && out_of_time(config, turn_started) {
+                eprintln!(
+                    "* turn_timeout reached after {}s: {tools_run} tool \
+                     calls ran, {} not run",
+                    turn_started.elapsed().as_secs(),
+                    tool_calls.len()
+                );
+                events::emit(
+                    "turn_timeout",
+                    json!({"tools_run": tools_run, "skipped": tool_calls.len()}),
+                );
+                // Saved now, so a resume still has them
+                session.push_message(Message {
+                    role: Role::User,
+                    content: Content::Blocks(not_run(&tool_calls)),
+                });
+            } else if wants_tools {
                 signal::clear();
+                tools_run += tool_calls.len();
+                let names: Vec<&str> = tool_calls
+                    .iter()
+                    .map(|(_, name, _)| name.as_str())
+                    .collect();
+                notify::title(&format!("running {}", names.join(", ")));
+                let config: &Config = config;
+                let files = &session.files;
                 let results: Vec<ContentBlock> = std::thread::scope(|s| {
                     let handles: Vec<_> = tool_calls
                         .iter()
                         .map(|(id, name, input)| {
                             let wd = &config.working_dir;
+                            let policy = &config.policy;
                             s.spawn(move || {
                                 if signal::is_interrupted() {
                                     return ContentBlock::ToolResult {
@@ -226,16 +859,47 @@ fn run_session(
                                         is_error: Some(true),
                                     };
                                 }
-                                let output = tool::execute(wd, name, input);
+                                let started = Instant::now();
+                                let output = if drafting
+                                    && !tool::READ_ONLY.contains(&name.as_str())
+                                {
+                                    Err(Error::Tool {
+                                        name: name.clone(),
+                                        message: "not available while the \
+                                                  plan is drafted"
+                                            .into(),
+                                    })
+                                } else {
+                                    tool::execute(wd, files, policy, name, input)
+                                };
+                                events::emit(
+                                    "tool",
+                                    json!({
+                                        "name": name,
+                                        "duration_ms":
+                                            started.elapsed().as_millis() as u64,
+                                        "is_error": output.is_err(),
+                                        "output_bytes": output
+                                            .as_ref()
+                                            .map_or(0, |o| o.len()),
+                                    }),
+                                );
                                 let (content, is_error) = match output {
                                     Ok(out) => {
-                                        let display = truncate(&out, 50_000);
+                                        let mut display =
+                                            truncate(&out, 50_000);
+                                        if let Some(path) =
+                                            tool::edited_path(wd, name, input)
+                                        {
+                                            display.push_str(&after_edit(
+                                                config, lsp, &path,
+                                            ));
+                                        }
                                         (display, None)
                                     }
                                     Err(e) => {
-                                        let msg = e.to_string();
-                                        eprintln!("* error: {msg}");
-                                        (msg, Some(true))
+                                        e.report("* error: ");
+                                        (e.to_string(), Some(true))
                                     }
                                 };
                                 ContentBlock::ToolResult {
@@ -246,7 +910,29 @@ fn run_session(
                             })
                         })
                         .collect();
-                    handles.into_iter().map(|h| h.join().unwrap()).collect()
+                    // A panic outside the tool itself, say in the
+                    // post-edit checks, still only fails its call
+                    handles
+                        .into_iter()
+                        .zip(&tool_calls)
+                        .map(|(h, (id, name, _))| {
+                            h.join().unwrap_or_else(|payload| {
+                                let e = Error::Tool {
+                                    name: name.clone(),
+                                    message: format!(
+                                        "panicked: {}",
+                                        tool::panic_message(&*payload)
+                                    ),
+                                };
+                                e.report("* error: ");
+                                ContentBlock::ToolResult {
+                                    tool_use_id: id.clone(),
+                                    content: e.to_string(),
+                                    is_error: Some(true),
+                                }
+                            })
+                        })
+                        .collect()
                 });
                 if signal::is_interrupted() {
                     eprintln!("* tools interrupted");
@@ -264,6 +950,7 @@ fn run_session(
                         if *is_error == Some(true) || content.is_empty() {
                             continue;
                         }
+                        note_skill_read(config, name, input);
                         let header = stream::tool_call_header(name, input);
                         tool_log.push(header, content.clone());
                         tool_log.print_last();
@@ -286,49 +973,415 @@ fn run_session(
             }
         }
 
-        // Update index
-        session.entry.message_count = session.messages.len() as u32;
-        session.entry.modified = session::iso_now();
-        session::update_entry(&config.session_dir, &session.entry);
+        if turn_started.elapsed().as_secs() >= config.notify_after {
+            notify::alert("turn finished");
+        }
+        if let Some(leave) =
+            next_input(config, editor, session, &mut tool_log, lsp, headless)?
+        {
+            return Ok(leave);
+        }
+        (turn_started, tools_run) = (Instant::now(), 0);
+    }
 
-        // Read next user input
-        match command::read_input(
-            editor,
-            config,
-            session,
-            &mut tool_log,
-            false,
-        )? {
-            InputResult::Ready => {}
-            InputResult::Continue => unreachable!(),
-            InputResult::Quit => {
-                eprintln!("bye");
-                return Ok(false);
+    Ok(false)
+}
+
+/// Results for tool calls a turn stopped at when it ran past
+/// `turn_timeout`.
+fn not_run(
+    tool_calls: &[(String, String, serde_json::Value)],
+) -> Vec<ContentBlock> {
+    tool_calls
+        .iter()
+        .map(|(id, _, _)| ContentBlock::ToolResult {
+            tool_use_id: id.clone(),
+            content: "(not run: the turn ran out of time; the user may \
+                      ask to continue)"
+                .to_string(),
+            is_error: Some(true),
+        })
+        .collect()
+}
+
+/// Whether the turn begun at `started` has used up
+/// `turn_timeout`.
+fn out_of_time(config: &Config, started: Instant) -> bool {
+    config.turn_timeout > 0
+        && started.elapsed().as_secs() >= config.turn_timeout
+}
+
+/// Finish a turn: name the session, update the index and read
+/// the next input. Returns `Some` with `run_session`'s result
+/// when the session is over.
+fn next_input(
+    config: &mut Config,
+    editor: &mut Editor,
+    session: &mut Session,
+    tool_log: &mut ToolOutputLog,
+    lsp: &LspManager,
+    headless: bool,
+) -> Result<Option<bool>> {
+    if config.auto_name
+        && !session.name_tried
+        && session.entry.summary.is_empty()
+    {
+        session.name_tried = true;
+        name_session(config, session);
+    }
+
+    // Update index
+    session.entry.message_count = session.messages.len() as u32;
+    session.entry.modified = session::iso_now();
+    session::update_entry(&config.session_dir, &session.entry);
+    if headless {
+        return Ok(Some(false));
+    }
+
+    // Read next user input
+    match read_input(editor, config, session, tool_log, lsp)? {
+        InputResult::Ready => Ok(None),
+        InputResult::Continue | InputResult::Idle => unreachable!(),
+        InputResult::Quit => {
+            eprintln!("bye");
+            Ok(Some(false))
+        }
+        InputResult::New => {
+            eprintln!("* starting new session");
+            Ok(Some(true))
+        }
+    }
+}
+
+/// `command::read_input`, handling `idle_timeout`: messages
+/// and the session index are on disk by then, so either quit
+/// or stop the language servers and wait for input without a
+/// timeout.
+fn read_input(
+    editor: &mut Editor,
+    config: &mut Config,
+    session: &mut Session,
+    tool_log: &mut ToolOutputLog,
+    lsp: &LspManager,
+) -> Result<InputResult> {
+    let mut waiting = false;
+    notify::title("waiting for input");
+    loop {
+        editor.set_idle_timeout(if waiting { 0 } else { config.idle_timeout });
+        let input = command::read_input(editor, config, session, tool_log)?;
+        if std::mem::take(&mut session.restart_lsp) {
+            lsp.restart();
+        }
+        match input {
+            InputResult::Idle => {
+                events::emit("idle", json!({"seconds": config.idle_timeout}));
+                if config.idle_exit {
+                    eprintln!(
+                        "* idle for {}s, session saved",
+                        config.idle_timeout
+                    );
+                    return Ok(InputResult::Quit);
+                }
+                lsp.stop();
+                waiting = true;
+                eprintln!("* idle, session saved and language servers stopped");
             }
-            InputResult::New => {
-                eprintln!("* starting new session");
-                return Ok(true);
+            other => return Ok(other),
+        }
+    }
+}
+
+/// Stop before a request once a spending limit is reached,
+/// until the user allows another limit's worth; headless,
+/// an error. Returns whether to go on.
+fn within_budget(
+    config: &Config,
+    session: &mut Session,
+    headless: bool,
+) -> Result<bool> {
+    let budget = &config.budget;
+    if !budget.is_set() {
+        return Ok(true);
+    }
+    let today = if budget.per_day.is_some() {
+        let (day, _) = cost::day_and_week_start(session::now_secs());
+        match session.spent_today {
+            Some((start, spent)) if start == day => spent,
+            _ => {
+                let ledger = cost::ledger_path(&config.dirs.data);
+                let spent = cost::spent_since(&ledger, day);
+                session.spent_today = Some((day, spent));
+                spent
             }
         }
+    } else {
+        0.0
+    };
+    let Some((limit, max)) =
+        budget.exceeded(session.cost, today, &session.approved)
+    else {
+        return Ok(true);
+    };
+    let (spent, what) = match limit {
+        cost::Limit::Session => (session.cost, "this session"),
+        cost::Limit::Day => (today, "today"),
+    };
+    events::emit(
+        "budget_exceeded",
+        json!({"limit": what, "max": max, "spent": spent}),
+    );
+    if headless {
+        return Err(Error::OverBudget { spent, max, what });
     }
+    let question = tr!(
+        "* spent ${spent} {what}, over the ${max} limit. Continue? [y/N] ",
+        spent = format!("{spent:.2}"),
+        what = crate::i18n::t(what),
+        max = format!("{max:.2}"),
+    );
+    notify::title("waiting for permission");
+    notify::alert("over the spending limit");
+    if !crate::readline::confirm(&question) {
+        eprintln!("{}", tr!("* paused"));
+        return Ok(false);
+    }
+    session.approved.raise(limit, max);
+    Ok(true)
+}
 
-    Ok(false)
+/// Print the next request's size and input cost, and ask
+/// first when a user turn adds more than `confirm_tokens`
+/// since the last reply; a tool loop already under way is
+/// never stopped. A declined message is dropped, though tool
+/// results sent with it are kept. Returns whether to send
+/// the request.
+fn preflight(
+    config: &mut Config,
+    session: &mut Session,
+    tools: &[crate::types::ToolDef],
+    headless: bool,
+) -> bool {
+    if !config.preflight && config.confirm_tokens == 0 {
+        return true;
+    }
+    let counted = config
+        .count_tokens
+        .then(|| {
+            let request = turn_request(config, &session.messages, tools);
+            api::count_tokens(config, &request).ok()
+        })
+        .flatten();
+    let (total, cached) = token_split(config, session, counted);
+    let added = total - cached;
+    let usage = Usage {
+        input_tokens: added,
+        cache_read_input_tokens: cached,
+        ..Default::default()
+    };
+    let cost = cost::cost(config.model_info.as_ref(), &usage);
+    if config.preflight {
+        let about = if counted.is_some() { "" } else { "~" };
+        let line = format!(
+            "* {about}{total} tokens in, {about}{added} new, \
+             {about}${cost:.4} before output"
+        );
+        eprintln!("{}", crate::theme::paint(crate::theme::Style::Dim, &line));
+    }
+    let user_turn = session.messages.last().is_some_and(|m| {
+        m.role == Role::User
+            && match &m.content {
+                Content::Text(_) => true,
+                Content::Blocks(blocks) => blocks
+                    .iter()
+                    .any(|b| !matches!(b, ContentBlock::ToolResult { .. })),
+            }
+    });
+    if headless
+        || !user_turn
+        || config.confirm_tokens == 0
+        || added <= config.confirm_tokens
+    {
+        return true;
+    }
+    notify::title("waiting for permission");
+    notify::alert("waiting for permission");
+    let question = tr!(
+        "* this request adds {about}{added} tokens. Send it? [y/N] ",
+        about = if counted.is_some() { "" } else { "~" },
+        added = added,
+    );
+    if (config.policy.confirm)(&question) {
+        return true;
+    }
+    let Some(Message { content, .. }) = session.messages.pop() else {
+        return false;
+    };
+    let results: Vec<ContentBlock> = match content {
+        Content::Blocks(blocks) => blocks
+            .into_iter()
+            .filter(|b| matches!(b, ContentBlock::ToolResult { .. }))
+            .collect(),
+        Content::Text(_) => Vec::new(),
+    };
+    if !results.is_empty() {
+        session.messages.push(Message {
+            role: Role::User,
+            content: Content::Blocks(results),
+        });
+    }
+    if let Err(e) = session.truncate_messages(session.messages.len()) {
+        eprintln!("* warning: failed to rewrite session: {e}");
+    }
+    eprintln!("{}", tr!("* not sent"));
+    false
+}
+
+/// Prompt tokens of the next request, and how many of them
+/// were sent before, up to the last reply, and are likely
+/// cached. `counted` is the API's count of the whole, which
+/// the local estimate is scaled to.
+fn token_split(
+    config: &mut Config,
+    session: &Session,
+    counted: Option<u32>,
+) -> (u32, u32) {
+    let estimated = session.estimate_tokens(config, &session.messages);
+    let sent = session
+        .messages
+        .iter()
+        .rposition(|m| m.role == Role::Assistant)
+        .map_or(0, |i| i + 1);
+    let before = match sent {
+        0 => 0,
+        n => session.estimate_tokens(config, &session.messages[..n]),
+    };
+    let total = counted.unwrap_or(estimated);
+    let scale = total as f64 / estimated.max(1) as f64;
+    let cached = ((before as f64 * scale) as u32).min(total);
+    (total, cached)
 }
 
-pub(crate) fn load_session(path: &std::path::Path) -> Result<Vec<Message>> {
+/// Feedback appended to a write/edit result: post-edit hook
+/// failures first, then LSP diagnostics on the final content.
+fn after_edit(config: &Config, lsp: &LspManager, path: &Path) -> String {
+    let mut out = String::new();
+    let hook =
+        tool::run_post_edit_hook(&config.working_dir, &config.post_edit, path);
+    for extra in [hook, lsp.diagnostics(path)].into_iter().flatten() {
+        out.push_str("\n\n");
+        out.push_str(&extra);
+    }
+    out
+}
+
+/// Count a read_file of a SKILL.md as the model using that skill.
+fn note_skill_read(config: &Config, name: &str, input: &serde_json::Value) {
+    if name != "read_file" {
+        return;
+    }
+    let Some(path) = input["path"].as_str() else {
+        return;
+    };
+    let path = config.working_dir.join(path);
+    let path = path.canonicalize().unwrap_or(path);
+    if let Some(skill) = skill::by_path(&config.skills, &path) {
+        skill::record_use(
+            &skill::stats_path(&config.dirs.data),
+            skill,
+            skill::Invoker::Model,
+        );
+    }
+}
+
+/// Format of the message lines in session files. Bump it
+/// when `Message` serialization changes, and teach
+/// `upgrade_message` to convert the older form.
+const SESSION_VERSION: u64 = 1;
+
+/// First line of every session file.
+fn session_header() -> String {
+    format!("{}\n", json!({"tapir_session": SESSION_VERSION}))
+}
+
+/// Messages and notes of a session file.
+pub(crate) fn load_transcript(
+    path: &std::path::Path,
+) -> Result<(Vec<Message>, Vec<Note>)> {
     let content = fs::read_to_string(path)?;
+    let (messages, notes, skipped) = parse_session(&content)?;
+    if skipped > 0 {
+        eprintln!(
+            "* warning: skipped {skipped} unreadable entries in {}",
+            path.display()
+        );
+    }
+    Ok((messages, notes))
+}
+
+/// Session file line of a note. Older builds skip it as an
+/// unreadable entry.
+fn note_line(note: &Note) -> String {
+    json!({"note": note.text, "time": note.time}).to_string()
+}
+
+/// Messages and notes of a session file and how many
+/// unreadable entries (newer or corrupt blocks) were left out.
+/// Files without a header predate versioning.
+fn parse_session(content: &str) -> Result<(Vec<Message>, Vec<Note>, usize)> {
+    let mut lines = content.lines().filter(|l| !l.is_empty()).peekable();
+    let mut version = 0;
+    if let Some(first) = lines.peek()
+        && let Ok(header) = serde_json::from_str::<serde_json::Value>(first)
+        && let Some(v) = header["tapir_session"].as_u64()
+    {
+        version = v;
+        lines.next();
+    }
+    if version > SESSION_VERSION {
+        eprintln!(
+            "* warning: session written by a newer tapir (format {version})"
+        );
+    }
     let mut messages = Vec::new();
-    for line in content.lines() {
-        if line.is_empty() {
+    let mut notes = Vec::new();
+    let mut skipped = 0;
+    for line in lines {
+        let mut value: serde_json::Value = serde_json::from_str(line)?;
+        if let Some(text) = value["note"].as_str() {
+            notes.push(Note {
+                at: messages.len(),
+                text: text.to_string(),
+                time: value["time"].as_str().unwrap_or("").to_string(),
+            });
             continue;
         }
-        let msg: Message = serde_json::from_str(line)?;
-        messages.push(msg);
+        upgrade_message(version, &mut value);
+        // Drop blocks this build can't read rather than the
+        // whole session
+        if let Some(blocks) = value["content"].as_array_mut() {
+            let before = blocks.len();
+            blocks.retain(|b| {
+                serde_json::from_value::<ContentBlock>(b.clone()).is_ok()
+            });
+            skipped += before - blocks.len();
+            if blocks.is_empty() {
+                continue;
+            }
+        }
+        match serde_json::from_value::<Message>(value) {
+            Ok(msg) => messages.push(msg),
+            Err(_) => skipped += 1,
+        }
     }
-    Ok(messages)
+    Ok((messages, notes, skipped))
 }
 
-fn meta_path(session: &std::path::Path) -> std::path::PathBuf {
+/// Convert a message line written in format `version` to the
+/// current one. Formats 0 (no header) and 1 store messages
+/// alike, so there is nothing to convert yet.
+fn upgrade_message(_version: u64, _value: &mut serde_json::Value) {}
+
+pub(crate) fn meta_path(session: &std::path::Path) -> std::path::PathBuf {
     let mut p = session.as_os_str().to_owned();
     p.push(".meta");
     std::path::PathBuf::from(p)
@@ -354,59 +1407,243 @@ fn save_message(path: &std::path::Path, msg: &Message) {
             return;
         }
     };
-    let mut file = match OpenOptions::new().create(true).append(true).open(path)
-    {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("* warning: cannot open session file: {e}");
-            return;
-        }
-    };
-    if let Err(e) = writeln!(file, "{json}") {
+    if let Err(e) = append_line(path, &json) {
         eprintln!("* warning: failed to write message: {e}");
     }
 }
 
+/// Append `line` to a session file, starting new files with
+/// the header.
+fn append_line(path: &std::path::Path, line: &str) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let header = match file.metadata() {
+        Ok(m) if m.len() == 0 => session_header(),
+        _ => String::new(),
+    };
+    writeln!(file, "{header}{line}")
+}
+
+/// Compact on request (/compact), steering the summary with
+/// `focus`. At least the older half is summarized even when
+/// the context is still small. Returns `false` if there was
+/// too little to compact.
+pub(crate) fn compact_session(
+    config: &Config,
+    session: &mut Session,
+    focus: &str,
+) -> Result<bool> {
+    let (_, keep_tokens) = config.compaction_limits();
+    let tokens = session.last_input_tokens.max(1);
+    let keep = (keep_tokens as f64 / tokens as f64).min(0.5);
+    let cut = compact(config, &mut session.messages, tokens, keep, focus)?;
+    session.compacted(cut);
+    Ok(cut > 0)
+}
+
+/// Replace the messages before a turn boundary with a
+/// summary, keeping about `keep_ratio` of them. Returns how
+/// many were summarized.
 fn compact(
     config: &Config,
     messages: &mut Vec<Message>,
     input_tokens: u32,
-) -> Result<()> {
-    let cut = find_cut_point(messages, input_tokens);
+    keep_ratio: f64,
+    focus: &str,
+) -> Result<usize> {
+    let cut = find_cut_point(messages, keep_ratio);
     if cut == 0 {
-        return Ok(());
+        return Ok(0);
     }
 
     eprintln!("* compacting ({cut} messages → summary)...");
 
     let old = &messages[..cut];
     let conversation = serialize_for_summary(old);
-    let summary = generate_summary(config, &conversation)?;
+    let summary = generate_summary(config, &conversation, focus)?;
 
     let kept = messages.split_off(cut);
-    messages.clear();
-    messages.push(Message {
-        role: Role::User,
-        content: Content::Text(format!("<context>\n{summary}\n</context>")),
-    });
-    messages.push(Message {
-        role: Role::Assistant,
-        content: Content::Text(
-            "Understood, continuing from where we left off.".to_string(),
-        ),
-    });
+    *messages = summary_messages(&summary).into();
     messages.extend(kept);
 
     eprintln!("* compacted: {} messages remaining", messages.len());
-    Ok(())
+    events::emit(
+        "compaction",
+        json!({
+            "input_tokens": input_tokens,
+            "messages_summarized": cut,
+            "messages_remaining": messages.len(),
+            "focus": focus,
+        }),
+    );
+    Ok(cut)
+}
+
+/// The exchange a compaction summary replaces earlier turns
+/// with.
+pub(crate) fn summary_messages(summary: &str) -> [Message; 2] {
+    [
+        Message {
+            role: Role::User,
+            content: Content::Text(format!("<context>\n{summary}\n</context>")),
+        },
+        Message {
+            role: Role::Assistant,
+            content: Content::Text(
+                "Understood, continuing from where we left off.".to_string(),
+            ),
+        },
+    ]
+}
+
+/// Tool results smaller than this are never pruned.
+const PRUNE_MIN_BYTES: usize = 2_000;
+
+/// Replace large tool results outside the most recent
+/// `keep_ratio` of messages with a short note, leaving the
+/// dialogue around them intact. The session file keeps the
+/// full output. Returns the estimated tokens freed.
+fn prune_tool_results(messages: &mut [Message], keep_ratio: f64) -> u32 {
+    let keep_count = (messages.len() as f64 * keep_ratio).ceil() as usize;
+    let end = messages.len().saturating_sub(keep_count.max(4));
+    let mut names: HashMap<String, String> = HashMap::new();
+    let mut freed = 0;
+    let mut pruned = 0;
+    for msg in &mut messages[..end] {
+        let Content::Blocks(blocks) = &mut msg.content else {
+            continue;
+        };
+        for block in blocks {
+            match block {
+                ContentBlock::ToolUse { id, name, .. } => {
+                    names.insert(id.clone(), name.clone());
+                }
+                ContentBlock::ToolResult {
+                    tool_use_id,
+                    content,
+                    ..
+                } if content.len() >= PRUNE_MIN_BYTES => {
+                    let name =
+                        names.get(tool_use_id).map_or("tool", |n| n.as_str());
+                    let note = format!(
+                        "[old {name} output pruned: {} lines, {} bytes; \
+                         run it again if needed]",
+                        content.lines().count(),
+                        content.len(),
+                    );
+                    freed += (content.len() - note.len()) / 4;
+                    *content = note;
+                    pruned += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+    if pruned > 0 {
+        eprintln!("* pruned {pruned} old tool results (~{freed} tokens)");
+        events::emit(
+            "tool_results_pruned",
+            json!({"results": pruned, "tokens": freed}),
+        );
+    }
+    freed as u32
+}
+
+/// Cut the largest tool results, recent ones included, to
+/// their first lines until about `excess` tokens are freed.
+/// The session file keeps the full output. Returns the
+/// estimated tokens freed.
+fn shrink_tool_results(messages: &mut [Message], excess: u32) -> u32 {
+    let mut results: Vec<&mut String> = messages
+        .iter_mut()
+        .filter_map(|m| match &mut m.content {
+            Content::Blocks(blocks) => Some(blocks),
+            Content::Text(_) => None,
+        })
+        .flatten()
+        .filter_map(|b| match b {
+            ContentBlock::ToolResult { content, .. }
+                if content.len() > PRUNE_MIN_BYTES =>
+            {
+                Some(content)
+            }
+            _ => None,
+        })
+        .collect();
+    results.sort_by_key(|c| std::cmp::Reverse(c.len()));
+    let mut freed = 0;
+    for content in results {
+        if freed >= excess as usize {
+            break;
+        }
+        let cut = floor_char_boundary(content, PRUNE_MIN_BYTES);
+        let note = format!(
+            "{}\n[output cut to fit the context window: {} lines, {} \
+             bytes in all]",
+            &content[..cut],
+            content.lines().count(),
+            content.len(),
+        );
+        freed += content.len().saturating_sub(note.len()) / 4;
+        *content = note;
+    }
+    freed as u32
+}
+
+/// Placeholder for tool output cleared by context management.
+const CLEARED_RESULT: &str = "(old tool result cleared to save context)";
+
+/// Mirror server-side clearing: blank every tool result but
+/// the newest `keep`, skipping `exclude`d tools, so later
+/// requests and /rewind see what the model saw. The server's
+/// count includes results cleared on earlier turns, so this
+/// applies its rule rather than its count. The session file
+/// keeps the full output. Returns how many were newly cleared.
+fn clear_tool_results(
+    messages: &mut [Message],
+    keep: usize,
+    exclude: &[String],
+) -> usize {
+    let mut names: HashMap<String, String> = HashMap::new();
+    let mut results = Vec::new();
+    for msg in messages.iter_mut() {
+        let Content::Blocks(blocks) = &mut msg.content else {
+            continue;
+        };
+        for block in blocks {
+            match block {
+                ContentBlock::ToolUse { id, name, .. } => {
+                    names.insert(id.clone(), name.clone());
+                }
+                ContentBlock::ToolResult {
+                    tool_use_id,
+                    content,
+                    ..
+                } if !names
+                    .get(tool_use_id)
+                    .is_some_and(|n| exclude.contains(n)) =>
+                {
+                    results.push(content);
+                }
+                _ => {}
+            }
+        }
+    }
+    let old = results.len().saturating_sub(keep);
+    let mut cleared = 0;
+    for content in results.into_iter().take(old) {
+        if content != CLEARED_RESULT {
+            *content = CLEARED_RESULT.to_string();
+            cleared += 1;
+        }
+    }
+    cleared
 }
 
-fn find_cut_point(messages: &[Message], input_tokens: u32) -> usize {
+fn find_cut_point(messages: &[Message], keep_ratio: f64) -> usize {
     if messages.len() < 6 {
         return 0;
     }
 
-    let keep_ratio = KEEP_RECENT_TOKENS as f64 / input_tokens as f64;
     let keep_count = (messages.len() as f64 * keep_ratio).ceil() as usize;
     let keep_count = keep_count.max(4);
 
@@ -468,28 +1705,115 @@ fn serialize_for_summary(messages: &[Message]) -> String {
     out
 }
 
-fn generate_summary(config: &Config, conversation: &str) -> Result<String> {
+const NAME_PROMPT: &str = "Name this coding session in 3 to 6 words, \
+     lowercase, like a commit subject (e.g. \"fix flaky websocket test\"). \
+     Reply with the name only.";
+
+/// Ask `naming_model` for a short title once the session has
+/// its first response, for the resume picker.
+fn name_session(config: &Config, session: &mut Session) {
+    match suggest_name(config, &session.messages) {
+        Ok(name) if !name.is_empty() => {
+            eprintln!("* name: {name}");
+            session.entry.summary = name;
+        }
+        Ok(_) => {}
+        Err(e) => {
+            events::emit("name_error", json!({"error": e.to_string()}));
+        }
+    }
+}
+
+/// A short title for a conversation, from its beginning.
+pub(crate) fn suggest_name(
+    config: &Config,
+    messages: &[Message],
+) -> Result<String> {
+    let conversation = serialize_for_summary(messages);
+    let excerpt = &conversation[..floor_char_boundary(&conversation, 4000)];
+    generate_name(config, excerpt)
+}
+
+fn generate_name(config: &Config, conversation: &str) -> Result<String> {
     let msgs = [Message {
         role: Role::User,
         content: Content::Text(conversation.to_string()),
     }];
     let request = Request {
-        model: &config.model,
+        model: &config.naming_model,
+        max_tokens: 32,
+        thinking: None,
+        system: vec![SystemBlock::text(NAME_PROMPT)],
+        messages: &msgs,
+        temperature: None,
+        top_p: None,
+        stop_sequences: &[],
+        tools: &[],
+        tool_choice: None,
+        stream: true,
+        context_management: None,
+    };
+
+    let mut reader = api::send_stream(config, &request)?;
+    let mut name = String::new();
+    loop {
+        match reader.next_event()? {
+            Some(SseEvent::ContentBlockDelta {
+                delta: Delta::Text(s),
+                ..
+            }) => name.push_str(&s),
+            Some(SseEvent::MessageStop) | None => break,
+            _ => {}
+        }
+    }
+    Ok(clean_name(&name))
+}
+
+/// First line of a model-written name, without quotes or a
+/// trailing period, capped at 60 characters.
+fn clean_name(raw: &str) -> String {
+    let line = raw.trim().lines().next().unwrap_or("");
+    let line = line.trim_matches(|c: char| c == '"' || c == '\'' || c == '`');
+    let line = line.trim_end_matches('.').trim();
+    crate::util::truncate_line(line, 60)
+}
+
+fn generate_summary(
+    config: &Config,
+    conversation: &str,
+    focus: &str,
+) -> Result<String> {
+    let mut prompt = String::from(
+        "Summarize this coding session. Capture:\n\
+         1. The user's goal\n\
+         2. What was accomplished (files read, created, modified)\n\
+         3. Key decisions and reasoning\n\
+         4. Current state and next steps\n\n\
+         Be concise. Preserve critical context needed to continue \
+         the work.",
+    );
+    if !focus.is_empty() {
+        prompt.push_str(&format!(
+            "\n\nThe user asked the summary to focus on: {focus}"
+        ));
+    }
+    let msgs = [Message {
+        role: Role::User,
+        content: Content::Text(conversation.to_string()),
+    }];
+    let request = Request {
+        model: config.summary_model.as_deref().unwrap_or(&config.model),
         max_tokens: 2048,
         thinking: None,
-        system: vec![SystemBlock::text(
-            "Summarize this coding session. Capture:\n\
-             1. The user's goal\n\
-             2. What was accomplished (files read, created, \
-             modified)\n\
-             3. Key decisions and reasoning\n\
-             4. Current state and next steps\n\n\
-             Be concise. Preserve critical context needed \
-             to continue the work.",
-        )],
+        system: vec![SystemBlock::text(&prompt)],
         messages: &msgs,
+        temperature: None,
+        top_p: None,
+        stop_sequences: &[],
         tools: &[],
+        tool_choice: None,
         stream: true,
+        context_management: None,
     };
 
     let mut reader = api::send_stream(config, &request)?;
@@ -510,3 +1834,502 @@ fn generate_summary(config: &Config, conversation: &str) -> Result<String> {
 
     Ok(summary)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `json` loaded as config, with home and project under
+    /// the fresh temp dir `name`.
+    fn config_in(name: &str, json: &str) -> Config {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        crate::config::test_config(&dir, serde_json::from_str(json).unwrap())
+    }
+
+    fn user(text: &str) -> Message {
+        Message {
+            role: Role::User,
+            content: Content::Text(text.to_string()),
+        }
+    }
+
+    #[test]
+    fn clear_tool_results_keeps_the_newest() {
+        let call = |id: &str, name: &str| Message {
+            role: Role::Assistant,
+            content: Content::Blocks(vec![ContentBlock::ToolUse {
+                id: id.into(),
+                name: name.into(),
+                input: json!({}),
+            }]),
+        };
+        let result = |id: &str| Message {
+            role: Role::User,
+            content: Content::Blocks(vec![ContentBlock::ToolResult {
+                tool_use_id: id.into(),
+                content: format!("output {id}"),
+                is_error: None,
+            }]),
+        };
+        let mut msgs = vec![
+            call("a", "read_file"),
+            result("a"),
+            call("b", "bash"),
+            result("b"),
+            call("c", "bash"),
+            result("c"),
+        ];
+        let exclude = vec!["read_file".to_string()];
+        assert_eq!(clear_tool_results(&mut msgs, 1, &exclude), 1);
+        // A later clear counts the earlier one again; only the
+        // newly blanked result is reported.
+        msgs.extend([call("d", "bash"), result("d")]);
+        assert_eq!(clear_tool_results(&mut msgs, 1, &exclude), 1);
+        assert_eq!(clear_tool_results(&mut msgs, 1, &exclude), 0);
+        let outputs: Vec<&str> = msgs
+            .iter()
+            .filter_map(|m| match &m.content {
+                Content::Blocks(b) => match &b[0] {
+                    ContentBlock::ToolResult { content, .. } => {
+                        Some(content.as_str())
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            outputs,
+            ["output a", CLEARED_RESULT, CLEARED_RESULT, "output d"]
+        );
+    }
+
+    #[test]
+    fn prune_keeps_recent_and_small_results() {
+        let big = "line\n".repeat(1000);
+        let mut msgs = Vec::new();
+        for (i, content) in [&big, "small", &big, &big].iter().enumerate() {
+            let id = format!("t{i}");
+            msgs.push(Message {
+                role: Role::Assistant,
+                content: Content::Blocks(vec![ContentBlock::ToolUse {
+                    id: id.clone(),
+                    name: "read_file".into(),
+                    input: json!({}),
+                }]),
+            });
+            msgs.push(Message {
+                role: Role::User,
+                content: Content::Blocks(vec![ContentBlock::ToolResult {
+                    tool_use_id: id,
+                    content: content.to_string(),
+                    is_error: None,
+                }]),
+            });
+        }
+        let freed = prune_tool_results(&mut msgs, 0.25);
+        assert!(freed > 1000, "{freed}");
+        let outputs: Vec<String> = msgs
+            .iter()
+            .filter_map(|m| match &m.content {
+                Content::Blocks(b) => match &b[0] {
+                    ContentBlock::ToolResult { content, .. } => {
+                        Some(content.chars().take(24).collect())
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            outputs,
+            [
+                "[old read_file output pr",
+                "small",
+                "line\nline\nline\nline\nline",
+                "line\nline\nline\nline\nline",
+            ]
+        );
+        assert_eq!(prune_tool_results(&mut msgs, 0.25), 0);
+
+        // The newest result goes first once pruning is not enough
+        let last = msgs.len() - 1;
+        if let Content::Blocks(b) = &mut msgs[last].content
+            && let ContentBlock::ToolResult { content, .. } = &mut b[0]
+        {
+            content.push_str(&big);
+        }
+        let freed = shrink_tool_results(&mut msgs, 100);
+        assert!(freed > 1000, "{freed}");
+        let Content::Blocks(b) = &msgs[last].content else {
+            panic!("expected blocks");
+        };
+        let ContentBlock::ToolResult { content, .. } = &b[0] else {
+            panic!("expected a tool result");
+        };
+        assert!(content.ends_with("2000 lines, 10000 bytes in all]"));
+        assert_eq!(shrink_tool_results(&mut msgs, 0), 0);
+    }
+
+    #[test]
+    fn parse_session_versions() {
+        let user = r#"{"role":"user","content":"hi"}"#;
+        let mixed = r#"{"role":"assistant","content":[{"type":"text","text":"a"},{"type":"hologram","x":1}]}"#;
+
+        // Legacy file without a header
+        let (msgs, _, skipped) = parse_session(&format!("{user}\n")).unwrap();
+        assert_eq!((msgs.len(), skipped), (1, 0));
+
+        // Unknown block types are dropped, not fatal
+        let text = format!("{}{user}\n{mixed}\n", session_header());
+        let (msgs, _, skipped) = parse_session(&text).unwrap();
+        assert_eq!((msgs.len(), skipped), (2, 1));
+        let Content::Blocks(blocks) = &msgs[1].content else {
+            panic!("expected blocks");
+        };
+        assert_eq!(blocks.len(), 1);
+
+        // Notes keep their place and are not messages
+        let note = note_line(&Note {
+            at: 0,
+            text: "abandoned".into(),
+            time: "t".into(),
+        });
+        let text = format!("{}{user}\n{note}\n{user}\n", session_header());
+        let (msgs, notes, skipped) = parse_session(&text).unwrap();
+        assert_eq!((msgs.len(), skipped), (2, 0));
+        assert_eq!(notes[0].at, 1);
+        assert_eq!(notes[0].text, "abandoned");
+    }
+
+    #[test]
+    fn overload_falls_back_once() {
+        let overloaded = Error::Api {
+            status: 529,
+            message: "Overloaded".into(),
+            retry_after: None,
+        };
+        let mut config = config_in("tapir_fallback", r#"{"model": "opus"}"#);
+        assert_eq!(fallback_model(&config, &overloaded), None);
+
+        config.fallback_model = Some("sonnet".into());
+        assert_eq!(
+            fallback_model(&config, &overloaded).as_deref(),
+            Some("claude-sonnet-4-5")
+        );
+        let limited = Error::Api {
+            status: 429,
+            message: "rate limited".into(),
+            retry_after: None,
+        };
+        assert_eq!(fallback_model(&config, &limited), None);
+        config.model = "claude-sonnet-4-5".into();
+        assert_eq!(fallback_model(&config, &overloaded), None);
+    }
+
+    #[test]
+    fn budget_reads_the_ledger_once_a_day() {
+        let config = config_in(
+            "tapir_budget_cache",
+            r#"{"max_cost_per_session": 1.0, "max_cost_per_day": 5.0}"#,
+        );
+        let mut session = session_in(&config);
+        let ledger = cost::ledger_path(&config.dirs.data);
+        let spend = |cost| {
+            cost::record(
+                &ledger,
+                &cost::Entry {
+                    time: session::now_secs(),
+                    session_id: "other".into(),
+                    project: config.working_dir.clone(),
+                    model: "m".into(),
+                    input_tokens: 0,
+                    output_tokens: 0,
+                    cache_read_tokens: 0,
+                    cache_write_tokens: 0,
+                    cost,
+                },
+            )
+        };
+        spend(2.0);
+        assert!(within_budget(&config, &mut session, true).unwrap());
+        let (_, today) = session.spent_today.unwrap();
+        assert_eq!(today, 2.0);
+        spend(10.0);
+        assert!(within_budget(&config, &mut session, true).unwrap());
+
+        session.cost = 1.5;
+        let err = within_budget(&config, &mut session, true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "spent $1.50 this session, over the $1.00 limit"
+        );
+    }
+
+    #[test]
+    fn notes_follow_compaction() {
+        let config = config_in("tapir_notes_compacted", "{}");
+        let mut session = session_in(&config);
+        session.messages = (0..10).map(|i| user(&i.to_string())).collect();
+        for at in [0, 3, 6, 9] {
+            session.notes.push(Note {
+                at,
+                text: String::new(),
+                time: String::new(),
+            });
+        }
+        session.compacted(0);
+        session.compacted(6);
+        let at: Vec<usize> = session.notes.iter().map(|n| n.at).collect();
+        assert_eq!(at, [0, 2, 2, 5]);
+
+        session.replace_messages(vec![user("a")]).unwrap();
+        let at: Vec<usize> = session.notes.iter().map(|n| n.at).collect();
+        assert_eq!(at, [0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn fallback_requests_fit_the_model() {
+        let thought = Message {
+            role: Role::Assistant,
+            content: Content::Blocks(vec![
+                ContentBlock::Thinking {
+                    thinking: "hmm".into(),
+                    signature: "sig".into(),
+                },
+                ContentBlock::Text { text: "hi".into() },
+            ]),
+        };
+        let msgs = vec![user("a"), thought.clone(), user("b"), thought];
+        let history = fallback_history(&msgs, 3);
+        let thinking = |m: &Message| match &m.content {
+            Content::Blocks(b) => {
+                b.iter().any(|b| matches!(b, ContentBlock::Thinking { .. }))
+            }
+            Content::Text(_) => false,
+        };
+        let kept: Vec<bool> = history.iter().map(thinking).collect();
+        assert_eq!(kept, [false, false, false, true]);
+
+        let mut config = config_in(
+            "tapir_fallback_fit",
+            r#"{"max_tokens": 32000, "thinking_budget": 16000}"#,
+        );
+        config.ensure_full_prompt();
+        let mut info = ModelInfo {
+            context: 200_000,
+            max_output: 8192,
+            input_cost_per_m: 0.0,
+            output_cost_per_m: 0.0,
+            extended_thinking: true,
+            notes: String::new(),
+            api_url: None,
+            api_key_env: None,
+            token_scale: None,
+        };
+        let mut request = turn_request(&config, &history, &[]);
+        fit_to_model(&mut request, Some(&info));
+        assert_eq!(request.max_tokens, 8192);
+        assert_eq!(request.thinking.map(|t| t.budget_tokens), Some(4096));
+
+        info.extended_thinking = false;
+        let mut request = turn_request(&config, &history, &[]);
+        fit_to_model(&mut request, Some(&info));
+        assert!(request.thinking.is_none());
+    }
+
+    #[test]
+    fn turns_run_out_of_time() {
+        let mut config =
+            config_in("tapir_turn_timeout", r#"{"turn_timeout": 60}"#);
+        let started = Instant::now() - std::time::Duration::from_secs(61);
+        assert!(out_of_time(&config, started));
+        assert!(!out_of_time(&config, Instant::now()));
+        config.turn_timeout = 0;
+        assert!(!out_of_time(&config, started));
+    }
+
+    /// A new session stored under `config`'s session dir.
+    fn session_in(config: &Config) -> Session {
+        fs::create_dir_all(&config.session_dir).unwrap();
+        let entry =
+            session::create_entry(&config.session_dir, &config.working_dir)
+                .unwrap();
+        Session::new(config, entry)
+    }
+
+    /// A bash call answered by a timeout's results, as a
+    /// turn that ran out of time leaves `session`.
+    fn cut_off_call(session: &mut Session) {
+        session.push_message(Message {
+            role: Role::Assistant,
+            content: Content::Blocks(vec![ContentBlock::ToolUse {
+                id: "t1".into(),
+                name: "bash".into(),
+                input: json!({"command": "make"}),
+            }]),
+        });
+        let calls = [("t1".to_string(), "bash".to_string(), json!({}))];
+        session.push_message(Message {
+            role: Role::User,
+            content: Content::Blocks(not_run(&calls)),
+        });
+    }
+
+    #[test]
+    fn skipped_tool_results_survive_a_resume() {
+        let config = config_in("tapir_skipped_tools", "{}");
+        let mut session = session_in(&config);
+        session.push_message(user("tidy up"));
+        cut_off_call(&mut session);
+
+        // As /resume loads it
+        let (messages, _) = load_transcript(&session.file).unwrap();
+        session.messages = messages;
+        session.push_user_text("go on");
+        let (mut messages, _) = load_transcript(&session.file).unwrap();
+        assert_eq!(messages.len(), 3);
+        assert!(crate::validate::repair(&mut messages).is_empty());
+        let Content::Blocks(blocks) = &messages[2].content else {
+            panic!("expected blocks");
+        };
+        assert!(matches!(
+            &blocks[..],
+            [
+                ContentBlock::ToolResult { content, .. },
+                ContentBlock::Text { text },
+            ] if content.starts_with("(not run") && text == "go on"
+        ));
+
+        session.push_user_text("and then?");
+        assert_eq!(session.messages.len(), 4);
+        assert_eq!(load_transcript(&session.file).unwrap().0.len(), 4);
+    }
+
+    #[test]
+    fn preflight_splits_cached_from_new_tokens() {
+        let mut config = config_in("tapir_preflight_split", "{}");
+        let mut session = session_in(&config);
+        session.messages = vec![
+            user(&"old ".repeat(400)),
+            Message {
+                role: Role::Assistant,
+                content: Content::Text("ok".into()),
+            },
+            user(&"new ".repeat(100)),
+        ];
+        let before =
+            session.estimate_tokens(&mut config, &session.messages[..2]);
+        let all = session.estimate_tokens(&mut config, &session.messages);
+        assert_eq!(token_split(&mut config, &session, None), (all, before));
+
+        // The API's count scales the cached part with it
+        let (total, cached) = token_split(&mut config, &session, Some(all * 2));
+        assert_eq!(total, all * 2);
+        assert!(cached.abs_diff(before * 2) <= 1);
+
+        session.messages.drain(..2);
+        assert_eq!(token_split(&mut config, &session, None).1, 0);
+    }
+
+    #[test]
+    fn declined_preflight_keeps_tool_results() {
+        let mut config = config_in(
+            "tapir_preflight_decline",
+            r#"{"preflight": false, "confirm_tokens": 10}"#,
+        );
+        config.policy.confirm = |_| false;
+        let mut session = session_in(&config);
+        session.push_message(user("tidy up"));
+        cut_off_call(&mut session);
+        // A tool loop under way is never stopped
+        assert!(preflight(&mut config, &mut session, &[], false));
+
+        session.push_user_text(&"go on ".repeat(100));
+        assert!(!preflight(&mut config, &mut session, &[], false));
+        let (messages, _) = load_transcript(&session.file).unwrap();
+        assert_eq!(messages.len(), 3);
+        let Content::Blocks(blocks) = &messages[2].content else {
+            panic!("expected the tool results");
+        };
+        assert!(matches!(blocks[..], [ContentBlock::ToolResult { .. }]));
+
+        session.push_message(Message {
+            role: Role::Assistant,
+            content: Content::Text("stopped".into()),
+        });
+        session.push_user_text(&"again ".repeat(100));
+        assert!(!preflight(&mut config, &mut session, &[], false));
+        assert_eq!(session.messages.len(), 4);
+        assert_eq!(load_transcript(&session.file).unwrap().0.len(), 4);
+
+        config.policy.confirm = |_| true;
+        session.push_user_text(&"again ".repeat(100));
+        assert!(preflight(&mut config, &mut session, &[], false));
+        assert_eq!(session.messages.len(), 5);
+    }
+
+    #[test]
+    fn without_tools_keeps_definitions_for_tool_history() {
+        let mut config = config_in("tapir_answer_only", "{}");
+        config.ensure_full_prompt();
+        let tools = tool::definitions(false);
+
+        let plain = [user("why?")];
+        let mut request = turn_request(&config, &plain, &tools);
+        without_tools(&mut request);
+        assert!(request.tools.is_empty());
+        assert!(request.tool_choice.is_none());
+
+        let history = [
+            user("ls"),
+            Message {
+                role: Role::Assistant,
+                content: Content::Blocks(vec![ContentBlock::ToolUse {
+                    id: "a".into(),
+                    name: "bash".into(),
+                    input: json!({}),
+                }]),
+            },
+        ];
+        let mut request = turn_request(&config, &history, &tools);
+        without_tools(&mut request);
+        assert_eq!(request.tools.len(), tools.len());
+        assert_eq!(request.tool_choice, Some(json!({"type": "none"})));
+    }
+
+    #[test]
+    fn sampling_is_sent_only_when_set() {
+        let request = |config: &Config| {
+            serde_json::to_value(turn_request(config, &[user("hi")], &[]))
+                .unwrap()
+        };
+        let mut config = config_in("tapir_sampling", "{}");
+        config.ensure_full_prompt();
+        let sent = request(&config);
+        assert!(sent.get("temperature").is_none());
+        assert!(sent.get("stop_sequences").is_none());
+
+        let mut config = config_in(
+            "tapir_sampling",
+            r#"{"temperature": 0, "top_p": 0.5, "stop_sequences": ["END"]}"#,
+        );
+        config.ensure_full_prompt();
+        assert!(config.warnings.is_empty());
+        let sent = request(&config);
+        assert_eq!(sent["temperature"], 0.0);
+        assert_eq!(sent["top_p"], 0.5);
+        assert_eq!(sent["stop_sequences"], json!(["END"]));
+
+        config.set("thinking_budget", "1000").unwrap();
+        assert_eq!(config.check().len(), 1);
+        let sent = request(&config);
+        assert!(sent.get("temperature").is_none());
+        assert!(sent.get("top_p").is_none());
+        assert_eq!(sent["stop_sequences"], json!(["END"]));
+        config.set("temperature", "none").unwrap();
+        config.set("top_p", "none").unwrap();
+        assert!(config.check().is_empty());
+    }
+}
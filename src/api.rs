@@ -5,7 +5,7 @@ use std::time::Duration;
 use crate::config::Config;
 use crate::error::{Error, Result};
 use crate::sse::SseReader;
-use crate::types::{ApiError, Request};
+use crate::types::{ApiError, ListedModel, ModelList, Request};
 
 const MAX_ATTEMPTS: u32 = 3;
 const HTTP_TIMEOUT: u64 = 60;
@@ -25,6 +25,14 @@ pub fn send_stream(
                     "* retry {attempt}/{MAX_ATTEMPTS} \
                      in {delay}s ({e})"
                 );
+                crate::events::emit(
+                    "retry",
+                    serde_json::json!({
+                        "attempt": attempt,
+                        "delay_s": delay,
+                        "error": e.to_string(),
+                    }),
+                );
                 thread::sleep(Duration::from_secs(delay));
             }
             Err(e) => return Err(e),
@@ -35,11 +43,12 @@ pub fn send_stream(
 }
 
 fn try_send(config: &Config, body: &str) -> Result<SseReader> {
-    let mut response = minreq::post(&config.api_url)
-        .with_header("x-api-key", &config.api_key)
-        .with_header("anthropic-version", "2023-06-01")
-        .with_header("anthropic-beta", "prompt-caching-2024-07-31")
-        .with_header("content-type", "application/json")
+    let (url, key) = config.endpoint()?;
+    let mut request = minreq::post(url).with_header("x-api-key", key);
+    for (name, value) in headers(config) {
+        request = request.with_header(name, value);
+    }
+    let mut response = request
         .with_body(body)
         .with_timeout(HTTP_TIMEOUT)
         .send_lazy()
@@ -57,25 +66,129 @@ fn try_send(config: &Config, body: &str) -> Result<SseReader> {
         response
             .read_to_string(&mut text)
             .map_err(|e| Error::Http(e.to_string()))?;
-
-        let api_err: ApiError =
-            serde_json::from_str(&text).unwrap_or(ApiError {
-                error: crate::types::ApiErrorDetail {
-                    kind: "unknown".to_string(),
-                    message: text,
-                },
-            });
-        return Err(Error::Api {
-            status,
-            message: api_err.error.message,
-            retry_after,
-        });
+        return Err(api_error(status, text, retry_after));
     }
 
     let reader = BufReader::new(response);
     Ok(SseReader::new(Box::new(reader)))
 }
 
+/// The error for a non-200 response with body `text`.
+fn api_error(status: u16, text: String, retry_after: Option<u64>) -> Error {
+    let api_err: ApiError = serde_json::from_str(&text).unwrap_or(ApiError {
+        error: crate::types::ApiErrorDetail {
+            kind: "unknown".to_string(),
+            message: text,
+        },
+    });
+    Error::Api {
+        status,
+        message: api_err.error.message,
+        retry_after,
+    }
+}
+
+/// Input tokens of `request` as the count-tokens endpoint
+/// beside the messages endpoint counts them.
+pub fn count_tokens(config: &Config, request: &Request<'_>) -> Result<u32> {
+    #[derive(serde::Deserialize)]
+    struct Count {
+        input_tokens: u32,
+    }
+    let body = count_body(request)?;
+    let (url, key) = config.endpoint()?;
+    let url = format!("{}/count_tokens", url.trim_end_matches('/'));
+    let mut request = minreq::post(url).with_header("x-api-key", key);
+    for (name, value) in headers(config) {
+        request = request.with_header(name, value);
+    }
+    let response = request
+        .with_body(body.to_string())
+        .with_timeout(HTTP_TIMEOUT)
+        .send()
+        .map_err(|e| Error::Http(e.to_string()))?;
+    let text = response
+        .as_str()
+        .map_err(|e| Error::Http(e.to_string()))?
+        .to_string();
+    if response.status_code != 200 {
+        return Err(api_error(response.status_code as u16, text, None));
+    }
+    Ok(serde_json::from_str::<Count>(&text)?.input_tokens)
+}
+
+/// `request` as the count-tokens endpoint takes it, which
+/// rejects sampling and streaming fields.
+fn count_body(request: &Request<'_>) -> Result<serde_json::Value> {
+    let mut body = serde_json::to_value(request)?;
+    if let Some(fields) = body.as_object_mut() {
+        fields.retain(|key, _| {
+            matches!(
+                key.as_str(),
+                "model"
+                    | "system"
+                    | "messages"
+                    | "tools"
+                    | "tool_choice"
+                    | "thinking"
+            )
+        });
+    }
+    Ok(body)
+}
+
+/// Every model the default endpoint lists, across pages.
+pub fn list_models(config: &Config) -> Result<Vec<ListedModel>> {
+    let url = models_url(&config.api_url);
+    let mut models = Vec::new();
+    let mut after: Option<String> = None;
+    loop {
+        let mut page_url = format!("{url}?limit=1000");
+        if let Some(id) = &after {
+            page_url.push_str(&format!("&after_id={id}"));
+        }
+        let mut request =
+            minreq::get(page_url).with_header("x-api-key", &config.api_key);
+        for (name, value) in headers(config) {
+            request = request.with_header(name, value);
+        }
+        let response = request
+            .with_timeout(HTTP_TIMEOUT)
+            .send()
+            .map_err(|e| Error::Http(e.to_string()))?;
+        let text = response
+            .as_str()
+            .map_err(|e| Error::Http(e.to_string()))?
+            .to_string();
+        if response.status_code != 200 {
+            return Err(api_error(response.status_code as u16, text, None));
+        }
+        let page: ModelList = serde_json::from_str(&text)?;
+        models.extend(page.data);
+        match page.last_id {
+            Some(id) if page.has_more => after = Some(id),
+            _ => return Ok(models),
+        }
+    }
+}
+
+/// The models endpoint beside the messages endpoint `api_url`.
+fn models_url(api_url: &str) -> String {
+    let base = api_url.trim_end_matches('/');
+    let base = base.strip_suffix("/messages").unwrap_or(base);
+    format!("{base}/models")
+}
+
+/// Headers sent with every request, besides the API key.
+pub(crate) fn headers(config: &Config) -> Vec<(&'static str, String)> {
+    let mut headers = vec![("anthropic-version", "2023-06-01".to_string())];
+    if !config.betas.is_empty() {
+        headers.push(("anthropic-beta", config.betas.join(",")));
+    }
+    headers.push(("content-type", "application/json".to_string()));
+    headers
+}
+
 fn is_retryable(err: &Error) -> bool {
     match err {
         Error::Http(_) => true,
@@ -101,6 +214,28 @@ fn retry_delay(err: &Error, attempt: u32) -> u64 {
 mod tests {
     use super::*;
 
+    #[test]
+    fn count_body_drops_sampling_fields() {
+        let request = Request {
+            model: "m",
+            max_tokens: 100,
+            thinking: None,
+            system: Vec::new(),
+            messages: &[],
+            temperature: Some(0.5),
+            top_p: None,
+            stop_sequences: &[],
+            tools: &[],
+            tool_choice: None,
+            stream: true,
+            context_management: None,
+        };
+        let body = count_body(&request).unwrap();
+        let mut keys: Vec<&String> = body.as_object().unwrap().keys().collect();
+        keys.sort();
+        assert_eq!(keys, ["messages", "model", "system", "tools"]);
+    }
+
     #[test]
     fn test_is_retryable_http_error() {
         assert!(is_retryable(&Error::Http("timeout".into())));
@@ -135,10 +270,24 @@ mod tests {
 
     #[test]
     fn test_not_retryable_other_errors() {
-        assert!(!is_retryable(&Error::NoApiKey));
+        assert!(!is_retryable(&Error::NoApiKey {
+            config_file: "config.toml".into()
+        }));
         assert!(!is_retryable(&Error::Json("bad".into())));
     }
 
+    #[test]
+    fn test_models_url() {
+        assert_eq!(
+            models_url("https://api.anthropic.com/v1/messages"),
+            "https://api.anthropic.com/v1/models"
+        );
+        assert_eq!(
+            models_url("http://localhost:11434/v1/"),
+            "http://localhost:11434/v1/models"
+        );
+    }
+
     #[test]
     fn test_retry_delay_exponential() {
         let err = Error::Http("timeout".into());
@@ -0,0 +1,292 @@
+use std::fs;
+use std::path::Path;
+
+use crate::tool::Policy;
+
+/// Files larger than this are listed but never included.
+const MAX_FILE_BYTES: u64 = 64 * 1024;
+
+/// Most files listed for one directory; the walk stops there.
+const MAX_LISTED: usize = 500;
+
+/// What one `@dir/` reference attached.
+#[derive(Debug, PartialEq)]
+pub struct Attached {
+    pub dir: String,
+    pub included: usize,
+    pub omitted: usize,
+    pub tokens: u32,
+    /// The listing stopped at `MAX_LISTED` files.
+    pub capped: bool,
+}
+
+/// `text` followed by a block for each `@path` in it naming a
+/// directory under `working_dir`: every file below it as a
+/// listing, then the contents of small text files in listing
+/// order until `budget` tokens are spent. Files the sandbox
+/// does not freely allow reading are left out of both,
+/// without asking.
+pub fn expand_dirs(
+    text: &str,
+    working_dir: &Path,
+    policy: &Policy,
+    budget: u32,
+) -> (String, Vec<Attached>) {
+    let mut out = text.to_string();
+    let mut attached: Vec<Attached> = Vec::new();
+    for word in text.split_whitespace() {
+        let Some(rel) = word.strip_prefix('@') else {
+            continue;
+        };
+        let rel = rel.trim_end_matches('/');
+        let dir = match crate::tool::safe_path(working_dir, rel) {
+            Ok(dir) if !rel.is_empty() && dir.is_dir() => dir,
+            _ => continue,
+        };
+        let name = format!("{rel}/");
+        if attached.iter().any(|a| a.dir == name) {
+            continue;
+        }
+        let (block, stats) =
+            directory_block(&name, &dir, working_dir, policy, budget);
+        out.push_str(&block);
+        attached.push(stats);
+    }
+    (out, attached)
+}
+
+/// What fetching one URL gave: the tokens attached, or why
+/// nothing was.
+pub type Fetched = (String, Result<u32, String>);
+
+/// `text` followed by the page text of each `https://` URL in
+/// `typed`, as `fetch` returns it, cut to about `budget`
+/// tokens. `typed` is the message before files were attached
+/// to it, so links in those are never followed.
+pub fn expand_urls(
+    text: &str,
+    typed: &str,
+    budget: u32,
+    fetch: &dyn Fn(&str) -> Result<String, String>,
+) -> (String, Vec<Fetched>) {
+    let mut out = text.to_string();
+    let mut fetched: Vec<Fetched> = Vec::new();
+    for word in typed.split_whitespace() {
+        let url = word
+            .trim_start_matches(['(', '<', '"', '\''])
+            .trim_end_matches([
+                '.', ',', ';', ':', '!', '?', ')', '>', '"', '\'',
+            ]);
+        if !url.starts_with("https://")
+            || url.len() == "https://".len()
+            || fetched.iter().any(|(u, _)| u == url)
+        {
+            continue;
+        }
+        let result = fetch(url).and_then(|page| {
+            if page.trim().is_empty() {
+                return Err("no text on the page".into());
+            }
+            // About four bytes a token
+            let page = crate::util::truncate(&page, budget as usize * 4);
+            let tokens = crate::tokens::estimate(&page, 1.0);
+            out.push_str(&format!(
+                "\n\n<page url=\"{url}\">\n{}\n</page>",
+                page.trim_end()
+            ));
+            Ok(tokens)
+        });
+        fetched.push((url.to_string(), result));
+    }
+    (out, fetched)
+}
+
+/// A pasted line too large to send as prose, set apart as
+/// an attachment the way files are.
+pub fn paste_block(text: &str) -> String {
+    format!(
+        "<pasted chars=\"{}\">\n{text}\n</pasted>",
+        text.chars().count()
+    )
+}
+
+fn directory_block(
+    name: &str,
+    dir: &Path,
+    working_dir: &Path,
+    policy: &Policy,
+    budget: u32,
+) -> (String, Attached) {
+    let root = working_dir.canonicalize().unwrap_or(working_dir.into());
+    let mut files = Vec::new();
+    let mut capped = false;
+    crate::tool::walk_files(dir, &mut |path| {
+        if policy.hidden(working_dir, path) {
+            return true;
+        }
+        if files.len() == MAX_LISTED {
+            capped = true;
+            return false;
+        }
+        let display = path.strip_prefix(&root).unwrap_or(path);
+        files
+            .push((path.to_path_buf(), display.to_string_lossy().into_owned()));
+        true
+    });
+
+    let mut stats = Attached {
+        dir: name.to_string(),
+        included: 0,
+        omitted: 0,
+        tokens: 0,
+        capped,
+    };
+    let mut listing = String::new();
+    let mut contents = String::new();
+    for (path, display) in &files {
+        let text = fs::metadata(path)
+            .ok()
+            .filter(|m| m.len() <= MAX_FILE_BYTES)
+            .and_then(|_| fs::read(path).ok())
+            .filter(|bytes| !bytes.contains(&0))
+            .and_then(|bytes| String::from_utf8(bytes).ok());
+        let tokens = text.as_deref().map(|t| crate::tokens::estimate(t, 1.0));
+        match (text, tokens) {
+            (Some(text), Some(n)) if stats.tokens + n <= budget => {
+                stats.tokens += n;
+                stats.included += 1;
+                listing.push_str(&format!("{display}\n"));
+                contents.push_str(&format!(
+                    "<file path=\"{display}\">\n{}{}</file>\n",
+                    text,
+                    if text.ends_with('\n') { "" } else { "\n" }
+                ));
+            }
+            _ => {
+                stats.omitted += 1;
+                listing.push_str(&format!("{display} (not included)\n"));
+            }
+        }
+    }
+    if capped {
+        listing.push_str(&format!("(listing stops at {MAX_LISTED} files)\n"));
+    }
+    let block = format!(
+        "\n\n<directory path=\"{name}\">\n{listing}{contents}</directory>"
+    );
+    (block, stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directories_attach_until_the_budget() {
+        let dir = std::env::temp_dir().join("tapir_attach_dirs");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src/api/.hidden")).unwrap();
+        fs::write(dir.join("src/api/a.rs"), "fn a() {}\n").unwrap();
+        fs::write(dir.join("src/api/b.rs"), "fn b() {}".repeat(50)).unwrap();
+        fs::write(dir.join("src/api/c.bin"), [0u8, 1, 2]).unwrap();
+        fs::write(dir.join("src/api/.hidden/x"), "x").unwrap();
+        let policy = Policy::default();
+
+        let (text, attached) =
+            expand_dirs("look at @src/api/ and @nope/", &dir, &policy, 20);
+        assert_eq!(
+            text,
+            "look at @src/api/ and @nope/\n\n\
+             <directory path=\"src/api/\">\n\
+             src/api/a.rs\n\
+             src/api/b.rs (not included)\n\
+             src/api/c.bin (not included)\n\
+             <file path=\"src/api/a.rs\">\nfn a() {}\n</file>\n\
+             </directory>"
+        );
+        assert_eq!(
+            attached,
+            [Attached {
+                dir: "src/api/".into(),
+                included: 1,
+                omitted: 2,
+                tokens: crate::tokens::estimate("fn a() {}\n", 1.0),
+                capped: false,
+            }]
+        );
+
+        let (text, attached) = expand_dirs("@src/api/a.rs", &dir, &policy, 20);
+        assert_eq!(text, "@src/api/a.rs");
+        assert!(attached.is_empty());
+    }
+
+    #[test]
+    fn directory_listings_are_capped_and_never_ask() {
+        let dir = std::env::temp_dir().join("tapir_attach_capped");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("big")).unwrap();
+        for i in 0..MAX_LISTED + 2 {
+            fs::write(dir.join(format!("big/{i:04}.txt")), "x").unwrap();
+        }
+        let mut policy = Policy::default();
+        policy.rules = vec![crate::tool::PathRule {
+            path: "big/0000.txt".into(),
+            read: Some(crate::tool::Access::Prompt),
+            write: None,
+        }];
+        policy.confirm = |_| panic!("attaching asked");
+        let (text, attached) = expand_dirs("@big/", &dir, &policy, 0);
+        assert!(!text.contains("big/0000.txt"));
+        assert!(text.contains("big/0001.txt"));
+        assert!(text.contains("(listing stops at 500 files)"));
+        assert!(attached[0].capped);
+        assert_eq!(attached[0].omitted, MAX_LISTED);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn urls_attach_their_page_text() {
+        let fetch = |url: &str| match url {
+            "https://example.com/issue/4" => Ok("Crash on start\n".to_string()),
+            _ => Err("HTTP 404 Not Found".to_string()),
+        };
+        let typed = "see (https://example.com/issue/4), \
+             https://example.com/gone. and http://example.com \
+             https://example.com/issue/4";
+        let (text, fetched) = expand_urls(typed, typed, 100, &fetch);
+        assert_eq!(
+            text,
+            "see (https://example.com/issue/4), https://example.com/gone. \
+             and http://example.com https://example.com/issue/4\n\n\
+             <page url=\"https://example.com/issue/4\">\n\
+             Crash on start\n</page>"
+        );
+        assert_eq!(
+            fetched,
+            [
+                (
+                    "https://example.com/issue/4".to_string(),
+                    Ok(crate::tokens::estimate("Crash on start\n", 1.0))
+                ),
+                (
+                    "https://example.com/gone".to_string(),
+                    Err("HTTP 404 Not Found".to_string())
+                ),
+            ]
+        );
+
+        let attached = "@notes/\n<file path=\"notes/a\">\n\
+             https://example.com/issue/4\n</file>";
+        let (text, fetched) = expand_urls(attached, "@notes/", 100, &fetch);
+        assert_eq!(text, attached);
+        assert!(fetched.is_empty());
+    }
+
+    #[test]
+    fn pastes_become_blocks() {
+        assert_eq!(
+            paste_block("héllo world"),
+            "<pasted chars=\"11\">\nhéllo world\n</pasted>"
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+/// Copies of files taken before the agent overwrote them,
+/// stored under `.tapir/checkpoints/<session>/` in the
+/// project: one `<seq>.snap` per write plus `index.jsonl`.
+pub struct Checkpoints {
+    dir: PathBuf,
+    /// Serializes index updates from parallel tool calls.
+    lock: Mutex<()>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    seq: u32,
+    path: PathBuf,
+    /// `false` if the write created the file.
+    existed: bool,
+    time: String,
+}
+
+impl Checkpoints {
+    pub fn new(working_dir: &Path, session_id: &str) -> Self {
+        Checkpoints {
+            dir: working_dir.join(".tapir/checkpoints").join(session_id),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Save the current content of `path` before a write.
+    pub fn snapshot(&self, path: &Path) -> io::Result<()> {
+        let _guard = self.lock.lock();
+        fs::create_dir_all(&self.dir)?;
+        let entries = self.load();
+        let seq = entries.last().map_or(1, |e| e.seq + 1);
+        let existed = match fs::read(path) {
+            Ok(data) => {
+                fs::write(self.snap_path(seq), data)?;
+                true
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e),
+        };
+        let entry = Entry {
+            seq,
+            path: path.to_path_buf(),
+            existed,
+            time: crate::session::iso_now(),
+        };
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.index_path())?;
+        writeln!(f, "{}", serde_json::to_string(&entry)?)
+    }
+
+    /// Put back the content `path` had before its most recent
+    /// write and drop that snapshot, so repeated calls walk
+    /// further back. Returns false if there is none left.
+    pub fn undo(&self, path: &Path) -> io::Result<bool> {
+        let _guard = self.lock.lock();
+        let mut entries = self.load();
+        let Some(pos) = entries.iter().rposition(|e| e.path == path) else {
+            return Ok(false);
+        };
+        let entry = entries.remove(pos);
+        self.restore(&entry)?;
+        let _ = fs::remove_file(self.snap_path(entry.seq));
+        self.save(&entries)?;
+        Ok(true)
+    }
+
+    /// Sequence number of the latest snapshot, 0 if none.
+    /// Pass it to `undo_since` to roll back to this point.
+    pub fn last_seq(&self) -> u32 {
+        let _guard = self.lock.lock();
+        self.load().last().map_or(0, |e| e.seq)
+    }
+
+    /// Files with snapshots taken after `seq`.
+    pub fn changed_since(&self, seq: u32) -> Vec<PathBuf> {
+        let _guard = self.lock.lock();
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for e in self.load().into_iter().filter(|e| e.seq > seq) {
+            if !paths.contains(&e.path) {
+                paths.push(e.path);
+            }
+        }
+        paths
+    }
+
+    /// Return every file written after snapshot `seq` to its
+    /// content at that point and drop the later snapshots.
+    /// Returns the restored paths.
+    pub fn undo_since(&self, seq: u32) -> io::Result<Vec<PathBuf>> {
+        let _guard = self.lock.lock();
+        let mut entries = self.load();
+        let mut restored: Vec<PathBuf> = Vec::new();
+        for entry in entries.iter().filter(|e| e.seq > seq) {
+            if restored.contains(&entry.path) {
+                continue;
+            }
+            self.restore(entry)?;
+            restored.push(entry.path.clone());
+        }
+        for entry in entries.iter().filter(|e| e.seq > seq) {
+            let _ = fs::remove_file(self.snap_path(entry.seq));
+        }
+        entries.retain(|e| e.seq <= seq);
+        if self.dir.exists() {
+            self.save(&entries)?;
+        }
+        Ok(restored)
+    }
+
+    /// Return every file to its state before the session's
+    /// first write and discard the snapshots. Returns the
+    /// restored paths.
+    pub fn restore_all(&self) -> io::Result<Vec<PathBuf>> {
+        let restored = self.undo_since(0)?;
+        let _guard = self.lock.lock();
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(restored)
+    }
+
+    fn restore(&self, entry: &Entry) -> io::Result<()> {
+        if entry.existed {
+            if let Some(parent) = entry.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(self.snap_path(entry.seq), &entry.path)?;
+        } else {
+            match fs::remove_file(&entry.path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(e);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn load(&self) -> Vec<Entry> {
+        let Ok(text) = fs::read_to_string(self.index_path()) else {
+            return Vec::new();
+        };
+        text.lines()
+            .filter_map(|l| serde_json::from_str(l).ok())
+            .collect()
+    }
+
+    fn save(&self, entries: &[Entry]) -> io::Result<()> {
+        let mut out = String::new();
+        for e in entries {
+            out.push_str(&serde_json::to_string(e)?);
+            out.push('\n');
+        }
+        fs::write(self.index_path(), out)
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.dir.join("index.jsonl")
+    }
+
+    fn snap_path(&self, seq: u32) -> PathBuf {
+        self.dir.join(format!("{seq:05}.snap"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tempdir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tapir_ckpt_{name}"));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn undo_walks_back_one_write_at_a_time() {
+        let dir = tempdir("undo");
+        let file = dir.join("a.txt");
+        fs::write(&file, "v1").unwrap();
+        let cp = Checkpoints::new(&dir, "s1");
+
+        cp.snapshot(&file).unwrap();
+        fs::write(&file, "v2").unwrap();
+        cp.snapshot(&file).unwrap();
+        fs::write(&file, "v3").unwrap();
+
+        assert!(cp.undo(&file).unwrap());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "v2");
+        assert!(cp.undo(&file).unwrap());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "v1");
+        assert!(!cp.undo(&file).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn undo_since_keeps_earlier_snapshots() {
+        let dir = tempdir("since");
+        let a = dir.join("a.txt");
+        let b = dir.join("b.txt");
+        fs::write(&a, "a1").unwrap();
+        let cp = Checkpoints::new(&dir, "s1");
+
+        cp.snapshot(&a).unwrap();
+        fs::write(&a, "a2").unwrap();
+        let mark = cp.last_seq();
+        cp.snapshot(&a).unwrap();
+        fs::write(&a, "a3").unwrap();
+        cp.snapshot(&b).unwrap();
+        fs::write(&b, "b1").unwrap();
+
+        assert_eq!(cp.changed_since(mark), vec![a.clone(), b.clone()]);
+        assert_eq!(cp.undo_since(mark).unwrap(), vec![a.clone(), b.clone()]);
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a2");
+        assert!(!b.exists());
+        assert!(cp.changed_since(mark).is_empty());
+        assert!(cp.undo(&a).unwrap());
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a1");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restore_all_uses_earliest_and_removes_created() {
+        let dir = tempdir("all");
+        let old = dir.join("old.txt");
+        let new = dir.join("new.txt");
+        fs::write(&old, "orig").unwrap();
+        let cp = Checkpoints::new(&dir, "s1");
+
+        cp.snapshot(&old).unwrap();
+        fs::write(&old, "changed").unwrap();
+        cp.snapshot(&old).unwrap();
+        fs::write(&old, "changed again").unwrap();
+        cp.snapshot(&new).unwrap();
+        fs::write(&new, "created").unwrap();
+
+        let restored = cp.restore_all().unwrap();
+        assert_eq!(restored, vec![old.clone(), new.clone()]);
+        assert_eq!(fs::read_to_string(&old).unwrap(), "orig");
+        assert!(!new.exists());
+        assert!(!dir.join(".tapir/checkpoints/s1").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+use std::path::{Path, PathBuf};
+
+use crate::agent;
+use crate::config::{self, Config};
+use crate::session::{self, SessionEntry};
+use crate::types::{Message, Note};
+use crate::util::{format_age, truncate_line};
+
+const USAGE: &str = "\
+usage: tapir sessions list [--here]
+       tapir sessions show <id> [--json]
+       tapir sessions export <id> [file.md|file.html]
+       tapir sessions delete <id>...
+       tapir sessions search <query>
+       tapir sessions summarize";
+
+/// Sessions touched more recently than this may still be in
+/// use, so `sessions summarize` leaves them alone.
+const SUMMARIZE_IDLE_SECS: i64 = 3600;
+
+/// `tapir sessions ...`, run without starting a session.
+/// Everything but `search` and `list --here` covers the
+/// sessions of every project. Returns the exit status.
+pub fn sessions(
+    args: &[String],
+    config_path: Option<&str>,
+    profile: Option<&str>,
+) -> i32 {
+    let tapir_dir = config::dirs().data;
+    let rest: Vec<&str> = args.iter().skip(1).map(String::as_str).collect();
+    let result = match (args.first().map(String::as_str), &rest[..]) {
+        (Some("list"), []) => list(&session::project_dirs(&tapir_dir)),
+        (Some("list"), ["--here"]) => here().and_then(|dir| list(&[dir])),
+        (Some("show"), [id]) => show(&tapir_dir, id),
+        (Some("show"), [id, "--json"]) => show_json(&tapir_dir, id),
+        (Some("export"), [id]) => export(&tapir_dir, id, None),
+        (Some("export"), [id, path]) => export(&tapir_dir, id, Some(path)),
+        (Some("delete"), ids) if !ids.is_empty() => delete(&tapir_dir, ids),
+        (Some("search"), words) if !words.is_empty() => {
+            here().and_then(|dir| search(&dir, &words.join(" ")))
+        }
+        (Some("summarize"), []) => summarize(config_path, profile),
+        _ => {
+            eprintln!("{USAGE}");
+            return 2;
+        }
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: {e}");
+            1
+        }
+    }
+}
+
+/// Session directory of the current project.
+fn here() -> Result<PathBuf, String> {
+    let cwd = std::env::current_dir().map_err(|e| e.to_string())?;
+    Ok(config::session_dir(&config::dirs().data, &cwd))
+}
+
+fn now() -> i64 {
+    session::parse_iso(&session::iso_now()).unwrap_or(0)
+}
+
+fn title(entry: &SessionEntry) -> String {
+    if entry.summary.is_empty() {
+        truncate_line(entry.first_prompt.lines().next().unwrap_or(""), 60)
+    } else {
+        entry.summary.clone()
+    }
+}
+
+/// Sessions with messages, grouped by project.
+fn list(dirs: &[PathBuf]) -> Result<(), String> {
+    let now = now();
+    for dir in dirs {
+        let entries = session::recent_entries(dir);
+        let Some(first) = entries.first() else {
+            continue;
+        };
+        println!("{}", first.project_path);
+        for e in &entries {
+            let age = session::parse_iso(&e.modified)
+                .map(|t| format_age(now - t))
+                .unwrap_or_default();
+            println!(
+                "  {}  {age:>8}  {:>4} msgs  {}",
+                short_id(e),
+                e.message_count,
+                title(e)
+            );
+        }
+    }
+    Ok(())
+}
+
+fn short_id(entry: &SessionEntry) -> &str {
+    &entry.session_id[..entry.session_id.len().min(8)]
+}
+
+/// The session whose id starts with `prefix`, in any project.
+fn find(
+    tapir_dir: &Path,
+    prefix: &str,
+) -> Result<(PathBuf, SessionEntry), String> {
+    let mut found: Vec<(PathBuf, SessionEntry)> = Vec::new();
+    for dir in session::project_dirs(tapir_dir) {
+        for e in session::load_index(&dir).entries {
+            if e.session_id.starts_with(prefix) {
+                found.push((dir.clone(), e));
+            }
+        }
+    }
+    match found.len() {
+        0 => Err(format!("no session matches {prefix}")),
+        1 => Ok(found.remove(0)),
+        n => Err(format!("{n} sessions match {prefix}, use a longer id")),
+    }
+}
+
+fn load(entry: &SessionEntry) -> Result<(Vec<Message>, Vec<Note>), String> {
+    agent::load_transcript(&session::session_path(entry))
+        .map_err(|e| format!("{}: {e}", entry.session_id))
+}
+
+/// Index details, then the transcript as Markdown.
+fn show(tapir_dir: &Path, id: &str) -> Result<(), String> {
+    let (_, e) = find(tapir_dir, id)?;
+    println!("id:       {}", e.session_id);
+    println!("project:  {}", e.project_path);
+    if !e.git_branch.is_empty() {
+        println!("branch:   {}", e.git_branch);
+    }
+    println!("created:  {}", e.created);
+    println!("modified: {}", e.modified);
+    println!("messages: {}", e.message_count);
+    println!();
+    let (msgs, notes) = load(&e)?;
+    print!("{}", crate::export::markdown(&title(&e), &msgs, &notes));
+    Ok(())
+}
+
+/// Index details with usage and cost from the ledger.
+fn show_json(tapir_dir: &Path, id: &str) -> Result<(), String> {
+    let (_, e) = find(tapir_dir, id)?;
+    let usage = crate::cost::session_totals(tapir_dir, &e.session_id);
+    println!("{}", session::info_json(&e, &usage));
+    Ok(())
+}
+
+/// Markdown to stdout, or to `path` (HTML for `.html`).
+fn export(
+    tapir_dir: &Path,
+    id: &str,
+    path: Option<&str>,
+) -> Result<(), String> {
+    let (_, e) = find(tapir_dir, id)?;
+    let (msgs, notes) = load(&e)?;
+    let Some(path) = path else {
+        print!("{}", crate::export::markdown(&title(&e), &msgs, &notes));
+        return Ok(());
+    };
+    let text = if path.ends_with(".html") || path.ends_with(".htm") {
+        crate::export::html(&title(&e), &msgs, &notes)
+    } else {
+        crate::export::markdown(&title(&e), &msgs, &notes)
+    };
+    std::fs::write(path, text).map_err(|err| format!("{path}: {err}"))?;
+    eprintln!("saved {path}");
+    Ok(())
+}
+
+fn delete(tapir_dir: &Path, ids: &[&str]) -> Result<(), String> {
+    // Resolve every id first so a typo deletes nothing
+    let found = ids
+        .iter()
+        .map(|id| find(tapir_dir, id))
+        .collect::<Result<Vec<_>, String>>()?;
+    for (dir, e) in found {
+        session::delete(&dir, &e)
+            .map_err(|err| format!("{}: {err}", e.session_id))?;
+        println!("deleted {}", e.session_id);
+    }
+    Ok(())
+}
+
+fn search(session_dir: &Path, query: &str) -> Result<(), String> {
+    let hits = session::search(session_dir, query);
+    let now = now();
+    for hit in &hits {
+        for line in session::hit_lines(hit, now) {
+            println!("{line}");
+        }
+    }
+    if hits.is_empty() {
+        return Err(format!("no sessions mention {query}"));
+    }
+    Ok(())
+}
+
+/// Name every idle, unnamed session of every project with
+/// the naming model, for running from cron.
+fn summarize(
+    config_path: Option<&str>,
+    profile: Option<&str>,
+) -> Result<(), String> {
+    let config =
+        Config::load(config_path, profile).map_err(|e| e.to_string())?;
+    let now = now();
+    let mut failed = 0;
+    for dir in session::project_dirs(&config.dirs.data) {
+        for mut entry in session::recent_entries(&dir) {
+            let idle = session::parse_iso(&entry.modified)
+                .is_some_and(|t| now - t >= SUMMARIZE_IDLE_SECS);
+            if !entry.summary.is_empty() || !idle {
+                continue;
+            }
+            let named = load(&entry).and_then(|(msgs, _)| {
+                agent::suggest_name(&config, &msgs).map_err(|e| e.to_string())
+            });
+            match named {
+                Ok(name) if !name.is_empty() => {
+                    println!("{}  {name}", entry.session_id);
+                    entry.summary = name;
+                    session::update_entry(&dir, &entry);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("error: {}: {e}", entry.session_id);
+                    failed += 1;
+                }
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{failed} sessions could not be named"));
+    }
+    Ok(())
+}
@@ -1,13 +1,27 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::api;
 use crate::config::Config;
+use crate::cost;
 use crate::display::ToolOutputLog;
 use crate::error::Result;
-use crate::readline::Editor;
+use crate::i18n::tr;
+use crate::keymap::{Action, Keymap};
+use crate::plan::Plan;
+use crate::readline::{self, Editor};
 use crate::session;
+use crate::sse::{Delta, SseEvent};
+use crate::theme::{Style, paint};
 use crate::tool;
-use crate::types::{Content, Message, Role};
-use crate::util::{floor_char_boundary, truncate};
+use crate::types::{
+    Content, ContentBlock, Message, Request, Role, SystemBlock,
+};
+use crate::util::{
+    floor_char_boundary, format_age, line_diff, truncate, truncate_line,
+};
 
-use super::agent::Session;
+use super::agent::{Session, Turn};
 
 /// What happened after reading one line of user input.
 pub enum InputResult {
@@ -19,6 +33,8 @@ pub enum InputResult {
     Quit,
     /// /new was requested — restart the session.
     New,
+    /// No input for `idle_timeout` seconds.
+    Idle,
 }
 
 // ----------------------------------------------------------
@@ -29,7 +45,7 @@ fn handle_command(
     line: &str,
     config: &mut Config,
     session: &mut Session,
-    at_startup: bool,
+    editor: &Editor,
 ) -> InputResult {
     let (cmd, arg) = match line.split_once(' ') {
         Some((c, a)) => (c, a.trim()),
@@ -42,12 +58,12 @@ fn handle_command(
 
     match cmd {
         "/help" => {
-            print_help();
+            print_help(config);
             InputResult::Continue
         }
         "/quit" | "/exit" => InputResult::Quit,
         "/new" => {
-            if at_startup {
+            if session.messages.is_empty() {
                 eprintln!("* already a new session");
                 InputResult::Continue
             } else {
@@ -55,32 +71,23 @@ fn handle_command(
             }
         }
         "/resume" => {
-            if !at_startup {
-                eprintln!(
-                    "* /resume only works before the \
-                     first prompt"
-                );
-                return InputResult::Continue;
-            }
-            match try_resume(config, &mut session.entry) {
-                Some((file, msgs, _pct)) => {
-                    session.file = file;
-                    session.messages = msgs;
-                    eprintln!(
-                        "session: {} (resumed, {} msgs)",
-                        session.file.display(),
-                        session.messages.len(),
-                    );
-                    InputResult::Ready
-                }
-                None => {
-                    eprintln!(
-                        "* no sessions found for this \
-                         directory"
-                    );
-                    InputResult::Continue
-                }
+            let pick = if arg.is_empty() {
+                pick_session(config, session, editor)
+            } else {
+                find_session(config, session, arg)
+            };
+            if let Some(pick) = pick {
+                resume(config, session, pick);
             }
+            InputResult::Continue
+        }
+        "/sessions" => {
+            sessions_command(config, session, arg);
+            InputResult::Continue
+        }
+        "/search" => {
+            search_sessions(config, arg);
+            InputResult::Continue
         }
         "/name" => {
             if arg.is_empty() {
@@ -96,41 +103,179 @@ fn handle_command(
             }
             InputResult::Continue
         }
+        "/ask" => {
+            if arg.is_empty() {
+                eprintln!("* usage: /ask <question>");
+                return InputResult::Continue;
+            }
+            let arg = &expand_vars(session, arg);
+            if !fits(config, session, arg) {
+                return InputResult::Continue;
+            }
+            if session.entry.first_prompt == "No prompt" {
+                session.entry.first_prompt = truncate(arg, 100);
+            }
+            add_user_message(session, arg);
+            session.answer_only = true;
+            InputResult::Ready
+        }
+        "/plan-refactor" => plan_command(config, session, arg),
+        "/set" => {
+            set_var(session, arg);
+            InputResult::Continue
+        }
+        "/note" => {
+            note(session, arg);
+            InputResult::Continue
+        }
+        "/session" if matches!(arg, "--json" | "json") => {
+            println!("{}", session_json(config, session));
+            InputResult::Continue
+        }
         "/session" => {
             print_session_info(config, session);
             InputResult::Continue
         }
+        "/cost" => {
+            print_costs(config, session);
+            InputResult::Continue
+        }
         "/model" => {
             if arg.is_empty() {
                 print_models(config);
+            } else if arg == "--refresh" {
+                refresh_models(config);
             } else {
                 switch_model(config, arg);
             }
             InputResult::Continue
         }
+        "/config" => {
+            config_command(config, arg);
+            InputResult::Continue
+        }
+        "/thinking" => {
+            thinking_command(config, arg);
+            InputResult::Continue
+        }
+        "/temperature" => {
+            if arg.is_empty() {
+                let value = config.setting("temperature").unwrap_or_default();
+                eprintln!("* temperature: {value}");
+            } else {
+                config_command(config, &format!("set temperature {arg}"));
+            }
+            InputResult::Continue
+        }
+        "/style" => {
+            style_command(config, arg);
+            InputResult::Continue
+        }
+        "/migrate" => {
+            migrate(config, session, arg);
+            InputResult::Continue
+        }
+        "/lsp" if arg == "restart" => {
+            session.restart_lsp = true;
+            eprintln!("* language servers restart before the next turn");
+            InputResult::Continue
+        }
+        "/lsp" => {
+            eprintln!("* usage: /lsp restart");
+            InputResult::Continue
+        }
+        "/system" => {
+            system_command(config, session, arg);
+            InputResult::Continue
+        }
+        "/compact" => {
+            compact(config, session, arg);
+            InputResult::Continue
+        }
+        "/rewind" => {
+            rewind(config, session, arg);
+            InputResult::Continue
+        }
+        "/undo-file" => {
+            undo_file(config, session, arg);
+            InputResult::Continue
+        }
+        "/apply-block" => {
+            apply_block(config, session, arg);
+            InputResult::Continue
+        }
+        "/restore-all" => {
+            restore_all(config, session);
+            InputResult::Continue
+        }
+        "/trash" => {
+            trash_command(session, arg);
+            InputResult::Continue
+        }
+        "/save" => {
+            save_transcript(config, session, arg);
+            InputResult::Continue
+        }
+        "/diff" => {
+            print_session_diff(config, session);
+            InputResult::Continue
+        }
+        "/commit" => {
+            commit(config, arg);
+            InputResult::Continue
+        }
         "/hotkeys" => {
-            print_hotkeys();
+            print_hotkeys(&config.keymap().unwrap_or_default());
             InputResult::Continue
         }
         "/skills" => {
             if config.skills.is_empty() {
                 eprintln!("* no skills loaded");
             } else {
+                let stats = crate::skill::load_stats(
+                    &crate::skill::stats_path(&config.dirs.data),
+                );
+                // Name and origin columns take 42; the
+                // description gets what the window has left
+                let room = crate::readline::terminal_width()
+                    .map_or(60, |w| w.saturating_sub(42).max(20));
                 for skill in &config.skills {
-                    let desc = if skill.description.len() > 60 {
-                        let end = floor_char_boundary(&skill.description, 57);
+                    let desc = if skill.description.len() > room {
+                        let end =
+                            floor_char_boundary(&skill.description, room - 3);
                         format!("{}...", &skill.description[..end])
                     } else {
                         skill.description.clone()
                     };
-                    eprintln!("  {:20} {}", skill.name, desc,);
+                    let origin = if skill.disabled {
+                        format!("{} (disabled)", skill.origin.as_str())
+                    } else if skill.shadowed {
+                        format!("{} (shadowed)", skill.origin.as_str())
+                    } else {
+                        skill.origin.as_str().to_string()
+                    };
+                    eprintln!("  {:20} {:18} {}", skill.name, origin, desc,);
+                    if let Some(u) =
+                        stats.get(skill.path.to_string_lossy().as_ref())
+                    {
+                        let day = u.last_used.get(..10).unwrap_or("");
+                        eprintln!(
+                            "    used {}x (user {}, model {}), last {day}",
+                            u.user + u.model,
+                            u.user,
+                            u.model,
+                        );
+                    }
+                    for problem in &skill.problems {
+                        eprintln!("    ! {problem}");
+                    }
                 }
             }
             InputResult::Continue
         }
         _ => {
             eprintln!("* unknown command: {cmd}");
-            print_help();
+            print_help(config);
             InputResult::Continue
         }
     }
@@ -139,10 +284,10 @@ fn handle_command(
 fn handle_skill_command(
     name: &str,
     arg: &str,
-    config: &Config,
+    config: &mut Config,
     session: &mut Session,
 ) -> InputResult {
-    let skill = match config.skills.iter().find(|s| s.name == name) {
+    let skill = match crate::skill::find(&config.skills, name) {
         Some(s) => s,
         None => {
             eprintln!("* unknown skill: {name}");
@@ -150,6 +295,13 @@ fn handle_skill_command(
             return InputResult::Continue;
         }
     };
+    if skill.disabled {
+        eprintln!("* skill {name} is disabled:");
+        for problem in &skill.problems {
+            eprintln!("    ! {problem}");
+        }
+        return InputResult::Continue;
+    }
 
     let content = match std::fs::read_to_string(&skill.path) {
         Ok(c) => c,
@@ -159,12 +311,22 @@ fn handle_skill_command(
         }
     };
 
+    crate::skill::record_use(
+        &crate::skill::stats_path(&config.dirs.data),
+        skill,
+        crate::skill::Invoker::User,
+    );
     let body = crate::skill::skill_body(&content);
     let mut text =
         format!("<skill name=\"{}\">\n{}\n</skill>", skill.name, body,);
     if !arg.is_empty() {
         text.push_str(&format!("\n\nUser: {arg}"));
     }
+    // Skills may hold `{{...}}` of their own; no warnings
+    let text = crate::util::interpolate(&text, &session.vars).0;
+    if !fits(config, session, &text) {
+        return InputResult::Continue;
+    }
 
     if session.entry.first_prompt == "No prompt" {
         session.entry.first_prompt = format!("/skill:{name}");
@@ -173,71 +335,249 @@ fn handle_skill_command(
     InputResult::Ready
 }
 
-fn print_help() {
-    eprintln!("  /resume          Resume last session");
-    eprintln!("  /new             Start a new session");
-    eprintln!("  /model [name]    Show or switch model");
-    eprintln!("  /name <name>     Set session display name");
-    eprintln!("  /session         Show session info");
-    eprintln!("  /quit, /exit     Quit tapir");
-    eprintln!("  /help            Show this help");
-    eprintln!();
-    eprintln!("  !cmd             Run cmd, send output to LLM");
-    eprintln!("  !!cmd            Run cmd, don't send to LLM");
-    eprintln!();
-    eprintln!("  /hotkeys         Show keyboard shortcuts");
-    eprintln!("  /skills          List available skills");
-    eprintln!("  /skill:name      Load and execute a skill");
-}
-
-fn print_hotkeys() {
-    eprintln!("  Navigation:");
-    eprintln!("    Left/Right       Move cursor");
-    eprintln!("    Ctrl+Left/Right  Move by word");
-    eprintln!("    Ctrl-A / Home    Beginning of line");
-    eprintln!("    Ctrl-E / End     End of line");
-    eprintln!("    Up / Ctrl-P      Previous history");
-    eprintln!("    Down / Ctrl-N    Next history");
+/// `/help` lines: usage and what it does.
+const COMMANDS: &[(&str, &str)] = &[
+    ("/resume [n|id]", "Resume a session (pick from a list)"),
+    ("/search <query>", "Find earlier sessions mentioning query"),
+    (
+        "/sessions [rm <n|id>]",
+        "List this project's sessions, or delete one",
+    ),
+    ("/new", "Start a new session"),
+    (
+        "/model [name|--refresh]",
+        "Show or switch model, or fetch the model list",
+    ),
+    (
+        "/migrate <model> [--text]",
+        "Continue on another model or endpoint",
+    ),
+    ("/name <name>", "Set session display name"),
+    ("/note [text]", "Annotate the transcript, or list notes"),
+    ("/ask <question>", "Answer from context only, without tools"),
+    (
+        "/plan-refactor [goal|skip|stop]",
+        "Plan a refactor, then run it a step at a time",
+    ),
+    (
+        "/set [name=value]",
+        "Set a variable for {{name}} in messages",
+    ),
+    (
+        "/session [--json]",
+        "Show session info, or print it as JSON",
+    ),
+    ("/cost", "Show spend for today, this week, project"),
+    (
+        "/system [show|edit|reset|save]",
+        "View or edit this session's system prompt",
+    ),
+    (
+        "/config [set <key> <value>]",
+        "Show settings and their sources, or change one",
+    ),
+    (
+        "/thinking [off|low|high|<tokens>]",
+        "Show or change the extended thinking budget",
+    ),
+    (
+        "/temperature [value|none]",
+        "Show or set the sampling temperature",
+    ),
+    (
+        "/style [terse|normal|detailed|lang <name|off>]",
+        "Show or change response verbosity and language",
+    ),
+    (
+        "/compact [focus]",
+        "Summarize older turns, steered by focus",
+    ),
+    ("/rewind [n]", "Drop the last n turns (default 1)"),
+    ("/diff", "Show files changed this session"),
+    ("/undo-file <path>", "Revert the last agent write to path"),
+    ("/restore-all", "Revert every file the agent wrote"),
+    ("/apply-block [n]", "Apply a diff block from the last reply"),
+    (
+        "/trash [list|restore <n>]",
+        "Show or restore files deleted this session",
+    ),
+    ("/commit [hint]", "Commit with a generated message"),
+    (
+        "/lsp restart",
+        "Restart language servers, retrying any that failed",
+    ),
+    (
+        "/save [path]",
+        "Export the transcript (.md, or .html to share)",
+    ),
+    ("/quit, /exit", "Quit tapir"),
+    ("/help", "Show this help"),
+];
+
+/// Print `usage` and its translated description, on the
+/// next line when the usage is too long to share one.
+fn print_usage(usage: &str, desc: &str) {
+    if usage.len() > 16 {
+        eprintln!("  {usage}");
+        eprintln!("{:19}{desc}", "");
+    } else {
+        eprintln!("  {usage:<17}{desc}");
+    }
+}
+
+fn print_help(config: &Config) {
+    for (usage, desc) in COMMANDS {
+        print_usage(usage, crate::i18n::t(desc));
+    }
     eprintln!();
-    eprintln!("  Editing:");
-    eprintln!("    Backspace        Delete char before cursor");
-    eprintln!("    Delete           Delete char at cursor");
-    eprintln!("    Ctrl-U           Clear entire line");
-    eprintln!("    Ctrl-K           Delete to end of line");
-    eprintln!("    Ctrl-W           Delete word backward");
-    eprintln!("    Ctrl-G           Open external editor");
-    eprintln!("    Tab              Complete @path");
+    let ShellPrefixes { send, discard } = &config.shell_prefixes;
+    let help = [
+        (
+            format!("{send}cmd"),
+            tr!("Run cmd, send output to LLM").to_string(),
+        ),
+        (
+            format!("{discard}cmd"),
+            tr!("Run cmd, don't send to LLM").to_string(),
+        ),
+        (
+            "!!".to_string(),
+            tr!("Re-run the previous {send} command", send = send),
+        ),
+        (
+            format!("{send}cd [dir]"),
+            tr!("Change the directory {send} commands run in", send = send),
+        ),
+        (
+            "@alias: msg".to_string(),
+            tr!("Send one turn to another model").to_string(),
+        ),
+        (
+            "@dir/".to_string(),
+            tr!("Attach the files under dir to the message").to_string(),
+        ),
+    ];
+    for (usage, desc) in help {
+        print_usage(&usage, &desc);
+    }
     eprintln!();
-    eprintln!("  Control:");
-    eprintln!("    Enter            Submit input");
-    eprintln!("    Ctrl-C           Cancel current line");
-    eprintln!("    Ctrl-D           Quit (on empty line)");
-    eprintln!("    Ctrl-O           Toggle tool output");
+    print_usage("/hotkeys", tr!("Show keyboard shortcuts"));
+    print_usage("/skills", tr!("List available skills"));
+    print_usage("/skill:name", tr!("Load and execute a skill"));
+    print_usage(
+        "/skill:user:name, /skill:project:name",
+        tr!("Pick a skill by origin"),
+    );
+}
+
+/// A row of `/hotkeys`: a fixed key, or an action with the
+/// keys that run it besides its control key.
+enum Hotkey {
+    Fixed(&'static str),
+    Bound(Action, &'static str),
+}
+
+/// `/hotkeys` sections: a heading and its keys.
+const HOTKEYS: &[(&str, &[(Hotkey, &str)])] = &[
+    (
+        "Navigation:",
+        &[
+            (Hotkey::Fixed("Left/Right"), "Move cursor"),
+            (Hotkey::Fixed("Right / End"), "Accept suggestion"),
+            (Hotkey::Fixed("Ctrl+Left/Right"), "Move by word"),
+            (Hotkey::Fixed("Alt-B / Alt-F"), "Move by word"),
+            (Hotkey::Bound(Action::Home, "Home"), "Beginning of line"),
+            (Hotkey::Bound(Action::End, "End"), "End of line"),
+            (Hotkey::Bound(Action::HistoryPrev, "Up"), "Previous history"),
+            (Hotkey::Bound(Action::HistoryNext, "Down"), "Next history"),
+        ],
+    ),
+    (
+        "Editing:",
+        &[
+            (Hotkey::Fixed("Backspace"), "Delete char before cursor"),
+            (Hotkey::Fixed("Delete"), "Delete char at cursor"),
+            (Hotkey::Bound(Action::KillLine, ""), "Clear entire line"),
+            (
+                Hotkey::Bound(Action::KillToEnd, ""),
+                "Delete to end of line",
+            ),
+            (
+                Hotkey::Bound(Action::KillWordBack, ""),
+                "Delete word backward",
+            ),
+            (Hotkey::Fixed("Alt-D"), "Delete word forward"),
+            (Hotkey::Bound(Action::TransposeChars, ""), "Swap characters"),
+            (
+                Hotkey::Bound(Action::ExternalEditor, ""),
+                "Open external editor",
+            ),
+            (Hotkey::Bound(Action::Complete, ""), "Complete @path"),
+        ],
+    ),
+    (
+        "Control:",
+        &[
+            (Hotkey::Fixed("Enter"), "Submit input"),
+            (Hotkey::Bound(Action::Cancel, ""), "Cancel current line"),
+            (Hotkey::Bound(Action::Quit, ""), "Quit (on empty line)"),
+            (Hotkey::Bound(Action::ToolOutput, ""), "Toggle tool output"),
+        ],
+    ),
+];
+
+/// `HOTKEYS` as `keymap` binds them; actions no key runs
+/// are left out.
+fn print_hotkeys(keymap: &Keymap) {
+    for (n, (heading, keys)) in HOTKEYS.iter().enumerate() {
+        if n > 0 {
+            eprintln!();
+        }
+        eprintln!("  {}", crate::i18n::t(heading));
+        for (hotkey, desc) in *keys {
+            let key = match hotkey {
+                Hotkey::Fixed(key) => key.to_string(),
+                Hotkey::Bound(action, also) => {
+                    match (keymap.key(*action), *also) {
+                        (Some(key), "") => key,
+                        (Some(key), also) => format!("{key} / {also}"),
+                        (None, "") => continue,
+                        (None, also) => also.to_string(),
+                    }
+                }
+            };
+            eprintln!("    {key:<17}{}", crate::i18n::t(desc));
+        }
+    }
 }
 
-fn print_session_info(config: &Config, session: &Session) {
+fn print_session_info(config: &mut Config, session: &Session) {
     eprintln!("  path:     {}", session.file.display());
     eprintln!("  id:       {}", session.entry.session_id);
     if !session.entry.summary.is_empty() {
         eprintln!("  name:     {}", session.entry.summary);
     }
     eprintln!("  model:    {}", config.model);
+    eprintln!("  thinking: {}", thinking_label(config.thinking_budget));
+    if let Some(profile) = &config.profile {
+        eprintln!("  profile:  {profile}");
+    }
     eprintln!("  messages: {}", session.messages.len());
-    if let Some(pct) = session.token_pct {
-        eprintln!("  context:  {pct}%");
+    match session.token_pct {
+        Some(pct) => eprintln!("  context:  {pct}%"),
+        None => {
+            let pct = session.estimated_pct(config);
+            eprintln!("  context:  ~{pct}% (estimated)");
+        }
     }
-    let (in_cost, out_cost) = match &config.model_info {
-        Some(m) => (m.input_cost_per_m, m.output_cost_per_m),
-        None => (3.0, 15.0),
-    };
-    let cost_in = session.total_input_tokens as f64 / 1_000_000.0 * in_cost;
-    let cost_out = session.total_output_tokens as f64 / 1_000_000.0 * out_cost;
-    let cost = cost_in + cost_out;
     eprintln!(
         "  tokens:   {} in / {} out",
         session.total_input_tokens, session.total_output_tokens,
     );
-    eprintln!("  cost:     ${cost:.4}");
+    eprintln!("  cost:     ${:.4}", session.cost);
+    if let Some(latency) = session.latency.summary() {
+        eprintln!("  latency:  {latency}");
+    }
     eprintln!("  created:  {}", session.entry.created);
     eprintln!("  modified: {}", session.entry.modified);
     if !session.entry.git_branch.is_empty() {
@@ -245,12 +585,118 @@ fn print_session_info(config: &Config, session: &Session) {
     }
 }
 
+fn thinking_label(budget: u32) -> String {
+    match budget {
+        0 => "off".to_string(),
+        n => format!("{n} tokens"),
+    }
+}
+
+/// Budgets `/thinking low` and `/thinking high` set.
+const THINKING_LOW: u32 = 4_000;
+const THINKING_HIGH: u32 = 16_000;
+
+/// Smallest budget the API accepts.
+const THINKING_MIN: u32 = 1_024;
+
+/// `/thinking` shows the thinking budget; `off`, `low`, `high`
+/// or a token count change it for this session.
+fn thinking_command(config: &mut Config, arg: &str) {
+    let budget = match arg {
+        "" => {
+            eprintln!("* thinking: {}", thinking_label(config.thinking_budget));
+            return;
+        }
+        "off" | "0" => 0,
+        "low" => THINKING_LOW,
+        "high" => THINKING_HIGH,
+        n => match n.parse::<u32>() {
+            Ok(n) if n >= THINKING_MIN => n,
+            Ok(_) => {
+                eprintln!("* the smallest thinking budget is {THINKING_MIN}");
+                return;
+            }
+            Err(_) => {
+                eprintln!("* usage: /thinking [off|low|high|<tokens>]");
+                return;
+            }
+        },
+    };
+    config_command(config, &format!("set thinking_budget {budget}"));
+}
+
+/// `/session --json`: what `/session` shows, as one line of
+/// JSON on stdout.
+fn session_json(config: &mut Config, session: &Session) -> String {
+    let usage =
+        cost::session_totals(&config.dirs.data, &session.entry.session_id);
+    let mut info = session::info_json(&session.entry, &usage);
+    let (pct, estimated) = match session.token_pct {
+        Some(pct) => (pct, false),
+        None => (session.estimated_pct(config), true),
+    };
+    if let Some(map) = info.as_object_mut() {
+        map.insert("path".into(), session.file.display().to_string().into());
+        map.insert("model".into(), config.model.clone().into());
+        map.insert("thinking_budget".into(), config.thinking_budget.into());
+        map.insert("profile".into(), config.profile.clone().into());
+        map.insert("messages".into(), session.messages.len().into());
+        map.insert(
+            "context".into(),
+            serde_json::json!({"percent": pct, "estimated": estimated}),
+        );
+    }
+    info.to_string()
+}
+
+/// Spend from the ledger: this session, today, this week and
+/// this project, with the week broken down by model.
+fn print_costs(config: &Config, session: &Session) {
+    let entries = cost::load(&cost::ledger_path(&config.dirs.data));
+    let (day, week) = cost::day_and_week_start(session::now_secs());
+    let rows = [
+        (
+            "session",
+            cost::totals(&entries, |e| {
+                e.session_id == session.entry.session_id
+            }),
+        ),
+        ("today", cost::totals(&entries, |e| e.time >= day)),
+        ("this week", cost::totals(&entries, |e| e.time >= week)),
+        (
+            "project",
+            cost::totals(&entries, |e| e.project == config.working_dir),
+        ),
+        ("all time", cost::totals(&entries, |_| true)),
+    ];
+    for (label, t) in &rows {
+        eprintln!(
+            "  {:<11}${:>9.4}  ({} requests)",
+            format!("{label}:"),
+            t.cost,
+            t.requests
+        );
+    }
+    let this_week = &rows[2].1;
+    if !this_week.by_model.is_empty() {
+        eprintln!("  this week by model:");
+        for (model, spent) in &this_week.by_model {
+            eprintln!("    {model:<28}${spent:.4}");
+        }
+    }
+}
+
 fn print_models(config: &Config) {
     eprintln!("  current: {}", config.model);
+    let mut aliases: Vec<_> = config.model_aliases.iter().collect();
+    aliases.sort();
+    let aliases: Vec<String> =
+        aliases.iter().map(|(a, m)| format!("{a}={m}")).collect();
+    eprintln!("  aliases: {}", aliases.join(" "));
     if config.models.is_empty() {
         eprintln!(
             "  (no models in config, set any model \
-             ID with /model <id>)"
+             ID with /model <id> or list them with /model --refresh)"
         );
         return;
     }
@@ -274,123 +720,1572 @@ fn print_models(config: &Config) {
     }
 }
 
+/// `/model --refresh`: fill in `config.models` from the
+/// provider's model list.
+fn refresh_models(config: &mut Config) {
+    let listed = match api::list_models(config) {
+        Ok(l) => l,
+        Err(e) => {
+            e.report("* error: ");
+            return;
+        }
+    };
+    let added = config.refresh_models(&listed);
+    eprintln!("* {} models listed, {added} new", listed.len());
+}
+
 fn switch_model(config: &mut Config, name: &str) {
+    let alias = config.model_aliases.get(name).cloned();
+    let name = alias.as_deref().unwrap_or(name);
+    crate::events::emit(
+        "model_switch",
+        serde_json::json!({"from": config.model, "to": name}),
+    );
     config.model = name.to_string();
     config.model_info = config.models.get(name).cloned();
+    config
+        .sources
+        .insert("model", crate::config::Source::Session);
     eprintln!("* model: {name}");
     if config.model_info.is_none() && !config.models.is_empty() {
         eprintln!("  (not in config, pricing unknown)");
     }
 }
 
-// ----------------------------------------------------------
-// Shell escape helpers
-// ----------------------------------------------------------
-
-enum ShellInput {
-    Text(String),
-    Send(String),
-    Discard(String),
+/// `/migrate <model> [--text]`: switch model and rebuild the
+/// conversation so it is valid there. `--text` also turns tool
+/// calls into plain text for models without tool use.
+fn migrate(config: &mut Config, session: &mut Session, arg: &str) {
+    let (flags, names): (Vec<&str>, Vec<&str>) =
+        arg.split_whitespace().partition(|w| w.starts_with("--"));
+    let (&[model], &[] | &["--text"]) = (&names[..], &flags[..]) else {
+        eprintln!("* usage: /migrate <model> [--text]");
+        return;
+    };
+    let text_tools = !flags.is_empty();
+    let before = session.messages.len();
+    let msgs = crate::export::migrate(&session.messages, text_tools);
+    if let Err(e) = session.replace_messages(msgs) {
+        eprintln!("* cannot rewrite session: {e}");
+        return;
+    }
+    switch_model(config, model);
+    let url = config.model_api_url();
+    eprintln!(
+        "* {} of {before} messages carried over, endpoint {url}",
+        session.messages.len()
+    );
+    if let Err(e) = config.endpoint() {
+        eprintln!("* warning: {e}");
+    }
 }
 
-fn classify_input(line: &str) -> ShellInput {
-    if let Some(cmd) = line.strip_prefix("!!") {
-        ShellInput::Discard(cmd.to_string())
-    } else if let Some(cmd) = line.strip_prefix('!') {
-        ShellInput::Send(cmd.to_string())
-    } else {
-        ShellInput::Text(line.to_string())
+/// Diff every file the agent wrote this session against
+/// its content before the first write.
+fn print_session_diff(config: &Config, session: &Session) {
+    let mut any = false;
+    for (path, original) in session.files.originals() {
+        let current = std::fs::read(&path).ok();
+        if current == original {
+            continue;
+        }
+        let display = path
+            .strip_prefix(&config.working_dir)
+            .unwrap_or(&path)
+            .display()
+            .to_string();
+        let old = String::from_utf8_lossy(original.as_deref().unwrap_or(&[]))
+            .into_owned();
+        let new = String::from_utf8_lossy(current.as_deref().unwrap_or(&[]))
+            .into_owned();
+        let diff = line_diff(&display, &old, &new);
+        if original.is_none() {
+            eprintln!(
+                "{}",
+                paint(Style::Bold, &format!("new file: {display}"))
+            );
+        } else if current.is_none() {
+            eprintln!("{}", paint(Style::Bold, &format!("deleted: {display}")));
+        }
+        print_diff(&diff, config.highlight);
+        any = true;
+    }
+    if !any {
+        eprintln!("* no files changed this session");
     }
 }
 
-fn run_shell(working_dir: &std::path::Path, cmd: &str) -> String {
-    tool::run_bash(working_dir, cmd, 30)
-        .unwrap_or_else(|e| format!("error: {e}"))
+/// Print a unified diff with added, removed and header lines
+/// colored. With `highlight`, added and unchanged lines are
+/// colored by the language of the file instead.
+fn print_diff(diff: &str, highlight: bool) {
+    let theme = crate::theme::current();
+    let mut lang = "";
+    for line in diff.lines() {
+        if let Some(path) = line.strip_prefix("+++ ") {
+            lang = Path::new(path.trim())
+                .extension()
+                .and_then(|e| e.to_str())
+                .filter(|e| highlight && crate::highlight::supported(e))
+                .unwrap_or("");
+        }
+        let style = crate::display::diff_style(line);
+        let code = line
+            .strip_prefix(['+', ' '])
+            .filter(|_| !lang.is_empty() && !line.starts_with("+++"));
+        if let Some(code) = code {
+            let marker = &line[..1];
+            let marker =
+                style.map_or(marker.into(), |s| theme.paint(s, marker));
+            let code = crate::highlight::ansi(code, lang, 0, &theme);
+            eprintln!("{marker}{code}");
+        } else if let Some(style) = style {
+            eprintln!("{}", theme.paint(style, line));
+        } else {
+            eprintln!("{line}");
+        }
+    }
 }
 
 // ----------------------------------------------------------
-// Read one line of user input, handling commands & shell
-// escapes. Returns InputResult to tell the caller what to
-// do next.
+// /apply-block
 // ----------------------------------------------------------
 
-pub fn read_input(
-    editor: &mut Editor,
-    config: &mut Config,
-    session: &mut Session,
-    tool_log: &mut ToolOutputLog,
-    at_startup: bool,
-) -> Result<InputResult> {
-    loop {
-        eprintln!();
-        let prompt = match session.token_pct {
-            Some(p) => format!("{p}% \x1b[1m>\x1b[0m "),
-            None => "\x1b[1m>\x1b[0m ".to_string(),
-        };
-        let line = match editor.readline(&prompt, Some(tool_log))? {
-            Some(line) if !line.is_empty() => line,
-            _ => return Ok(InputResult::Quit),
-        };
+/// The prose of an assistant message.
+fn reply_text(msg: &Message) -> String {
+    match &msg.content {
+        Content::Text(text) => text.clone(),
+        Content::Blocks(blocks) => blocks
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+    }
+}
 
-        if line == "?" {
-            print_help();
-            continue;
+/// Apply diff block `arg` (1-based; optional when there is
+/// only one) from the last assistant message, after showing
+/// the result and asking. Nothing is written unless every
+/// file patches cleanly.
+fn apply_block(config: &Config, session: &Session, arg: &str) {
+    let Some(text) = session
+        .messages
+        .iter()
+        .rev()
+        .filter(|m| m.role == Role::Assistant)
+        .map(reply_text)
+        .find(|t| !t.is_empty())
+    else {
+        eprintln!("* no assistant message yet");
+        return;
+    };
+    let blocks = crate::patch::diff_blocks(&text);
+    let block = match (arg.parse::<usize>(), blocks.len()) {
+        _ if arg.is_empty() && blocks.len() == 1 => &blocks[0],
+        (Ok(n), len) if (1..=len).contains(&n) => &blocks[n - 1],
+        (_, 0) => {
+            eprintln!("* no diff blocks in the last reply");
+            return;
         }
-
-        // Slash commands
-        if line.starts_with('/') {
-            match handle_command(&line, config, session, at_startup) {
-                InputResult::Continue => continue,
-                other => return Ok(other),
+        (_, len) => {
+            for (n, block) in blocks.iter().enumerate() {
+                let files: Vec<String> = crate::patch::parse(block)
+                    .map(|ps| {
+                        ps.iter()
+                            .filter_map(|p| p.path().map(String::from))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                eprintln!("  {:>3}  {}", n + 1, files.join(", "));
             }
+            eprintln!("* usage: /apply-block <1-{len}>");
+            return;
+        }
+    };
+    let patches = match crate::patch::parse(block) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("* {e}");
+            return;
         }
+    };
 
-        // Shell escapes
-        match classify_input(&line) {
-            ShellInput::Discard(cmd) => {
-                let out = run_shell(&config.working_dir, &cmd);
-                eprintln!("{out}");
-                continue;
+    let mut writes = Vec::new();
+    for patch in &patches {
+        let (Some(display), Some(_)) = (patch.path(), &patch.new) else {
+            eprintln!(
+                "* not applied: the diff deletes {}",
+                patch.path().unwrap_or("a file")
+            );
+            return;
+        };
+        let path = match tool::safe_path_for_write(&config.working_dir, display)
+        {
+            Ok(p) => p,
+            Err(e) => {
+                eprintln!("* {e}");
+                return;
             }
-            ShellInput::Send(cmd) => {
-                let out = run_shell(&config.working_dir, &cmd);
-                eprintln!("{out}");
-                let text = format!("Shell command: {cmd}\nOutput:\n{out}");
-                add_user_message(session, &text);
-                if session.entry.first_prompt == "No prompt" {
-                    session.entry.first_prompt = truncate(&cmd, 100);
+        };
+        let old = match std::fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(_) if patch.old.is_none() => String::new(),
+            Err(e) => {
+                eprintln!("* {display}: {e}");
+                return;
+            }
+        };
+        let new = match patch.apply(&old) {
+            Ok(new) => new,
+            Err(e) => {
+                eprintln!("* {display}: {e}");
+                return;
+            }
+        };
+        print_diff(&line_diff(display, &old, &new), config.highlight);
+        // The same sandbox, protected-file and size checks as
+        // write_file
+        if let Err(e) = config.policy.check_write(
+            "/apply-block",
+            &config.working_dir,
+            &path,
+            display,
+            Some(new.len()),
+        ) {
+            match e {
+                // Declined; the message is worded for the model
+                crate::error::Error::Tool { .. } => {
+                    eprintln!("{}", tr!("* not applied"))
                 }
-                return Ok(InputResult::Ready);
+                e => eprintln!("* {e}"),
             }
-            ShellInput::Text(text) => {
-                if session.entry.first_prompt == "No prompt" {
-                    session.entry.first_prompt = truncate(&text, 100);
+            return;
+        }
+        writes.push((path, display, new));
+    }
+
+    let question = match writes.as_slice() {
+        [(_, display, _)] => {
+            tr!("apply to {display}? [y/N] ", display = display)
+        }
+        _ => tr!("apply to {n} files? [y/N] ", n = writes.len()),
+    };
+    if !readline::confirm(&question) {
+        eprintln!("{}", tr!("* not applied"));
+        return;
+    }
+    for (path, display, new) in writes {
+        match session.files.write(&path, new.as_bytes()) {
+            Ok(()) => eprintln!("* patched {display}"),
+            Err(e) => eprintln!("* {display}: {e}"),
+        }
+    }
+}
+
+fn system_command(config: &mut Config, session: &Session, arg: &str) {
+    config.ensure_full_prompt();
+    match arg {
+        "" | "show" => {
+            eprintln!("{}", config.full_prompt());
+            if config.prompt_override.is_some() {
+                eprintln!("\n* (edited with /system edit)");
+            }
+        }
+        "edit" => {
+            let current = config.full_prompt().to_string();
+            let name =
+                crate::readline::temp_name(&session.entry.session_id, "system");
+            match crate::readline::edit_in_editor(&current, &name) {
+                Ok(Some(edited)) if edited.trim().is_empty() => {
+                    eprintln!("* empty prompt, keeping the current one");
                 }
-                add_user_message(session, &text);
-                return Ok(InputResult::Ready);
+                Ok(Some(edited)) if edited != current => {
+                    config.prompt_override = Some(edited);
+                    config.full_prompt = None;
+                    eprintln!("* system prompt replaced for this session");
+                }
+                Ok(Some(_)) => eprintln!("* no changes"),
+                Ok(None) => eprintln!("* editor failed, prompt unchanged"),
+                Err(e) => eprintln!("* error: {e}"),
             }
         }
+        "reset" => {
+            config.prompt_override = None;
+            config.full_prompt = None;
+            eprintln!("* system prompt restored");
+        }
+        "save" => save_system_prompt(config),
+        _ => eprintln!("* usage: /system [show|edit|reset|save]"),
     }
 }
 
-fn add_user_message(session: &mut Session, text: &str) {
-    session.push_message(Message {
-        role: Role::User,
-        content: Content::Text(text.to_string()),
-    });
+/// `/config` lists the main settings and where each came
+/// from; `/config set <key> <value>` changes one for the rest
+/// of the session.
+fn config_command(config: &mut Config, arg: &str) {
+    if arg.is_empty() {
+        for key in crate::config::SETTINGS {
+            let value = config.setting(key).unwrap_or_default();
+            let source = config.sources.get(key).copied().unwrap_or_default();
+            eprintln!("  {key:<21}{value}  ({source})");
+        }
+        return;
+    }
+    let Some((key, value)) = arg
+        .strip_prefix("set ")
+        .and_then(|rest| rest.trim().split_once(char::is_whitespace))
+    else {
+        eprintln!("* usage: /config [set <key> <value>]");
+        return;
+    };
+    if key == "model" {
+        switch_model(config, value.trim());
+        return;
+    }
+    let before = config.check();
+    match config.set(key, value.trim()) {
+        Ok(()) => {
+            let value = config.setting(key).unwrap_or_default();
+            eprintln!("* {key}: {value} (this session)");
+            for w in config.check().iter().filter(|w| !before.contains(w)) {
+                eprintln!("* warning: {w}");
+            }
+        }
+        Err(e) => eprintln!("* {e}"),
+    }
 }
 
-fn try_resume(
-    config: &Config,
-    entry: &mut session::SessionEntry,
-) -> Option<(std::path::PathBuf, Vec<Message>, Option<u32>)> {
-    let latest = session::latest_entry(&config.session_dir)?;
-    let path = session::session_path(&latest);
-    let msgs = super::agent::load_session(&path).ok()?;
-    if msgs.is_empty() {
-        return None;
+/// `/style` shows the response style, `/style <verbosity>`
+/// and `/style lang <name|off>` change it for this session.
+fn style_command(config: &mut Config, arg: &str) {
+    let (sub, rest) = arg.split_once(' ').unwrap_or((arg, ""));
+    let rest = rest.trim();
+    match sub {
+        "" => {}
+        "lang" | "language" if !rest.is_empty() => {
+            config.style.language = (rest != "off").then(|| rest.to_string());
+        }
+        _ => match crate::context::Verbosity::parse(sub) {
+            Some(v) => config.style.verbosity = v,
+            None => {
+                eprintln!(
+                    "* usage: /style [terse|normal|detailed|lang <name|off>]"
+                );
+                return;
+            }
+        },
+    }
+    if !arg.is_empty() {
+        config.full_prompt = None;
+    }
+    eprintln!(
+        "* style: {}, language: {}",
+        config.style.verbosity.name(),
+        config.style.language.as_deref().unwrap_or("default")
+    );
+    if !arg.is_empty() && config.prompt_override.is_some() {
+        eprintln!("* note: /system edit is in effect; /system reset to apply");
+    }
+}
+
+/// Write the edited base prompt to `.tapir/SYSTEM.md`. Only
+/// the part before the generated sections (working directory,
+/// context files, project, environment, skills) is saved, so
+/// those must be left as they were.
+fn save_system_prompt(config: &Config) {
+    let Some(edited) = &config.prompt_override else {
+        eprintln!("* nothing to save, use /system edit first");
+        return;
+    };
+    let assembled = config.assembled_prompt();
+    let Some(generated) = assembled.strip_prefix(&config.base_prompt) else {
+        eprintln!("* cannot locate the base prompt, not saving");
+        return;
+    };
+    let Some(base) = edited.strip_suffix(generated) else {
+        eprintln!(
+            "* generated sections were edited; only the text before \
+             \"Working directory:\" can be saved"
+        );
+        return;
+    };
+    let path = config.working_dir.join(".tapir/SYSTEM.md");
+    let result = std::fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| std::fs::write(&path, format!("{base}\n")));
+    match result {
+        Ok(()) => eprintln!("* saved {}", path.display()),
+        Err(e) => eprintln!("* error: {e}"),
     }
-    let pct = super::agent::load_token_pct(&path);
-    *entry = latest;
-    Some((path, msgs, pct))
+}
+
+/// Summarize older turns now, with `arg` as focus
+/// instructions for the summary.
+fn compact(config: &Config, session: &mut Session, arg: &str) {
+    match super::agent::compact_session(config, session, arg) {
+        Ok(true) => {}
+        Ok(false) => eprintln!("* not enough conversation to compact"),
+        Err(e) => eprintln!("* compaction failed: {e}"),
+    }
+}
+
+/// Cut the conversation back to before the n-th most recent
+/// user turn, offering to restore the files written since.
+fn rewind(config: &Config, session: &mut Session, arg: &str) {
+    let n: usize = if arg.is_empty() {
+        1
+    } else {
+        match arg.parse() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                eprintln!("* usage: /rewind [n]");
+                return;
+            }
+        }
+    };
+    let user_turns: Vec<usize> = session
+        .messages
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| {
+            m.role == Role::User && matches!(m.content, Content::Text(_))
+        })
+        .map(|(i, _)| i)
+        .collect();
+    if user_turns.len() < n {
+        eprintln!("* only {} turn(s) in this session", user_turns.len());
+        return;
+    }
+    let index = user_turns[user_turns.len() - n];
+    let mark = session
+        .turns
+        .iter()
+        .find(|t| t.message_index == index)
+        .map(|t| t.checkpoint);
+
+    let restore = match (mark, session.files.checkpoints()) {
+        (Some(seq), Some(cp)) => {
+            let changed = cp.changed_since(seq);
+            if changed.is_empty() {
+                None
+            } else {
+                eprintln!("* files written since then:");
+                for path in &changed {
+                    let display =
+                        path.strip_prefix(&config.working_dir).unwrap_or(path);
+                    eprintln!("    {}", display.display());
+                }
+                readline::confirm(tr!("restore them too? [y/N] "))
+                    .then_some(seq)
+            }
+        }
+        _ => None,
+    };
+
+    if let Content::Text(text) = &session.messages[index].content {
+        eprintln!("* rewound to before: {}", truncate(text.trim(), 80));
+    }
+    if let Err(e) = session.truncate_messages(index) {
+        eprintln!("* error rewriting session: {e}");
+    }
+    crate::events::emit(
+        "rewind",
+        serde_json::json!({
+            "turns": n,
+            "messages": session.messages.len(),
+            "restore_files": restore.is_some(),
+        }),
+    );
+    session.entry.message_count = session.messages.len() as u32;
+    session::update_entry(&config.session_dir, &session.entry);
+
+    if let Some(seq) = restore
+        && let Some(cp) = session.files.checkpoints()
+    {
+        match cp.undo_since(seq) {
+            Ok(paths) => eprintln!("* restored {} file(s)", paths.len()),
+            Err(e) => eprintln!("* error restoring files: {e}"),
+        }
+    }
+}
+
+fn undo_file(config: &Config, session: &Session, arg: &str) {
+    if arg.is_empty() {
+        eprintln!("* usage: /undo-file <path>");
+        return;
+    }
+    let Some(cp) = session.files.checkpoints() else {
+        eprintln!("* checkpoints are not enabled");
+        return;
+    };
+    let path = match tool::safe_path_for_write(&config.working_dir, arg) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("* {e}");
+            return;
+        }
+    };
+    match cp.undo(&path) {
+        Ok(true) => eprintln!("* restored {arg}"),
+        Ok(false) => eprintln!("* no checkpoint for {arg}"),
+        Err(e) => eprintln!("* error: {e}"),
+    }
+}
+
+fn restore_all(config: &Config, session: &Session) {
+    let Some(cp) = session.files.checkpoints() else {
+        eprintln!("* checkpoints are not enabled");
+        return;
+    };
+    match cp.restore_all() {
+        Ok(paths) if paths.is_empty() => {
+            eprintln!("* nothing to restore");
+        }
+        Ok(paths) => {
+            for path in paths {
+                let display =
+                    path.strip_prefix(&config.working_dir).unwrap_or(&path);
+                eprintln!("* restored {}", display.display());
+            }
+        }
+        Err(e) => eprintln!("* error: {e}"),
+    }
+}
+
+/// `/trash [list]` and `/trash restore <n>` for files the
+/// agent deleted this session.
+fn trash_command(session: &Session, arg: &str) {
+    let Some(trash) = session.files.trash() else {
+        eprintln!("* no trash for this session");
+        return;
+    };
+    let (sub, rest) = arg.split_once(' ').unwrap_or((arg, ""));
+    match sub {
+        "" | "list" => {
+            let entries = trash.list();
+            if entries.is_empty() {
+                eprintln!("* trash is empty");
+            }
+            for e in entries {
+                eprintln!(
+                    "  {:>3}  {}  ({} bytes, {})",
+                    e.id,
+                    e.path.display(),
+                    e.size,
+                    e.time
+                );
+            }
+        }
+        "restore" => match rest.trim().parse() {
+            Ok(id) => match trash.restore(id) {
+                Ok(path) => eprintln!("* restored {}", path.display()),
+                Err(e) => eprintln!("* cannot restore {id}: {e}"),
+            },
+            Err(_) => eprintln!("* usage: /trash restore <n>"),
+        },
+        _ => eprintln!("* usage: /trash [list|restore <n>]"),
+    }
+}
+
+/// `/save [path]`: write the transcript as Markdown, or as a
+/// standalone HTML page when `path` ends in `.html`.
+fn save_transcript(config: &Config, session: &Session, arg: &str) {
+    if session.messages.is_empty() {
+        eprintln!("* nothing to save yet");
+        return;
+    }
+    let path = if arg.is_empty() {
+        config
+            .working_dir
+            .join(format!("{}.md", session.entry.session_id))
+    } else {
+        config.working_dir.join(arg)
+    };
+    if path.exists()
+        && !readline::confirm(&tr!(
+            "overwrite {path}? [y/N] ",
+            path = path.display()
+        ))
+    {
+        eprintln!("{}", tr!("* not saved"));
+        return;
+    }
+    let title = if session.entry.summary.is_empty() {
+        truncate_line(&session.entry.first_prompt, 80)
+    } else {
+        session.entry.summary.clone()
+    };
+    let is_html = path.extension().is_some_and(|e| e == "html" || e == "htm");
+    let text = if is_html {
+        crate::export::html(&title, &session.messages, &session.notes)
+    } else {
+        crate::export::markdown(&title, &session.messages, &session.notes)
+    };
+    match std::fs::write(&path, text) {
+        Ok(()) => eprintln!("* saved {}", path.display()),
+        Err(e) => eprintln!("* cannot write {}: {e}", path.display()),
+    }
+}
+
+/// `/set name=value` stores a variable for `{{name}}` in
+/// later messages, `/set name=` drops it and a bare `/set`
+/// lists them.
+fn set_var(session: &mut Session, arg: &str) {
+    if arg.is_empty() {
+        if session.vars.is_empty() {
+            eprintln!("* no variables; /set name=value adds one");
+        }
+        for (name, value) in &session.vars {
+            eprintln!("  {name}={value}");
+        }
+        return;
+    }
+    let Some((name, value)) = arg.split_once('=') else {
+        eprintln!("* usage: /set name=value");
+        return;
+    };
+    let (name, value) = (name.trim(), value.trim());
+    if !crate::util::is_var_name(name) {
+        eprintln!("* invalid name {name:?}: use letters, digits, _ - .");
+    } else if value.is_empty() {
+        match session.vars.remove(name) {
+            Some(_) => eprintln!("* unset {name}"),
+            None => eprintln!("* {name} is not set"),
+        }
+    } else {
+        session.vars.insert(name.to_string(), value.to_string());
+        eprintln!("* {name}={value}");
+    }
+}
+
+/// What `prompt` and `prompt_right` can show, over the
+/// session's `/set` variables.
+fn prompt_vars(
+    config: &mut Config,
+    session: &Session,
+) -> std::collections::BTreeMap<String, String> {
+    let context = match session.token_pct {
+        Some(p) => format!("{p}%"),
+        None => format!("~{}%", session.estimated_pct(config)),
+    };
+    let mut vars = session.vars.clone();
+    let theme = crate::theme::current();
+    for (name, value) in [
+        ("context", context),
+        ("model", config.model_short_name().to_string()),
+        ("branch", session.entry.git_branch.clone()),
+        ("cost", format!("${:.2}", session.cost)),
+        ("profile", config.profile.clone().unwrap_or_default()),
+        ("bold", theme.start(Style::Bold)),
+        ("dim", theme.start(Style::Dim)),
+        ("reset", theme.reset().into()),
+    ] {
+        vars.insert(name.into(), value);
+    }
+    vars
+}
+
+/// `text` with session variables filled in, warning about
+/// the ones not set.
+fn expand_vars(session: &Session, text: &str) -> String {
+    let (out, unknown) = crate::util::interpolate(text, &session.vars);
+    for name in unknown {
+        eprintln!("* warning: {{{{{name}}}}} is not set, sent as is");
+    }
+    out
+}
+
+/// `/note <text>` annotates the transcript at this point; a
+/// bare `/note` lists the session's notes.
+fn note(session: &mut Session, arg: &str) {
+    if !arg.is_empty() {
+        session.add_note(arg);
+        eprintln!("* noted after message {}", session.messages.len());
+        return;
+    }
+    if session.notes.is_empty() {
+        eprintln!("* no notes; /note <text> adds one");
+    }
+    for note in &session.notes {
+        eprintln!("  [{}] {}  {}", note.at, note.time, note.text);
+    }
+}
+
+// ----------------------------------------------------------
+// /plan-refactor
+// ----------------------------------------------------------
+
+/// `/plan-refactor <goal>` asks the model for a plan; a bare
+/// `/plan-refactor` shows it and goes on with the next step,
+/// `skip` passes that step over and `stop` drops the plan.
+fn plan_command(
+    config: &mut Config,
+    session: &mut Session,
+    arg: &str,
+) -> InputResult {
+    match arg {
+        "" | "skip" => {
+            let Some(plan) = session.plan.as_mut() else {
+                eprintln!("* no plan; /plan-refactor <goal> makes one");
+                return InputResult::Continue;
+            };
+            if arg == "skip"
+                && let Some(next) = plan.next()
+            {
+                plan.steps[next].skipped = true;
+                eprintln!("* skipped step {}", next + 1);
+            }
+            plan.paused = false;
+            plan.save(&session.file);
+            if advance_plan(config, session) {
+                InputResult::Ready
+            } else {
+                InputResult::Continue
+            }
+        }
+        "stop" => {
+            if session.plan.take().is_some() {
+                crate::plan::remove(&session.file);
+                eprintln!("* plan dropped");
+            } else {
+                eprintln!("* no plan");
+            }
+            InputResult::Continue
+        }
+        goal => {
+            let goal = &expand_vars(session, goal);
+            let plan = Plan::new(goal);
+            let prompt = plan.prompt();
+            if !fits(config, session, &prompt) {
+                return InputResult::Continue;
+            }
+            if session.plan.as_ref().is_some_and(|p| p.next().is_some()) {
+                eprintln!("* replacing the unfinished plan");
+            }
+            if session.entry.first_prompt == "No prompt" {
+                session.entry.first_prompt = truncate(goal, 100);
+            }
+            add_user_message(session, &prompt);
+            let plan = Plan {
+                since: session.messages.len(),
+                ..plan
+            };
+            plan.save(&session.file);
+            session.plan = Some(plan);
+            InputResult::Ready
+        }
+    }
+}
+
+/// Move the session's plan along after a turn: read the plan
+/// from the reply that wrote it, or check off the step that
+/// just ran, then offer the next step. Returns whether its
+/// message was added.
+fn advance_plan(config: &mut Config, session: &mut Session) -> bool {
+    let Some(plan) = session.plan.as_mut() else {
+        return false;
+    };
+    // A turn ends on an assistant reply unless it was cut
+    // short in the middle of tool calls or by max_tokens. One
+    // from before the prompt is left from a declined request.
+    let reply = session
+        .messages
+        .last()
+        .filter(|_| session.messages.len() > plan.since && !session.cut_off)
+        .filter(|m| m.role == Role::Assistant)
+        .filter(|m| !has_tool_use(m));
+    if plan.drafting {
+        if !reply.is_some_and(|m| plan.fill(&reply_text(m))) {
+            eprintln!(
+                "* no steps in the reply; /plan-refactor <goal> to try again"
+            );
+            crate::plan::remove(&session.file);
+            session.plan = None;
+            return false;
+        }
+    } else if let Some(running) = plan.running.take() {
+        if reply.is_some() {
+            plan.steps[running].done = true;
+        } else {
+            eprintln!("* step {} did not finish", running + 1);
+            plan.paused = true;
+        }
+    }
+    plan.save(&session.file);
+    if plan.paused {
+        return false;
+    }
+    print_plan(plan);
+    let Some(next) = plan.next() else {
+        eprintln!("* plan done");
+        crate::plan::remove(&session.file);
+        session.plan = None;
+        return false;
+    };
+    crate::notify::alert("plan step waiting");
+    if !readline::confirm(&tr!("run step {n}? [y/N] ", n = next + 1)) {
+        plan.paused = true;
+        plan.save(&session.file);
+        eprintln!("* plan paused; /plan-refactor goes on, skip passes it over");
+        return false;
+    }
+    let prompt = plan.step_prompt(next);
+    if !fits(config, session, &prompt) {
+        if let Some(plan) = session.plan.as_mut() {
+            plan.paused = true;
+            plan.save(&session.file);
+        }
+        return false;
+    }
+    add_user_message(session, &prompt);
+    if let Some(plan) = session.plan.as_mut() {
+        plan.running = Some(next);
+        plan.since = session.messages.len();
+        plan.save(&session.file);
+    }
+    true
+}
+
+fn has_tool_use(msg: &Message) -> bool {
+    match &msg.content {
+        Content::Text(_) => false,
+        Content::Blocks(blocks) => blocks
+            .iter()
+            .any(|b| matches!(b, ContentBlock::ToolUse { .. })),
+    }
+}
+
+/// The plan's checklist, with steps over dimmed.
+fn print_plan(plan: &Plan) {
+    let (over, total) = plan.progress();
+    let max = readline::terminal_width().map_or(usize::MAX, |w| w.max(20));
+    for line in plan.checklist() {
+        let line = truncate_line(&line, max);
+        if line.starts_with("  [x]") || line.starts_with("  [-]") {
+            eprintln!("{}", paint(Style::Dim, &line));
+        } else if line.starts_with("plan: ") {
+            eprintln!("{}", paint(Style::Bold, &line));
+        } else {
+            eprintln!("{line}");
+        }
+    }
+    eprintln!("* {over} of {total} steps over");
+}
+
+// ----------------------------------------------------------
+// /commit
+// ----------------------------------------------------------
+
+const COMMIT_PROMPT: &str = "Write a git commit message for this diff \
+     in the Conventional Commits format: a `type(scope): summary` \
+     subject under 72 characters, a blank line, then a short body \
+     explaining what changed and why. Output only the message, no \
+     code fences or commentary.";
+
+/// Largest diff sent to the model; the rest is cut.
+const COMMIT_DIFF_MAX: usize = 60_000;
+
+/// Commit the staged diff (or, if nothing is staged, all
+/// tracked changes) with a model-written message the user
+/// approves first.
+fn commit(config: &Config, hint: &str) {
+    let wd = &config.working_dir;
+    let staged = match git(wd, &["diff", "--cached"]) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("* {e}");
+            return;
+        }
+    };
+    let (diff, all) = if staged.trim().is_empty() {
+        (git(wd, &["diff"]).unwrap_or_default(), true)
+    } else {
+        (staged, false)
+    };
+    if diff.trim().is_empty() {
+        eprintln!("* nothing to commit");
+        return;
+    }
+
+    let mut text =
+        format!("<diff>\n{}\n</diff>", truncate(&diff, COMMIT_DIFF_MAX));
+    if !hint.is_empty() {
+        text.push_str(&format!("\n\nContext from the user: {hint}"));
+    }
+    eprintln!("* writing commit message...");
+    let message = match generate_commit_message(config, &text) {
+        Ok(m) if !m.trim().is_empty() => m.trim().to_string(),
+        Ok(_) => {
+            eprintln!("* model returned an empty message");
+            return;
+        }
+        Err(e) => {
+            e.report("* error: ");
+            return;
+        }
+    };
+
+    eprintln!();
+    for line in message.lines() {
+        eprintln!("  {line}");
+    }
+    eprintln!();
+    let scope = if all {
+        "all tracked changes"
+    } else {
+        "staged changes"
+    };
+    let answer = readline::ask(&format!("commit {scope}? [y/e/N] "));
+    let edit = match answer.as_str() {
+        "y" | "yes" => false,
+        "e" | "edit" => true,
+        _ => {
+            eprintln!("* commit cancelled");
+            return;
+        }
+    };
+
+    let msg_file = std::env::temp_dir()
+        .join(format!(".tapir-commit-{}.txt", std::process::id()));
+    if let Err(e) = std::fs::write(&msg_file, format!("{message}\n")) {
+        eprintln!("* error: {e}");
+        return;
+    }
+    let mut cmd = Command::new("git");
+    cmd.arg("commit").arg("-F").arg(&msg_file).current_dir(wd);
+    if all {
+        cmd.arg("-a");
+    }
+    if edit {
+        cmd.arg("-e");
+    }
+    let status = cmd.status();
+    let _ = std::fs::remove_file(&msg_file);
+    match status {
+        Ok(s) if s.success() => {}
+        Ok(s) => eprintln!("* git commit failed ({s})"),
+        Err(e) => eprintln!("* git commit: {e}"),
+    }
+}
+
+fn generate_commit_message(config: &Config, diff: &str) -> Result<String> {
+    let msgs = [Message {
+        role: Role::User,
+        content: Content::Text(diff.to_string()),
+    }];
+    let request = Request {
+        model: &config.model,
+        max_tokens: 1024,
+        thinking: None,
+        system: vec![SystemBlock::text(COMMIT_PROMPT)],
+        messages: &msgs,
+        temperature: None,
+        top_p: None,
+        stop_sequences: &[],
+        tools: &[],
+        tool_choice: None,
+        stream: true,
+        context_management: None,
+    };
+
+    let mut reader = api::send_stream(config, &request)?;
+    let mut message = String::new();
+    loop {
+        match reader.next_event()? {
+            Some(SseEvent::ContentBlockDelta {
+                delta: Delta::Text(s),
+                ..
+            }) => message.push_str(&s),
+            Some(SseEvent::MessageStop) | None => break,
+            _ => {}
+        }
+    }
+    Ok(message)
+}
+
+/// Run git and return stdout, or stderr as the error.
+fn git(dir: &Path, args: &[&str]) -> std::result::Result<String, String> {
+    let out = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("git: {e}"))?;
+    if !out.status.success() {
+        return Err(String::from_utf8_lossy(&out.stderr).trim().to_string());
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+// ----------------------------------------------------------
+// Shell escape helpers
+// ----------------------------------------------------------
+
+/// Line prefixes that turn input into a shell escape, from
+/// `shell_prefix` and `shell_discard_prefix` in config.
+#[derive(Clone)]
+pub struct ShellPrefixes {
+    /// Run and send the output to the model.
+    pub send: String,
+    /// Run and only show the output.
+    pub discard: String,
+}
+
+impl Default for ShellPrefixes {
+    fn default() -> Self {
+        ShellPrefixes {
+            send: "!".to_string(),
+            discard: "!!".to_string(),
+        }
+    }
+}
+
+enum ShellInput {
+    Text(String),
+    Send(String),
+    Discard(String),
+    /// A bare `!!`: run the previous escape again.
+    Repeat,
+}
+
+fn classify_input(line: &str, prefixes: &ShellPrefixes) -> ShellInput {
+    if line == "!!" {
+        return ShellInput::Repeat;
+    }
+    // Try the longer prefix first so `!!` wins over `!`
+    let mut order = [(&prefixes.send, true), (&prefixes.discard, false)];
+    order.sort_by_key(|(p, _)| std::cmp::Reverse(p.len()));
+    for (prefix, send) in order {
+        if let Some(cmd) = line.strip_prefix(prefix.as_str()) {
+            let cmd = cmd.to_string();
+            return if send {
+                ShellInput::Send(cmd)
+            } else {
+                ShellInput::Discard(cmd)
+            };
+        }
+    }
+    ShellInput::Text(line.to_string())
+}
+
+/// Timeout for `!` escapes, in seconds.
+const SHELL_TIMEOUT: u64 = 30;
+
+/// Run a `!` escape in the session's shell directory after
+/// echoing what will run where.
+fn run_shell(config: &Config, session: &Session, cmd: &str) -> String {
+    let dir =
+        crate::context::display_path(&session.shell_dir, &config.working_dir);
+    let echo = format!("$ {cmd}  (in {dir}, timeout {SHELL_TIMEOUT}s)");
+    eprintln!("{}", paint(Style::Dim, &echo));
+    tool::run_bash(&session.shell_dir, cmd, SHELL_TIMEOUT)
+        .unwrap_or_else(|e| format!("error: {e}"))
+}
+
+/// `cd` for `!` escapes, if `cmd` is one. No argument goes
+/// back to the working directory.
+fn shell_cd(config: &Config, session: &mut Session, cmd: &str) -> bool {
+    let cmd = cmd.trim();
+    let arg = match cmd.strip_prefix("cd") {
+        Some("") => "",
+        Some(rest) if rest.starts_with(char::is_whitespace) => rest.trim(),
+        _ => return false,
+    };
+    let target = if arg.is_empty() {
+        config.working_dir.clone()
+    } else if let Some(rest) =
+        arg.strip_prefix("~/").or((arg == "~").then_some(""))
+    {
+        std::path::PathBuf::from(std::env::var("HOME").unwrap_or_default())
+            .join(rest)
+    } else {
+        session.shell_dir.join(arg)
+    };
+    match target.canonicalize() {
+        Ok(dir) if dir.is_dir() => {
+            eprintln!(
+                "* shell cwd: {}",
+                crate::context::display_path(&dir, &config.working_dir)
+            );
+            session.shell_dir = dir;
+        }
+        Ok(_) => eprintln!("* cd: {arg}: not a directory"),
+        Err(e) => eprintln!("* cd: {arg}: {e}"),
+    }
+    true
+}
+
+// ----------------------------------------------------------
+// Read one line of user input, handling commands & shell
+// escapes. Returns InputResult to tell the caller what to
+// do next.
+// ----------------------------------------------------------
+
+pub fn read_input(
+    editor: &mut Editor,
+    config: &mut Config,
+    session: &mut Session,
+    tool_log: &mut ToolOutputLog,
+) -> Result<InputResult> {
+    // An `@name:` turn is over
+    if let Some((model, info)) = session.default_model.take() {
+        config.model = model;
+        config.model_info = info;
+    }
+    session.fell_back_at = None;
+    if advance_plan(config, session) {
+        return Ok(InputResult::Ready);
+    }
+    loop {
+        eprintln!();
+        let vars = prompt_vars(config, session);
+        let (prompt, _) = crate::util::interpolate(&config.prompt, &vars);
+        let (right, _) = crate::util::interpolate(&config.prompt_right, &vars);
+        editor.set_right_prompt(right);
+        editor.set_recent_files(session.files.recent());
+        editor.set_session_id(&session.entry.session_id);
+        let line = match editor.readline(&prompt, Some(tool_log)) {
+            Ok(Some(line)) if !line.is_empty() => line,
+            Ok(_) => return Ok(InputResult::Quit),
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                return Ok(InputResult::Idle);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if line == "?" {
+            print_help(config);
+            continue;
+        }
+
+        // Slash commands
+        if line.starts_with('/') {
+            match handle_command(&line, config, session, editor) {
+                InputResult::Continue => continue,
+                other => return Ok(other),
+            }
+        }
+
+        // Shell escapes
+        let mut input = classify_input(&line, &config.shell_prefixes);
+        if let ShellInput::Repeat = input {
+            let Some(last) = session.last_escape.clone() else {
+                eprintln!("* no previous shell command");
+                continue;
+            };
+            input = classify_input(&last, &config.shell_prefixes);
+        } else if !matches!(input, ShellInput::Text(_)) {
+            session.last_escape = Some(line.clone());
+        }
+        match input {
+            ShellInput::Discard(cmd) => {
+                if !shell_cd(config, session, &cmd) {
+                    let out = run_shell(config, session, &cmd);
+                    eprintln!("{out}");
+                }
+                continue;
+            }
+            ShellInput::Send(cmd) => {
+                if shell_cd(config, session, &cmd) {
+                    continue;
+                }
+                let out = run_shell(config, session, &cmd);
+                eprintln!("{out}");
+                let text = if session.shell_dir == config.working_dir {
+                    format!("Shell command: {cmd}\nOutput:\n{out}")
+                } else {
+                    let dir = crate::context::display_path(
+                        &session.shell_dir,
+                        &config.working_dir,
+                    );
+                    format!("Shell command (in {dir}): {cmd}\nOutput:\n{out}")
+                };
+                if !fits(config, session, &text) {
+                    continue;
+                }
+                add_user_message(session, &text);
+                if session.entry.first_prompt == "No prompt" {
+                    session.entry.first_prompt = truncate(&cmd, 100);
+                }
+                return Ok(InputResult::Ready);
+            }
+            ShellInput::Repeat => continue,
+            ShellInput::Text(mut text) => {
+                let mut turn_model = None;
+                if let Some((model, rest)) = model_override(config, &text) {
+                    turn_model = Some(model);
+                    text = rest;
+                }
+                let typed = text.clone();
+                let text = expand_vars(session, &text);
+                let Some(text) = guard_paste(config, text) else {
+                    continue;
+                };
+                let (text, attached) = crate::attach::expand_dirs(
+                    &text,
+                    &config.working_dir,
+                    &config.policy,
+                    config.attach_budget,
+                );
+                for a in &attached {
+                    eprintln!(
+                        "* attached {}: {} files, ~{} tokens{}",
+                        a.dir,
+                        a.included,
+                        a.tokens,
+                        if a.omitted > 0 {
+                            format!(" ({} left out)", a.omitted)
+                        } else {
+                            String::new()
+                        }
+                    );
+                    if a.capped {
+                        eprintln!(
+                            "* listing of {} stopped, the tree is big",
+                            a.dir
+                        );
+                    }
+                }
+                let text = if config.attach_urls {
+                    attach_urls(config, &text, &typed)
+                } else {
+                    text
+                };
+                // Sized for the turn's model, which stays only
+                // if the message is sent
+                let prev = turn_model.map(|model| {
+                    let info = config.models.get(&model).cloned();
+                    (
+                        std::mem::replace(&mut config.model, model),
+                        std::mem::replace(&mut config.model_info, info),
+                    )
+                });
+                if !fits(config, session, &text) {
+                    if let Some((model, info)) = prev {
+                        config.model = model;
+                        config.model_info = info;
+                    }
+                    continue;
+                }
+                if prev.is_some() {
+                    session.default_model = prev;
+                    eprintln!("* this turn: {}", config.model);
+                    crate::events::emit(
+                        "model_override",
+                        serde_json::json!({"model": config.model}),
+                    );
+                }
+                if session.entry.first_prompt == "No prompt" {
+                    session.entry.first_prompt = truncate(&text, 100);
+                }
+                add_user_message(session, &text);
+                return Ok(InputResult::Ready);
+            }
+        }
+    }
+}
+
+/// `text`, or what the user chose instead when it is longer
+/// than `paste_warn_chars`: an attached block, the first
+/// `paste_warn_chars` characters, or nothing.
+fn guard_paste(config: &Config, text: String) -> Option<String> {
+    let limit = config.paste_warn_chars;
+    let chars = text.chars().count();
+    if limit == 0 || chars <= limit {
+        return Some(text);
+    }
+    let scale = config.model_info.as_ref().and_then(|m| m.token_scale);
+    let tokens = crate::tokens::estimate(&text, scale.unwrap_or(1.0));
+    let price = config
+        .model_info
+        .as_ref()
+        .map_or(cost::DEFAULT_PRICES.0, |m| m.input_cost_per_m);
+    eprintln!(
+        "* this message is {chars} characters, ~{tokens} tokens \
+         (~${:.2} each time it is sent)",
+        tokens as f64 * price / 1_000_000.0
+    );
+    let answer =
+        readline::ask("[s]end, [a]ttach as a block, [t]runcate, [C]ancel? ");
+    match answer.as_str() {
+        "s" | "send" => Some(text),
+        "a" | "attach" => Some(crate::attach::paste_block(&text)),
+        "t" | "truncate" => {
+            let end = text
+                .char_indices()
+                .nth(limit)
+                .map_or(text.len(), |(i, _)| i);
+            eprintln!("* sending the first {limit} characters");
+            Some(text[..end].to_string())
+        }
+        _ => {
+            eprintln!("* not sent");
+            None
+        }
+    }
+}
+
+/// `text` with the pages `typed` links to attached, saying
+/// which could not be fetched.
+fn attach_urls(config: &Config, text: &str, typed: &str) -> String {
+    let (text, fetched) =
+        crate::attach::expand_urls(text, typed, config.attach_budget, &|url| {
+            eprintln!("* fetching {url}");
+            crate::web::fetch_text(url)
+        });
+    for (url, result) in fetched {
+        match result {
+            Ok(tokens) => eprintln!("* attached {url}: ~{tokens} tokens"),
+            Err(e) => eprintln!("* {url}: {e}, sent as a link only"),
+        }
+    }
+    text
+}
+
+/// The model and the rest of a message starting `@name:`,
+/// if `name` is a model alias or a configured model.
+fn model_override(config: &Config, text: &str) -> Option<(String, String)> {
+    let (name, rest) = text.strip_prefix('@')?.split_once(':')?;
+    let rest = rest.trim();
+    if name.is_empty() || name.contains(char::is_whitespace) || rest.is_empty()
+    {
+        return None;
+    }
+    let model = config.resolve_model(name)?;
+    Some((model.to_string(), rest.to_string()))
+}
+
+/// Whether `text` fits the context window; says why not.
+fn fits(config: &mut Config, session: &Session, text: &str) -> bool {
+    match session.check_fits(config, text) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("* {e}");
+            false
+        }
+    }
+}
+
+pub(crate) fn add_user_message(session: &mut Session, text: &str) {
+    let checkpoint = session.files.checkpoints().map_or(0, |c| c.last_seq());
+    session.push_user_text(text);
+    session.turns.push(Turn {
+        message_index: session.messages.len() - 1,
+        checkpoint,
+    });
+}
+
+/// Most sessions listed by the /resume picker.
+const RESUME_LIST_MAX: usize = 20;
+
+/// Ask which earlier session of this directory to resume,
+/// most recent first.
+fn pick_session(
+    config: &Config,
+    session: &Session,
+    editor: &Editor,
+) -> Option<session::SessionEntry> {
+    let mut entries = session::recent_entries(&config.session_dir);
+    entries.retain(|e| e.session_id != session.entry.session_id);
+    if entries.is_empty() {
+        eprintln!("* no sessions found for this directory");
+        return None;
+    }
+    entries.truncate(RESUME_LIST_MAX);
+
+    let now = session::parse_iso(&session::iso_now()).unwrap_or(0);
+    let rows: Vec<String> = entries
+        .iter()
+        .map(|e| {
+            let prompt = e.first_prompt.lines().next().unwrap_or("");
+            let title = if e.summary.is_empty() {
+                truncate_line(prompt, 60)
+            } else {
+                format!("{}: {}", e.summary, truncate_line(prompt, 40))
+            };
+            let age = session::parse_iso(&e.modified)
+                .map(|t| format_age(now - t))
+                .unwrap_or_default();
+            let count = format!("({} msgs, {age})", e.message_count);
+            format!("{title}  {}", paint(Style::Dim, &count))
+        })
+        .collect();
+    match editor.select(&rows) {
+        Ok(Some(i)) => Some(entries.swap_remove(i)),
+        Ok(None) => None,
+        Err(e) => {
+            eprintln!("* {e}");
+            None
+        }
+    }
+}
+
+/// `/sessions [list]` and `/sessions rm <n|id>`. The current
+/// session cannot be removed.
+fn sessions_command(config: &Config, session: &Session, arg: &str) {
+    let (sub, rest) = arg.split_once(' ').unwrap_or((arg, ""));
+    match sub {
+        "" | "list" => {
+            let mut entries = session::recent_entries(&config.session_dir);
+            entries.retain(|e| e.session_id != session.entry.session_id);
+            if entries.is_empty() {
+                eprintln!("* no other sessions for this directory");
+            }
+            let now = session::parse_iso(&session::iso_now()).unwrap_or(0);
+            for (i, e) in entries.iter().enumerate() {
+                let age = session::parse_iso(&e.modified)
+                    .map(|t| format_age(now - t))
+                    .unwrap_or_default();
+                let title = if e.summary.is_empty() {
+                    truncate_line(
+                        e.first_prompt.lines().next().unwrap_or(""),
+                        60,
+                    )
+                } else {
+                    e.summary.clone()
+                };
+                let count = format!("({} msgs, {age})", e.message_count);
+                eprintln!(
+                    "  {:>3}  {}  {title}  {}",
+                    i + 1,
+                    &e.session_id[..e.session_id.len().min(8)],
+                    paint(Style::Dim, &count)
+                );
+            }
+        }
+        "rm" if !rest.trim().is_empty() => {
+            let Some(entry) = find_session(config, session, rest.trim()) else {
+                return;
+            };
+            let question = tr!(
+                "delete session {id} ({n} msgs)? [y/N] ",
+                id = entry.session_id,
+                n = entry.message_count,
+            );
+            if !readline::confirm(&question) {
+                eprintln!("* kept");
+                return;
+            }
+            match session::delete(&config.session_dir, &entry) {
+                Ok(()) => eprintln!("* deleted session {}", entry.session_id),
+                Err(e) => {
+                    eprintln!("* cannot delete {}: {e}", entry.session_id)
+                }
+            }
+        }
+        _ => eprintln!("* usage: /sessions [list|rm <n|id>]"),
+    }
+}
+
+/// `/search <query>` over this project's sessions, including
+/// the current one.
+fn search_sessions(config: &Config, query: &str) {
+    if query.is_empty() {
+        eprintln!("* usage: /search <query>");
+        return;
+    }
+    let hits = session::search(&config.session_dir, query);
+    if hits.is_empty() {
+        eprintln!("* no sessions mention {query}");
+        return;
+    }
+    let now = session::parse_iso(&session::iso_now()).unwrap_or(0);
+    for hit in &hits {
+        for line in session::hit_lines(hit, now) {
+            eprintln!("  {line}");
+        }
+    }
+    eprintln!("* {} sessions; /resume <id> to open one", hits.len());
+}
+
+/// Earlier session named by `arg`: its number in the
+/// /resume list or a prefix of its id.
+fn find_session(
+    config: &Config,
+    session: &Session,
+    arg: &str,
+) -> Option<session::SessionEntry> {
+    let mut entries = session::recent_entries(&config.session_dir);
+    entries.retain(|e| e.session_id != session.entry.session_id);
+    if let Ok(n) = arg.parse::<usize>()
+        && n >= 1
+        && n <= entries.len()
+    {
+        return Some(entries.swap_remove(n - 1));
+    }
+    let mut matches: Vec<session::SessionEntry> = entries
+        .into_iter()
+        .filter(|e| e.session_id.starts_with(arg))
+        .collect();
+    match matches.len() {
+        0 => {
+            eprintln!("* no session matches {arg}");
+            None
+        }
+        1 => matches.pop(),
+        n => {
+            eprintln!("* {n} sessions match {arg}:");
+            for e in &matches {
+                eprintln!("  {}  {}", e.session_id, e.first_prompt);
+            }
+            None
+        }
+    }
+}
+
+/// Switch to `entry`, first recording the current session in
+/// the index so it can be resumed later.
+pub(crate) fn resume(
+    config: &Config,
+    session: &mut Session,
+    entry: session::SessionEntry,
+) {
+    let path = session::session_path(&entry);
+    let (msgs, notes) = match super::agent::load_transcript(&path) {
+        Ok((msgs, notes)) if !msgs.is_empty() => (msgs, notes),
+        _ => {
+            eprintln!("* cannot load session {}", entry.session_id);
+            return;
+        }
+    };
+    if !session.messages.is_empty() {
+        session.entry.message_count = session.messages.len() as u32;
+        session.entry.modified = session::iso_now();
+        session::update_entry(&config.session_dir, &session.entry);
+        eprintln!("* saved session {}", session.entry.session_id);
+    }
+
+    session.token_pct = super::agent::load_token_pct(&path);
+    session.entry = entry;
+    session.file = path;
+    session.messages = msgs;
+    session.notes = notes;
+    session.plan = Plan::load(&session.file).map(|mut plan| {
+        // Whatever was running, the turn that ran it is over
+        plan.running = None;
+        plan.paused = true;
+        plan
+    });
+    session.turns.clear();
+    session.name_tried = false;
+    session.last_input_tokens = 0;
+    session.total_input_tokens = 0;
+    session.total_output_tokens = 0;
+    // Spend survives the process in the ledger
+    session.cost =
+        cost::session_totals(&config.dirs.data, &session.entry.session_id).cost;
+    session.files =
+        super::agent::file_tracker(config, &session.entry.session_id);
+    crate::events::open(&session.file);
+    crate::events::emit(
+        "resume",
+        serde_json::json!({"messages": session.messages.len()}),
+    );
+    eprintln!(
+        "session: {} (resumed, {} msgs)",
+        session.file.display(),
+        session.messages.len(),
+    );
 }
@@ -2,8 +2,12 @@ use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use serde::Deserialize;
+
 pub struct SystemPrompt {
     pub prompt: String,
+    /// The SYSTEM.md (or default) part `prompt` starts with.
+    pub base: String,
     pub context_files: Vec<PathBuf>,
 }
 
@@ -14,11 +18,11 @@ pub struct SystemPrompt {
 /// 2. APPEND_SYSTEM.md files
 /// 3. Working directory line
 /// 4. Context files (AGENTS.md/CLAUDE.md)
-pub fn load_system_prompt(working_dir: &Path) -> SystemPrompt {
-    let home = env::var("HOME")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| PathBuf::from("/tmp"));
-    let home_dir = home.join(".tapir").join("agent");
+pub fn load_system_prompt(
+    config_dir: &Path,
+    working_dir: &Path,
+) -> SystemPrompt {
+    let home_dir = config_dir.join("agent");
     load_system_prompt_with_home(&home_dir, working_dir)
 }
 
@@ -39,6 +43,7 @@ fn load_system_prompt_with_home(
 
     SystemPrompt {
         prompt,
+        base: read_base_prompt(home_dir, working_dir),
         context_files,
     }
 }
@@ -48,7 +53,7 @@ You are a coding assistant. You help users with software \
 engineering tasks including solving bugs, adding features, \
 refactoring code, and explaining code.\n\n\
 # Tools\n\n\
-You have seven tools:\n\
+You have nine tools:\n\
 - read_file: Read file contents with line numbers. \
 Supports offset (1-indexed) and limit parameters for \
 reading specific sections of large files.\n\
@@ -57,9 +62,14 @@ reading specific sections of large files.\n\
 fuzzy matching for whitespace and unicode variations \
 (smart quotes, dashes) when exact match fails.\n\
 - bash: Run a shell command\n\
-- ls: List directory contents\n\
+- ls: List directory contents (hides gitignored entries \
+unless asked)\n\
 - find: Find files by glob pattern (uses fd)\n\
-- grep: Search file contents by regex (uses ripgrep)\n\n\
+- grep: Search file contents by regex (uses ripgrep)\n\
+- stat: Size, line count, mtime and language of a file, \
+or totals for a directory or glob\n\
+- delete_file: Delete a file or directory (moved to a \
+trash the user can restore from)\n\n\
 All file paths are sandboxed to the working directory. \
 Paths outside it will be rejected.\n\n\
 # Guidelines\n\n\
@@ -115,11 +125,7 @@ user navigate.\n\
 /// Then append APPEND_SYSTEM.md from both locations.
 fn load_base_prompt(home_dir: &Path, working_dir: &Path) -> String {
     let project_tapir = working_dir.join(".tapir");
-
-    // Base: project > global > default
-    let mut prompt = read_optional_file(&project_tapir.join("SYSTEM.md"))
-        .or_else(|| read_optional_file(&home_dir.join("SYSTEM.md")))
-        .unwrap_or_else(|| DEFAULT_PROMPT.to_string());
+    let mut prompt = read_base_prompt(home_dir, working_dir);
 
     // Append files
     for path in [
@@ -135,6 +141,13 @@ fn load_base_prompt(home_dir: &Path, working_dir: &Path) -> String {
     prompt
 }
 
+/// SYSTEM.md content: project > global > default.
+fn read_base_prompt(home_dir: &Path, working_dir: &Path) -> String {
+    read_optional_file(&working_dir.join(".tapir/SYSTEM.md"))
+        .or_else(|| read_optional_file(&home_dir.join("SYSTEM.md")))
+        .unwrap_or_else(|| DEFAULT_PROMPT.to_string())
+}
+
 /// Discover and concatenate AGENTS.md/CLAUDE.md files.
 ///
 /// Search order (root-first):
@@ -147,37 +160,26 @@ fn find_context_files(
     home_dir: &Path,
     working_dir: &Path,
 ) -> (String, Vec<PathBuf>) {
-    let mut parts = Vec::new();
-    let mut paths = Vec::new();
-
-    // Global (AGENTS.md preferred, CLAUDE.md fallback)
-    if let Some((s, path)) = read_context_in(home_dir) {
-        parts.push(s);
-        paths.push(path);
-    }
-
-    // Walk ancestors root-first (skip working_dir itself
-    // and home_dir which is handled above as global)
+    // Global (AGENTS.md preferred, CLAUDE.md fallback), then
+    // ancestors root-first, skipping home_dir which is handled
+    // as global, then the working dir
+    let mut dirs = vec![home_dir];
     let ancestors: Vec<&Path> = working_dir.ancestors().skip(1).collect();
-    for dir in ancestors.into_iter().rev() {
-        if dir == home_dir {
-            continue;
-        }
-        if let Some((s, path)) = read_context_in(dir) {
-            parts.push(s);
-            paths.push(path);
-        }
-    }
-
-    // Working dir (skip if same as home_dir, already handled)
-    if working_dir == home_dir {
-        return (parts.join("\n\n"), paths);
-    }
-    if let Some((s, path)) = read_context_in(working_dir) {
-        parts.push(s);
-        paths.push(path);
+    dirs.extend(ancestors.into_iter().rev().filter(|d| *d != home_dir));
+    if working_dir != home_dir {
+        dirs.push(working_dir);
     }
 
+    // Each lookup is a round trip on network filesystems
+    let found: Vec<Option<(String, PathBuf)>> = std::thread::scope(|s| {
+        let handles: Vec<_> = dirs
+            .iter()
+            .map(|dir| s.spawn(move || read_context_in(dir)))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    let (parts, paths): (Vec<String>, Vec<PathBuf>) =
+        found.into_iter().flatten().unzip();
     (parts.join("\n\n"), paths)
 }
 
@@ -194,6 +196,153 @@ fn read_context_in(dir: &Path) -> Option<(String, PathBuf)> {
     None
 }
 
+/// Git state of the working directory for the environment
+/// block.
+pub(crate) struct GitInfo {
+    branch: String,
+    changed: usize,
+    commits: Vec<String>,
+}
+
+const RECENT_COMMITS: usize = 5;
+
+/// Build the `<env>` block (platform, date, git branch,
+/// status, recent commits) appended to the system prompt.
+/// Rebuilt at the start of each session.
+pub fn environment_block(working_dir: &Path) -> String {
+    let platform =
+        format!("{} {}", std::env::consts::OS, std::env::consts::ARCH);
+    let date = crate::session::iso_now()[..10].to_string();
+    format_environment(&platform, &date, git_info(working_dir).as_ref())
+}
+
+pub(crate) fn format_environment(
+    platform: &str,
+    date: &str,
+    git: Option<&GitInfo>,
+) -> String {
+    let mut out = format!("<env>\nPlatform: {platform}\nDate: {date}\n");
+    match git {
+        None => out.push_str("Git: not a repository\n"),
+        Some(g) => {
+            out.push_str(&format!("Git branch: {}\n", g.branch));
+            if g.changed == 0 {
+                out.push_str("Git status: clean\n");
+            } else {
+                out.push_str(&format!(
+                    "Git status: {} changed file(s)\n",
+                    g.changed
+                ));
+            }
+            if !g.commits.is_empty() {
+                out.push_str("Recent commits:\n");
+                for c in &g.commits {
+                    out.push_str(&format!("  {c}\n"));
+                }
+            }
+        }
+    }
+    out.push_str("</env>");
+    out
+}
+
+fn git_info(working_dir: &Path) -> Option<GitInfo> {
+    let branch =
+        git_output(working_dir, &["rev-parse", "--abbrev-ref", "HEAD"])?;
+    let status = git_output(working_dir, &["status", "--porcelain"])?;
+    let log = git_output(
+        working_dir,
+        &["log", "--oneline", &format!("-{RECENT_COMMITS}")],
+    )
+    .unwrap_or_default();
+    Some(GitInfo {
+        branch: branch.trim().to_string(),
+        changed: status.lines().filter(|l| !l.is_empty()).count(),
+        commits: log.lines().map(str::to_string).collect(),
+    })
+}
+
+/// Run `git <args>` in `dir`, returning stdout on success.
+fn git_output(dir: &Path, args: &[&str]) -> Option<String> {
+    let out = std::process::Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&out.stdout).to_string())
+}
+
+/// How long answers should be, from `verbosity` in config.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verbosity {
+    Terse,
+    #[default]
+    Normal,
+    Detailed,
+}
+
+impl Verbosity {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "terse" => Some(Verbosity::Terse),
+            "normal" => Some(Verbosity::Normal),
+            "detailed" => Some(Verbosity::Detailed),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Verbosity::Terse => "terse",
+            Verbosity::Normal => "normal",
+            Verbosity::Detailed => "detailed",
+        }
+    }
+}
+
+/// Response style, set by `response_language` and
+/// `verbosity` in config and changed with `/style`.
+#[derive(Clone, Default)]
+pub struct Style {
+    pub language: Option<String>,
+    pub verbosity: Verbosity,
+}
+
+/// The `<style>` block appended to the system prompt, empty
+/// when nothing differs from the default.
+pub fn style_block(style: &Style) -> String {
+    let mut lines = Vec::new();
+    if let Some(lang) = &style.language {
+        lines.push(format!(
+            "Language: reply in {lang}; keep code, identifiers and \
+             commit messages as the project has them"
+        ));
+    }
+    let verbosity = match style.verbosity {
+        Verbosity::Terse => Some(
+            "terse: answer in as few words as possible, skip \
+             explanations unless asked, never restate the question",
+        ),
+        Verbosity::Normal => None,
+        Verbosity::Detailed => Some(
+            "detailed: explain your reasoning, the alternatives you \
+             considered and the trade-offs",
+        ),
+    };
+    if let Some(v) = verbosity {
+        lines.push(format!("Verbosity: {v}"));
+    }
+    if lines.is_empty() {
+        return String::new();
+    }
+    format!("<style>\n{}\n</style>", lines.join("\n"))
+}
+
 /// Format a path for display: `~` for home, `./` for working dir.
 pub fn display_path(path: &Path, working_dir: &Path) -> String {
     if let Ok(rel) = path.strip_prefix(working_dir) {
@@ -394,6 +543,7 @@ mod tests {
 
         // Base prompt replaced
         assert!(sp.prompt.starts_with("custom base"));
+        assert_eq!(sp.base, "custom base");
         // Append applied
         assert!(sp.prompt.contains("extra"));
         // Context files present, in order
@@ -409,6 +559,61 @@ mod tests {
         fs::remove_dir_all(&home).unwrap();
     }
 
+    #[test]
+    fn format_environment_with_git() {
+        let git = GitInfo {
+            branch: "main".into(),
+            changed: 2,
+            commits: vec!["abc123 Fix parser".into()],
+        };
+        let env = format_environment("linux x86_64", "2026-01-02", Some(&git));
+        assert!(env.starts_with("<env>\n"));
+        assert!(env.ends_with("</env>"));
+        assert!(env.contains("Platform: linux x86_64"));
+        assert!(env.contains("Date: 2026-01-02"));
+        assert!(env.contains("Git branch: main"));
+        assert!(env.contains("Git status: 2 changed file(s)"));
+        assert!(env.contains("  abc123 Fix parser"));
+    }
+
+    #[test]
+    fn format_environment_clean_and_no_repo() {
+        let git = GitInfo {
+            branch: "dev".into(),
+            changed: 0,
+            commits: Vec::new(),
+        };
+        let env = format_environment("p", "d", Some(&git));
+        assert!(env.contains("Git status: clean"));
+        assert!(!env.contains("Recent commits"));
+        let env = format_environment("p", "d", None);
+        assert!(env.contains("Git: not a repository"));
+    }
+
+    #[test]
+    fn environment_block_outside_repo() {
+        let dir = tempdir("ctx_env");
+        let env = environment_block(&dir);
+        assert!(env.contains("Platform:"));
+        assert!(env.contains("Date: "));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn style_block_only_lists_changes() {
+        assert_eq!(style_block(&Style::default()), "");
+        let style = Style {
+            language: Some("Portuguese".into()),
+            verbosity: Verbosity::Terse,
+        };
+        let block = style_block(&style);
+        assert!(block.starts_with("<style>\nLanguage: reply in Portuguese"));
+        assert!(block.contains("\nVerbosity: terse: "));
+        assert!(block.ends_with("\n</style>"));
+        assert_eq!(Verbosity::parse("detailed"), Some(Verbosity::Detailed));
+        assert_eq!(Verbosity::parse("loud"), None);
+    }
+
     fn tempdir(name: &str) -> std::path::PathBuf {
         let d = std::env::temp_dir().join(format!("tapir_{name}"));
         let _ = fs::remove_dir_all(&d);
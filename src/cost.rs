@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::ModelInfo;
+use crate::types::Usage;
+
+/// Prices assumed for models without `_models` info, in
+/// dollars per million input and output tokens.
+pub(crate) const DEFAULT_PRICES: (f64, f64) = (3.0, 15.0);
+/// Cache reads and writes, relative to the input price.
+const CACHE_READ_FACTOR: f64 = 0.1;
+const CACHE_WRITE_FACTOR: f64 = 1.25;
+
+/// Spending limits in dollars, from `max_cost_per_session`
+/// and `max_cost_per_day` in config.
+#[derive(Clone, Default)]
+pub struct Budget {
+    pub per_session: Option<f64>,
+    pub per_day: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Limit {
+    Session,
+    Day,
+}
+
+impl Budget {
+    pub fn is_set(&self) -> bool {
+        self.per_session.is_some() || self.per_day.is_some()
+    }
+
+    /// The first limit `session` or `today` spend has reached,
+    /// each raised by what was already approved past it.
+    pub fn exceeded(
+        &self,
+        session: f64,
+        today: f64,
+        approved: &Approved,
+    ) -> Option<(Limit, f64)> {
+        if let Some(max) = self.per_session
+            && session >= max + approved.session
+        {
+            return Some((Limit::Session, max));
+        }
+        if let Some(max) = self.per_day
+            && today >= max + approved.day
+        {
+            return Some((Limit::Day, max));
+        }
+        None
+    }
+}
+
+/// Spend the user allowed past each limit so far.
+#[derive(Default)]
+pub struct Approved {
+    pub session: f64,
+    pub day: f64,
+}
+
+impl Approved {
+    /// Allow another `max` past `limit`.
+    pub fn raise(&mut self, limit: Limit, max: f64) {
+        match limit {
+            Limit::Session => self.session += max,
+            Limit::Day => self.day += max,
+        }
+    }
+}
+
+/// One API response, as appended to the ledger.
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    /// Seconds since the epoch.
+    pub time: i64,
+    pub session_id: String,
+    pub project: PathBuf,
+    pub model: String,
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    #[serde(default)]
+    pub cache_read_tokens: u32,
+    #[serde(default)]
+    pub cache_write_tokens: u32,
+    pub cost: f64,
+}
+
+/// Spend across some ledger entries.
+#[derive(Default)]
+pub struct Totals {
+    pub cost: f64,
+    pub requests: u32,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_read_tokens: u64,
+    pub cache_write_tokens: u64,
+    pub by_model: BTreeMap<String, f64>,
+}
+
+impl Totals {
+    fn add(&mut self, entry: &Entry) {
+        self.cost += entry.cost;
+        self.requests += 1;
+        self.input_tokens += entry.input_tokens as u64;
+        self.output_tokens += entry.output_tokens as u64;
+        self.cache_read_tokens += entry.cache_read_tokens as u64;
+        self.cache_write_tokens += entry.cache_write_tokens as u64;
+        *self.by_model.entry(entry.model.clone()).or_default() += entry.cost;
+    }
+}
+
+pub fn ledger_path(tapir_dir: &Path) -> PathBuf {
+    tapir_dir.join("costs.jsonl")
+}
+
+/// Dollar cost of one response. `input_tokens` excludes
+/// cached tokens, which are billed at a discount (reads) or a
+/// premium (writes).
+pub fn cost(info: Option<&ModelInfo>, usage: &Usage) -> f64 {
+    let (input, output) = info.map_or(DEFAULT_PRICES, |m| {
+        (m.input_cost_per_m, m.output_cost_per_m)
+    });
+    let tokens = usage.input_tokens as f64
+        + usage.cache_read_input_tokens as f64 * CACHE_READ_FACTOR
+        + usage.cache_creation_input_tokens as f64 * CACHE_WRITE_FACTOR;
+    (tokens * input + usage.output_tokens as f64 * output) / 1_000_000.0
+}
+
+/// Append `entry` to the ledger.
+pub fn record(path: &Path, entry: &Entry) {
+    let Ok(json) = serde_json::to_string(entry) else {
+        return;
+    };
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path);
+    if let Ok(mut file) = file {
+        let _ = writeln!(file, "{json}");
+    }
+}
+
+/// Every readable entry of the ledger, oldest first.
+pub fn load(path: &Path) -> Vec<Entry> {
+    let Ok(text) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    text.lines()
+        .filter_map(|l| serde_json::from_str(l).ok())
+        .collect()
+}
+
+/// Totals of the entries `keep` accepts.
+pub fn totals(entries: &[Entry], keep: impl Fn(&Entry) -> bool) -> Totals {
+    let mut totals = Totals::default();
+    for entry in entries.iter().filter(|e| keep(e)) {
+        totals.add(entry);
+    }
+    totals
+}
+
+/// Totals of one session across all its requests.
+pub fn session_totals(tapir_dir: &Path, session_id: &str) -> Totals {
+    totals(&load(&ledger_path(tapir_dir)), |e| {
+        e.session_id == session_id
+    })
+}
+
+/// Spend recorded since `since` (seconds since the epoch).
+pub fn spent_since(path: &Path, since: i64) -> f64 {
+    totals(&load(path), |e| e.time >= since).cost
+}
+
+/// Start of the local day and of the local week (Monday) that
+/// `now` falls in, as seconds since the epoch.
+pub fn day_and_week_start(now: i64) -> (i64, i64) {
+    let t = now as libc::time_t;
+    let tm = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&t, &mut tm);
+        tm
+    };
+    let since_midnight =
+        (tm.tm_hour * 3600 + tm.tm_min * 60 + tm.tm_sec) as i64;
+    let day = now - since_midnight;
+    let days_since_monday = ((tm.tm_wday + 6) % 7) as i64;
+    (day, day - days_since_monday * 86_400)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(time: i64, project: &str, model: &str, cost: f64) -> Entry {
+        Entry {
+            time,
+            session_id: "s".into(),
+            project: project.into(),
+            model: model.into(),
+            input_tokens: 0,
+            output_tokens: 0,
+            cache_read_tokens: 0,
+            cache_write_tokens: 0,
+            cost,
+        }
+    }
+
+    #[test]
+    fn cost_discounts_cache_reads() {
+        let usage = Usage {
+            input_tokens: 1_000_000,
+            output_tokens: 100_000,
+            cache_creation_input_tokens: 0,
+            cache_read_input_tokens: 1_000_000,
+        };
+        // 1M input at $3, 1M cached at $0.30, 100k out at $1.50
+        assert!((cost(None, &usage) - 4.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn ledger_round_trip_and_totals() {
+        let dir = std::env::temp_dir().join("tapir_cost_ledger");
+        let _ = fs::remove_dir_all(&dir);
+        let path = ledger_path(&dir);
+        record(&path, &entry(100, "/a", "sonnet", 1.0));
+        record(&path, &entry(200, "/b", "haiku", 0.25));
+        record(&path, &entry(300, "/a", "haiku", 0.5));
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"not json\n")
+            .unwrap();
+
+        let entries = load(&path);
+        assert_eq!(entries.len(), 3);
+        let a = totals(&entries, |e| e.project == Path::new("/a"));
+        assert_eq!((a.cost, a.requests), (1.5, 2));
+        let recent = totals(&entries, |e| e.time >= 200);
+        assert_eq!(recent.by_model["haiku"], 0.75);
+        assert!(!recent.by_model.contains_key("sonnet"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn budget_pauses_again_after_approval() {
+        let budget = Budget {
+            per_session: Some(1.0),
+            per_day: Some(5.0),
+        };
+        let mut approved = Approved::default();
+        assert_eq!(budget.exceeded(0.5, 0.5, &approved), None);
+        assert_eq!(
+            budget.exceeded(1.2, 1.2, &approved),
+            Some((Limit::Session, 1.0))
+        );
+        approved.raise(Limit::Session, 1.0);
+        assert_eq!(budget.exceeded(1.2, 1.2, &approved), None);
+        assert_eq!(
+            budget.exceeded(2.0, 6.0, &approved),
+            Some((Limit::Session, 1.0))
+        );
+        approved.raise(Limit::Session, 1.0);
+        assert_eq!(
+            budget.exceeded(2.0, 6.0, &approved),
+            Some((Limit::Day, 5.0))
+        );
+        approved.raise(Limit::Day, 5.0);
+        assert_eq!(budget.exceeded(2.0, 6.0, &approved), None);
+        assert_eq!(Budget::default().exceeded(99.0, 99.0, &approved), None);
+    }
+
+    #[test]
+    fn week_starts_on_monday() {
+        let now = 1_760_000_000;
+        let (day, week) = day_and_week_start(now);
+        assert!(day <= now && now - day < 86_400);
+        assert!(week <= day && day - week < 7 * 86_400);
+    }
+}
@@ -1,5 +1,11 @@
 use std::io::{self, Write};
 
+use std::sync::Arc;
+
+use crate::highlight;
+use crate::theme::{self, Style, Theme};
+use crate::util::truncate_line;
+
 const COLLAPSED_LINES: usize = 3;
 const INDENT: &str = "    ";
 
@@ -13,28 +19,64 @@ pub(crate) struct ToolOutput {
 impl ToolOutput {
     fn print(&self) {
         let mut stderr = io::stderr();
-        let _ = writeln!(stderr, "{INDENT}\x1b[2m⎿\x1b[0m");
-
-        let lines: Vec<&str> = self.output.lines().collect();
-        if lines.is_empty() {
-            return;
+        let _ = writeln!(stderr, "{INDENT}{}", theme::paint(Style::Dim, "⎿"));
+        // Cut to the window rather than let the terminal fold
+        // lines out from under the indent
+        let max = crate::readline::terminal_width()
+            .map_or(usize::MAX, |w| w.saturating_sub(INDENT.len() + 4));
+        for line in self.lines(&theme::current(), max) {
+            let _ = writeln!(stderr, "{INDENT} {line}");
         }
+    }
 
-        if self.expanded || lines.len() <= COLLAPSED_LINES {
-            for line in &lines {
-                let _ = writeln!(stderr, "{INDENT} {line}");
-            }
-        } else {
-            for line in &lines[..COLLAPSED_LINES] {
-                let _ = writeln!(stderr, "{INDENT} {line}");
-            }
-            let remaining = lines.len() - COLLAPSED_LINES;
-            let _ = writeln!(
-                stderr,
-                "{INDENT} \x1b[2m\u{2026} +{remaining} lines \
-                 (ctrl+o to expand)\x1b[0m"
-            );
+    /// The output as shown, each line cut to `max` columns.
+    /// A diff loses its `---`/`+++` lines, which the header
+    /// already names, and is colored from the first hunk on.
+    fn lines(&self, theme: &Theme, max: usize) -> Vec<String> {
+        let mut lines: Vec<&str> = self.output.lines().collect();
+        let diff = lines
+            .windows(2)
+            .position(|w| w[0].starts_with("--- ") && w[1].starts_with("+++ "));
+        if let Some(at) = diff {
+            lines.drain(at..at + 2);
         }
+        let total = lines.len();
+        if !self.expanded && total > COLLAPSED_LINES {
+            lines.truncate(COLLAPSED_LINES);
+        }
+        let mut shown: Vec<String> = lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let line = truncate_line(line, max);
+                match diff.filter(|&at| i >= at).and(diff_style(&line)) {
+                    Some(style) => theme.paint(style, &line),
+                    None => line,
+                }
+            })
+            .collect();
+        if shown.len() < total {
+            let remaining = total - shown.len();
+            let more =
+                format!("\u{2026} +{remaining} lines (ctrl+o to expand)");
+            shown.push(theme.paint(Style::Dim, &more));
+        }
+        shown
+    }
+}
+
+/// How a line of a unified diff is colored.
+pub(crate) fn diff_style(line: &str) -> Option<Style> {
+    if line.starts_with("+++") || line.starts_with("---") {
+        Some(Style::Bold)
+    } else if line.starts_with('+') {
+        Some(Style::Added)
+    } else if line.starts_with('-') {
+        Some(Style::Removed)
+    } else if line.starts_with("@@") {
+        Some(Style::Hunk)
+    } else {
+        None
     }
 }
 
@@ -79,3 +121,523 @@ impl ToolOutputLog {
         self.entries.clear();
     }
 }
+
+const RULE_WIDTH: usize = 40;
+
+/// How the start of a line renders, once enough of it has
+/// arrived to tell.
+#[derive(Debug, PartialEq)]
+enum Block {
+    Text,
+    /// `#` to `######` and a space.
+    Heading(usize),
+    /// `-`, `*` or `+` and a space.
+    Bullet,
+    /// `---`, `***` or `___` alone on the line.
+    Rule,
+    /// ```` ``` ```` or `~~~`, opening or closing a code block.
+    Fence,
+    /// A line inside a code block.
+    Code,
+    /// `>`, with its space if any.
+    Quote,
+}
+
+/// What the line start `head` renders as and how many bytes
+/// of it are markup, or None while the next character could
+/// still change that. `complete` once the line has ended.
+fn classify(
+    head: &str,
+    in_fence: bool,
+    complete: bool,
+) -> Option<(Block, usize)> {
+    let t = head.trim_start_matches(' ');
+    let marker = |len: usize| head.len() - t.len() + len;
+    let is_fence = t.starts_with("```") || t.starts_with("~~~");
+    let maybe_fence = is_fence || "```".starts_with(t) || "~~~".starts_with(t);
+    if maybe_fence && !complete {
+        return None;
+    }
+    if is_fence {
+        return Some((Block::Fence, 0));
+    }
+    if in_fence {
+        return Some((Block::Code, 0));
+    }
+    match t.chars().next() {
+        Some('#') => {
+            let hashes = t.len() - t.trim_start_matches('#').len();
+            match t[hashes..].chars().next() {
+                Some(' ') if hashes <= 6 => {
+                    Some((Block::Heading(hashes), marker(hashes + 1)))
+                }
+                None if !complete => None,
+                _ => Some((Block::Text, 0)),
+            }
+        }
+        Some(first @ ('-' | '*' | '+' | '_')) => {
+            let same = t.chars().all(|c| c == first);
+            if same && !complete {
+                return None;
+            }
+            if same && first != '+' && t.len() >= 3 {
+                return Some((Block::Rule, 0));
+            }
+            match t[1..].chars().next() {
+                Some(' ') if first != '_' => Some((Block::Bullet, marker(2))),
+                _ => Some((Block::Text, 0)),
+            }
+        }
+        Some('>') => match t[1..].chars().next() {
+            None if !complete => None,
+            Some(' ') => Some((Block::Quote, marker(2))),
+            _ => Some((Block::Quote, marker(1))),
+        },
+        _ => Some((Block::Text, 0)),
+    }
+}
+
+/// Renders the assistant's markdown for the terminal as it
+/// streams in: headings, lists, rules, quotes, code blocks,
+/// `**bold**` and `` `code` ``. Text goes out as soon as its
+/// meaning can't change; only the start of a line and a lone
+/// `*` or `\` wait for what follows, and with `highlight`,
+/// each line of a code block, colored whole. Without a
+/// theme, it only adds the `< ` gutter.
+pub(crate) struct Markdown {
+    theme: Option<Arc<Theme>>,
+    highlight: bool,
+    first_line: bool,
+    /// Something of the current line is written.
+    mid_line: bool,
+    /// Start of the current line, held until `classify`
+    /// decides it.
+    head: Option<String>,
+    in_fence: bool,
+    /// Language on the open fence, and the block's lines so
+    /// far for the highlighter's context.
+    fence_lang: String,
+    fence_code: String,
+    heading: usize,
+    bold: bool,
+    code: bool,
+    code_line: bool,
+    /// `*` or `\` waiting on the next character.
+    pending: Option<char>,
+}
+
+impl Markdown {
+    pub(crate) fn new(theme: Option<Arc<Theme>>, highlight: bool) -> Self {
+        Markdown {
+            theme,
+            highlight,
+            first_line: true,
+            mid_line: false,
+            head: Some(String::new()),
+            in_fence: false,
+            fence_lang: String::new(),
+            fence_code: String::new(),
+            heading: 0,
+            bold: false,
+            code: false,
+            code_line: false,
+            pending: None,
+        }
+    }
+
+    /// Terminal output for the next piece of the reply.
+    pub(crate) fn push(&mut self, text: &str) -> String {
+        let mut out = String::new();
+        for ch in text.chars() {
+            if ch == '\n' {
+                self.end_line(&mut out);
+            } else if self.theme.is_none() {
+                self.start_line(&mut out);
+                out.push(ch);
+            } else if let Some(head) = &mut self.head {
+                head.push(ch);
+                self.decide(false, &mut out);
+            } else {
+                self.inline(ch, &mut out);
+            }
+        }
+        out
+    }
+
+    /// Output for the end of the reply: anything held back,
+    /// and a newline if the last line has none.
+    pub(crate) fn finish(&mut self) -> String {
+        let mut out = String::new();
+        if self.mid_line || self.head.as_ref().is_some_and(|h| !h.is_empty()) {
+            self.end_line(&mut out);
+        }
+        out
+    }
+
+    fn start_line(&mut self, out: &mut String) {
+        if !self.mid_line {
+            out.push_str(if self.first_line { "< " } else { "  " });
+            self.mid_line = true;
+        }
+    }
+
+    fn end_line(&mut self, out: &mut String) {
+        if self.theme.is_some() && self.head.is_some() {
+            self.decide(true, out);
+        }
+        self.start_line(out);
+        out.extend(self.pending.take());
+        if self.heading > 0 || self.bold || self.code || self.code_line {
+            out.push_str(self.theme().reset());
+        }
+        self.heading = 0;
+        self.bold = false;
+        self.code = false;
+        self.code_line = false;
+        out.push('\n');
+        self.first_line = false;
+        self.mid_line = false;
+        self.head = Some(String::new());
+    }
+
+    fn theme(&self) -> &Theme {
+        static PLAIN: std::sync::OnceLock<Theme> = std::sync::OnceLock::new();
+        self.theme
+            .as_deref()
+            .unwrap_or_else(|| PLAIN.get_or_init(Theme::plain))
+    }
+
+    /// Escape sequence for the current styles.
+    fn sgr(&self) -> String {
+        let theme = self.theme();
+        let mut sgr = theme.reset().to_string();
+        match self.heading {
+            0 => {}
+            1 => sgr.push_str(&theme.start(Style::Title)),
+            _ => sgr.push_str(&theme.start(Style::Heading)),
+        }
+        if self.bold {
+            sgr.push_str(&theme.start(Style::Bold));
+        }
+        if self.code || self.code_line {
+            sgr.push_str(&theme.start(Style::Code));
+        }
+        sgr
+    }
+
+    /// Write the held line start once `classify` can tell
+    /// what it is.
+    fn decide(&mut self, complete: bool, out: &mut String) {
+        let Some(head) = &self.head else {
+            return;
+        };
+        if self.in_fence && self.highlight && !complete {
+            return;
+        }
+        let Some((block, skip)) = classify(head, self.in_fence, complete)
+        else {
+            return;
+        };
+        let head = self.head.take().unwrap_or_default();
+        self.start_line(out);
+        let indent = &head[..head.len() - head.trim_start_matches(' ').len()];
+        match block {
+            Block::Fence => {
+                self.in_fence = !self.in_fence;
+                self.fence_lang = head
+                    .trim_start_matches([' ', '`', '~'])
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or_default()
+                    .to_string();
+                self.fence_code.clear();
+                out.push_str(&self.theme().paint(Style::Dim, &head));
+                return;
+            }
+            Block::Code if self.highlight => {
+                let from = self.fence_code.len();
+                self.fence_code.push_str(&head);
+                let lang = match self.fence_lang.as_str() {
+                    "" => highlight::detect(&self.fence_code).unwrap_or(""),
+                    lang => lang,
+                };
+                let theme = self.theme();
+                if highlight::supported(lang) {
+                    out.push_str(&highlight::ansi(
+                        &self.fence_code,
+                        lang,
+                        from,
+                        theme,
+                    ));
+                } else {
+                    out.push_str(&theme.paint(Style::Code, &head));
+                }
+                self.fence_code.push('\n');
+                return;
+            }
+            Block::Rule => {
+                let rule = "─".repeat(RULE_WIDTH);
+                out.push_str(&self.theme().paint(Style::Dim, &rule));
+                return;
+            }
+            Block::Code => {
+                self.code_line = true;
+                out.push_str(&self.sgr());
+            }
+            Block::Heading(level) => {
+                self.heading = level;
+                out.push_str(&self.sgr());
+            }
+            Block::Bullet => out.push_str(&format!("{indent}• ")),
+            Block::Quote => out.push_str(&format!(
+                "{indent}{} ",
+                self.theme().paint(Style::Dim, "│")
+            )),
+            Block::Text => {}
+        }
+        for ch in head[skip..].chars() {
+            self.inline(ch, out);
+        }
+    }
+
+    fn inline(&mut self, ch: char, out: &mut String) {
+        if self.code_line {
+            out.push(ch);
+            return;
+        }
+        match self.pending.take() {
+            Some('\\') if ch.is_ascii_punctuation() => {
+                out.push(ch);
+                return;
+            }
+            Some('*') if ch == '*' => {
+                self.bold = !self.bold;
+                out.push_str(&self.sgr());
+                return;
+            }
+            pending => out.extend(pending),
+        }
+        match ch {
+            '`' => {
+                self.code = !self.code;
+                out.push_str(&self.sgr());
+            }
+            _ if self.code => out.push(ch),
+            '*' | '\\' => self.pending = Some(ch),
+            _ => out.push(ch),
+        }
+    }
+}
+
+/// Columns the reply's `< ` or `  ` takes; wrapped lines
+/// are indented to match.
+const REPLY_INDENT: &str = "  ";
+
+/// Word-wraps terminal output as it streams, ahead of the
+/// terminal's own wrapping, which breaks mid-word and loses
+/// the reply's indent. Escape sequences take no room. A word
+/// is held until the space or newline after it shows whether
+/// it fits; one longer than a line is broken.
+pub(crate) struct Wrap {
+    /// Queried per word, so a resize applies mid-reply.
+    width: fn() -> Option<usize>,
+    col: usize,
+    spaces: usize,
+    word: String,
+    word_cols: usize,
+    in_escape: bool,
+}
+
+impl Wrap {
+    pub(crate) fn new(width: fn() -> Option<usize>) -> Self {
+        Wrap {
+            width,
+            col: 0,
+            spaces: 0,
+            word: String::new(),
+            word_cols: 0,
+            in_escape: false,
+        }
+    }
+
+    /// `text` with line breaks added where it would overflow.
+    pub(crate) fn push(&mut self, text: &str) -> String {
+        let mut out = String::new();
+        for ch in text.chars() {
+            if self.in_escape {
+                self.word.push(ch);
+                self.in_escape = !ch.is_ascii_alphabetic();
+                continue;
+            }
+            match ch {
+                '\x1b' => {
+                    self.word.push(ch);
+                    self.in_escape = true;
+                }
+                '\n' => {
+                    self.flush(&mut out);
+                    self.spaces = 0;
+                    self.col = 0;
+                    out.push('\n');
+                }
+                ' ' => {
+                    self.flush(&mut out);
+                    self.spaces += 1;
+                }
+                _ => {
+                    self.word.push(ch);
+                    self.word_cols += 1;
+                }
+            }
+        }
+        out
+    }
+
+    /// The word held back, for the end of the output.
+    pub(crate) fn finish(&mut self) -> String {
+        let mut out = String::new();
+        self.flush(&mut out);
+        out
+    }
+
+    fn flush(&mut self, out: &mut String) {
+        if self.word.is_empty() {
+            return;
+        }
+        let width = (self.width)();
+        let fits = |col: usize| width.is_none_or(|w| col <= w);
+        if self.col > REPLY_INDENT.len()
+            && !fits(self.col + self.spaces + self.word_cols)
+        {
+            self.break_line(out);
+        } else {
+            out.extend(std::iter::repeat_n(' ', self.spaces));
+            self.col += self.spaces;
+        }
+        self.spaces = 0;
+        let mut escape = false;
+        for ch in std::mem::take(&mut self.word).chars() {
+            if escape || ch == '\x1b' {
+                escape = ch == '\x1b' || !ch.is_ascii_alphabetic();
+            } else {
+                if !fits(self.col + 1) {
+                    self.break_line(out);
+                }
+                self.col += 1;
+            }
+            out.push(ch);
+        }
+        self.word_cols = 0;
+    }
+
+    fn break_line(&mut self, out: &mut String) {
+        out.push('\n');
+        out.push_str(REPLY_INDENT);
+        self.col = REPLY_INDENT.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(text: &str) -> String {
+        crate::readline::strip_ansi(text)
+    }
+
+    #[test]
+    fn renders_markdown_however_it_is_split() {
+        let reply = "# Plan\n\nUse **bold** and `a*b`:\n\n- one\n  * two\n\
+                     ---\n> quoted\n```rust\nlet x = 2 * 3;\n```\n1. done \\*";
+        let theme = Some(Arc::new(Theme::dark()));
+        let mut whole = Markdown::new(theme.clone(), false);
+        let rendered = whole.push(reply) + &whole.finish();
+        let mut split = Markdown::new(theme, false);
+        let mut pieces = String::new();
+        for ch in reply.chars() {
+            pieces.push_str(&split.push(&ch.to_string()));
+        }
+        pieces.push_str(&split.finish());
+        assert_eq!(rendered, pieces);
+        assert_eq!(
+            strip(&rendered),
+            format!(
+                "< Plan\n  \n  Use bold and a*b:\n  \n  • one\n    • two\n  \
+                 {}\n  │ quoted\n  ```rust\n  let x = 2 * 3;\n  ```\n  1. done *\n",
+                "─".repeat(RULE_WIDTH)
+            )
+        );
+        assert!(rendered.starts_with("< \x1b[0m\x1b[1;4mPlan"));
+
+        let mut plain = Markdown::new(None, true);
+        let text = plain.push("**hi**\nthere") + &plain.finish();
+        assert_eq!(text, "< **hi**\n  there\n");
+    }
+
+    #[test]
+    fn wraps_words_to_the_width() {
+        let mut wrap = Wrap::new(|| Some(12));
+        let mut out = String::new();
+        for piece in ["< one \x1b[1mtwo\x1b[0m thr", "ee four\n  ", "x\n"] {
+            out.push_str(&wrap.push(piece));
+        }
+        out.push_str(&wrap.finish());
+        assert_eq!(strip(&out), "< one two\n  three four\n  x\n");
+        assert!(out.contains("\x1b[1mtwo\x1b[0m"));
+
+        // Longer than a line: broken; leading spaces kept
+        let mut wrap = Wrap::new(|| Some(8));
+        let out = wrap.push("< abcdefghij\n      k\n") + &wrap.finish();
+        assert_eq!(out, "< abcdef\n  ghij\n      k\n");
+
+        let mut wrap = Wrap::new(|| None);
+        let long = format!("< {}\n", "word ".repeat(40).trim_end());
+        assert_eq!(wrap.push(&long) + &wrap.finish(), long);
+    }
+
+    #[test]
+    fn tool_diffs_are_colored() {
+        let output = ToolOutput {
+            header: "edit: src/a.rs".into(),
+            output: "Edited src/a.rs\n--- src/a.rs\n+++ src/a.rs\n\
+                     @@ -1,2 +1,2 @@\n-old\n+new\n ctx\n"
+                .into(),
+            expanded: false,
+        };
+        let theme = Theme::dark();
+        assert_eq!(
+            output.lines(&theme, 80),
+            [
+                "Edited src/a.rs".to_string(),
+                theme.paint(Style::Hunk, "@@ -1,2 +1,2 @@"),
+                theme.paint(Style::Removed, "-old"),
+                theme.paint(Style::Dim, "\u{2026} +2 lines (ctrl+o to expand)"),
+            ]
+        );
+
+        let plain = ToolOutput {
+            header: "bash: ls".into(),
+            output: "-rw-r--r-- a\n".into(),
+            expanded: true,
+        };
+        assert_eq!(plain.lines(&theme, 5), ["-rw-r..."]);
+    }
+
+    #[test]
+    fn highlights_whole_code_lines() {
+        let reply = "```rust\nlet s = \"/*\";\n// done\n```\n";
+        let theme = Some(Arc::new(Theme::dark()));
+        let mut md = Markdown::new(theme.clone(), true);
+        let mut out = String::new();
+        for ch in reply.chars() {
+            out.push_str(&md.push(&ch.to_string()));
+        }
+        out.push_str(&md.finish());
+        assert!(out.contains("\x1b[35mlet\x1b[0m s = \x1b[32m\"/*\"\x1b[0m;"));
+        assert!(out.contains("\x1b[2m// done\x1b[0m"));
+        assert_eq!(
+            strip(&out),
+            strip(&Markdown::new(theme, false).push(reply))
+        );
+    }
+}
@@ -1,11 +1,18 @@
 use std::fmt;
 use std::io;
+use std::path::PathBuf;
+
+use crate::i18n::tr;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
 pub enum Error {
-    NoApiKey,
+    /// No key in the environment or in `config_file`.
+    NoApiKey {
+        config_file: PathBuf,
+    },
+    Config(String),
     Http(String),
     Api {
         status: u16,
@@ -19,32 +26,148 @@ pub enum Error {
     },
     Io(io::Error),
     Security(String),
+    /// A request estimated to exceed the model's context
+    /// window, refused before sending.
+    ContextOverflow {
+        tokens: u32,
+        window: u32,
+    },
+    /// A spending limit was reached with nobody to approve
+    /// more (headless runs).
+    OverBudget {
+        spent: f64,
+        max: f64,
+        /// "this session" or "today".
+        what: &'static str,
+    },
 }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl Error {
+    /// The message with its text looked up by `t`. English
+    /// goes to the model and the logs; the terminal gets the
+    /// user's language.
+    fn render(&self, t: fn(&'static str) -> &'static str) -> String {
+        let fill = crate::i18n::fill;
         match self {
-            Error::NoApiKey => {
-                write!(f, "ANTHROPIC_API_KEY not set")
+            Error::NoApiKey { .. } => {
+                t("ANTHROPIC_API_KEY not set").to_string()
             }
-            Error::Http(msg) => write!(f, "HTTP error: {msg}"),
+            Error::Config(msg) => fill(t("config: {msg}"), &[("msg", msg)]),
+            Error::Http(msg) => fill(t("HTTP error: {msg}"), &[("msg", msg)]),
             Error::Api {
                 status, message, ..
-            } => {
-                write!(f, "API error ({status}): {message}")
+            } => fill(
+                t("API error ({status}): {message}"),
+                &[("status", status), ("message", message)],
+            ),
+            Error::Json(msg) => fill(t("JSON error: {msg}"), &[("msg", msg)]),
+            Error::Tool { name, message } => fill(
+                t("tool {name}: {message}"),
+                &[("name", name), ("message", message)],
+            ),
+            Error::Io(err) => fill(t("I/O error: {err}"), &[("err", err)]),
+            Error::Security(msg) => fill(t("security: {msg}"), &[("msg", msg)]),
+            Error::ContextOverflow { tokens, window } => fill(
+                t("message not sent: ~{tokens} tokens with the system \
+                   prompt, over the {window}-token context window"),
+                &[("tokens", tokens), ("window", window)],
+            ),
+            Error::OverBudget { spent, max, what } => fill(
+                t("spent ${spent} {what}, over the ${max} limit"),
+                &[
+                    ("spent", &format!("{spent:.2}")),
+                    ("what", &t(what)),
+                    ("max", &format!("{max:.2}")),
+                ],
+            ),
+        }
+    }
+
+    /// The message in the user's language, for the terminal.
+    pub fn localized(&self) -> String {
+        self.render(crate::i18n::t)
+    }
+
+    /// What the user can do about the error, or None when the
+    /// message says it all.
+    pub fn hint(&self) -> Option<String> {
+        let hint = match self {
+            Error::NoApiKey { config_file } => {
+                return Some(tr!(
+                    "export ANTHROPIC_API_KEY, or set api_key in {path}; \
+                     keys are made at \
+                     https://console.anthropic.com/settings/keys",
+                    path = config_file.display()
+                ));
+            }
+            Error::Config(_) => {
+                tr!("fix the file named above; /config lists the settings \
+                 and where each one came from")
+            }
+            Error::Http(_) => {
+                tr!("check the network connection and the api_url setting")
+            }
+            Error::Api { status, .. } => match status {
+                401 => {
+                    tr!("the API key was refused; check ANTHROPIC_API_KEY or \
+                     api_key for a typo or a revoked key")
+                }
+                403 => {
+                    tr!("the API key has no access to this model or endpoint")
+                }
+                404 => {
+                    tr!("no such model or endpoint; /model --refresh lists \
+                     the models the API offers")
+                }
+                413 => tr!("the request is too large; /compact or /rewind \
+                     shrinks the conversation"),
+                429 => tr!("rate limited; wait a minute, or lower max_tokens \
+                     and thinking_budget"),
+                500..=599 => {
+                    tr!("the API is having trouble; try again shortly, or \
+                     set fallback_model for overloads")
+                }
+                _ => return None,
+            },
+            Error::Security(_) => {
+                tr!("tools reach only files under the working directory that \
+                 no `sandbox` rule denies; start tapir in a directory \
+                 containing the path to work on it")
+            }
+            Error::ContextOverflow { .. } => {
+                tr!("/compact summarizes older turns, /rewind drops them")
             }
-            Error::Json(msg) => write!(f, "JSON error: {msg}"),
-            Error::Tool { name, message } => {
-                write!(f, "tool {name}: {message}")
+            Error::OverBudget { .. } => {
+                tr!("raise max_cost_per_session or max_cost_per_day to go on")
             }
-            Error::Io(err) => write!(f, "I/O error: {err}"),
-            Error::Security(msg) => {
-                write!(f, "security: {msg}")
+            Error::Json(_) | Error::Tool { .. } | Error::Io(_) => {
+                return None;
             }
+        };
+        Some(hint.to_string())
+    }
+
+    /// Print the error after `prefix`, with its hint on the
+    /// line under it.
+    pub fn report(&self, prefix: &str) {
+        eprintln!("{prefix}{}", self.localized());
+        if let Some(hint) = self.hint() {
+            eprintln!(
+                "{:indent$}{} {hint}",
+                "",
+                tr!("hint:"),
+                indent = prefix.len()
+            );
         }
     }
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(|text| text))
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Self {
         Error::Io(err)
@@ -80,4 +203,26 @@ mod tests {
         };
         assert_eq!(err.to_string(), "API error (500): internal",);
     }
+
+    #[test]
+    fn hints_follow_the_status() {
+        let api = |status| Error::Api {
+            status,
+            message: String::new(),
+            retry_after: None,
+        };
+        assert!(api(401).hint().unwrap().contains("API key"));
+        assert!(api(529).hint().unwrap().contains("fallback_model"));
+        assert_eq!(api(400).hint(), None);
+        let no_key = Error::NoApiKey {
+            config_file: "/home/u/.config/tapir/config.toml".into(),
+        };
+        assert!(
+            no_key
+                .hint()
+                .unwrap()
+                .contains("api_key in /home/u/.config/tapir/config.toml")
+        );
+        assert_eq!(Error::Json("eof".into()).hint(), None);
+    }
 }
@@ -0,0 +1,75 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde_json::{Value, json};
+
+/// Events file of the current session. Global so that code
+/// without a session handle (API retries) can still log.
+static LOG_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// `<id>.events.jsonl` next to the session's message file.
+pub fn events_path(session_file: &Path) -> PathBuf {
+    session_file.with_extension("events.jsonl")
+}
+
+/// Send later events to the log of `session_file`.
+pub fn open(session_file: &Path) {
+    if let Ok(mut path) = LOG_PATH.lock() {
+        *path = Some(events_path(session_file));
+    }
+}
+
+/// Append `{"ts", "event", ...fields}` to the session's
+/// events log and count it for telemetry. `fields` must be a
+/// JSON object. A no-op before `open`, and write errors are
+/// ignored: the log is diagnostics, not state.
+pub fn emit(event: &str, fields: Value) {
+    crate::telemetry::record(event, &fields);
+    let Ok(guard) = LOG_PATH.lock() else {
+        return;
+    };
+    let Some(path) = guard.as_ref() else {
+        return;
+    };
+    let line = event_line(&crate::session::iso_now(), event, fields);
+    if let Ok(mut f) = OpenOptions::new().create(true).append(true).open(path) {
+        let _ = writeln!(f, "{line}");
+    }
+}
+
+fn event_line(ts: &str, event: &str, fields: Value) -> String {
+    let mut obj = json!({ "ts": ts, "event": event });
+    if let (Some(map), Value::Object(extra)) = (obj.as_object_mut(), fields) {
+        map.extend(extra);
+    }
+    obj.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_line_merges_fields() {
+        let line = event_line(
+            "2026-01-01T00:00:00.000Z",
+            "tool",
+            json!({"name": "bash", "duration_ms": 12}),
+        );
+        let v: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(v["event"], "tool");
+        assert_eq!(v["ts"], "2026-01-01T00:00:00.000Z");
+        assert_eq!(v["name"], "bash");
+        assert_eq!(v["duration_ms"], 12);
+    }
+
+    #[test]
+    fn events_path_sits_next_to_session() {
+        assert_eq!(
+            events_path(Path::new("/s/abc.jsonl")),
+            Path::new("/s/abc.events.jsonl")
+        );
+    }
+}
@@ -0,0 +1,473 @@
+use crate::highlight::{self, Kind};
+use crate::types::{Content, ContentBlock, Message, Note, Role};
+
+/// A session transcript as Markdown, with `/note`s as quotes
+/// where they were taken.
+pub fn markdown(title: &str, messages: &[Message], notes: &[Note]) -> String {
+    let mut out = format!("# {title}\n");
+    let mut notes = notes.iter().peekable();
+    for (i, msg) in messages.iter().enumerate() {
+        while let Some(note) = notes.next_if(|n| n.at <= i) {
+            push_markdown_note(&mut out, note);
+        }
+        match &msg.content {
+            Content::Text(text) => {
+                let text = highlight::tag_fences(text);
+                out.push_str(&format!("\n## {}\n\n{text}\n", role_name(msg)));
+            }
+            Content::Blocks(blocks) => {
+                if !only_tool_results(blocks) {
+                    out.push_str(&format!("\n## {}\n", role_name(msg)));
+                }
+                for block in blocks {
+                    push_markdown_block(&mut out, block);
+                }
+            }
+        }
+    }
+    for note in notes {
+        push_markdown_note(&mut out, note);
+    }
+    out
+}
+
+fn push_markdown_note(out: &mut String, note: &Note) {
+    out.push_str(&format!("\n> **Note** ({})\n", note.time));
+    for line in note.text.lines() {
+        out.push_str(&format!("> {line}\n"));
+    }
+}
+
+fn push_markdown_block(out: &mut String, block: &ContentBlock) {
+    match block {
+        ContentBlock::Text { text } => {
+            out.push_str(&format!("\n{}\n", highlight::tag_fences(text)))
+        }
+        ContentBlock::Thinking { thinking, .. } => {
+            out.push('\n');
+            for line in thinking.lines() {
+                out.push_str(&format!("> {line}\n"));
+            }
+        }
+        ContentBlock::ToolUse { name, input, .. } => {
+            let header = crate::stream::tool_call_header(name, input);
+            let input = serde_json::to_string_pretty(input).unwrap_or_default();
+            out.push_str(&format!(
+                "\n**{header}**\n\n{}",
+                fence(&input, "json")
+            ));
+        }
+        ContentBlock::ToolResult {
+            content, is_error, ..
+        } => {
+            let label = if *is_error == Some(true) {
+                "Error"
+            } else {
+                "Output"
+            };
+            out.push_str(&format!("\n{label}:\n\n{}", fence(content, "")));
+        }
+    }
+}
+
+/// A fence longer than any backtick run inside `text`.
+fn fence(text: &str, lang: &str) -> String {
+    let mut ticks = 3;
+    while text.contains(&"`".repeat(ticks)) {
+        ticks += 1;
+    }
+    let ticks = "`".repeat(ticks);
+    format!("{ticks}{lang}\n{}\n{ticks}\n", text.trim_end_matches('\n'))
+}
+
+const HTML_STYLE: &str = "\
+body{font:15px/1.5 system-ui,sans-serif;max-width:900px;margin:2em auto;\
+padding:0 1em;color:#222;background:#fff}\
+h1{font-size:1.4em}\
+.msg{margin:1.2em 0;padding:.6em 1em;border-radius:6px}\
+.user{background:#eef4ff}\
+.assistant{background:#f6f6f6}\
+.note{margin:1.2em 0;padding:.4em 1em;border-left:3px solid #e0b000;\
+background:#fffbea}\
+.role{font-weight:600;font-size:.85em;color:#555;margin-bottom:.3em}\
+.text{white-space:pre-wrap}\
+pre{background:#1e1e1e;color:#ddd;padding:.7em;border-radius:4px;\
+overflow-x:auto;font:13px/1.4 ui-monospace,monospace}\
+code{font-family:ui-monospace,monospace;font-size:.92em}\
+details{margin:.4em 0}\
+summary{cursor:pointer;color:#444;font-family:ui-monospace,monospace;\
+font-size:.9em}\
+.error summary{color:#b00}\
+.thinking{color:#666;font-style:italic;white-space:pre-wrap}\
+.k{color:#c586c0}.s{color:#ce9178}.c{color:#6a9955}.n{color:#b5cea8}";
+
+/// A self-contained HTML page for the transcript: inline CSS,
+/// tool calls and output in collapsed `<details>`, and
+/// highlighted code blocks.
+pub fn html(title: &str, messages: &[Message], notes: &[Note]) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <title>{t}</title><style>{HTML_STYLE}</style></head>\n\
+         <body><h1>{t}</h1>\n",
+        t = escape(title)
+    );
+    let mut notes = notes.iter().peekable();
+    for (i, msg) in messages.iter().enumerate() {
+        while let Some(note) = notes.next_if(|n| n.at <= i) {
+            push_html_note(&mut out, note);
+        }
+        let blocks: Vec<ContentBlock> = match &msg.content {
+            Content::Text(text) => {
+                vec![ContentBlock::Text { text: text.clone() }]
+            }
+            Content::Blocks(blocks) => blocks.clone(),
+        };
+        if only_tool_results(&blocks) {
+            for block in &blocks {
+                push_html_block(&mut out, block);
+            }
+            continue;
+        }
+        let class = match msg.role {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        };
+        out.push_str(&format!(
+            "<div class=\"msg {class}\"><div class=\"role\">{}</div>\n",
+            role_name(msg)
+        ));
+        for block in &blocks {
+            push_html_block(&mut out, block);
+        }
+        out.push_str("</div>\n");
+    }
+    for note in notes {
+        push_html_note(&mut out, note);
+    }
+    out.push_str("</body></html>\n");
+    out
+}
+
+fn push_html_note(out: &mut String, note: &Note) {
+    out.push_str(&format!(
+        "<div class=\"note\"><div class=\"role\">Note ({})</div>\
+         <div class=\"text\">{}</div></div>\n",
+        escape(&note.time),
+        escape(&note.text)
+    ));
+}
+
+fn push_html_block(out: &mut String, block: &ContentBlock) {
+    match block {
+        ContentBlock::Text { text } => out.push_str(&markdown_to_html(text)),
+        ContentBlock::Thinking { thinking, .. } => {
+            out.push_str(&format!(
+                "<details><summary>thinking</summary>\
+                 <div class=\"thinking\">{}</div></details>\n",
+                escape(thinking)
+            ));
+        }
+        ContentBlock::ToolUse { name, input, .. } => {
+            let header = crate::stream::tool_call_header(name, input);
+            let input = serde_json::to_string_pretty(input).unwrap_or_default();
+            out.push_str(&format!(
+                "<details><summary>{}</summary>{}</details>\n",
+                escape(&header),
+                code_block(&input, "json")
+            ));
+        }
+        ContentBlock::ToolResult {
+            content, is_error, ..
+        } => {
+            let (class, label) = if *is_error == Some(true) {
+                (" class=\"error\"", "error")
+            } else {
+                ("", "output")
+            };
+            let lines = content.lines().count();
+            out.push_str(&format!(
+                "<details{class}><summary>{label} ({lines} lines)</summary>\
+                 {}</details>\n",
+                code_block(content, "")
+            ));
+        }
+    }
+}
+
+/// Message text to HTML: fenced code blocks become
+/// highlighted `<pre>`, everything else keeps its line breaks
+/// with inline `code` spans.
+fn markdown_to_html(text: &str) -> String {
+    let mut out = String::new();
+    let mut prose = String::new();
+    let mut code: Option<(String, String)> = None;
+    let flush = |out: &mut String, prose: &mut String| {
+        let trimmed = prose.trim_matches('\n');
+        if !trimmed.is_empty() {
+            out.push_str(&format!(
+                "<div class=\"text\">{}</div>\n",
+                inline_code(trimmed)
+            ));
+        }
+        prose.clear();
+    };
+    for line in text.lines() {
+        let fence = line.trim_start().starts_with("```");
+        match &mut code {
+            Some((lang, body)) => {
+                if fence {
+                    out.push_str(&code_block(body, lang));
+                    code = None;
+                } else {
+                    body.push_str(line);
+                    body.push('\n');
+                }
+            }
+            None if fence => {
+                flush(&mut out, &mut prose);
+                let lang = line.trim_start().trim_start_matches('`').trim();
+                code = Some((lang.to_string(), String::new()));
+            }
+            None => {
+                prose.push_str(line);
+                prose.push('\n');
+            }
+        }
+    }
+    // Unterminated fence: still show it as code
+    if let Some((lang, body)) = code {
+        out.push_str(&code_block(&body, &lang));
+    }
+    flush(&mut out, &mut prose);
+    out
+}
+
+fn code_block(code: &str, lang: &str) -> String {
+    let lang = match lang {
+        "" => highlight::detect(code).unwrap_or(""),
+        lang => lang,
+    };
+    let mut out = String::from("<pre><code>");
+    for (kind, text) in highlight::tokenize(code.trim_end_matches('\n'), lang) {
+        let class = match kind {
+            Kind::Plain => {
+                out.push_str(&escape(text));
+                continue;
+            }
+            Kind::Keyword => "k",
+            Kind::String => "s",
+            Kind::Comment => "c",
+            Kind::Number => "n",
+        };
+        out.push_str(&format!(
+            "<span class=\"{class}\">{}</span>",
+            escape(text)
+        ));
+    }
+    out.push_str("</code></pre>\n");
+    out
+}
+
+fn inline_code(text: &str) -> String {
+    let mut out = String::new();
+    for (i, part) in text.split('`').enumerate() {
+        // Odd parts sit between backticks
+        if i % 2 == 1 {
+            out.push_str(&format!("<code>{}</code>", escape(part)));
+        } else {
+            out.push_str(&escape(part));
+        }
+    }
+    out
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn role_name(msg: &Message) -> &'static str {
+    match msg.role {
+        Role::User => "User",
+        Role::Assistant => "Assistant",
+    }
+}
+
+fn only_tool_results(blocks: &[ContentBlock]) -> bool {
+    !blocks.is_empty()
+        && blocks
+            .iter()
+            .all(|b| matches!(b, ContentBlock::ToolResult { .. }))
+}
+
+/// The conversation rebuilt for another model: thinking blocks
+/// (whose signatures only the original model accepts) are
+/// dropped, and with `text_tools` tool calls and results become
+/// plain text for models without tool support.
+pub fn migrate(messages: &[Message], text_tools: bool) -> Vec<Message> {
+    let mut out: Vec<Message> = Vec::new();
+    for msg in messages {
+        let blocks = match &msg.content {
+            Content::Text(text) => {
+                vec![ContentBlock::Text { text: text.clone() }]
+            }
+            Content::Blocks(blocks) => blocks.clone(),
+        };
+        let blocks: Vec<ContentBlock> = blocks
+            .into_iter()
+            .filter_map(|block| match block {
+                ContentBlock::Thinking { .. } => None,
+                ContentBlock::ToolUse { name, input, .. } if text_tools => {
+                    Some(ContentBlock::Text {
+                        text: format!("[Tool call]: {name}({input})"),
+                    })
+                }
+                ContentBlock::ToolResult {
+                    content, is_error, ..
+                } if text_tools => {
+                    let tag = if is_error == Some(true) {
+                        "Tool error"
+                    } else {
+                        "Tool result"
+                    };
+                    Some(ContentBlock::Text {
+                        text: format!("[{tag}]: {content}"),
+                    })
+                }
+                other => Some(other),
+            })
+            .collect();
+        if blocks.is_empty() {
+            continue;
+        }
+        // Roles must alternate once tool turns turned into text
+        if let Some(last) = out.last_mut()
+            && last.role == msg.role
+            && let Content::Blocks(prev) = &mut last.content
+        {
+            prev.extend(blocks);
+            continue;
+        }
+        out.push(Message {
+            role: msg.role,
+            content: Content::Blocks(blocks),
+        });
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transcript() -> Vec<Message> {
+        vec![
+            Message {
+                role: Role::User,
+                content: Content::Text("fix <main>".to_string()),
+            },
+            Message {
+                role: Role::Assistant,
+                content: Content::Blocks(vec![
+                    ContentBlock::Text {
+                        text: "Use `x`:\n```rust\nlet x = 1;\n```".to_string(),
+                    },
+                    ContentBlock::ToolUse {
+                        id: "t1".to_string(),
+                        name: "bash".to_string(),
+                        input: serde_json::json!({"command": "ls"}),
+                    },
+                ]),
+            },
+            Message {
+                role: Role::User,
+                content: Content::Blocks(vec![ContentBlock::ToolResult {
+                    tool_use_id: "t1".to_string(),
+                    content: "a\n```\nb".to_string(),
+                    is_error: None,
+                }]),
+            },
+        ]
+    }
+
+    #[test]
+    fn markdown_export() {
+        let md = markdown("s1", &transcript(), &[]);
+        assert!(md.starts_with("# s1\n\n## User\n\nfix <main>\n"));
+        assert!(md.contains("**bash: ls**\n\n```json\n"));
+        // Output containing ``` gets a longer fence
+        assert!(md.contains("Output:\n\n````\na\n```\nb\n````\n"), "{md}");
+        assert_eq!(md.matches("## User").count(), 1);
+
+        let msgs = [Message {
+            role: Role::Assistant,
+            content: Content::Text("```\n#include <x.h>\n```".to_string()),
+        }];
+        assert!(markdown("s2", &msgs, &[]).contains("```c\n#include"));
+        assert!(html("s2", &msgs, &[]).contains("<span class=\"k\">"));
+    }
+
+    #[test]
+    fn migrate_drops_thinking_and_flattens_tools() {
+        let mut msgs = transcript();
+        if let Content::Blocks(blocks) = &mut msgs[1].content {
+            blocks.insert(
+                0,
+                ContentBlock::Thinking {
+                    thinking: "hmm".to_string(),
+                    signature: "sig".to_string(),
+                },
+            );
+        }
+        let kept = migrate(&msgs, false);
+        assert_eq!(kept.len(), 3);
+        let Content::Blocks(blocks) = &kept[1].content else {
+            panic!("expected blocks");
+        };
+        assert_eq!(blocks.len(), 2);
+        assert!(matches!(blocks[1], ContentBlock::ToolUse { .. }));
+
+        let text = migrate(&msgs, true);
+        assert_eq!(text.len(), 3);
+        let Content::Blocks(blocks) = &text[2].content else {
+            panic!("expected blocks");
+        };
+        assert!(matches!(
+            &blocks[0],
+            ContentBlock::Text { text } if text.starts_with("[Tool result]: a")
+        ));
+    }
+
+    #[test]
+    fn html_export() {
+        let page = html("s1", &transcript(), &[]);
+        assert!(page.contains("fix &lt;main&gt;"));
+        assert!(page.contains("Use <code>x</code>:"));
+        assert!(page.contains("<span class=\"k\">let</span> x = "));
+        assert!(page.contains("<details><summary>bash: ls</summary>"));
+        assert!(page.contains("<summary>output (3 lines)</summary>"));
+        assert!(!page.contains("<script"));
+    }
+
+    #[test]
+    fn notes_sit_between_messages() {
+        let note = |at, text: &str| Note {
+            at,
+            text: text.to_string(),
+            time: "2026-01-01T00:00:00.000Z".to_string(),
+        };
+        let notes = [note(1, "tried <x>\nabandoned"), note(9, "end")];
+        let md = markdown("s1", &transcript(), &notes);
+        let at = md.find("> **Note** (2026-01-01T00:00:00.000Z)\n").unwrap();
+        assert!(md.find("fix <main>").unwrap() < at);
+        assert!(at < md.find("## Assistant").unwrap());
+        assert!(md.contains("> tried <x>\n> abandoned\n"));
+        assert!(md.trim_end().ends_with("> end"));
+
+        let page = html("s1", &transcript(), &notes);
+        assert!(page.contains("<div class=\"text\">tried &lt;x&gt;"));
+        assert_eq!(page.matches("class=\"note\"").count(), 2);
+    }
+}
@@ -0,0 +1,629 @@
+use crate::theme::{Style, Theme};
+
+/// What a piece of highlighted source is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Plain,
+    Keyword,
+    String,
+    Comment,
+    Number,
+}
+
+/// Lexical rules for one language, enough to color keywords,
+/// strings, comments and numbers.
+struct Syntax {
+    keywords: &'static [&'static str],
+    line_comment: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+}
+
+const RUST: Syntax = Syntax {
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn",
+        "else", "enum", "extern", "false", "fn", "for", "if", "impl", "in",
+        "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+        "self", "Self", "static", "struct", "super", "trait", "true", "type",
+        "unsafe", "use", "where", "while",
+    ],
+    line_comment: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+};
+
+const PYTHON: Syntax = Syntax {
+    keywords: &[
+        "and", "as", "assert", "async", "await", "break", "class", "continue",
+        "def", "del", "elif", "else", "except", "False", "finally", "for",
+        "from", "global", "if", "import", "in", "is", "lambda", "None",
+        "nonlocal", "not", "or", "pass", "raise", "return", "True", "try",
+        "while", "with", "yield",
+    ],
+    line_comment: &["#"],
+    block_comment: None,
+    quotes: &['"', '\''],
+};
+
+const JS: Syntax = Syntax {
+    keywords: &[
+        "async",
+        "await",
+        "break",
+        "case",
+        "catch",
+        "class",
+        "const",
+        "continue",
+        "default",
+        "delete",
+        "else",
+        "export",
+        "extends",
+        "false",
+        "finally",
+        "for",
+        "from",
+        "function",
+        "if",
+        "import",
+        "in",
+        "instanceof",
+        "interface",
+        "let",
+        "new",
+        "null",
+        "return",
+        "switch",
+        "this",
+        "throw",
+        "true",
+        "try",
+        "type",
+        "typeof",
+        "undefined",
+        "var",
+        "while",
+        "yield",
+    ],
+    line_comment: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\'', '`'],
+};
+
+const GO: Syntax = Syntax {
+    keywords: &[
+        "break",
+        "case",
+        "chan",
+        "const",
+        "continue",
+        "default",
+        "defer",
+        "else",
+        "false",
+        "for",
+        "func",
+        "go",
+        "if",
+        "import",
+        "interface",
+        "map",
+        "nil",
+        "package",
+        "range",
+        "return",
+        "select",
+        "struct",
+        "switch",
+        "true",
+        "type",
+        "var",
+    ],
+    line_comment: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\'', '`'],
+};
+
+const C: Syntax = Syntax {
+    keywords: &[
+        "auto",
+        "break",
+        "case",
+        "char",
+        "class",
+        "const",
+        "continue",
+        "default",
+        "do",
+        "double",
+        "else",
+        "enum",
+        "extern",
+        "false",
+        "float",
+        "for",
+        "if",
+        "include",
+        "int",
+        "long",
+        "namespace",
+        "new",
+        "nullptr",
+        "private",
+        "public",
+        "return",
+        "short",
+        "signed",
+        "sizeof",
+        "static",
+        "struct",
+        "switch",
+        "template",
+        "true",
+        "typedef",
+        "union",
+        "unsigned",
+        "void",
+        "while",
+    ],
+    line_comment: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+};
+
+const SHELL: Syntax = Syntax {
+    keywords: &[
+        "case", "do", "done", "elif", "else", "esac", "export", "fi", "for",
+        "function", "if", "in", "local", "return", "then", "while",
+    ],
+    line_comment: &["#"],
+    block_comment: None,
+    quotes: &['"', '\''],
+};
+
+const CONFIG: Syntax = Syntax {
+    keywords: &["true", "false", "null"],
+    line_comment: &["#"],
+    block_comment: None,
+    quotes: &['"', '\''],
+};
+
+fn syntax(lang: &str) -> Option<&'static Syntax> {
+    Some(match lang.to_ascii_lowercase().as_str() {
+        "rust" | "rs" => &RUST,
+        "python" | "py" => &PYTHON,
+        "javascript" | "js" | "jsx" | "typescript" | "ts" | "tsx" | "json" => {
+            &JS
+        }
+        "go" => &GO,
+        "c" | "h" | "cpp" | "c++" | "cc" | "hpp" | "java" => &C,
+        "sh" | "bash" | "shell" | "zsh" => &SHELL,
+        "toml" | "yaml" | "yml" | "ini" => &CONFIG,
+        _ => return None,
+    })
+}
+
+/// Split `code` into highlighted spans. Unknown languages come
+/// back as one plain span.
+pub fn tokenize<'a>(code: &'a str, lang: &str) -> Vec<(Kind, &'a str)> {
+    let Some(syn) = syntax(lang) else {
+        return vec![(Kind::Plain, code)];
+    };
+    let is_rust = std::ptr::eq(syn, &RUST);
+    let bytes = code.as_bytes();
+    let mut spans: Vec<(Kind, &'a str)> = Vec::new();
+    let mut plain_start = 0;
+    let mut i = 0;
+
+    let push = |spans: &mut Vec<(Kind, &'a str)>, kind, start, end| {
+        if start < end {
+            spans.push((kind, &code[start..end]));
+        }
+    };
+
+    while i < bytes.len() {
+        let rest = &code[i..];
+        let prev_ident = i > 0 && is_ident(bytes[i - 1]);
+        let end = if syn.line_comment.iter().any(|c| rest.starts_with(c)) {
+            Some((Kind::Comment, rest.find('\n').map_or(code.len(), |n| i + n)))
+        } else if let Some((open, close)) = syn.block_comment
+            && rest.starts_with(open)
+        {
+            let end = rest[open.len()..]
+                .find(close)
+                .map_or(code.len(), |n| i + open.len() + n + close.len());
+            Some((Kind::Comment, end))
+        } else if let Some(q) =
+            syn.quotes.iter().find(|&&q| rest.starts_with(q))
+        {
+            // A Rust `'` is a lifetime unless it closes a char
+            if is_rust && *q == '\'' && !rest[1..].starts_with("\\") {
+                let closes = rest[1..]
+                    .char_indices()
+                    .nth(1)
+                    .is_some_and(|(n, c)| c == '\'' && n > 0);
+                if !closes {
+                    i += 1;
+                    continue;
+                }
+            }
+            Some((Kind::String, string_end(code, i, *q)))
+        } else if bytes[i].is_ascii_digit() && !prev_ident {
+            let n = rest
+                .find(|c: char| {
+                    !(c.is_ascii_alphanumeric() || c == '_' || c == '.')
+                })
+                .unwrap_or(rest.len());
+            Some((Kind::Number, i + n))
+        } else if is_ident(bytes[i]) && !prev_ident {
+            let n = rest
+                .bytes()
+                .position(|b| !is_ident(b))
+                .unwrap_or(rest.len());
+            if syn.keywords.contains(&&rest[..n]) {
+                Some((Kind::Keyword, i + n))
+            } else {
+                i += n;
+                continue;
+            }
+        } else {
+            None
+        };
+        match end {
+            Some((kind, end)) => {
+                push(&mut spans, Kind::Plain, plain_start, i);
+                push(&mut spans, kind, i, end);
+                i = end;
+                plain_start = end;
+            }
+            None => i += rest.chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    push(&mut spans, Kind::Plain, plain_start, code.len());
+    spans
+}
+
+/// Substrings that suggest a language, by weight. A marker
+/// counts once however often it appears.
+const MARKERS: &[(&str, &[(&str, u32)])] = &[
+    (
+        "rust",
+        &[
+            ("fn ", 2),
+            ("let mut ", 3),
+            ("impl ", 2),
+            ("pub fn ", 3),
+            ("::", 1),
+            ("-> ", 1),
+            ("#[derive", 3),
+            ("println!", 3),
+            ("&mut ", 2),
+            ("use std::", 3),
+            ("Some(", 1),
+            ("Ok(", 1),
+        ],
+    ),
+    (
+        "python",
+        &[
+            ("def ", 2),
+            ("import ", 1),
+            ("self.", 1),
+            ("elif ", 3),
+            ("print(", 1),
+            ("__init__", 3),
+            ("from ", 1),
+            ("None", 1),
+            ("):\n", 2),
+        ],
+    ),
+    (
+        "go",
+        &[
+            ("package ", 3),
+            ("func ", 3),
+            (":= ", 2),
+            ("fmt.", 3),
+            ("err != nil", 3),
+        ],
+    ),
+    (
+        "javascript",
+        &[
+            ("const ", 1),
+            ("function ", 2),
+            ("=> ", 1),
+            ("console.log", 3),
+            ("require(", 3),
+            ("===", 2),
+            ("export ", 1),
+            ("document.", 2),
+        ],
+    ),
+    (
+        "c",
+        &[
+            ("#include", 4),
+            ("int main", 3),
+            ("printf(", 2),
+            ("void ", 1),
+            ("NULL", 1),
+            ("std::", 2),
+        ],
+    ),
+    (
+        "sh",
+        &[
+            ("#!/bin/sh", 5),
+            ("#!/bin/bash", 5),
+            ("#!/usr/bin/env bash", 5),
+            ("$ ", 1),
+            ("echo ", 2),
+            ("sudo ", 2),
+            (" | ", 1),
+            ("export ", 1),
+            ("fi\n", 2),
+            ("cargo ", 1),
+            ("git ", 1),
+            ("cd ", 1),
+        ],
+    ),
+];
+
+/// Whether `tokenize` colors `lang`, a language name or
+/// file extension.
+pub fn supported(lang: &str) -> bool {
+    syntax(lang).is_some()
+}
+
+/// `code` from byte `from` on, colored for the terminal. What
+/// comes before is lexed only for context, so a line inside
+/// a block comment or string still colors as one.
+pub fn ansi(code: &str, lang: &str, from: usize, theme: &Theme) -> String {
+    let mut out = String::new();
+    let base = code.as_ptr() as usize;
+    for (kind, text) in tokenize(code, lang) {
+        let start = text.as_ptr() as usize - base;
+        let Some(text) = text.get(from.saturating_sub(start)..) else {
+            continue;
+        };
+        if text.is_empty() {
+            continue;
+        }
+        let style = match kind {
+            Kind::Plain => {
+                out.push_str(text);
+                continue;
+            }
+            Kind::Keyword => Style::Keyword,
+            Kind::String => Style::String,
+            Kind::Comment => Style::Comment,
+            Kind::Number => Style::Number,
+        };
+        out.push_str(&theme.paint(style, text));
+    }
+    out
+}
+
+/// Guess the language of an untagged code block from its
+/// content, as a name `tokenize` knows (or `diff`). None when
+/// nothing stands out.
+pub fn detect(code: &str) -> Option<&'static str> {
+    let trimmed = code.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
+    let first = trimmed.lines().next().unwrap_or("");
+    if let Some(shebang) = first.strip_prefix("#!") {
+        return Some(if shebang.contains("python") {
+            "python"
+        } else if shebang.contains("node") {
+            "javascript"
+        } else {
+            "sh"
+        });
+    }
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(trimmed).is_ok()
+    {
+        return Some("json");
+    }
+    let lines: Vec<&str> = trimmed.lines().collect();
+    if lines.iter().any(|l| l.starts_with("@@ "))
+        && lines
+            .iter()
+            .any(|l| l.starts_with("--- ") || l.starts_with("+++ "))
+    {
+        return Some("diff");
+    }
+    let code = format!("{trimmed}\n");
+    let (lang, score) = MARKERS
+        .iter()
+        .map(|(lang, markers)| {
+            let score: u32 = markers
+                .iter()
+                .filter(|(m, _)| code.contains(m))
+                .map(|(_, w)| w)
+                .sum();
+            (*lang, score)
+        })
+        .max_by_key(|&(_, score)| score)?;
+    if score >= 3 {
+        return Some(lang);
+    }
+    // key = value or [section] lines throughout
+    let config_lines = lines
+        .iter()
+        .filter(|l| {
+            let l = l.trim();
+            l.is_empty()
+                || l.starts_with('#')
+                || (l.starts_with('[') && l.ends_with(']'))
+                || l.split_once(" = ").is_some_and(|(k, _)| is_key(k))
+        })
+        .count();
+    if config_lines == lines.len() {
+        return Some("toml");
+    }
+    let yaml_lines = lines
+        .iter()
+        .filter(|l| {
+            let l = l.trim();
+            l.is_empty()
+                || l.starts_with('#')
+                || l.starts_with("- ")
+                || l.split_once(':').is_some_and(|(k, _)| is_key(k))
+        })
+        .count();
+    (yaml_lines == lines.len()).then_some("yaml")
+}
+
+/// `text` with a language added to each fenced code block
+/// that has none and whose language `detect` recognizes.
+pub fn tag_fences(text: &str) -> String {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let ticks = line.trim_start().trim_end_matches(['\n', '\r']);
+        let open = ticks.len() >= 3 && ticks.bytes().all(|b| b == b'`');
+        let close = open
+            .then(|| {
+                lines[i + 1..]
+                    .iter()
+                    .position(|l| l.trim() == ticks)
+                    .map(|n| i + 1 + n)
+            })
+            .flatten();
+        let Some(close) = close else {
+            out.push_str(line);
+            i += 1;
+            continue;
+        };
+        let body = lines[i + 1..close].concat();
+        match detect(&body) {
+            Some(lang) => {
+                let indent = &line[..line.len() - line.trim_start().len()];
+                out.push_str(&format!("{indent}{ticks}{lang}\n"));
+            }
+            None => out.push_str(line),
+        }
+        out.push_str(&body);
+        out.push_str(lines[close]);
+        i = close + 1;
+    }
+    out
+}
+
+fn is_key(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| is_ident(b) || b == b'-' || b == b'.' || b == b'"')
+}
+
+fn is_ident(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+/// Byte index just past the string opened by `quote` at
+/// `start`, honoring backslash escapes.
+fn string_end(code: &str, start: usize, quote: char) -> usize {
+    let mut escaped = false;
+    for (n, c) in code[start + 1..].char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == quote {
+            return start + 1 + n + c.len_utf8();
+        }
+    }
+    code.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rust_spans() {
+        let spans =
+            tokenize("let x = \"a\\\"b\"; // hi\nfn f<'a>() {}", "rust");
+        assert_eq!(
+            spans,
+            vec![
+                (Kind::Keyword, "let"),
+                (Kind::Plain, " x = "),
+                (Kind::String, "\"a\\\"b\""),
+                (Kind::Plain, "; "),
+                (Kind::Comment, "// hi"),
+                (Kind::Plain, "\n"),
+                (Kind::Keyword, "fn"),
+                (Kind::Plain, " f<'a>() {}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn ansi_keeps_earlier_lines_as_context() {
+        let code = "/* open\nfn x */ fn y";
+        assert_eq!(
+            ansi(code, "rust", 8, &Theme::dark()),
+            "\x1b[2mfn x */\x1b[0m \x1b[35mfn\x1b[0m y"
+        );
+        assert_eq!(ansi("fn y", "cobol", 0, &Theme::dark()), "fn y");
+    }
+
+    #[test]
+    fn numbers_and_unknown_languages() {
+        let spans = tokenize("x1 = 0x1f + 2.5", "python");
+        assert_eq!(
+            spans,
+            vec![
+                (Kind::Plain, "x1 = "),
+                (Kind::Number, "0x1f"),
+                (Kind::Plain, " + "),
+                (Kind::Number, "2.5"),
+            ]
+        );
+        assert_eq!(tokenize("let x", "cobol"), vec![(Kind::Plain, "let x")]);
+    }
+
+    #[test]
+    fn untagged_fences_get_a_language() {
+        let text = "Try:\n```\nfn main() {\n    let mut x = 1;\n}\n```\n\
+                    ```\nhello there\n```\n```py\nx = 1\n```\n```\nopen";
+        assert_eq!(
+            tag_fences(text),
+            "Try:\n```rust\nfn main() {\n    let mut x = 1;\n}\n```\n\
+             ```\nhello there\n```\n```py\nx = 1\n```\n```\nopen"
+        );
+    }
+
+    #[test]
+    fn detects_common_languages() {
+        let cases = [
+            ("fn main() {\n    let mut x = 1;\n}", Some("rust")),
+            ("def f(self):\n    return None", Some("python")),
+            ("package main\n\nfunc main() {}", Some("go")),
+            (
+                "const f = (x) => x;\nconsole.log(f(1));",
+                Some("javascript"),
+            ),
+            ("#include <stdio.h>\nint main(void) {}", Some("c")),
+            ("#!/bin/sh\nls", Some("sh")),
+            ("$ cargo build\n$ git status | head", Some("sh")),
+            ("{\"a\": [1, 2]}", Some("json")),
+            ("[package]\nname = \"tapir\"\n", Some("toml")),
+            ("name: ci\non:\n  - push", Some("yaml")),
+            ("--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\n+b", Some("diff")),
+            ("Just some words here.", None),
+            ("", None),
+        ];
+        for (code, lang) in cases {
+            assert_eq!(detect(code), lang, "{code}");
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Translations built in: a language code and a JSON object
+/// mapping the English text to its translation. Text stays
+/// English in the code and is looked up by it, gettext
+/// style, so anything a catalog lacks prints in English.
+const CATALOGS: &[(&str, &str)] = &[("es", include_str!("../locale/es.json"))];
+
+/// Index into `CATALOGS` plus one; 0 is English.
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+
+static PARSED: [OnceLock<HashMap<String, String>>; CATALOGS.len()] =
+    [const { OnceLock::new() }; CATALOGS.len()];
+
+fn catalog(index: usize) -> &'static HashMap<String, String> {
+    PARSED[index].get_or_init(|| {
+        serde_json::from_str(CATALOGS[index].1).unwrap_or_default()
+    })
+}
+
+/// The language of a locale like `es_ES.UTF-8`.
+fn language(locale: &str) -> &str {
+    locale.split(['_', '.', '@', '-']).next().unwrap_or(locale)
+}
+
+/// The locale messages are asked for: `LC_ALL`,
+/// `LC_MESSAGES`, then `LANG`, the first one `var` has.
+fn env_locale(var: &dyn Fn(&str) -> Option<String>) -> String {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|k| var(k))
+        .find(|v| !v.is_empty())
+        .unwrap_or_default()
+}
+
+/// Whether messages can be shown in `locale`: English, or a
+/// language with a catalog.
+pub fn supported(locale: &str) -> bool {
+    let lang = language(locale);
+    matches!(lang, "" | "en" | "C" | "POSIX")
+        || CATALOGS.iter().any(|(code, _)| *code == lang)
+}
+
+/// Translate into `locale` from now on, or the locale of
+/// the environment `var` reads when it is empty. Unsupported
+/// locales fall back to English.
+pub fn set_locale(locale: &str, var: &dyn Fn(&str) -> Option<String>) {
+    let locale = match locale {
+        "" => env_locale(var),
+        l => l.to_string(),
+    };
+    let lang = language(&locale);
+    let index = CATALOGS
+        .iter()
+        .position(|(code, _)| *code == lang)
+        .map_or(0, |i| i + 1);
+    CURRENT.store(index, Ordering::Relaxed);
+}
+
+/// `text` in the current language.
+pub fn t(text: &'static str) -> &'static str {
+    match CURRENT.load(Ordering::Relaxed) {
+        0 => text,
+        i => catalog(i - 1).get(text).map_or(text, String::as_str),
+    }
+}
+
+/// `text` with each `{name}` replaced by its value, in one
+/// pass, so a value that holds braces is left as it is.
+pub fn fill(text: &str, args: &[(&str, &dyn Display)]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        rest = &rest[open..];
+        let value = rest.find('}').and_then(|close| {
+            let (_, value) =
+                args.iter().find(|(n, _)| *n == &rest[1..close])?;
+            Some((close, value))
+        });
+        match value {
+            Some((close, value)) => {
+                out.push_str(&value.to_string());
+                rest = &rest[close + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Translate a literal, filling `{name}` placeholders from
+/// `name = value` arguments after the lookup, so a
+/// translation may reorder them:
+/// `tr!("delete {file}?", file = path.display())`.
+macro_rules! tr {
+    ($text:literal) => {
+        $crate::i18n::t($text)
+    };
+    ($text:literal, $($name:ident = $value:expr),+ $(,)?) => {
+        $crate::i18n::fill(
+            $crate::i18n::t($text),
+            &[$((stringify!($name), &$value as &dyn std::fmt::Display)),+],
+        )
+    };
+}
+pub(crate) use tr;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `{name}` placeholders of `text`, sorted.
+    fn placeholders(text: &str) -> Vec<&str> {
+        let mut names: Vec<&str> = text
+            .split('{')
+            .skip(1)
+            .filter_map(|s| s.split_once('}').map(|(name, _)| name))
+            .filter(|name| {
+                name.chars().all(|c| c.is_alphanumeric() || c == '_')
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn catalogs_keep_placeholders() {
+        for (i, (code, json)) in CATALOGS.iter().enumerate() {
+            let parsed: HashMap<String, String> = serde_json::from_str(json)
+                .unwrap_or_else(|e| panic!("locale/{code}.json: {e}"));
+            assert_eq!(parsed.len(), catalog(i).len());
+            for (english, translated) in &parsed {
+                assert_eq!(
+                    placeholders(english),
+                    placeholders(translated),
+                    "{code}: {english}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn locales_and_filling() {
+        assert_eq!(language("es_ES.UTF-8"), "es");
+        assert!(supported("es_MX") && supported("C") && supported(""));
+        assert!(!supported("xx_XX"));
+        assert_eq!(
+            fill("apply to {n} files? [y/N] ", &[("n", &3)]),
+            "apply to 3 files? [y/N] "
+        );
+        assert_eq!(
+            fill("{path}: {name} {x}", &[("path", &"{name}"), ("name", &"n")]),
+            "{name}: n {x}"
+        );
+    }
+}
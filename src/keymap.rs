@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+/// What a control key does in the line editor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Clear the line.
+    Cancel,
+    /// Quit on an empty line.
+    Quit,
+    Home,
+    End,
+    HistoryPrev,
+    HistoryNext,
+    KillLine,
+    KillToEnd,
+    KillWordBack,
+    TransposeChars,
+    /// Edit the line in `$VISUAL` or `$EDITOR`.
+    ExternalEditor,
+    /// Expand or collapse the last tool output.
+    ToolOutput,
+    /// Complete the `@path` before the cursor.
+    Complete,
+}
+
+/// Each action's name in the `keys` config and its default
+/// control key.
+const ACTIONS: &[(Action, &str, u8)] = &[
+    (Action::Cancel, "cancel", b'c'),
+    (Action::Quit, "quit", b'd'),
+    (Action::Home, "home", b'a'),
+    (Action::End, "end", b'e'),
+    (Action::HistoryPrev, "history_prev", b'p'),
+    (Action::HistoryNext, "history_next", b'n'),
+    (Action::KillLine, "kill_line", b'u'),
+    (Action::KillToEnd, "kill_to_end", b'k'),
+    (Action::KillWordBack, "kill_word_back", b'w'),
+    (Action::TransposeChars, "transpose_chars", b't'),
+    (Action::ExternalEditor, "external_editor", b'g'),
+    (Action::ToolOutput, "tool_output", b'o'),
+    (Action::Complete, "complete", b'i'),
+];
+
+/// Control keys that keep their meaning: Backspace (Ctrl-H),
+/// Enter (Ctrl-J, Ctrl-M) and Escape (Ctrl-[).
+const RESERVED: &[u8] = b"hjm[";
+
+/// Which action each control byte runs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keymap {
+    bound: [Option<Action>; 32],
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut bound = [None; 32];
+        for &(action, _, key) in ACTIONS {
+            bound[control(key)] = Some(action);
+        }
+        Keymap { bound }
+    }
+}
+
+impl Keymap {
+    /// The default keymap with `keys` rebinding actions by
+    /// name, like `{"external_editor": "ctrl-x"}`, or
+    /// unbinding them with `"none"`. A key may run one action
+    /// only, so moving an action onto a key that another
+    /// still holds is an error.
+    pub fn new(keys: &HashMap<String, String>) -> Result<Keymap, String> {
+        let mut map = Keymap::default();
+        let mut wanted = Vec::new();
+        for (name, key) in keys {
+            let Some(&(action, ..)) =
+                ACTIONS.iter().find(|(_, n, _)| n == name)
+            else {
+                return Err(format!("no action `{name}` in keys"));
+            };
+            let byte = match key.to_ascii_lowercase().as_str() {
+                "none" | "" => None,
+                k => Some(parse_key(k).ok_or_else(|| {
+                    format!("keys.{name}: `{key}` is not a key like `ctrl-g`")
+                })?),
+            };
+            map.bound.iter_mut().for_each(|b| {
+                if *b == Some(action) {
+                    *b = None;
+                }
+            });
+            if let Some(byte) = byte {
+                wanted.push((byte, action, name));
+            }
+        }
+        wanted.sort_by_key(|(byte, _, name)| (*byte, name.as_str()));
+        for (byte, action, name) in wanted {
+            if let Some(other) = map.bound[byte as usize] {
+                return Err(format!(
+                    "keys.{name}: {} is already bound to {}",
+                    key_name(byte),
+                    action_name(other)
+                ));
+            }
+            map.bound[byte as usize] = Some(action);
+        }
+        Ok(map)
+    }
+
+    /// The action `byte` runs, if it is a bound control key.
+    pub fn action(&self, byte: u8) -> Option<Action> {
+        self.bound.get(byte as usize).copied().flatten()
+    }
+
+    /// The key running `action`, like `Ctrl-G`, if any.
+    pub fn key(&self, action: Action) -> Option<String> {
+        let byte = self.bound.iter().position(|b| *b == Some(action))?;
+        Some(key_name(byte as u8))
+    }
+}
+
+fn control(letter: u8) -> usize {
+    (letter & 0x1f) as usize
+}
+
+/// The control byte for `ctrl-x` or `tab`.
+fn parse_key(key: &str) -> Option<u8> {
+    if key == "tab" {
+        return Some(b'\t');
+    }
+    let rest = key.strip_prefix("ctrl-").or(key.strip_prefix("c-"))?;
+    let &[letter] = rest.as_bytes() else {
+        return None;
+    };
+    let ok = letter.is_ascii_lowercase() || b"\\]^_".contains(&letter);
+    (ok && !RESERVED.contains(&letter)).then_some(control(letter) as u8)
+}
+
+fn key_name(byte: u8) -> String {
+    match byte {
+        b'\t' => "Tab".to_string(),
+        b => format!("Ctrl-{}", ((b | 0x40) as char).to_ascii_uppercase()),
+    }
+}
+
+fn action_name(action: Action) -> &'static str {
+    ACTIONS
+        .iter()
+        .find(|(a, ..)| *a == action)
+        .map_or("", |(_, name, _)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(a, k)| (a.to_string(), k.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn rebinds_and_detects_conflicts() {
+        let map = Keymap::default();
+        assert_eq!(map.action(7), Some(Action::ExternalEditor));
+        assert_eq!(map.action(b'\t'), Some(Action::Complete));
+        assert_eq!(map.key(Action::Home).as_deref(), Some("Ctrl-A"));
+
+        let map = Keymap::new(&keys(&[
+            ("external_editor", "Ctrl-X"),
+            ("tool_output", "none"),
+        ]))
+        .unwrap();
+        assert_eq!(map.action(7), None);
+        assert_eq!(map.action(24), Some(Action::ExternalEditor));
+        assert_eq!(map.action(15), None);
+        assert_eq!(map.key(Action::ToolOutput), None);
+
+        // Swapping two keys moves both off first
+        let map = Keymap::new(&keys(&[("home", "ctrl-e"), ("end", "ctrl-a")]))
+            .unwrap();
+        assert_eq!(map.key(Action::Home).as_deref(), Some("Ctrl-E"));
+
+        assert_eq!(
+            Keymap::new(&keys(&[("external_editor", "ctrl-e")])),
+            Err("keys.external_editor: Ctrl-E is already bound to end".into())
+        );
+        assert_eq!(
+            Keymap::new(&keys(&[("home", "ctrl-x"), ("end", "ctrl-x")])),
+            Err("keys.home: Ctrl-X is already bound to end".into())
+        );
+        assert!(Keymap::new(&keys(&[("home", "ctrl-m")])).is_err());
+        assert!(Keymap::new(&keys(&[("home", "alt-a")])).is_err());
+        assert!(Keymap::new(&keys(&[("launch", "ctrl-x")])).is_err());
+    }
+}
@@ -0,0 +1,461 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{Value, json};
+
+const INIT_TIMEOUT: Duration = Duration::from_secs(30);
+const DIAGNOSTIC_TIMEOUT: Duration = Duration::from_secs(10);
+/// After the first diagnostics arrive, keep listening this long
+/// for a refined set (servers often publish twice).
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+const MAX_DIAGNOSTICS: usize = 20;
+
+/// Language servers keyed by file extension, started lazily
+/// the first time a file with that extension is edited.
+pub struct LspManager {
+    commands: HashMap<String, Vec<String>>,
+    root: PathBuf,
+    servers: Mutex<HashMap<String, Server>>,
+    /// Extensions whose server would not start, left alone
+    /// until `restart`.
+    failed: Mutex<HashSet<String>>,
+}
+
+impl LspManager {
+    pub fn new(commands: HashMap<String, Vec<String>>, root: &Path) -> Self {
+        Self {
+            commands,
+            root: root.to_path_buf(),
+            servers: Mutex::new(HashMap::new()),
+            failed: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Stop the servers and try the ones that failed to start
+    /// again on the next edit (`/lsp restart`).
+    pub fn restart(&self) {
+        self.stop();
+        if let Ok(mut failed) = self.failed.lock() {
+            failed.clear();
+        }
+    }
+
+    /// Shut down the running servers; the next edit starts
+    /// them again.
+    pub fn stop(&self) {
+        if let Ok(mut servers) = self.servers.lock() {
+            servers.clear();
+        }
+    }
+
+    /// Sync `path` with its language server and return the
+    /// formatted errors and warnings, if any.
+    pub fn diagnostics(&self, path: &Path) -> Option<String> {
+        let ext = path.extension()?.to_str()?;
+        let argv = self.commands.get(ext)?;
+        let mut failed = self.failed.lock().ok()?;
+        if failed.contains(ext) {
+            return None;
+        }
+        let text = std::fs::read_to_string(path).ok()?;
+
+        let mut servers = self.servers.lock().ok()?;
+        if !servers.contains_key(ext) {
+            match Server::start(argv, &self.root) {
+                Ok(s) => {
+                    servers.insert(ext.to_string(), s);
+                }
+                Err(e) => {
+                    eprintln!(
+                        "* lsp: cannot start {}: {e} (/lsp restart to retry)",
+                        argv[0]
+                    );
+                    failed.insert(ext.to_string());
+                    return None;
+                }
+            }
+        }
+        let server = servers.get_mut(ext)?;
+        let diags = match server.check(path, ext, &text) {
+            Ok(d) => d,
+            Err(e) => {
+                eprintln!("* lsp: {}: {e}", argv[0]);
+                servers.remove(ext);
+                return None;
+            }
+        };
+        format_diagnostics(&argv[0], path, &self.root, &diags)
+    }
+}
+
+struct Server {
+    child: Child,
+    stdin: Arc<Mutex<ChildStdin>>,
+    rx: Receiver<Value>,
+    next_id: u64,
+    /// Open documents and their current version.
+    versions: HashMap<String, i64>,
+}
+
+impl Server {
+    fn start(argv: &[String], root: &Path) -> io::Result<Self> {
+        let mut child = Command::new(&argv[0])
+            .args(&argv[1..])
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = Arc::new(Mutex::new(child.stdin.take().unwrap()));
+        let stdout = child.stdout.take().unwrap();
+        let (tx, rx) = mpsc::channel();
+
+        let reply_to = Arc::clone(&stdin);
+        thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            while let Ok(Some(msg)) = read_message(&mut reader) {
+                // Server-to-client requests need a reply or some
+                // servers stall; we accept them all with null.
+                if msg.get("method").is_some()
+                    && let Some(id) = msg.get("id")
+                {
+                    let reply = json!({
+                        "jsonrpc": "2.0", "id": id, "result": null
+                    });
+                    if let Ok(mut w) = reply_to.lock() {
+                        let _ = write_message(&mut *w, &reply);
+                    }
+                    continue;
+                }
+                if tx.send(msg).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut server = Server {
+            child,
+            stdin,
+            rx,
+            next_id: 1,
+            versions: HashMap::new(),
+        };
+        server.initialize(root)?;
+        Ok(server)
+    }
+
+    fn initialize(&mut self, root: &Path) -> io::Result<()> {
+        let id = self.request(
+            "initialize",
+            json!({
+                "processId": std::process::id(),
+                "rootUri": file_uri(root),
+                "capabilities": {
+                    "textDocument": {
+                        "publishDiagnostics": { "versionSupport": true }
+                    }
+                }
+            }),
+        )?;
+        let deadline = Instant::now() + INIT_TIMEOUT;
+        loop {
+            let msg = self.recv_until(deadline)?;
+            if msg.get("id").and_then(Value::as_u64) == Some(id) {
+                break;
+            }
+        }
+        self.notify("initialized", json!({}))
+    }
+
+    /// Send the latest text of `path` and wait for the
+    /// server's diagnostics for it.
+    fn check(
+        &mut self,
+        path: &Path,
+        ext: &str,
+        text: &str,
+    ) -> io::Result<Vec<Value>> {
+        let uri = file_uri(path);
+        // Drop stale notifications from earlier edits
+        while self.rx.try_recv().is_ok() {}
+
+        match self.versions.get_mut(&uri) {
+            Some(version) => {
+                *version += 1;
+                let version = *version;
+                self.notify(
+                    "textDocument/didChange",
+                    json!({
+                        "textDocument": { "uri": uri, "version": version },
+                        "contentChanges": [{ "text": text }]
+                    }),
+                )?;
+            }
+            None => {
+                self.versions.insert(uri.clone(), 1);
+                self.notify(
+                    "textDocument/didOpen",
+                    json!({
+                        "textDocument": {
+                            "uri": uri,
+                            "languageId": language_id(ext),
+                            "version": 1,
+                            "text": text
+                        }
+                    }),
+                )?;
+            }
+        }
+        self.notify(
+            "textDocument/didSave",
+            json!({ "textDocument": { "uri": uri } }),
+        )?;
+
+        let mut latest = None;
+        let mut deadline = Instant::now() + DIAGNOSTIC_TIMEOUT;
+        loop {
+            let msg = match self.recv_until(deadline) {
+                Ok(m) => m,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => break,
+                Err(e) => return Err(e),
+            };
+            if msg["method"] != "textDocument/publishDiagnostics"
+                || msg["params"]["uri"] != uri.as_str()
+            {
+                continue;
+            }
+            if latest.is_none() {
+                deadline = Instant::now() + SETTLE_TIME;
+            }
+            latest = msg["params"]["diagnostics"].as_array().cloned();
+        }
+        Ok(latest.unwrap_or_default())
+    }
+
+    fn recv_until(&self, deadline: Instant) -> io::Result<Value> {
+        let wait = deadline.saturating_duration_since(Instant::now());
+        match self.rx.recv_timeout(wait) {
+            Ok(msg) => Ok(msg),
+            Err(RecvTimeoutError::Timeout) => {
+                Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"))
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "server exited"))
+            }
+        }
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> io::Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(&json!({
+            "jsonrpc": "2.0", "id": id, "method": method, "params": params
+        }))?;
+        Ok(id)
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> io::Result<()> {
+        self.send(&json!({
+            "jsonrpc": "2.0", "method": method, "params": params
+        }))
+    }
+
+    fn send(&self, msg: &Value) -> io::Result<()> {
+        let mut w = self
+            .stdin
+            .lock()
+            .map_err(|_| io::Error::other("lsp stdin poisoned"))?;
+        write_message(&mut *w, msg)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.request("shutdown", Value::Null);
+        let _ = self.notify("exit", Value::Null);
+        thread::sleep(Duration::from_millis(50));
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn write_message(w: &mut impl Write, msg: &Value) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(w, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    w.flush()
+}
+
+/// Read one `Content-Length` framed JSON-RPC message.
+/// Returns `Ok(None)` on EOF.
+fn read_message(r: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(v) = line.strip_prefix("Content-Length:") {
+            len = v.trim().parse::<usize>().ok();
+        }
+    }
+    let len = len.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length")
+    })?;
+    let mut body = vec![0u8; len];
+    r.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for ch in path.to_string_lossy().chars() {
+        match ch {
+            ' ' => uri.push_str("%20"),
+            '#' => uri.push_str("%23"),
+            '%' => uri.push_str("%25"),
+            '?' => uri.push_str("%3F"),
+            c => uri.push(c),
+        }
+    }
+    uri
+}
+
+fn language_id(ext: &str) -> &str {
+    match ext {
+        "rs" => "rust",
+        "py" => "python",
+        "js" => "javascript",
+        "ts" => "typescript",
+        "tsx" => "typescriptreact",
+        "jsx" => "javascriptreact",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "hpp" => "cpp",
+        "go" => "go",
+        "rb" => "ruby",
+        "sh" => "shellscript",
+        other => other,
+    }
+}
+
+/// Format errors and warnings as `path:line:col: severity: msg`.
+/// Returns `None` when there is nothing worth reporting.
+fn format_diagnostics(
+    server: &str,
+    path: &Path,
+    root: &Path,
+    diags: &[Value],
+) -> Option<String> {
+    let display = path.strip_prefix(root).unwrap_or(path).display();
+    let mut lines = Vec::new();
+    for d in diags {
+        let severity = match d["severity"].as_u64().unwrap_or(1) {
+            1 => "error",
+            2 => "warning",
+            _ => continue,
+        };
+        let line = d["range"]["start"]["line"].as_u64().unwrap_or(0) + 1;
+        let col = d["range"]["start"]["character"].as_u64().unwrap_or(0) + 1;
+        let msg = d["message"].as_str().unwrap_or("").replace('\n', " ");
+        lines.push(format!("  {display}:{line}:{col}: {severity}: {msg}"));
+    }
+    if lines.is_empty() {
+        return None;
+    }
+    let total = lines.len();
+    lines.truncate(MAX_DIAGNOSTICS);
+    let mut out = format!("LSP diagnostics ({server}):\n");
+    out.push_str(&lines.join("\n"));
+    if total > MAX_DIAGNOSTICS {
+        out.push_str(&format!("\n  ... ({total} total)"));
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn failed_servers_wait_for_restart() {
+        let dir = std::env::temp_dir().join("tapir_lsp_failed");
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.zz");
+        std::fs::write(&file, "x").unwrap();
+        let argv = vec!["tapir-no-such-language-server".to_string()];
+        let lsp = LspManager::new(HashMap::from([("zz".into(), argv)]), &dir);
+        assert_eq!(lsp.diagnostics(&file), None);
+        assert!(lsp.failed.lock().unwrap().contains("zz"));
+        lsp.stop();
+        assert!(lsp.failed.lock().unwrap().contains("zz"));
+        lsp.restart();
+        assert!(lsp.failed.lock().unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn message_round_trip() {
+        let msg = json!({ "jsonrpc": "2.0", "method": "x", "params": [1] });
+        let mut buf = Vec::new();
+        write_message(&mut buf, &msg).unwrap();
+        let mut r = Cursor::new(buf);
+        assert_eq!(read_message(&mut r).unwrap(), Some(msg));
+        assert_eq!(read_message(&mut r).unwrap(), None);
+    }
+
+    #[test]
+    fn read_message_requires_length() {
+        let mut r = Cursor::new(b"X-Other: 1\r\n\r\n{}".to_vec());
+        assert!(read_message(&mut r).is_err());
+    }
+
+    #[test]
+    fn file_uri_escapes_reserved() {
+        assert_eq!(file_uri(Path::new("/a b/c#d")), "file:///a%20b/c%23d");
+    }
+
+    #[test]
+    fn format_diagnostics_keeps_errors_and_warnings() {
+        let diags = vec![
+            json!({
+                "severity": 1,
+                "range": { "start": { "line": 9, "character": 4 } },
+                "message": "mismatched types"
+            }),
+            json!({
+                "severity": 3,
+                "range": { "start": { "line": 0, "character": 0 } },
+                "message": "info"
+            }),
+        ];
+        let out = format_diagnostics(
+            "rust-analyzer",
+            Path::new("/p/src/main.rs"),
+            Path::new("/p"),
+            &diags,
+        )
+        .unwrap();
+        assert!(out.contains("src/main.rs:10:5: error: mismatched types"));
+        assert!(!out.contains("info"));
+    }
+
+    #[test]
+    fn format_diagnostics_empty_is_none() {
+        assert!(
+            format_diagnostics("x", Path::new("/a"), Path::new("/"), &[])
+                .is_none()
+        );
+    }
+}
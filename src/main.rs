@@ -1,31 +1,124 @@
 mod agent;
 mod api;
+mod attach;
+mod checkpoint;
+mod cli;
 mod command;
 mod config;
 mod context;
+mod cost;
 mod display;
 mod error;
+mod events;
+mod export;
+mod highlight;
+mod i18n;
+mod keymap;
+mod lsp;
+mod manifest;
+mod memory;
+mod notify;
+mod patch;
+mod plan;
 mod readline;
 mod session;
 mod signal;
 mod skill;
 mod sse;
 mod stream;
+mod telemetry;
+#[cfg(test)]
+mod testing;
+mod theme;
 mod timer;
+mod tokens;
+mod toml;
 mod tool;
+mod trash;
 mod types;
 mod util;
+mod validate;
+mod web;
+mod worktree;
 
 use std::process;
 
 const VERSION: &str = "tapir v0.1.0";
 
 fn main() {
-    let config_path = match parse_args() {
-        Some(path) => path,
+    let args = match parse_args() {
+        Some(args) => args,
         None => return,
     };
+    for (from, to) in config::dirs().migrate() {
+        eprintln!("moved {} to {}", from.display(), to.display());
+    }
+    if let Some(cmd) = &args.sessions {
+        process::exit(cli::sessions(
+            cmd,
+            args.config_path.as_deref(),
+            args.profile.as_deref(),
+        ));
+    }
+
+    if args.prompt.is_none() {
+        print_banner();
+    }
+    signal::install_handler();
 
+    // Enter the worktree before loading config so that
+    // working_dir, sessions and the sandbox all point at it
+    let worktree = args.worktree.as_deref().map(|branch| {
+        let cwd = std::env::current_dir().unwrap_or_else(|e| {
+            eprintln!("error: {e}");
+            process::exit(1);
+        });
+        let created = worktree::Worktree::create(&cwd, branch).and_then(|wt| {
+            std::env::set_current_dir(&wt.path).map_err(|e| {
+                format!("cannot enter {}: {e}", wt.path.display())
+            })?;
+            Ok(wt)
+        });
+        match created {
+            Ok(wt) => {
+                eprintln!("worktree: {} ({branch})", wt.path.display());
+                wt
+            }
+            Err(e) => {
+                eprintln!("error: {e}");
+                process::exit(1);
+            }
+        }
+    });
+
+    let result = config::Config::load(
+        args.config_path.as_deref(),
+        args.profile.as_deref(),
+    )
+    .and_then(|mut config| {
+        let launch = agent::Launch {
+            continue_last: args.continue_last,
+            prompt: args.prompt.clone(),
+        };
+        agent::run(&mut config, launch)
+    });
+    notify::restore_title();
+
+    if let Some(wt) = worktree {
+        let _ = std::env::set_current_dir(&wt.root);
+        wt.cleanup();
+    }
+    if let Err(e) = &result {
+        events::emit("error", serde_json::json!({"error": e.to_string()}));
+    }
+    telemetry::shutdown();
+    if let Err(e) = result {
+        e.report("error: ");
+        process::exit(1);
+    }
+}
+
+fn print_banner() {
     eprintln!(
         r#"
    ░██                          ░██
@@ -41,46 +134,61 @@ fn main() {
                   v0.1.0
 "#
     );
-    signal::install_handler();
+}
 
-    let mut config = match config::Config::load(config_path.as_deref()) {
-        Ok(c) => c,
-        Err(e) => {
-            eprintln!("error: {e}");
-            process::exit(1);
-        }
-    };
+const USAGE: &str = "usage: tapir [-V] [-c config.toml] [--profile name]
+                   [--worktree branch] [--continue] [-p prompt]
+       tapir sessions list|show|export|delete|search|summarize";
 
-    if let Err(e) = agent::run(&mut config) {
-        eprintln!("error: {e}");
-        process::exit(1);
-    }
+/// Command-line options.
+#[derive(Default)]
+struct Args {
+    config_path: Option<String>,
+    /// Config profile to apply, overriding `$TAPIR_PROFILE`.
+    profile: Option<String>,
+    /// Run inside a git worktree checked out on this branch.
+    worktree: Option<String>,
+    /// Resume the most recent session.
+    continue_last: bool,
+    /// Answer this prompt without the line editor, then exit.
+    prompt: Option<String>,
+    /// `tapir sessions <args>` subcommand.
+    sessions: Option<Vec<String>>,
 }
 
-/// Returns `Some(config_path)` to continue, `None` to exit.
-fn parse_args() -> Option<Option<String>> {
-    let args: Vec<String> = std::env::args().skip(1).collect();
-    match args.first().map(String::as_str) {
-        None => Some(None),
-        Some("-V") => {
-            println!("{VERSION}");
-            None
-        }
-        Some("-c") => {
-            let path = args.get(1).unwrap_or_else(|| {
-                eprintln!("error: -c requires a path");
+/// Returns `Some(args)` to continue, `None` to exit.
+fn parse_args() -> Option<Args> {
+    let mut args = Args::default();
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = |flag: &str, what: &str| {
+            iter.next().unwrap_or_else(|| {
+                eprintln!("error: {flag} requires {what}");
                 process::exit(1);
-            });
-            if args.len() > 2 {
-                eprintln!("error: unexpected argument: {}", args[2]);
+            })
+        };
+        match arg.as_str() {
+            "-V" => {
+                println!("{VERSION}");
+                return None;
+            }
+            "-c" => args.config_path = Some(value("-c", "a path")),
+            "--profile" => args.profile = Some(value("--profile", "a name")),
+            "--worktree" => {
+                args.worktree = Some(value("--worktree", "a branch"));
+            }
+            "--continue" => args.continue_last = true,
+            "-p" => args.prompt = Some(value("-p", "a prompt")),
+            "sessions" => {
+                args.sessions = Some(iter.collect());
+                break;
+            }
+            other => {
+                eprintln!("error: unknown argument: {other}");
+                eprintln!("{USAGE}");
                 process::exit(1);
             }
-            Some(Some(path.clone()))
-        }
-        Some(other) => {
-            eprintln!("error: unknown argument: {other}");
-            eprintln!("usage: tapir [-V] [-c config.json]");
-            process::exit(1);
         }
     }
+    Some(args)
 }
@@ -0,0 +1,227 @@
+use std::fs;
+use std::path::Path;
+
+use serde_json::Value;
+
+/// Longest dependency list shown per group before "+N more".
+const MAX_DEPS: usize = 40;
+
+type Summarizer = fn(&Value) -> String;
+
+/// Summarize Cargo.toml, package.json and pyproject.toml in
+/// `working_dir` (name, version, direct dependencies, scripts)
+/// as a `<project>` block for the system prompt. Empty when
+/// there are no manifests.
+pub fn project_block(working_dir: &Path) -> String {
+    let mut parts = Vec::new();
+    let manifests: [(&str, Summarizer); 3] = [
+        ("Cargo.toml", summarize_cargo),
+        ("package.json", summarize_package_json),
+        ("pyproject.toml", summarize_pyproject),
+    ];
+    for (file, summarize) in manifests {
+        let path = working_dir.join(file);
+        let Ok(text) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let parsed = if file.ends_with(".json") {
+            serde_json::from_str(&text).map_err(|e| e.to_string())
+        } else {
+            crate::toml::parse(&text)
+        };
+        match parsed {
+            Ok(v) => parts.push(format!("{file}: {}", summarize(&v))),
+            Err(e) => eprintln!("warning: {}: {e}", path.display()),
+        }
+    }
+    if parts.is_empty() {
+        return String::new();
+    }
+    format!("<project>\n{}\n</project>", parts.join("\n"))
+}
+
+fn summarize_cargo(v: &Value) -> String {
+    let pkg = &v["package"];
+    let mut out = name_version(pkg);
+    if let Some(members) = v["workspace"]["members"].as_array() {
+        let members: Vec<&str> =
+            members.iter().filter_map(Value::as_str).collect();
+        out.push_str(&format!("\n  workspace members: {}", members.join(", ")));
+    }
+    for (table, label) in [
+        ("dependencies", "dependencies"),
+        ("dev-dependencies", "dev-dependencies"),
+        ("build-dependencies", "build-dependencies"),
+    ] {
+        push_list(&mut out, label, &table_keys(&v[table]));
+    }
+    let features = table_keys(&v["features"]);
+    push_list(&mut out, "features", &features);
+    out
+}
+
+fn summarize_package_json(v: &Value) -> String {
+    let mut out = name_version(v);
+    for key in ["dependencies", "devDependencies", "peerDependencies"] {
+        push_list(&mut out, key, &table_keys(&v[key]));
+    }
+    if let Some(scripts) = v["scripts"].as_object() {
+        let scripts: Vec<String> = scripts
+            .iter()
+            .map(|(k, cmd)| format!("{k}: {}", cmd.as_str().unwrap_or("")))
+            .collect();
+        if !scripts.is_empty() {
+            out.push_str("\n  scripts:");
+            for s in scripts {
+                out.push_str(&format!("\n    {s}"));
+            }
+        }
+    }
+    out
+}
+
+fn summarize_pyproject(v: &Value) -> String {
+    let project = &v["project"];
+    let poetry = &v["tool"]["poetry"];
+    let meta = if project.is_object() { project } else { poetry };
+    let mut out = name_version(meta);
+
+    // PEP 621 lists requirement strings; Poetry uses a table
+    let deps: Vec<String> = match project["dependencies"].as_array() {
+        Some(list) => list
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        None => table_keys(&poetry["dependencies"])
+            .into_iter()
+            .filter(|k| k != "python")
+            .collect(),
+    };
+    push_list(&mut out, "dependencies", &deps);
+    if let Some(groups) = project["optional-dependencies"].as_object() {
+        for (group, list) in groups {
+            let list: Vec<String> = list
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect();
+            push_list(&mut out, &format!("optional [{group}]"), &list);
+        }
+    }
+    let scripts = if project["scripts"].is_object() {
+        &project["scripts"]
+    } else {
+        &poetry["scripts"]
+    };
+    if let Some(scripts) = scripts.as_object() {
+        let scripts: Vec<String> = scripts
+            .iter()
+            .map(|(k, cmd)| format!("{k} = {}", cmd.as_str().unwrap_or("")))
+            .collect();
+        push_list(&mut out, "scripts", &scripts);
+    }
+    out
+}
+
+fn name_version(v: &Value) -> String {
+    let name = v["name"].as_str().unwrap_or("(unnamed)");
+    match v["version"].as_str() {
+        Some(ver) => format!("{name} {ver}"),
+        None => name.to_string(),
+    }
+}
+
+fn table_keys(v: &Value) -> Vec<String> {
+    v.as_object()
+        .map(|m| m.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+fn push_list(out: &mut String, label: &str, items: &[String]) {
+    if items.is_empty() {
+        return;
+    }
+    let shown = &items[..items.len().min(MAX_DEPS)];
+    out.push_str(&format!("\n  {label}: {}", shown.join(", ")));
+    if items.len() > MAX_DEPS {
+        out.push_str(&format!(" (+{} more)", items.len() - MAX_DEPS));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tempdir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("tapir_manifest_{name}"));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn cargo_and_package_json() {
+        let dir = tempdir("mixed");
+        fs::write(
+            dir.join("Cargo.toml"),
+            "[package]\nname = \"tapir\"\nversion = \"0.1.0\"\n\n\
+             [dependencies]\nlibc = \"0.2\"\n\
+             serde = { version = \"1\", features = [\"derive\"] }\n\n\
+             [dev-dependencies]\ntempfile = \"3\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("package.json"),
+            r#"{"name": "web", "version": "1.2.3",
+                "dependencies": {"react": "^18"},
+                "scripts": {"test": "vitest", "build": "vite build"}}"#,
+        )
+        .unwrap();
+
+        let block = project_block(&dir);
+        assert_eq!(
+            block,
+            "<project>\n\
+             Cargo.toml: tapir 0.1.0\n  \
+             dependencies: libc, serde\n  \
+             dev-dependencies: tempfile\n\
+             package.json: web 1.2.3\n  \
+             dependencies: react\n  \
+             scripts:\n    build: vite build\n    test: vitest\n\
+             </project>"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pyproject_pep621_and_poetry() {
+        let pep = crate::toml::parse(
+            "[project]\nname = \"app\"\nversion = \"2.0\"\n\
+             dependencies = [\"requests>=2\", \"click\"]\n\
+             [project.scripts]\napp = \"app.cli:main\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            summarize_pyproject(&pep),
+            "app 2.0\n  dependencies: requests>=2, click\n  \
+             scripts: app = app.cli:main"
+        );
+
+        let poetry = crate::toml::parse(
+            "[tool.poetry]\nname = \"p\"\n\
+             [tool.poetry.dependencies]\npython = \"^3.11\"\nhttpx = \"*\"\n",
+        )
+        .unwrap();
+        assert_eq!(summarize_pyproject(&poetry), "p\n  dependencies: httpx");
+    }
+
+    #[test]
+    fn no_manifests_is_empty() {
+        let dir = tempdir("none");
+        assert_eq!(project_block(&dir), "");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,261 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use serde_json::Value;
+
+/// Tool type for the API's client-side memory tool.
+pub const TOOL_TYPE: &str = "memory_20250818";
+
+/// Directory the model's `/memories` maps to, per project.
+pub fn dir(working_dir: &Path) -> PathBuf {
+    working_dir.join(".tapir/agent-memory")
+}
+
+/// Run one memory tool command against `root`. The model
+/// addresses files as `/memories/...`; nothing outside `root`
+/// can be reached.
+pub fn execute(root: &Path, input: &Value) -> Result<String, String> {
+    let command = input["command"].as_str().ok_or("missing command")?;
+    let arg = |key: &str| {
+        input[key]
+            .as_str()
+            .ok_or_else(|| format!("{command}: missing {key}"))
+    };
+    fs::create_dir_all(root)
+        .map_err(|e| format!("cannot create memory directory: {e}"))?;
+    match command {
+        "view" => {
+            let path = arg("path")?;
+            view(root, path, input["view_range"].as_array())
+        }
+        "create" => {
+            let path = arg("path")?;
+            let file = resolve(root, path)?;
+            if let Some(parent) = file.parent() {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            fs::write(&file, arg("file_text")?)
+                .map_err(|e| format!("cannot write {path}: {e}"))?;
+            Ok(format!("File created successfully at {path}"))
+        }
+        "str_replace" => {
+            let path = arg("path")?;
+            let (old, new) = (arg("old_str")?, arg("new_str")?);
+            let file = resolve(root, path)?;
+            let text = read(&file, path)?;
+            match text.matches(old).count() {
+                0 => return Err(format!("old_str not found in {path}")),
+                1 => {}
+                n => {
+                    return Err(format!(
+                        "old_str appears {n} times in {path}; \
+                         make it unique"
+                    ));
+                }
+            }
+            fs::write(&file, text.replacen(old, new, 1))
+                .map_err(|e| format!("cannot write {path}: {e}"))?;
+            Ok(format!("The memory file {path} has been edited"))
+        }
+        "insert" => {
+            let path = arg("path")?;
+            let at = input["insert_line"]
+                .as_u64()
+                .ok_or("insert: missing insert_line")?
+                as usize;
+            let file = resolve(root, path)?;
+            let text = read(&file, path)?;
+            let mut lines: Vec<&str> = text.lines().collect();
+            if at > lines.len() {
+                return Err(format!(
+                    "insert_line {at} is past the end of {path} \
+                     ({} lines)",
+                    lines.len()
+                ));
+            }
+            let insert = arg("insert_text")?;
+            lines.splice(at..at, insert.lines());
+            let mut out = lines.join("\n");
+            out.push('\n');
+            fs::write(&file, out)
+                .map_err(|e| format!("cannot write {path}: {e}"))?;
+            Ok(format!("Text inserted at line {at} of {path}"))
+        }
+        "delete" => {
+            let path = arg("path")?;
+            let target = resolve(root, path)?;
+            if target == root {
+                return Err("cannot delete /memories itself".to_string());
+            }
+            let removed = if target.is_dir() {
+                fs::remove_dir_all(&target)
+            } else {
+                fs::remove_file(&target)
+            };
+            removed.map_err(|e| format!("cannot delete {path}: {e}"))?;
+            Ok(format!("Deleted {path}"))
+        }
+        "rename" => {
+            let (from, to) = (arg("old_path")?, arg("new_path")?);
+            let src = resolve(root, from)?;
+            let dst = resolve(root, to)?;
+            if dst.exists() {
+                return Err(format!("{to} already exists"));
+            }
+            if let Some(parent) = dst.parent() {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            fs::rename(&src, &dst)
+                .map_err(|e| format!("cannot rename {from}: {e}"))?;
+            Ok(format!("Renamed {from} to {to}"))
+        }
+        _ => Err(format!("unknown memory command: {command}")),
+    }
+}
+
+/// List a directory two levels deep, or show a file with line
+/// numbers, optionally only `[start, end]` (1-indexed, -1 for
+/// the last line).
+fn view(
+    root: &Path,
+    path: &str,
+    range: Option<&Vec<Value>>,
+) -> Result<String, String> {
+    let target = resolve(root, path)?;
+    if target.is_dir() {
+        let mut out = format!("Directory: {path}\n");
+        list(&target, 0, &mut out);
+        return Ok(out);
+    }
+    let text = read(&target, path)?;
+    let lines: Vec<&str> = text.lines().collect();
+    let (start, end) = match range.map(|r| r.as_slice()) {
+        Some([s, e]) => {
+            let s = s.as_i64().unwrap_or(1).max(1) as usize;
+            let e = match e.as_i64() {
+                Some(e) if e >= 0 => e as usize,
+                _ => lines.len(),
+            };
+            (s, e.min(lines.len()))
+        }
+        Some(_) => return Err("view_range must be [start, end]".to_string()),
+        None => (1, lines.len()),
+    };
+    let mut out = String::new();
+    for (i, line) in lines.iter().enumerate().take(end).skip(start - 1) {
+        out.push_str(&format!("{:6}\t{line}\n", i + 1));
+    }
+    Ok(out)
+}
+
+fn list(dir: &Path, depth: usize, out: &mut String) {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut entries: Vec<_> = read_dir.flatten().collect();
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        let indent = "  ".repeat(depth + 1);
+        let path = entry.path();
+        if path.is_dir() {
+            out.push_str(&format!("{indent}{name}/\n"));
+            if depth == 0 {
+                list(&path, depth + 1, out);
+            }
+        } else {
+            let size = entry.metadata().map_or(0, |m| m.len());
+            out.push_str(&format!("{indent}{name} ({size} bytes)\n"));
+        }
+    }
+}
+
+fn read(file: &Path, path: &str) -> Result<String, String> {
+    fs::read_to_string(file).map_err(|e| format!("cannot read {path}: {e}"))
+}
+
+/// Map `/memories/a/b.md` to `root/a/b.md`, refusing `..`,
+/// paths outside `/memories` and symlinks that leave `root`.
+fn resolve(root: &Path, path: &str) -> Result<PathBuf, String> {
+    let rest = path
+        .strip_prefix("/memories")
+        .filter(|r| r.is_empty() || r.starts_with('/'))
+        .ok_or_else(|| format!("{path}: paths must start with /memories"))?;
+    let mut out = root.to_path_buf();
+    for part in Path::new(rest).components() {
+        match part {
+            Component::Normal(p) => out.push(p),
+            Component::RootDir | Component::CurDir => {}
+            _ => return Err(format!("{path}: invalid path")),
+        }
+    }
+    // The deepest existing ancestor must still be inside root
+    let real_root = root.canonicalize().map_err(|e| e.to_string())?;
+    let existing = out.ancestors().find(|p| p.exists()).unwrap_or(root);
+    let real = existing.canonicalize().map_err(|e| e.to_string())?;
+    if !real.starts_with(&real_root) {
+        return Err(format!("{path}: escapes the memory directory"));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn memory_commands() {
+        let root = std::env::temp_dir().join("tapir_memory_commands");
+        let _ = fs::remove_dir_all(&root);
+        let run = |input: Value| execute(&root, &input);
+
+        run(json!({
+            "command": "create",
+            "path": "/memories/notes/api.md",
+            "file_text": "one\nthree\n",
+        }))
+        .unwrap();
+        run(json!({
+            "command": "insert",
+            "path": "/memories/notes/api.md",
+            "insert_line": 1,
+            "insert_text": "two",
+        }))
+        .unwrap();
+        run(json!({
+            "command": "str_replace",
+            "path": "/memories/notes/api.md",
+            "old_str": "three",
+            "new_str": "3",
+        }))
+        .unwrap();
+        let out = run(json!({
+            "command": "view",
+            "path": "/memories/notes/api.md",
+            "view_range": [2, -1],
+        }))
+        .unwrap();
+        assert_eq!(out, "     2\ttwo\n     3\t3\n");
+
+        run(json!({
+            "command": "rename",
+            "old_path": "/memories/notes",
+            "new_path": "/memories/project",
+        }))
+        .unwrap();
+        let out = run(json!({"command": "view", "path": "/memories"})).unwrap();
+        assert!(out.contains("  project/\n    api.md (10 bytes)"), "{out}");
+
+        for bad in ["/memories/../x", "/etc/passwd", "/memoriesx"] {
+            let input = json!({"command": "view", "path": bad});
+            assert!(run(input).is_err(), "{bad}");
+        }
+        run(json!({"command": "delete", "path": "/memories/project"})).unwrap();
+        assert!(!root.join("project").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use serde::Deserialize;
+
+/// How to get the user's attention when tapir is done or
+/// waiting on them, from `notify` in config.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Alert {
+    #[default]
+    Off,
+    /// Ring the terminal bell.
+    Bell,
+    /// `notify-send`, or `osascript` on macOS; the bell when
+    /// neither works.
+    Desktop,
+}
+
+impl Alert {
+    pub fn name(self) -> &'static str {
+        match self {
+            Alert::Off => "off",
+            Alert::Bell => "bell",
+            Alert::Desktop => "desktop",
+        }
+    }
+}
+
+static TITLE: AtomicBool = AtomicBool::new(false);
+static ALERT: AtomicU8 = AtomicU8::new(0);
+
+/// Show what tapir is doing in the terminal title when
+/// `title`, and alert the user by `alert`. The title from
+/// before is saved, for `restore_title`.
+pub fn configure(title: bool, alert: Alert) {
+    let title = title && terminal();
+    if title && !TITLE.swap(true, Ordering::Relaxed) {
+        // Push the title onto xterm's stack
+        eprint!("\x1b[22;0t");
+    } else if !title {
+        restore_title();
+    }
+    ALERT.store(alert as u8, Ordering::Relaxed);
+}
+
+/// Put back the title from before `configure`.
+pub fn restore_title() {
+    if TITLE.swap(false, Ordering::Relaxed) {
+        eprint!("\x1b[23;0t");
+    }
+}
+
+/// Set the terminal title to `activity`.
+pub fn title(activity: &str) {
+    if TITLE.load(Ordering::Relaxed) {
+        eprint!("\x1b]0;{}\x07", title_text(activity));
+    }
+}
+
+/// `tapir: activity`, without control characters, which
+/// would end the title sequence early, and cut short.
+fn title_text(activity: &str) -> String {
+    let text: String = format!("tapir: {activity}")
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    crate::util::truncate_line(&text, 60)
+}
+
+/// Get the user's attention with `message`, as configured.
+pub fn alert(message: &str) {
+    let alert = match ALERT.load(Ordering::Relaxed) {
+        1 => Alert::Bell,
+        2 => Alert::Desktop,
+        _ => return,
+    };
+    if alert == Alert::Desktop && desktop(message) {
+        return;
+    }
+    if terminal() {
+        eprint!("\x07");
+    }
+}
+
+fn desktop(message: &str) -> bool {
+    let mut cmd = if cfg!(target_os = "macos") {
+        let mut cmd = Command::new("osascript");
+        cmd.arg("-e").arg(format!(
+            "display notification {message:?} with title \"tapir\""
+        ));
+        cmd
+    } else {
+        let mut cmd = Command::new("notify-send");
+        cmd.args(["tapir", message]);
+        cmd
+    };
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+/// Whether stderr is a terminal that takes escape sequences.
+fn terminal() -> bool {
+    let dumb = matches!(
+        std::env::var("TERM").as_deref(),
+        Err(_) | Ok("" | "dumb" | "unknown")
+    );
+    !dumb && unsafe { libc::isatty(2) } == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_are_safe_to_print() {
+        assert_eq!(title_text("running bash"), "tapir: running bash");
+        assert_eq!(title_text("a\x07b\nc"), "tapir: a b c");
+        assert!(title_text(&"x".repeat(100)).ends_with("..."));
+    }
+}
@@ -0,0 +1,298 @@
+/// One file's changes in a unified diff.
+#[derive(Debug, PartialEq)]
+pub struct FilePatch {
+    /// Path on the `---` line; None for `/dev/null`.
+    pub old: Option<String>,
+    /// Path on the `+++` line; None for `/dev/null`.
+    pub new: Option<String>,
+    hunks: Vec<Hunk>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Hunk {
+    /// 1-based first line of the hunk in the old file.
+    old_start: usize,
+    /// `' '`, `'-'` or `'+'` with the line after it.
+    lines: Vec<(char, String)>,
+}
+
+impl FilePatch {
+    /// The file the patch writes, or deletes when it writes
+    /// none.
+    pub fn path(&self) -> Option<&str> {
+        self.new.as_deref().or(self.old.as_deref())
+    }
+
+    /// `content` with every hunk applied in order. A hunk is
+    /// looked for nearest its stated line first, then with
+    /// trailing whitespace ignored, since models rarely get
+    /// line numbers or blank context lines exactly right.
+    pub fn apply(&self, content: &str) -> Result<String, String> {
+        let eol = if content.contains("\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        };
+        let mut lines: Vec<String> =
+            content.lines().map(String::from).collect();
+        let mut from = 0;
+        for (n, hunk) in self.hunks.iter().enumerate() {
+            let old: Vec<&str> = hunk
+                .lines
+                .iter()
+                .filter(|(op, _)| *op != '+')
+                .map(|(_, l)| l.as_str())
+                .collect();
+            let hint = hunk.old_start.saturating_sub(1).max(from);
+            let at = if old.is_empty() {
+                Some(hint.min(lines.len()))
+            } else {
+                find(&lines, &old, from, hint, |a, b| a == b).or_else(|| {
+                    find(&lines, &old, from, hint, |a, b| {
+                        a.trim_end() == b.trim_end()
+                    })
+                })
+            };
+            let Some(at) = at else {
+                return Err(format!("hunk {} does not match", n + 1));
+            };
+            // Context keeps the file's own text
+            let mut kept = lines[at..at + old.len()].iter();
+            let new: Vec<String> = hunk
+                .lines
+                .iter()
+                .filter_map(|(op, l)| match op {
+                    '+' => Some(l.clone()),
+                    '-' => kept.next().and(None),
+                    _ => kept.next().cloned(),
+                })
+                .collect();
+            let added = new.len();
+            lines.splice(at..at + old.len(), new);
+            from = at + added;
+        }
+        let mut out = lines.join(eol);
+        if !lines.is_empty() && (content.is_empty() || content.ends_with('\n'))
+        {
+            out.push_str(eol);
+        }
+        Ok(out)
+    }
+}
+
+/// Start of `old` in `lines` at or after `from`, the match
+/// closest to `hint` winning.
+fn find(
+    lines: &[String],
+    old: &[&str],
+    from: usize,
+    hint: usize,
+    eq: impl Fn(&str, &str) -> bool,
+) -> Option<usize> {
+    let last = lines.len().checked_sub(old.len())?;
+    if from > last {
+        return None;
+    }
+    let matches = |at: usize| {
+        lines[at..at + old.len()]
+            .iter()
+            .zip(old)
+            .all(|(a, b)| eq(a, b))
+    };
+    let hint = hint.clamp(from, last);
+    (0..=last - from).find_map(|d| {
+        [hint.checked_sub(d), Some(hint + d)]
+            .into_iter()
+            .flatten()
+            .find(|&at| at >= from && at <= last && matches(at))
+    })
+}
+
+/// The file patches in a unified diff. Hunk line counts are
+/// not trusted: a hunk runs until the next header.
+pub fn parse(diff: &str) -> Result<Vec<FilePatch>, String> {
+    let lines: Vec<&str> = diff.lines().collect();
+    let mut patches: Vec<FilePatch> = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if let (Some(old), Some(new)) = (
+            line.strip_prefix("--- "),
+            lines.get(i + 1).and_then(|l| l.strip_prefix("+++ ")),
+        ) {
+            patches.push(FilePatch {
+                old: header_path(old),
+                new: header_path(new),
+                hunks: Vec::new(),
+            });
+            i += 2;
+            continue;
+        }
+        if line.starts_with("@@") {
+            let Some(patch) = patches.last_mut() else {
+                return Err("hunk before any ---/+++ header".into());
+            };
+            let old_start = line
+                .strip_prefix("@@ -")
+                .and_then(|h| h.split([',', ' ']).next())
+                .and_then(|n| n.parse().ok())
+                .ok_or_else(|| format!("bad hunk header: {line}"))?;
+            let mut hunk = Hunk {
+                old_start,
+                lines: Vec::new(),
+            };
+            i += 1;
+            while let Some(&l) = lines.get(i) {
+                let header = l.starts_with("@@")
+                    || l.starts_with("diff ")
+                    || (l.starts_with("--- ")
+                        && lines
+                            .get(i + 1)
+                            .is_some_and(|n| n.starts_with("+++ ")));
+                if header {
+                    break;
+                }
+                match l.chars().next() {
+                    Some(op @ (' ' | '-' | '+')) => {
+                        hunk.lines.push((op, l[1..].to_string()))
+                    }
+                    // A context line whose space was stripped
+                    None => hunk.lines.push((' ', String::new())),
+                    // `\ No newline at end of file`
+                    Some('\\') => {}
+                    Some(_) => {
+                        return Err(format!("unexpected line in hunk: {l}"));
+                    }
+                }
+                i += 1;
+            }
+            // Blank lines trailing the block aren't context
+            while hunk
+                .lines
+                .last()
+                .is_some_and(|(op, l)| *op == ' ' && l.is_empty())
+            {
+                hunk.lines.pop();
+            }
+            patch.hunks.push(hunk);
+            continue;
+        }
+        // `diff --git`, `index`, mode lines and prose
+        i += 1;
+    }
+    if patches.iter().all(|p| p.hunks.is_empty()) {
+        return Err("no hunks found".into());
+    }
+    patches.retain(|p| !p.hunks.is_empty());
+    Ok(patches)
+}
+
+/// The path in a `---`/`+++` header, without a trailing
+/// timestamp or the `a/`/`b/` prefix.
+fn header_path(header: &str) -> Option<String> {
+    let path = header.split('\t').next().unwrap_or(header).trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    let path = path
+        .strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path);
+    Some(path.to_string())
+}
+
+/// Bodies of the fenced code blocks in `text` that hold a
+/// unified diff: tagged `diff` or `patch`, or untagged and
+/// detected as one.
+pub fn diff_blocks(text: &str) -> Vec<String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim();
+        let ticks = line.len() - line.trim_start_matches('`').len();
+        if ticks < 3 {
+            i += 1;
+            continue;
+        }
+        let fence = &line[..ticks];
+        let lang = line[ticks..].trim();
+        let Some(close) = lines[i + 1..].iter().position(|l| l.trim() == fence)
+        else {
+            break;
+        };
+        let body = lines[i + 1..i + 1 + close].join("\n") + "\n";
+        let is_diff = match lang {
+            "diff" | "patch" | "udiff" => true,
+            "" => crate::highlight::detect(&body) == Some("diff"),
+            _ => false,
+        };
+        if is_diff {
+            blocks.push(body);
+        }
+        i += close + 2;
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_hunks_with_drifted_line_numbers() {
+        let diff = "\
+diff --git a/src/lib.rs b/src/lib.rs
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ fn a() {
+-    1
++    2
+ }
+@@ -40,2 +40,3 @@
+ fn b() {
++    // b
+ }
+";
+        let patches = parse(diff).unwrap();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].path(), Some("src/lib.rs"));
+        let content = "fn a() {\n    1\n}\n\nfn b() {\n}\n";
+        assert_eq!(
+            patches[0].apply(content).unwrap(),
+            "fn a() {\n    2\n}\n\nfn b() {\n    // b\n}\n"
+        );
+        assert_eq!(
+            patches[0].apply("fn a() {\n    3\n}\n"),
+            Err("hunk 1 does not match".into())
+        );
+    }
+
+    #[test]
+    fn new_files_and_loose_whitespace() {
+        let diff =
+            "--- /dev/null\n+++ b/notes.txt\n@@ -0,0 +1,2 @@\n+one\n+two\n";
+        let patches = parse(diff).unwrap();
+        assert_eq!(patches[0].old, None);
+        assert_eq!(patches[0].apply("").unwrap(), "one\ntwo\n");
+
+        // Blank context line with its space stripped, and a
+        // trailing space the model dropped
+        let diff = "--- a/x\n+++ b/x\n@@ -1,3 +1,3 @@\n a \n\n-b\n+c\n";
+        let patches = parse(diff).unwrap();
+        assert_eq!(patches[0].apply("a  \n\nb").unwrap(), "a  \n\nc");
+    }
+
+    #[test]
+    fn finds_diff_blocks() {
+        let text = "Change this:\n\n```diff\n--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\n+b\n```\n\
+                    Then:\n```rust\nfn main() {}\n```\n\
+                    ```\n--- a/y\n+++ b/y\n@@ -1 +1 @@\n-c\n+d\n```\n";
+        let blocks = diff_blocks(text);
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks[0].starts_with("--- a/x\n"));
+        assert!(blocks[1].ends_with("+d\n"));
+        assert!(parse("just prose\n").is_err());
+    }
+}
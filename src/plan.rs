@@ -0,0 +1,318 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Asks the model for a plan in the sections `Plan::fill`
+/// reads back.
+const PLAN_PROMPT: &str = "Plan this refactor before changing anything: \
+     {goal}\n\nLook around the code as much as you need; only the tools \
+     that read are available until the plan is approved. End your reply with the plan in exactly these three \
+     markdown sections:\n\n## Files\n- path: why it changes\n\n## Steps\n\
+     1. one self-contained change, in the order to make them\n\n## Risks\n\
+     - what could break, and how to check it\n\nEach step will run as its \
+     own turn once the user approves it, so keep steps small enough to \
+     review one at a time.";
+
+/// A `/plan-refactor` plan and how far it has got, kept
+/// next to the session so it survives a resume.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Plan {
+    pub goal: String,
+    pub files: Vec<String>,
+    pub steps: Vec<Step>,
+    pub risks: Vec<String>,
+    /// The model is still writing the plan.
+    #[serde(default)]
+    pub drafting: bool,
+    /// Index of the step the current turn carries out.
+    #[serde(default)]
+    pub running: Option<usize>,
+    /// The user declined a step; wait for `/plan-refactor`.
+    #[serde(default)]
+    pub paused: bool,
+    /// History length once the draft or step prompt was added;
+    /// only a reply after it counts.
+    #[serde(default)]
+    pub since: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Step {
+    pub text: String,
+    #[serde(default)]
+    pub done: bool,
+    /// Passed over with `/plan-refactor skip`.
+    #[serde(default)]
+    pub skipped: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Section {
+    Files,
+    Steps,
+    Risks,
+}
+
+impl Plan {
+    /// A plan for `goal` waiting on the model.
+    pub fn new(goal: &str) -> Plan {
+        Plan {
+            goal: goal.to_string(),
+            drafting: true,
+            ..Plan::default()
+        }
+    }
+
+    /// The message asking for this plan.
+    pub fn prompt(&self) -> String {
+        PLAN_PROMPT.replace("{goal}", &self.goal)
+    }
+
+    /// Take files, steps and risks from the model's `reply`,
+    /// from list items under headings naming them. Returns
+    /// whether any steps were found.
+    pub fn fill(&mut self, reply: &str) -> bool {
+        let mut section = None;
+        let mut items: Vec<(Section, String)> = Vec::new();
+        for line in reply.lines() {
+            if let Some(s) = heading(line) {
+                section = s;
+                continue;
+            }
+            let Some(current) = section else {
+                continue;
+            };
+            let indented = line.starts_with("  ") || line.starts_with('\t');
+            match list_item(line.trim()) {
+                Some(text) if !indented => {
+                    items.push((current, text.to_string()))
+                }
+                // Sub-items and wrapped lines belong to the
+                // item above
+                _ if !line.trim().is_empty() => {
+                    if let Some((_, last)) = items.last_mut() {
+                        let text = list_item(line.trim()).unwrap_or(line);
+                        last.push(' ');
+                        last.push_str(text.trim());
+                    }
+                }
+                _ => {}
+            }
+        }
+        let of = |want: Section| {
+            items
+                .iter()
+                .filter(move |(s, _)| *s == want)
+                .map(|(_, text)| text.clone())
+        };
+        self.files = of(Section::Files).collect();
+        self.risks = of(Section::Risks).collect();
+        self.steps = of(Section::Steps)
+            .map(|text| Step {
+                text,
+                ..Step::default()
+            })
+            .collect();
+        self.drafting = false;
+        !self.steps.is_empty()
+    }
+
+    /// The first step neither done nor skipped.
+    pub fn next(&self) -> Option<usize> {
+        self.steps.iter().position(|s| !s.done && !s.skipped)
+    }
+
+    /// The message carrying out step `index`.
+    pub fn step_prompt(&self, index: usize) -> String {
+        format!(
+            "Carry out step {} of the plan for: {}\n\n{}\n\nDo only this \
+             step; the rest follow in their own turns. When it is done, \
+             say in a sentence or two what changed.\n\nThe plan so far:\n{}",
+            index + 1,
+            self.goal,
+            self.steps[index].text,
+            self.checklist().join("\n"),
+        )
+    }
+
+    /// The plan as a markdown checklist, done steps `[x]`
+    /// and skipped ones `[-]`.
+    pub fn checklist(&self) -> Vec<String> {
+        let mut out = vec![format!("plan: {}", self.goal)];
+        let list = |out: &mut Vec<String>, title: &str, items: &[String]| {
+            if !items.is_empty() {
+                out.push(format!("{title}:"));
+                out.extend(items.iter().map(|item| format!("  - {item}")));
+            }
+        };
+        list(&mut out, "files", &self.files);
+        out.push("steps:".to_string());
+        for (i, step) in self.steps.iter().enumerate() {
+            let mark = match step {
+                Step { done: true, .. } => 'x',
+                Step { skipped: true, .. } => '-',
+                _ => ' ',
+            };
+            out.push(format!("  [{mark}] {}. {}", i + 1, step.text));
+        }
+        list(&mut out, "risks", &self.risks);
+        out
+    }
+
+    /// Steps done or skipped, and all steps.
+    pub fn progress(&self) -> (usize, usize) {
+        let over = self.steps.iter().filter(|s| s.done || s.skipped);
+        (over.count(), self.steps.len())
+    }
+
+    pub fn save(&self, session: &Path) {
+        let json = match serde_json::to_string_pretty(self) {
+            Ok(j) => j,
+            Err(e) => {
+                eprintln!("* warning: failed to serialize plan: {e}");
+                return;
+            }
+        };
+        if let Err(e) = fs::write(path(session), json) {
+            eprintln!("* warning: failed to save plan: {e}");
+        }
+    }
+
+    /// The plan saved for `session`, if any.
+    pub fn load(session: &Path) -> Option<Plan> {
+        let text = fs::read_to_string(path(session)).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+}
+
+/// Where the plan of `session` is kept.
+pub fn path(session: &Path) -> PathBuf {
+    let mut p = session.as_os_str().to_owned();
+    p.push(".plan");
+    PathBuf::from(p)
+}
+
+pub fn remove(session: &Path) {
+    let _ = fs::remove_file(path(session));
+}
+
+/// The section a heading line like `## Steps` or `**Risks:**`
+/// starts: `Some(None)` for a heading of anything else.
+fn heading(line: &str) -> Option<Option<Section>> {
+    let line = line.trim();
+    let bold = line.starts_with("**") && line.ends_with("**");
+    if !line.starts_with('#') && !bold {
+        return None;
+    }
+    let name = line
+        .trim_matches(|c: char| c == '#' || c == '*' || c == ':')
+        .trim()
+        .to_lowercase();
+    Some(match name.as_str() {
+        n if n.starts_with("file") => Some(Section::Files),
+        n if n.starts_with("step") => Some(Section::Steps),
+        n if n.starts_with("risk") => Some(Section::Risks),
+        _ => None,
+    })
+}
+
+/// The text of a `- item`, `* item` or `1. item` line,
+/// without a `[ ]` checkbox.
+fn list_item(line: &str) -> Option<&str> {
+    let rest = if let Some(rest) = line
+        .strip_prefix("- ")
+        .or_else(|| line.strip_prefix("* "))
+        .or_else(|| line.strip_prefix("+ "))
+    {
+        rest
+    } else {
+        let digits = line.len()
+            - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits == 0 {
+            return None;
+        }
+        line[digits..]
+            .strip_prefix(". ")
+            .or_else(|| line[digits..].strip_prefix(") "))?
+    };
+    let rest = rest.trim();
+    let rest = ["[ ] ", "[x] ", "[X] "]
+        .iter()
+        .find_map(|b| rest.strip_prefix(b))
+        .unwrap_or(rest);
+    Some(rest.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_plan_from_a_reply() {
+        let reply = "I looked at the parser.\n\n## Files\n\
+            - src/parse.rs: split the lexer out\n\
+            - `src/lex.rs` (new)\n\n\
+            ## Steps\n\
+            1. Move the lexer into src/lex.rs\n\
+            2. [ ] Update callers\n   \
+               - parse.rs and main.rs\n\
+            3) Delete the old code\n\n\
+            **Risks:**\n\
+            * tokens change position; run the parser tests\n";
+        let mut plan = Plan::new("split the lexer");
+        assert!(plan.fill(reply));
+        assert!(!plan.drafting);
+        assert_eq!(
+            plan.files,
+            ["src/parse.rs: split the lexer out", "`src/lex.rs` (new)"]
+        );
+        let steps: Vec<&str> =
+            plan.steps.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(
+            steps,
+            [
+                "Move the lexer into src/lex.rs",
+                "Update callers parse.rs and main.rs",
+                "Delete the old code",
+            ]
+        );
+        assert_eq!(
+            plan.risks,
+            ["tokens change position; run the parser tests"]
+        );
+
+        assert!(!Plan::new("x").fill("Sure, here is my plan: do it."));
+    }
+
+    #[test]
+    fn tracks_progress_as_a_checklist() {
+        let mut plan = Plan::new("g");
+        plan.fill("## Steps\n1. a\n2. b\n3. c\n");
+        plan.steps[0].done = true;
+        plan.steps[1].skipped = true;
+        assert_eq!(plan.next(), Some(2));
+        assert_eq!(plan.progress(), (2, 3));
+        assert_eq!(
+            plan.checklist(),
+            [
+                "plan: g",
+                "steps:",
+                "  [x] 1. a",
+                "  [-] 2. b",
+                "  [ ] 3. c"
+            ]
+        );
+        assert!(plan.step_prompt(2).starts_with("Carry out step 3 of"));
+
+        let dir = std::env::temp_dir().join("tapir_plan");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let session = dir.join("s.jsonl");
+        plan.save(&session);
+        assert_eq!(Plan::load(&session), Some(plan));
+        remove(&session);
+        assert_eq!(Plan::load(&session), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
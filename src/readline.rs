@@ -1,51 +1,120 @@
 use std::fs::{self, OpenOptions};
 use std::io::{self, BufRead, Read, Write};
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 use crate::display::ToolOutputLog;
+use crate::keymap::{Action, Keymap};
+use crate::theme::{Style, paint};
 
 const HISTORY_SIZE: usize = 100;
 
 pub struct Editor {
     history: Vec<String>,
     history_path: PathBuf,
+    /// Size and mtime of the history file when last read,
+    /// used to pick up lines appended by other instances.
+    history_stamp: Option<FileStamp>,
     orig_termios: libc::termios,
     working_dir: PathBuf,
+    /// No cursor addressing (TERM=dumb, or stdin is not a
+    /// tty): read whole lines in cooked mode and let the
+    /// terminal echo, instead of redrawing in place.
+    dumb: bool,
+    /// Drawn at the right edge of the input line, from
+    /// `prompt_right` in config.
+    right_prompt: String,
+    /// Files the session touched, most recent first; `@`
+    /// completion offers these before the rest.
+    recent_files: Vec<PathBuf>,
+    /// Seconds without a keypress after which `readline`
+    /// gives up with `TimedOut` (0: wait forever).
+    idle_timeout: u64,
+    /// What the control keys do, from `keys` in config.
+    keymap: Keymap,
+    /// Names the Ctrl-G temp file, so instances editing at
+    /// once don't share one.
+    session_id: String,
 }
 
 impl Editor {
-    pub fn new() -> io::Result<Self> {
+    /// An editor keeping its history in `history_path`.
+    pub fn new(history_path: PathBuf) -> io::Result<Self> {
+        let mut dumb = is_dumb_term(std::env::var("TERM").ok().as_deref());
         let orig = unsafe {
             let mut t: libc::termios = std::mem::zeroed();
             if libc::tcgetattr(0, &mut t) != 0 {
-                return Err(io::Error::last_os_error());
+                dumb = true;
             }
             t
         };
 
-        let home = std::env::var("HOME").unwrap_or_else(|_| "/tmp".into());
-        let history_path = PathBuf::from(home).join(".tapir/history");
-
         let history = load_history(&history_path);
+        let history_stamp = file_stamp(&history_path);
         let working_dir =
             std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
 
         Ok(Editor {
             history,
             history_path,
+            history_stamp,
             orig_termios: orig,
             working_dir,
+            dumb,
+            right_prompt: String::new(),
+            recent_files: Vec::new(),
+            idle_timeout: 0,
+            keymap: Keymap::default(),
+            session_id: String::new(),
         })
     }
 
+    /// Segment for the right edge of the next input lines.
+    pub fn set_right_prompt(&mut self, right: String) {
+        self.right_prompt = right;
+    }
+
+    /// Give up on input after `secs` without a keypress
+    /// (0: never). Only a raw-mode terminal keeps the timer.
+    pub fn set_idle_timeout(&mut self, secs: u64) {
+        self.idle_timeout = secs;
+    }
+
+    /// Control-key bindings for the next input lines.
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    /// The session input is for.
+    pub fn set_session_id(&mut self, id: &str) {
+        self.session_id = id.to_string();
+    }
+
+    /// Files to rank first in `@` completion, most recent
+    /// first, as canonical paths.
+    pub fn set_recent_files(&mut self, files: Vec<PathBuf>) {
+        self.recent_files = files;
+    }
+
     pub fn readline(
         &mut self,
         prompt: &str,
         tool_log: Option<&mut ToolOutputLog>,
     ) -> io::Result<Option<String>> {
+        self.reload_history();
+        if self.dumb {
+            return self.read_line_plain(prompt);
+        }
         self.enable_raw()?;
-        let result = self.read_line_raw(prompt, tool_log);
+        let result =
+            self.read_line_raw(prompt, tool_log, &mut io::stdin().lock());
+        if crate::signal::hung_up() {
+            return Ok(None);
+        }
         self.disable_raw()?;
         // Move to next line after input
         println!();
@@ -57,6 +126,7 @@ impl Editor {
         &mut self,
         prompt: &str,
         mut tool_log: Option<&mut ToolOutputLog>,
+        stdin: &mut impl Read,
     ) -> io::Result<Option<String>> {
         let mut buf: Vec<u8> = Vec::new();
         let mut cursor: usize = 0;
@@ -65,31 +135,44 @@ impl Editor {
 
         self.print_line(prompt, &buf, cursor)?;
 
-        let mut stdin = io::stdin().lock();
         let mut byte = [0u8; 1];
+        let idle = (self.idle_timeout > 0)
+            .then(|| IdleTimer::start(Duration::from_secs(self.idle_timeout)));
 
         loop {
-            if stdin.read(&mut byte)? == 0 {
-                if buf.is_empty() {
-                    return Ok(None);
+            let read = stdin.read(&mut byte);
+            if let Some(idle) = &idle {
+                idle.touch();
+            }
+            // Whatever the read got, nobody is there to see it
+            if crate::signal::hung_up() {
+                return Ok(None);
+            }
+            match read {
+                Ok(0) if buf.is_empty() => return Ok(None),
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e)
+                    if e.kind() == io::ErrorKind::Interrupted
+                        && crate::signal::take_idle() =>
+                {
+                    return Err(io::ErrorKind::TimedOut.into());
                 }
-                break;
+                Err(e) => return Err(e),
             }
 
-            match byte[0] {
-                // Ctrl-D
-                4 if buf.is_empty() => return Ok(None),
-                // Ctrl-C
-                3 => {
+            match (byte[0], self.keymap.action(byte[0])) {
+                (_, Some(Action::Quit)) if buf.is_empty() => return Ok(None),
+                (_, Some(Action::Cancel)) => {
                     buf.clear();
                     cursor = 0;
                     print!("\r\n");
                     self.print_line(prompt, &buf, cursor)?;
                 }
                 // Enter
-                b'\r' | b'\n' => break,
+                (b'\r' | b'\n', _) => break,
                 // Backspace
-                127 | 8 => {
+                (127 | 8, _) => {
                     if cursor > 0 {
                         cursor -= 1;
                         buf.remove(cursor);
@@ -97,13 +180,30 @@ impl Editor {
                     }
                 }
                 // Escape sequence
-                27 => {
+                (27, _) => {
                     let mut seq = [0u8; 2];
                     if stdin.read(&mut seq[0..1])? == 0 {
                         continue;
                     }
-                    if seq[0] != b'[' {
-                        continue;
+                    match seq[0] {
+                        b'[' => {}
+                        // Alt-B / Alt-F: word backward / forward
+                        b'b' | b'f' => {
+                            cursor = if seq[0] == b'b' {
+                                word_start(&buf, cursor)
+                            } else {
+                                word_end(&buf, cursor)
+                            };
+                            self.print_line(prompt, &buf, cursor)?;
+                            continue;
+                        }
+                        // Alt-D: delete word forward
+                        b'd' => {
+                            buf.drain(cursor..word_end(&buf, cursor));
+                            self.print_line(prompt, &buf, cursor)?;
+                            continue;
+                        }
+                        _ => continue,
                     }
                     if stdin.read(&mut seq[1..2])? == 0 {
                         continue;
@@ -138,12 +238,16 @@ impl Editor {
                                 self.print_line(prompt, &buf, cursor)?;
                             }
                         }
-                        // Right arrow
+                        // Right arrow, taking the suggestion at the
+                        // end of the line
                         b'C' => {
                             if cursor < buf.len() {
                                 cursor += 1;
-                                self.print_line(prompt, &buf, cursor)?;
+                            } else {
+                                self.accept_suggestion(&mut buf);
+                                cursor = buf.len();
                             }
+                            self.print_line(prompt, &buf, cursor)?;
                         }
                         // Left arrow
                         b'D' => {
@@ -163,30 +267,12 @@ impl Editor {
                                 match ext[2] {
                                     // Ctrl+Right: word forward
                                     b'C' => {
-                                        while cursor < buf.len()
-                                            && buf[cursor] == b' '
-                                        {
-                                            cursor += 1;
-                                        }
-                                        while cursor < buf.len()
-                                            && buf[cursor] != b' '
-                                        {
-                                            cursor += 1;
-                                        }
+                                        cursor = word_end(&buf, cursor);
                                         self.print_line(prompt, &buf, cursor)?;
                                     }
                                     // Ctrl+Left: word backward
                                     b'D' => {
-                                        while cursor > 0
-                                            && buf[cursor - 1] == b' '
-                                        {
-                                            cursor -= 1;
-                                        }
-                                        while cursor > 0
-                                            && buf[cursor - 1] != b' '
-                                        {
-                                            cursor -= 1;
-                                        }
+                                        cursor = word_start(&buf, cursor);
                                         self.print_line(prompt, &buf, cursor)?;
                                     }
                                     _ => {}
@@ -209,14 +295,16 @@ impl Editor {
                         }
                         // End (ESC [ F)
                         b'F' => {
+                            if cursor == buf.len() {
+                                self.accept_suggestion(&mut buf);
+                            }
                             cursor = buf.len();
                             self.print_line(prompt, &buf, cursor)?;
                         }
                         _ => {}
                     }
                 }
-                // Ctrl-P (history prev)
-                16 => {
+                (_, Some(Action::HistoryPrev)) => {
                     if hist_idx > 0 {
                         if hist_idx == self.history.len() {
                             saved_line =
@@ -228,8 +316,7 @@ impl Editor {
                         self.print_line(prompt, &buf, cursor)?;
                     }
                 }
-                // Ctrl-N (history next)
-                14 => {
+                (_, Some(Action::HistoryNext)) => {
                     if hist_idx < self.history.len() {
                         hist_idx += 1;
                         if hist_idx == self.history.len() {
@@ -241,58 +328,57 @@ impl Editor {
                         self.print_line(prompt, &buf, cursor)?;
                     }
                 }
-                // Ctrl-O (toggle tool output)
-                15 => {
+                (_, Some(Action::ToolOutput)) => {
                     if let Some(ref mut log) = tool_log {
                         print!("\r\n");
                         log.toggle_last();
                         self.print_line(prompt, &buf, cursor)?;
                     }
                 }
-                // Ctrl-A (home)
-                1 => {
+                (_, Some(Action::Home)) => {
                     cursor = 0;
                     self.print_line(prompt, &buf, cursor)?;
                 }
-                // Ctrl-E (end)
-                5 => {
+                (_, Some(Action::End)) => {
+                    if cursor == buf.len() {
+                        self.accept_suggestion(&mut buf);
+                    }
                     cursor = buf.len();
                     self.print_line(prompt, &buf, cursor)?;
                 }
-                // Ctrl-U (kill line)
-                21 => {
+                (_, Some(Action::KillLine)) => {
                     buf.clear();
                     cursor = 0;
                     self.print_line(prompt, &buf, cursor)?;
                 }
-                // Ctrl-K (kill to end of line)
-                11 => {
+                (_, Some(Action::KillToEnd)) => {
                     buf.truncate(cursor);
                     self.print_line(prompt, &buf, cursor)?;
                 }
-                // Ctrl-W (kill word back)
-                23 => {
-                    while cursor > 0 && buf[cursor - 1] == b' ' {
-                        cursor -= 1;
-                        buf.remove(cursor);
-                    }
-                    while cursor > 0 && buf[cursor - 1] != b' ' {
-                        cursor -= 1;
-                        buf.remove(cursor);
-                    }
+                (_, Some(Action::KillWordBack)) => {
+                    let start = word_start(&buf, cursor);
+                    buf.drain(start..cursor);
+                    cursor = start;
                     self.print_line(prompt, &buf, cursor)?;
                 }
-                // Ctrl-G (open external editor)
-                7 => {
+                (_, Some(Action::TransposeChars)) => {
+                    cursor = transpose(&mut buf, cursor);
+                    self.print_line(prompt, &buf, cursor)?;
+                }
+                (_, Some(Action::ExternalEditor)) => {
                     let text = String::from_utf8_lossy(&buf).to_string();
                     if let Some(edited) = self.open_editor(&text)? {
+                        let at_end = cursor == buf.len();
                         buf = edited.into_bytes();
-                        cursor = buf.len();
+                        cursor = if at_end {
+                            buf.len()
+                        } else {
+                            char_floor(&buf, cursor)
+                        };
                     }
                     self.print_line(prompt, &buf, cursor)?;
                 }
-                // Tab — complete @path
-                b'\t' => {
+                (_, Some(Action::Complete)) => {
                     if let Some((at_pos, completions)) =
                         self.find_completions(&buf, cursor)
                     {
@@ -306,7 +392,7 @@ impl Editor {
                     }
                 }
                 // Printable
-                c if c >= 32 => {
+                (c, _) if c >= 32 => {
                     buf.insert(cursor, c);
                     cursor += 1;
                     self.print_line(prompt, &buf, cursor)?;
@@ -322,6 +408,23 @@ impl Editor {
         Ok(Some(line))
     }
 
+    /// Line input for terminals without ANSI support: print
+    /// the prompt once and let the tty driver handle editing.
+    fn read_line_plain(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let mut out = io::stdout();
+        write!(out, "{}", strip_ansi(prompt))?;
+        out.flush()?;
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end_matches(['\n', '\r']).to_string();
+        if !line.is_empty() {
+            self.add_history(&line);
+        }
+        Ok(Some(line))
+    }
+
     fn print_line(
         &self,
         prompt: &str,
@@ -333,13 +436,64 @@ impl Editor {
         // Clear line, print prompt + buffer, position
         // cursor
         write!(out, "\r\x1b[K{prompt}{s}")?;
-        let back = buf.len() - cursor;
+        let width = terminal_width();
+        let left = strip_ansi(prompt).chars().count();
+        let typed = s.chars().count();
+        // Ghost text needs dim to tell it from typed text, and
+        // must not wrap
+        let room =
+            width.map_or(usize::MAX, |w| w.saturating_sub(left + typed + 1));
+        let ghost: String = self
+            .suggestion(buf, cursor)
+            .filter(|_| !crate::theme::current().start(Style::Dim).is_empty())
+            .map(|g| g.chars().take(room).collect())
+            .unwrap_or_default();
+        let ghost_cols = ghost.chars().count();
+        if ghost_cols > 0 {
+            write!(out, "{}", paint(Style::Dim, &ghost))?;
+        }
+        if !self.right_prompt.is_empty()
+            && let Some(width) = width
+        {
+            let right = strip_ansi(&self.right_prompt).chars().count();
+            if let Some(col) =
+                right_column(width, left + typed + ghost_cols, right)
+            {
+                let at = left
+                    + String::from_utf8_lossy(&buf[..cursor]).chars().count();
+                write!(
+                    out,
+                    "\x1b[{}G{}\x1b[{}G",
+                    col + 1,
+                    self.right_prompt,
+                    at + 1
+                )?;
+                return out.flush();
+            }
+        }
+        let back = buf.len() - cursor + ghost_cols;
         if back > 0 {
             write!(out, "\x1b[{back}D")?;
         }
         out.flush()
     }
 
+    /// What history suggests after `buf`, shown while the
+    /// cursor is at the end of the line.
+    fn suggestion(&self, buf: &[u8], cursor: usize) -> Option<&str> {
+        if cursor != buf.len() {
+            return None;
+        }
+        history_match(&self.history, std::str::from_utf8(buf).ok()?)
+    }
+
+    /// Complete `buf` with the suggestion, if there is one.
+    fn accept_suggestion(&self, buf: &mut Vec<u8>) {
+        if let Some(rest) = self.suggestion(buf, buf.len()) {
+            buf.extend_from_slice(rest.as_bytes());
+        }
+    }
+
     fn add_history(&mut self, line: &str) {
         // Don't add duplicates of the last entry
         if self.history.last().map(|s| s.as_str()) == Some(line) {
@@ -350,6 +504,18 @@ impl Editor {
             self.history.remove(0);
         }
         append_history(&self.history_path, line);
+        self.history_stamp = file_stamp(&self.history_path);
+    }
+
+    /// Re-read the history file if another instance has
+    /// appended to it since we last looked.
+    fn reload_history(&mut self) {
+        let stamp = file_stamp(&self.history_path);
+        if stamp == self.history_stamp {
+            return;
+        }
+        self.history = load_history(&self.history_path);
+        self.history_stamp = stamp;
     }
 
     // -------------------------------------------------
@@ -357,7 +523,8 @@ impl Editor {
     // -------------------------------------------------
 
     /// Scan backward from cursor to find `@`, then
-    /// collect matching filesystem entries.
+    /// collect matching filesystem entries, recently touched
+    /// ones first.
     fn find_completions(
         &self,
         buf: &[u8],
@@ -376,7 +543,8 @@ impl Editor {
             Err(_) => return Some((at_pos, Vec::new())),
         };
 
-        let mut matches: Vec<String> = Vec::new();
+        let canonical = dir.canonicalize().unwrap_or_else(|_| dir.clone());
+        let mut matches: Vec<(Option<usize>, String)> = Vec::new();
         for entry in entries.flatten() {
             let name = entry.file_name();
             let name = name.to_string_lossy();
@@ -397,11 +565,14 @@ impl Editor {
                 if is_dir {
                     path.push('/');
                 }
-                matches.push(path);
+                let rank = recency(&canonical.join(&*name), &self.recent_files);
+                matches.push((rank, path));
             }
         }
-        matches.sort();
-        Some((at_pos, matches))
+        matches.sort_by(|a, b| {
+            (a.0.is_none(), a.0, &a.1).cmp(&(b.0.is_none(), b.0, &b.1))
+        });
+        Some((at_pos, matches.into_iter().map(|(_, p)| p).collect()))
     }
 
     fn apply_completion(
@@ -455,42 +626,120 @@ impl Editor {
         Ok(())
     }
 
-    fn open_editor(&self, text: &str) -> io::Result<Option<String>> {
-        let editor = std::env::var("VISUAL")
-            .or_else(|_| std::env::var("EDITOR"))
-            .unwrap_or_else(|_| "vi".into());
-
-        let tmp = std::env::temp_dir().join(".tapir-edit.md");
-        fs::write(&tmp, text)?;
-
+    /// Let the user pick one of `items` with the arrow keys or
+    /// by typing its number. Enter picks, q or Ctrl-C cancels.
+    pub fn select(&self, items: &[String]) -> io::Result<Option<usize>> {
+        if items.is_empty() {
+            return Ok(None);
+        }
+        if self.dumb {
+            for (i, item) in items.iter().enumerate() {
+                println!("{:>3}. {}", i + 1, strip_ansi(item));
+            }
+            let answer = ask("number (empty to cancel): ");
+            return Ok(answer
+                .parse::<usize>()
+                .ok()
+                .filter(|n| (1..=items.len()).contains(n))
+                .map(|n| n - 1));
+        }
+        self.enable_raw()?;
+        let result = self.select_raw(items);
         self.disable_raw()?;
-        print!("\r\n");
-        io::stdout().flush()?;
+        result
+    }
 
-        let status = Command::new(&editor)
-            .arg(&tmp)
-            .stdin(std::process::Stdio::inherit())
-            .stdout(std::process::Stdio::inherit())
-            .stderr(std::process::Stdio::inherit())
-            .status();
+    fn select_raw(&self, items: &[String]) -> io::Result<Option<usize>> {
+        let mut sel = 0;
+        let mut num = String::new();
+        let mut stdin = io::stdin().lock();
+        let mut byte = [0u8; 1];
+        let mut out = io::stdout();
 
-        self.enable_raw()?;
+        let draw = |out: &mut io::Stdout, sel: usize, num: &str| {
+            for (i, item) in items.iter().enumerate() {
+                if i == sel {
+                    let row = format!(">{:>3}. {item}", i + 1);
+                    write!(
+                        out,
+                        "\r\x1b[K{}\r\n",
+                        paint(Style::Selected, &row)
+                    )?;
+                } else {
+                    write!(out, "\r\x1b[K {:>3}. {item}\r\n", i + 1)?;
+                }
+            }
+            let help = "↑/↓ or number, Enter to select, q to cancel:";
+            write!(out, "\r\x1b[K  {} {num}", paint(Style::Dim, help))?;
+            out.flush()
+        };
+        draw(&mut out, sel, &num)?;
 
-        match status {
-            Ok(s) if s.success() => {
-                let content = fs::read_to_string(&tmp)?;
-                let _ = fs::remove_file(&tmp);
-                let trimmed = content
-                    .trim_end_matches('\n')
-                    .trim_end_matches('\r')
-                    .to_string();
-                Ok(Some(trimmed))
+        let result = loop {
+            if stdin.read(&mut byte)? == 0 {
+                break None;
             }
-            _ => {
-                let _ = fs::remove_file(&tmp);
-                Ok(None)
+            match byte[0] {
+                b'\r' | b'\n' => break Some(sel),
+                // Ctrl-C, Ctrl-D
+                3 | 4 | b'q' => break None,
+                b'0'..=b'9' => {
+                    if let Some(i) =
+                        push_digit(&mut num, byte[0] as char, items.len())
+                    {
+                        sel = i;
+                    }
+                }
+                127 | 8 => {
+                    num.pop();
+                    if let Ok(n) = num.parse::<usize>() {
+                        sel = n - 1;
+                    }
+                }
+                b'k' => sel = sel.saturating_sub(1),
+                b'j' => sel = (sel + 1).min(items.len() - 1),
+                27 => {
+                    let mut seq = [0u8; 2];
+                    if stdin.read(&mut seq[0..1])? == 0
+                        || seq[0] != b'['
+                        || stdin.read(&mut seq[1..2])? == 0
+                    {
+                        continue;
+                    }
+                    num.clear();
+                    match seq[1] {
+                        b'A' => sel = sel.saturating_sub(1),
+                        b'B' => sel = (sel + 1).min(items.len() - 1),
+                        _ => {}
+                    }
+                }
+                _ => continue,
             }
-        }
+            write!(out, "\r\x1b[{}A", items.len())?;
+            draw(&mut out, sel, &num)?;
+        };
+        write!(out, "\r\n")?;
+        out.flush()?;
+        Ok(result)
+    }
+
+    /// Edit `text` in `$VISUAL` or `$EDITOR`, as markdown,
+    /// under a comment listing the files the session touched.
+    fn open_editor(&self, text: &str) -> io::Result<Option<String>> {
+        self.disable_raw()?;
+        print!("\r\n");
+        io::stdout().flush()?;
+        let root = self.working_dir.canonicalize().unwrap_or_default();
+        let files: Vec<String> = self
+            .recent_files
+            .iter()
+            .take(20)
+            .map(|f| f.strip_prefix(&root).unwrap_or(f).display().to_string())
+            .collect();
+        let name = temp_name(&self.session_id, "prompt");
+        let result = edit_in_editor(&editor_template(text, &files), &name);
+        self.enable_raw()?;
+        Ok(result?.map(|edited| strip_template(&edited).to_string()))
     }
 
     fn enable_raw(&self) -> io::Result<()> {
@@ -530,6 +779,148 @@ impl Drop for Editor {
     }
 }
 
+/// Raises SIGALRM in the thread that started it once no
+/// `touch` came for the timeout, breaking it out of a blocking
+/// read. Dropping it disarms it.
+struct IdleTimer {
+    /// When to fire; None once dropped.
+    deadline: Arc<(Mutex<Option<Instant>>, Condvar)>,
+    timeout: Duration,
+}
+
+/// The thread to signal, which `pthread_kill` may reach from
+/// any thread.
+struct Target(libc::pthread_t);
+unsafe impl Send for Target {}
+
+impl IdleTimer {
+    fn start(timeout: Duration) -> Self {
+        crate::signal::take_idle();
+        let deadline = Arc::new((
+            Mutex::new(Some(Instant::now() + timeout)),
+            Condvar::new(),
+        ));
+        let target = Target(unsafe { libc::pthread_self() });
+        let shared = Arc::clone(&deadline);
+        std::thread::spawn(move || {
+            let target = target;
+            let (lock, cvar) = &*shared;
+            let Ok(mut guard) = lock.lock() else {
+                return;
+            };
+            while let Some(at) = *guard {
+                let now = Instant::now();
+                if now >= at {
+                    unsafe { libc::pthread_kill(target.0, libc::SIGALRM) };
+                    return;
+                }
+                guard = match cvar.wait_timeout(guard, at - now) {
+                    Ok((g, _)) => g,
+                    Err(_) => return,
+                };
+            }
+        });
+        IdleTimer { deadline, timeout }
+    }
+
+    /// Input arrived: start the timeout over.
+    fn touch(&self) {
+        if let Ok(mut at) = self.deadline.0.lock() {
+            *at = Some(Instant::now() + self.timeout);
+        }
+    }
+}
+
+impl Drop for IdleTimer {
+    fn drop(&mut self) {
+        let (lock, cvar) = &*self.deadline;
+        if let Ok(mut at) = lock.lock() {
+            *at = None;
+        }
+        cvar.notify_one();
+    }
+}
+
+/// Add `digit` to the number typed in a `select` list and
+/// return the item it names. A digit that would run past the
+/// list starts a new number.
+fn push_digit(num: &mut String, digit: char, len: usize) -> Option<usize> {
+    let valid = |n: &str| n.parse().ok().filter(|n| (1..=len).contains(n));
+    num.push(digit);
+    if valid(num).is_none() {
+        *num = digit.to_string();
+    }
+    match valid(num) {
+        Some(n) => Some(n - 1),
+        None => {
+            num.clear();
+            None
+        }
+    }
+}
+
+/// The rest of the most recent history entry that starts
+/// with `typed` and goes on past it, as fish suggests.
+fn history_match<'a>(history: &'a [String], typed: &str) -> Option<&'a str> {
+    if typed.is_empty() {
+        return None;
+    }
+    history.iter().rev().find_map(|entry| {
+        entry.strip_prefix(typed).filter(|rest| !rest.is_empty())
+    })
+}
+
+/// Where the word before `cursor` starts, skipping the
+/// spaces between.
+fn word_start(buf: &[u8], cursor: usize) -> usize {
+    let mut i = cursor;
+    while i > 0 && buf[i - 1] == b' ' {
+        i -= 1;
+    }
+    while i > 0 && buf[i - 1] != b' ' {
+        i -= 1;
+    }
+    i
+}
+
+/// Where the word after `cursor` ends, skipping the spaces
+/// between.
+fn word_end(buf: &[u8], cursor: usize) -> usize {
+    let mut i = cursor;
+    while i < buf.len() && buf[i] == b' ' {
+        i += 1;
+    }
+    while i < buf.len() && buf[i] != b' ' {
+        i += 1;
+    }
+    i
+}
+
+/// Swap the characters either side of `cursor`, or the last
+/// two at the end of the line, as readline's Ctrl-T does.
+/// Returns the cursor, moved past the pair.
+fn transpose(buf: &mut Vec<u8>, cursor: usize) -> usize {
+    // Whole UTF-8 characters, not bytes
+    let prev =
+        |i: usize| (0..i).rev().find(|&j| buf[j] & 0xc0 != 0x80).unwrap_or(0);
+    let at = if cursor == buf.len() {
+        prev(cursor)
+    } else {
+        cursor
+    };
+    if at == 0 {
+        return cursor;
+    }
+    let before = prev(at);
+    let after = (at + 1..=buf.len())
+        .find(|&j| j == buf.len() || buf[j] & 0xc0 != 0x80)
+        .unwrap_or(buf.len());
+    let first = buf[before..at].to_vec();
+    let second = buf[at..after].to_vec();
+    buf.splice(before..after, second.into_iter().chain(first));
+    after
+}
+
 /// Split a partial path into (directory_to_list,
 /// filename_prefix). E.g. "src/ma" → ("<wd>/src", "ma"),
 /// "" → ("<wd>", "").
@@ -551,6 +942,12 @@ fn split_path_prefix<'a>(
     }
 }
 
+/// Position in `recent` of the first file that is `path` or
+/// lies under it.
+fn recency(path: &Path, recent: &[PathBuf]) -> Option<usize> {
+    recent.iter().position(|r| r.starts_with(path))
+}
+
 fn common_prefix(items: &[String]) -> String {
     if items.is_empty() {
         return String::new();
@@ -569,10 +966,193 @@ fn common_prefix(items: &[String]) -> String {
     first[..len].to_string()
 }
 
-fn load_history(path: &PathBuf) -> Vec<String> {
+/// Open `text` in `$VISUAL`/`$EDITOR` (default vi) on a temp
+/// file with extension `ext`. Returns the saved text without
+/// trailing newlines, or `None` if the editor failed. The
+/// terminal must be in cooked mode.
+/// Prompt on stderr and read one answer from stdin, trimmed
+/// and lowercased (the terminal is in cooked mode outside of
+/// `read_line`). Empty on EOF.
+pub fn ask(prompt: &str) -> String {
+    static LOCK: Mutex<()> = Mutex::new(());
+    // Parallel tool calls must not interleave their questions
+    let _guard = LOCK.lock();
+    eprint!("{prompt}");
+    let _ = io::stderr().flush();
+    let mut line = String::new();
+    let _ = io::stdin().lock().read_line(&mut line);
+    line.trim().to_lowercase()
+}
+
+/// `ask` for a yes/no question; anything but y/yes, or
+/// their translation, is no.
+pub fn confirm(prompt: &str) -> bool {
+    use crate::i18n::t;
+    let answer = ask(prompt);
+    ["y", "yes"]
+        .into_iter()
+        .any(|yes| answer == yes || answer == t(yes).to_lowercase())
+}
+
+/// Start of the comment `editor_template` adds; it and all
+/// after it are dropped on return.
+const TEMPLATE_MARK: &str = "<!-- tapir:";
+
+/// `text` to edit, followed by a comment naming `files` for
+/// `@` references.
+fn editor_template(text: &str, files: &[String]) -> String {
+    let mut out = format!(
+        "{text}\n\n{TEMPLATE_MARK} write the prompt above; this comment \
+         is dropped.\n"
+    );
+    if !files.is_empty() {
+        out.push_str("Files this session touched:\n");
+        for file in files {
+            out.push_str(&format!("  @{file}\n"));
+        }
+    }
+    out.push_str("-->\n");
+    out
+}
+
+/// The prompt in an edited `editor_template`.
+fn strip_template(edited: &str) -> &str {
+    edited
+        .rfind(TEMPLATE_MARK)
+        .map_or(edited, |i| &edited[..i])
+        .trim_end()
+}
+
+/// Name for a temp file of `session`'s, with `.md` so the
+/// editor highlights it as markdown.
+pub fn temp_name(session: &str, what: &str) -> String {
+    match session {
+        "" => format!(".tapir-{}-{what}.md", std::process::id()),
+        id => format!(".tapir-{id}-{what}.md"),
+    }
+}
+
+/// `i` moved back to the start of the character it is in.
+fn char_floor(buf: &[u8], i: usize) -> usize {
+    let mut i = i.min(buf.len());
+    while i > 0 && i < buf.len() && buf[i] & 0xc0 == 0x80 {
+        i -= 1;
+    }
+    i
+}
+
+/// Edit `text` in `$VISUAL` or `$EDITOR` through a temp file
+/// called `name`. None when the editor fails.
+pub fn edit_in_editor(text: &str, name: &str) -> io::Result<Option<String>> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".into());
+
+    let tmp = std::env::temp_dir().join(name);
+    fs::write(&tmp, text)?;
+
+    let status = Command::new(&editor)
+        .arg(&tmp)
+        .stdin(std::process::Stdio::inherit())
+        .stdout(std::process::Stdio::inherit())
+        .stderr(std::process::Stdio::inherit())
+        .status();
+
+    match status {
+        Ok(s) if s.success() => {
+            let content = fs::read_to_string(&tmp)?;
+            let _ = fs::remove_file(&tmp);
+            let trimmed = content
+                .trim_end_matches('\n')
+                .trim_end_matches('\r')
+                .to_string();
+            Ok(Some(trimmed))
+        }
+        _ => {
+            let _ = fs::remove_file(&tmp);
+            Ok(None)
+        }
+    }
+}
+
+/// Whether `TERM` names a terminal we should not send
+/// escape sequences to.
+fn is_dumb_term(term: Option<&str>) -> bool {
+    matches!(term, None | Some("" | "dumb" | "unknown" | "cons25"))
+}
+
+/// Drop CSI escape sequences (colors, cursor movement).
+pub fn strip_ansi(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        if chars.next() != Some('[') {
+            continue;
+        }
+        for c in chars.by_ref() {
+            if ('@'..='~').contains(&c) {
+                break;
+            }
+        }
+    }
+    out
+}
+
+/// Columns of the terminal on stdout, or stderr when stdout
+/// is redirected. Read again only after a SIGWINCH.
+pub fn terminal_width() -> Option<usize> {
+    static WIDTH: AtomicUsize = AtomicUsize::new(0);
+    if crate::signal::take_resized() {
+        let width = [1, 2].into_iter().find_map(|fd| {
+            let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+            let ok = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut ws) } == 0;
+            (ok && ws.ws_col > 0).then_some(ws.ws_col as usize)
+        });
+        WIDTH.store(width.unwrap_or(0), Ordering::Relaxed);
+    }
+    Some(WIDTH.load(Ordering::Relaxed)).filter(|&w| w > 0)
+}
+
+/// Column where a right prompt `right` wide starts in a
+/// terminal `width` wide, leaving the last column and a gap
+/// after the `left` columns of prompt and input free. None
+/// when it doesn't fit.
+fn right_column(width: usize, left: usize, right: usize) -> Option<usize> {
+    let col = width.checked_sub(right + 1)?;
+    (col > left).then_some(col)
+}
+
+/// Size and modification time of a file.
+#[derive(Clone, Copy, PartialEq)]
+struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+fn file_stamp(path: &Path) -> Option<FileStamp> {
+    let meta = fs::metadata(path).ok()?;
+    Some(FileStamp {
+        len: meta.len(),
+        modified: meta.modified().ok(),
+    })
+}
+
+/// Take an advisory `flock` on `file`. The lock is released
+/// when the file is closed.
+fn lock_file(file: &fs::File, op: libc::c_int) -> bool {
+    unsafe { libc::flock(file.as_raw_fd(), op) == 0 }
+}
+
+fn load_history(path: &Path) -> Vec<String> {
     let Ok(file) = fs::File::open(path) else {
         return Vec::new();
     };
+    // Shared lock so we never observe a half-written line
+    let _ = lock_file(&file, libc::LOCK_SH);
     let lines: Vec<String> = io::BufReader::new(file)
         .lines()
         .map_while(Result::ok)
@@ -585,11 +1165,211 @@ fn load_history(path: &PathBuf) -> Vec<String> {
     }
 }
 
-fn append_history(path: &PathBuf, line: &str) {
+/// Append one line to the history file. The line is written
+/// with a single `write` under an exclusive lock so entries
+/// from concurrent instances never interleave.
+fn append_history(path: &Path, line: &str) {
     if let Some(dir) = path.parent() {
         let _ = fs::create_dir_all(dir);
     }
-    if let Ok(mut f) = OpenOptions::new().create(true).append(true).open(path) {
-        let _ = writeln!(f, "{line}");
+    let Ok(mut f) = OpenOptions::new().create(true).append(true).open(path)
+    else {
+        return;
+    };
+    if !lock_file(&f, libc::LOCK_EX) {
+        return;
+    }
+    let mut buf = line.replace('\n', " ");
+    buf.push('\n');
+    let _ = f.write_all(buf.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_history(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tapir_hist_{name}"));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("history")
+    }
+
+    /// A pipe nothing is written to, and its write end, kept
+    /// open so reads block.
+    fn silent_pipe() -> (fs::File, fs::File) {
+        use std::os::unix::io::FromRawFd;
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        unsafe {
+            (fs::File::from_raw_fd(fds[0]), fs::File::from_raw_fd(fds[1]))
+        }
+    }
+
+    // One test, as both go through the process-wide signal
+    // flags
+    #[test]
+    fn idle_and_hangup_end_the_read() {
+        crate::signal::install_handler();
+        let mut editor = Editor::new(temp_history("idle")).unwrap();
+        editor.set_idle_timeout(1);
+
+        let (mut input, _writer) = silent_pipe();
+        let started = Instant::now();
+        let err = editor.read_line_raw("", None, &mut input).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert!(!crate::signal::take_idle());
+
+        // The read that SIGHUP breaks gives up the line
+        crate::signal::set_hung_up(true);
+        let (mut input, mut writer) = silent_pipe();
+        writer.write_all(b"half a line").unwrap();
+        let line = editor.read_line_raw("", None, &mut input);
+        crate::signal::set_hung_up(false);
+        assert_eq!(line.unwrap(), None);
+
+        // A dropped timer never fires
+        drop(IdleTimer::start(Duration::from_millis(50)));
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(!crate::signal::take_idle());
+    }
+
+    #[test]
+    fn append_history_merges_concurrent_writers() {
+        let path = temp_history("concurrent");
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let path = &path;
+                s.spawn(move || {
+                    for i in 0..25 {
+                        append_history(path, &format!("t{t}-line{i}"));
+                    }
+                });
+            }
+        });
+        let lines = load_history(&path);
+        assert_eq!(lines.len(), HISTORY_SIZE);
+        assert!(
+            lines
+                .iter()
+                .all(|l| l.starts_with('t') && l.contains("-line"))
+        );
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn append_history_flattens_multiline_entries() {
+        let path = temp_history("multiline");
+        append_history(&path, "first\nsecond");
+        assert_eq!(load_history(&path), vec!["first second".to_string()]);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn file_stamp_changes_after_append() {
+        let path = temp_history("stamp");
+        append_history(&path, "one");
+        let before = file_stamp(&path);
+        append_history(&path, "two");
+        assert!(before != file_stamp(&path));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn select_digits() {
+        let mut num = String::new();
+        assert_eq!(push_digit(&mut num, '1', 12), Some(0));
+        assert_eq!(push_digit(&mut num, '2', 12), Some(11));
+        // "123" is past the list: start over at 3
+        assert_eq!(push_digit(&mut num, '3', 12), Some(2));
+        assert_eq!(num, "3");
+        assert_eq!(push_digit(&mut num, '0', 12), None);
+        assert_eq!(num, "");
+    }
+
+    #[test]
+    fn suggests_the_latest_matching_entry() {
+        let history: Vec<String> =
+            ["fix the tests", "fix the build", "fix", "explain"]
+                .map(String::from)
+                .to_vec();
+        assert_eq!(history_match(&history, "fix t"), Some("he build"));
+        assert_eq!(history_match(&history, "ex"), Some("plain"));
+        // An entry that is just what was typed is passed over
+        assert_eq!(history_match(&history, "fix"), Some(" the build"));
+        // Typed in full, or nothing typed: no suggestion
+        assert_eq!(history_match(&history, "explain"), None);
+        assert_eq!(history_match(&history, ""), None);
+        assert_eq!(history_match(&history, "run"), None);
+    }
+
+    #[test]
+    fn editor_template_round_trip() {
+        let files = ["src/main.rs".to_string()];
+        let template = editor_template("fix @src/main.rs", &files);
+        assert!(template.contains("\n  @src/main.rs\n-->"));
+        let edited = template.replace("fix", "refactor");
+        assert_eq!(strip_template(&edited), "refactor @src/main.rs");
+        assert_eq!(strip_template("no comment left\n"), "no comment left");
+
+        assert_eq!(temp_name("abc", "prompt"), ".tapir-abc-prompt.md");
+        let buf = "añb".as_bytes();
+        assert_eq!(char_floor(buf, 2), 1);
+        assert_eq!(char_floor(buf, 9), 4);
+    }
+
+    #[test]
+    fn word_motion_and_transpose() {
+        let buf = b"git  commit -m";
+        assert_eq!(word_start(buf, 11), 5);
+        assert_eq!(word_start(buf, 5), 0);
+        assert_eq!(word_end(buf, 3), 11);
+        assert_eq!(word_end(buf, 14), 14);
+
+        let mut buf = b"teh".to_vec();
+        assert_eq!(transpose(&mut buf, 2), 3);
+        assert_eq!(buf, b"the");
+        // At the end: the last two
+        let mut buf = "añb".as_bytes().to_vec();
+        assert_eq!(transpose(&mut buf, 4), 4);
+        assert_eq!(String::from_utf8(buf).unwrap(), "abñ");
+        let mut buf = b"x".to_vec();
+        assert_eq!(transpose(&mut buf, 0), 0);
+        assert_eq!(transpose(&mut buf, 1), 1);
+        assert_eq!(buf, b"x");
+    }
+
+    #[test]
+    fn dumb_terms() {
+        assert!(is_dumb_term(None));
+        assert!(is_dumb_term(Some("dumb")));
+        assert!(is_dumb_term(Some("")));
+        assert!(!is_dumb_term(Some("xterm-256color")));
+    }
+
+    #[test]
+    fn recency_counts_files_under_directories() {
+        let recent =
+            [PathBuf::from("/p/src/main.rs"), PathBuf::from("/p/README")];
+        assert_eq!(recency(Path::new("/p/src"), &recent), Some(0));
+        assert_eq!(recency(Path::new("/p/src/main.rs"), &recent), Some(0));
+        assert_eq!(recency(Path::new("/p/README"), &recent), Some(1));
+        assert_eq!(recency(Path::new("/p/sr"), &recent), None);
+    }
+
+    #[test]
+    fn right_prompt_needs_room() {
+        assert_eq!(right_column(80, 10, 9), Some(70));
+        assert_eq!(right_column(80, 69, 9), Some(70));
+        assert_eq!(right_column(80, 70, 9), None);
+        assert_eq!(right_column(8, 0, 9), None);
+    }
+
+    #[test]
+    fn strip_ansi_removes_sequences() {
+        assert_eq!(strip_ansi("42% \x1b[1m>\x1b[0m "), "42% > ");
+        assert_eq!(strip_ansi("\r\x1b[Kabc\x1b[3D"), "\rabc");
+        assert_eq!(strip_ansi("plain"), "plain");
     }
 }
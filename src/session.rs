@@ -1,9 +1,12 @@
 use std::fs;
 use std::io::Read;
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 
+use crate::types::{Content, ContentBlock, Message};
+
 #[derive(Serialize, Deserialize)]
 pub struct SessionIndex {
     pub version: u32,
@@ -35,20 +38,92 @@ fn index_path(session_dir: &Path) -> PathBuf {
     session_dir.join("sessions-index.json")
 }
 
+/// Per-session metadata, written on every turn.
+const ENTRY_SUFFIX: &str = ".entry.json";
+
+fn entry_path(session_dir: &Path, session_id: &str) -> PathBuf {
+    session_dir.join(format!("{session_id}{ENTRY_SUFFIX}"))
+}
+
+/// Every session of `session_dir`: the entries of
+/// `sessions-index.json`, which older versions rewrote on
+/// every turn, merged with the per-session entry files that
+/// replace it. An entry file wins over the index.
 pub fn load_index(session_dir: &Path) -> SessionIndex {
-    let path = index_path(session_dir);
-    let Ok(text) = fs::read_to_string(&path) else {
-        return empty_index("");
+    let mut index = fs::read_to_string(index_path(session_dir))
+        .ok()
+        .and_then(|t| serde_json::from_str(&t).ok())
+        .unwrap_or_else(|| empty_index(""));
+    let Ok(read_dir) = fs::read_dir(session_dir) else {
+        return index;
     };
-    serde_json::from_str(&text).unwrap_or_else(|_| empty_index(""))
+    let mut sidecars: Vec<SessionEntry> = read_dir
+        .flatten()
+        .filter(|e| e.file_name().to_string_lossy().ends_with(ENTRY_SUFFIX))
+        .filter_map(|e| fs::read_to_string(e.path()).ok())
+        .filter_map(|t| serde_json::from_str(&t).ok())
+        .collect();
+    sidecars.sort_by(|a, b| a.created.cmp(&b.created));
+    for entry in sidecars {
+        if index.original_path.is_empty() {
+            index.original_path = entry.project_path.clone();
+        }
+        match index
+            .entries
+            .iter_mut()
+            .find(|e| e.session_id == entry.session_id)
+        {
+            Some(e) => *e = entry,
+            None => index.entries.push(entry),
+        }
+    }
+    // The stored path goes stale when the sessions directory
+    // moves, as it does on the move to XDG directories
+    for entry in &mut index.entries {
+        let file = session_dir.join(format!("{}.jsonl", entry.session_id));
+        entry.full_path = file.to_string_lossy().to_string();
+    }
+    index
 }
 
-pub fn save_index(session_dir: &Path, index: &SessionIndex) {
+/// Write `json` to a temporary file and rename it over `path`,
+/// so readers never see a partial file.
+fn write_atomic(path: &Path, json: &str) {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}.tmp", std::process::id()));
+    let tmp = PathBuf::from(tmp);
+    if fs::write(&tmp, json).is_err() || fs::rename(&tmp, path).is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+}
+
+/// Read, change and save the legacy index while holding an
+/// exclusive `flock` on `sessions-index.lock`, so concurrent
+/// instances in the same project don't drop each other's
+/// changes. Does nothing without an index.
+fn modify_index(session_dir: &Path, f: impl FnOnce(&mut SessionIndex)) {
     let path = index_path(session_dir);
-    let Ok(json) = serde_json::to_string_pretty(index) else {
+    if !path.is_file() {
+        return;
+    }
+    let lock = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(session_dir.join("sessions-index.lock"));
+    if let Ok(file) = &lock {
+        unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) };
+    }
+    let Some(mut index) = fs::read_to_string(&path)
+        .ok()
+        .and_then(|t| serde_json::from_str::<SessionIndex>(&t).ok())
+    else {
         return;
     };
-    let _ = fs::write(path, json);
+    f(&mut index);
+    if let Ok(json) = serde_json::to_string_pretty(&index) {
+        write_atomic(&path, &json);
+    }
 }
 
 fn empty_index(project_path: &str) -> SessionIndex {
@@ -59,13 +134,31 @@ fn empty_index(project_path: &str) -> SessionIndex {
     }
 }
 
-pub fn create_entry(session_dir: &Path, working_dir: &Path) -> SessionEntry {
-    let id = gen_uuid();
-    let full_path = session_dir.join(format!("{id}.jsonl"));
+/// A fresh entry under an id no session in `session_dir`
+/// has used yet.
+pub fn create_entry(
+    session_dir: &Path,
+    working_dir: &Path,
+) -> std::io::Result<SessionEntry> {
+    let mut tries = 0;
+    let (id, full_path) = loop {
+        let id = gen_uuid()?;
+        let full_path = session_dir.join(format!("{id}.jsonl"));
+        if !full_path.exists() && !entry_path(session_dir, &id).exists() {
+            break (id, full_path);
+        }
+        tries += 1;
+        if tries == 8 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "random source keeps repeating session ids",
+            ));
+        }
+    };
     let now = iso_now();
     let branch = git_branch(working_dir);
 
-    SessionEntry {
+    Ok(SessionEntry {
         session_id: id,
         full_path: full_path.to_string_lossy().to_string(),
         first_prompt: "No prompt".to_string(),
@@ -75,47 +168,347 @@ pub fn create_entry(session_dir: &Path, working_dir: &Path) -> SessionEntry {
         modified: now,
         git_branch: branch,
         project_path: working_dir.to_string_lossy().to_string(),
-    }
+    })
 }
 
+/// Save `entry` to its own file; other sessions' entries are
+/// not touched.
 pub fn update_entry(session_dir: &Path, entry: &SessionEntry) {
-    let mut index = load_index(session_dir);
-    if let Some(e) = index
-        .entries
-        .iter_mut()
-        .find(|e| e.session_id == entry.session_id)
-    {
-        *e = entry.clone();
-    } else {
-        index.entries.push(entry.clone());
-    }
-    if index.original_path.is_empty() {
-        index.original_path = entry.project_path.clone();
+    if let Ok(json) = serde_json::to_string_pretty(entry) {
+        write_atomic(&entry_path(session_dir, &entry.session_id), &json);
     }
-    save_index(session_dir, &index);
 }
 
-pub fn latest_entry(session_dir: &Path) -> Option<SessionEntry> {
-    let index = load_index(session_dir);
-    index
+/// Sessions with at least one message, most recently
+/// modified first.
+pub fn recent_entries(session_dir: &Path) -> Vec<SessionEntry> {
+    let mut entries: Vec<SessionEntry> = load_index(session_dir)
         .entries
         .into_iter()
-        .max_by(|a, b| a.modified.cmp(&b.modified))
+        .filter(|e| e.message_count > 0)
+        .collect();
+    entries.sort_by(|a, b| b.modified.cmp(&a.modified));
+    entries
+}
+
+/// Session directories of every project under
+/// `~/.tapir/sessions`.
+pub fn project_dirs(tapir_dir: &Path) -> Vec<PathBuf> {
+    let Ok(read_dir) = fs::read_dir(tapir_dir.join("sessions")) else {
+        return Vec::new();
+    };
+    let mut dirs: Vec<PathBuf> = read_dir
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_dir())
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+/// Remove a session: its transcript, the `.meta`, plan, events
+/// and entry sidecars, its file checkpoints and its index entry.
+pub fn delete(session_dir: &Path, entry: &SessionEntry) -> std::io::Result<()> {
+    let path = session_path(entry);
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let _ = fs::remove_file(crate::agent::meta_path(&path));
+    let _ = fs::remove_file(crate::events::events_path(&path));
+    crate::plan::remove(&path);
+    let _ = fs::remove_file(entry_path(session_dir, &entry.session_id));
+    let _ = fs::remove_dir_all(
+        Path::new(&entry.project_path)
+            .join(".tapir/checkpoints")
+            .join(&entry.session_id),
+    );
+    modify_index(session_dir, |index| {
+        index.entries.retain(|e| e.session_id != entry.session_id);
+    });
+    Ok(())
+}
+
+/// How many sessions to keep per project, from
+/// `max_sessions` and `max_session_age_days` in config.
+#[derive(Clone, Default)]
+pub struct Retention {
+    pub max_sessions: Option<usize>,
+    pub max_age_days: Option<u64>,
+}
+
+/// Empty sessions younger than this may be another tapir's
+/// that has not sent its first message yet.
+const EMPTY_GRACE_SECS: i64 = 3600;
+
+/// Delete the sessions `retention` no longer keeps, and any
+/// left empty for a while. Returns how many non-empty
+/// sessions went.
+pub fn prune(session_dir: &Path, retention: &Retention) -> usize {
+    if retention.max_sessions.is_none() && retention.max_age_days.is_none() {
+        return 0;
+    }
+    let now = parse_iso(&iso_now()).unwrap_or(0);
+    let cutoff = retention.max_age_days.map(|days| now - days as i64 * 86400);
+    let mut index = load_index(session_dir);
+    index.entries.sort_by(|a, b| b.modified.cmp(&a.modified));
+    let mut kept = 0;
+    let mut doomed = Vec::new();
+    for entry in &index.entries {
+        if entry.message_count == 0 {
+            let stale = parse_iso(&entry.modified)
+                .is_some_and(|t| t < now - EMPTY_GRACE_SECS);
+            if stale {
+                doomed.push(entry.clone());
+            }
+            continue;
+        }
+        let too_old = cutoff
+            .is_some_and(|c| parse_iso(&entry.modified).is_some_and(|t| t < c));
+        let too_many = retention.max_sessions.is_some_and(|n| kept >= n);
+        if too_old || too_many {
+            doomed.push(entry.clone());
+        } else {
+            kept += 1;
+        }
+    }
+    let mut deleted = 0;
+    for entry in &doomed {
+        if delete(session_dir, entry).is_ok() && entry.message_count > 0 {
+            deleted += 1;
+        }
+    }
+    deleted
+}
+
+/// A session whose transcript mentions a search query.
+pub struct SearchHit {
+    pub entry: SessionEntry,
+    pub matches: usize,
+    /// A few one-line excerpts around the first matches.
+    pub snippets: Vec<String>,
+}
+
+const SEARCH_SNIPPETS: usize = 3;
+
+/// Sessions in `session_dir` whose name or messages contain
+/// `query` (ASCII case-insensitive), most recent first.
+pub fn search(session_dir: &Path, query: &str) -> Vec<SearchHit> {
+    let mut hits = Vec::new();
+    for entry in recent_entries(session_dir) {
+        let Ok(content) = fs::read_to_string(session_path(&entry)) else {
+            continue;
+        };
+        let mut matches = count_matches(&entry.summary, query);
+        let mut snippets = Vec::new();
+        for line in content.lines() {
+            let texts = match serde_json::from_str::<Message>(line) {
+                Ok(msg) => searchable_text(&msg),
+                // `/note` lines
+                Err(_) => serde_json::from_str::<serde_json::Value>(line)
+                    .ok()
+                    .and_then(|v| v["note"].as_str().map(str::to_string))
+                    .into_iter()
+                    .collect(),
+            };
+            for text in texts {
+                matches += count_matches(&text, query);
+                let room = SEARCH_SNIPPETS.saturating_sub(snippets.len());
+                snippets.extend(crate::util::snippets(&text, query, room));
+            }
+        }
+        if matches > 0 {
+            hits.push(SearchHit {
+                entry,
+                matches,
+                snippets,
+            });
+        }
+    }
+    hits
+}
+
+/// Header and excerpt lines for one search hit, shared by
+/// `/search` and `tapir sessions search`.
+pub fn hit_lines(hit: &SearchHit, now: i64) -> Vec<String> {
+    let e = &hit.entry;
+    let title = if e.summary.is_empty() {
+        crate::util::truncate_line(
+            e.first_prompt.lines().next().unwrap_or(""),
+            60,
+        )
+    } else {
+        e.summary.clone()
+    };
+    let age = parse_iso(&e.modified)
+        .map(|t| crate::util::format_age(now - t))
+        .unwrap_or_default();
+    let plural = if hit.matches == 1 { "" } else { "es" };
+    let mut lines = vec![format!(
+        "{}  {title}  ({} match{plural}, {age})",
+        &e.session_id[..e.session_id.len().min(8)],
+        hit.matches
+    )];
+    lines.extend(hit.snippets.iter().map(|s| format!("    {s}")));
+    lines
+}
+
+fn count_matches(text: &str, query: &str) -> usize {
+    text.to_ascii_lowercase()
+        .matches(&query.to_ascii_lowercase())
+        .count()
+}
+
+/// Prose, tool inputs and tool output of a message; thinking
+/// is left out.
+fn searchable_text(msg: &Message) -> Vec<String> {
+    match &msg.content {
+        Content::Text(text) => vec![text.clone()],
+        Content::Blocks(blocks) => blocks
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text } => Some(text.clone()),
+                ContentBlock::ToolUse { input, .. } => Some(input.to_string()),
+                ContentBlock::ToolResult { content, .. } => {
+                    Some(content.clone())
+                }
+                ContentBlock::Thinking { .. } => None,
+            })
+            .collect(),
+    }
+}
+
+/// Metadata, usage and cost of a session, for `--json`.
+pub fn info_json(
+    entry: &SessionEntry,
+    usage: &crate::cost::Totals,
+) -> serde_json::Value {
+    serde_json::json!({
+        "id": entry.session_id,
+        "name": entry.summary,
+        "first_prompt": entry.first_prompt,
+        "project": entry.project_path,
+        "branch": entry.git_branch,
+        "path": entry.full_path,
+        "created": entry.created,
+        "modified": entry.modified,
+        "messages": entry.message_count,
+        "usage": {
+            "requests": usage.requests,
+            "input_tokens": usage.input_tokens,
+            "output_tokens": usage.output_tokens,
+            "cache_read_tokens": usage.cache_read_tokens,
+            "cache_write_tokens": usage.cache_write_tokens,
+        },
+        "cost": usage.cost,
+        "cost_by_model": usage.by_model,
+    })
 }
 
 pub fn session_path(entry: &SessionEntry) -> PathBuf {
     PathBuf::from(&entry.full_path)
 }
 
-fn gen_uuid() -> String {
-    let mut buf = [0u8; 16];
-    if let Ok(mut f) = fs::File::open("/dev/urandom") {
-        let _ = f.read_exact(&mut buf);
+/// Where session ids and timestamps come from. The system
+/// unless a test installs a [`Seeded`] one with
+/// [`set_source`], so runs come out the same every time.
+pub trait Source {
+    /// 16 random bytes for a session id.
+    fn random(&mut self) -> std::io::Result<[u8; 16]>;
+    /// Seconds since the epoch.
+    fn now(&mut self) -> i64;
+}
+
+/// The kernel's entropy and the system clock.
+pub struct System;
+
+impl Source for System {
+    /// `getentropy`, which needs no device node, falling
+    /// back to `/dev/urandom` on kernels too old for it.
+    fn random(&mut self) -> std::io::Result<[u8; 16]> {
+        let mut buf = [0u8; 16];
+        if unsafe { libc::getentropy(buf.as_mut_ptr().cast(), buf.len()) } == 0
+        {
+            return Ok(buf);
+        }
+        let err = std::io::Error::last_os_error();
+        fs::File::open("/dev/urandom")
+            .and_then(|mut f| f.read_exact(&mut buf))
+            .map_err(|e| {
+                std::io::Error::new(
+                    e.kind(),
+                    format!(
+                        "no random source: getentropy: {err}; /dev/urandom: {e}"
+                    ),
+                )
+            })?;
+        Ok(buf)
+    }
+
+    fn now(&mut self) -> i64 {
+        let mut t: libc::time_t = 0;
+        unsafe { libc::time(&mut t) };
+        t as i64
+    }
+}
+
+/// Ids from a seeded generator and a clock starting at
+/// `now` that moves one second per reading.
+#[cfg(test)]
+pub struct Seeded {
+    state: u64,
+    now: i64,
+}
+
+#[cfg(test)]
+impl Seeded {
+    pub fn new(seed: u64, now: i64) -> Self {
+        Seeded { state: seed, now }
+    }
+
+    /// splitmix64
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+impl Source for Seeded {
+    fn random(&mut self) -> std::io::Result<[u8; 16]> {
+        let mut buf = [0u8; 16];
+        buf[..8].copy_from_slice(&self.next().to_le_bytes());
+        buf[8..].copy_from_slice(&self.next().to_le_bytes());
+        Ok(buf)
+    }
+
+    fn now(&mut self) -> i64 {
+        self.now += 1;
+        self.now - 1
     }
+}
+
+thread_local! {
+    static SOURCE: std::cell::RefCell<Box<dyn Source>> =
+        std::cell::RefCell::new(Box::new(System));
+}
+
+/// Use `source` for ids and timestamps on this thread,
+/// returning the one it replaces.
+#[cfg(test)]
+pub fn set_source(source: Box<dyn Source>) -> Box<dyn Source> {
+    SOURCE.with(|s| s.replace(source))
+}
+
+fn gen_uuid() -> std::io::Result<String> {
+    let mut buf = SOURCE.with(|s| s.borrow_mut().random())?;
     // Set version 4 and variant bits
     buf[6] = (buf[6] & 0x0f) | 0x40;
     buf[8] = (buf[8] & 0x3f) | 0x80;
-    format!(
+    Ok(format!(
         "{:02x}{:02x}{:02x}{:02x}-\
          {:02x}{:02x}-\
          {:02x}{:02x}-\
@@ -137,13 +530,22 @@ fn gen_uuid() -> String {
         buf[13],
         buf[14],
         buf[15],
-    )
+    ))
 }
 
 pub fn iso_now() -> String {
+    iso_time(now_secs())
+}
+
+/// Seconds since the epoch.
+pub fn now_secs() -> i64 {
+    SOURCE.with(|s| s.borrow_mut().now())
+}
+
+/// Format seconds since the epoch like `iso_now`.
+pub fn iso_time(secs: i64) -> String {
+    let t = secs as libc::time_t;
     unsafe {
-        let mut t: libc::time_t = 0;
-        libc::time(&mut t);
         let mut tm: libc::tm = std::mem::zeroed();
         libc::gmtime_r(&t, &mut tm);
         format!(
@@ -158,6 +560,21 @@ pub fn iso_now() -> String {
     }
 }
 
+/// Seconds since the epoch of an `iso_time` timestamp.
+pub fn parse_iso(s: &str) -> Option<i64> {
+    let num = |r: std::ops::Range<usize>| s.get(r)?.parse::<i32>().ok();
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        tm.tm_year = num(0..4)? - 1900;
+        tm.tm_mon = num(5..7)? - 1;
+        tm.tm_mday = num(8..10)?;
+        tm.tm_hour = num(11..13)?;
+        tm.tm_min = num(14..16)?;
+        tm.tm_sec = num(17..19)?;
+        Some(libc::timegm(&mut tm) as i64)
+    }
+}
+
 fn git_branch(working_dir: &Path) -> String {
     let output = std::process::Command::new("git")
         .arg("rev-parse")
@@ -172,3 +589,187 @@ fn git_branch(working_dir: &Path) -> String {
         _ => String::new(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concurrent_updates_keep_every_entry() {
+        let dir = std::env::temp_dir().join("tapir_session_index");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let dir = &dir;
+                s.spawn(move || {
+                    for i in 0..10 {
+                        let mut entry = create_entry(dir, dir).unwrap();
+                        entry.session_id = format!("t{t}-{i}");
+                        update_entry(dir, &entry);
+                    }
+                });
+            }
+        });
+        assert_eq!(load_index(&dir).entries.len(), 40);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn entry_files_merge_over_the_legacy_index() {
+        let dir = std::env::temp_dir().join("tapir_session_legacy");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut old = create_entry(&dir, &dir).unwrap();
+        old.session_id = "old".into();
+        let mut both = create_entry(&dir, &dir).unwrap();
+        both.session_id = "both".into();
+        let legacy = SessionIndex {
+            version: 1,
+            entries: vec![old.clone(), both.clone()],
+            original_path: "/project".into(),
+        };
+        let json = serde_json::to_string(&legacy).unwrap();
+        fs::write(index_path(&dir), json).unwrap();
+
+        both.summary = "renamed".into();
+        update_entry(&dir, &both);
+        let index = load_index(&dir);
+        assert_eq!(index.original_path, "/project");
+        let names: Vec<_> = index
+            .entries
+            .iter()
+            .map(|e| (e.session_id.as_str(), e.summary.as_str()))
+            .collect();
+        assert_eq!(names, [("old", ""), ("both", "renamed")]);
+
+        delete(&dir, &both).unwrap();
+        delete(&dir, &old).unwrap();
+        assert!(load_index(&dir).entries.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prune_spares_fresh_empty_sessions() {
+        let dir = std::env::temp_dir().join("tapir_session_prune");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let now = 1_700_000_000;
+        let old = set_source(Box::new(Seeded::new(5, now)));
+        let add = |messages: u32, age: i64| {
+            let mut entry = create_entry(&dir, &dir).unwrap();
+            entry.message_count = messages;
+            entry.modified = iso_time(now - age);
+            fs::write(session_path(&entry), "").unwrap();
+            update_entry(&dir, &entry);
+            entry.session_id
+        };
+        let _stale_empty = add(0, 2 * 86400);
+        let fresh_empty = add(0, 60);
+        let _too_old = add(3, 10 * 86400);
+        let recent = add(3, 86400);
+
+        let retention = Retention {
+            max_sessions: None,
+            max_age_days: Some(5),
+        };
+        assert_eq!(prune(&dir, &retention), 1);
+        set_source(old);
+        let mut left: Vec<String> = load_index(&dir)
+            .entries
+            .into_iter()
+            .map(|e| e.session_id)
+            .collect();
+        left.sort();
+        let mut want = vec![fresh_empty, recent];
+        want.sort();
+        assert_eq!(left, want);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn info_json_has_usage_and_cost() {
+        let dir = std::env::temp_dir().join("tapir_session_info");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut entry = create_entry(&dir, &dir).unwrap();
+        entry.summary = "parser".into();
+        let usage = crate::cost::Totals {
+            cost: 0.5,
+            requests: 2,
+            input_tokens: 300,
+            ..Default::default()
+        };
+        let info = info_json(&entry, &usage);
+        assert_eq!(info["id"], entry.session_id);
+        assert_eq!(info["name"], "parser");
+        assert_eq!(info["usage"]["requests"], 2);
+        assert_eq!(info["usage"]["input_tokens"], 300);
+        assert_eq!(info["cost"], 0.5);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn seeded_source_repeats_sessions() {
+        let dir = std::env::temp_dir().join("tapir_session_seeded");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let run = || {
+            let old = set_source(Box::new(Seeded::new(7, 1_700_000_000)));
+            let first = create_entry(&dir, &dir).unwrap();
+            let second = create_entry(&dir, &dir).unwrap();
+            set_source(old);
+            (first, second)
+        };
+        let (mut a, mut b) = run();
+        let (again, _) = run();
+        assert_eq!(a.session_id, again.session_id);
+        assert_ne!(a.session_id, b.session_id);
+        assert_eq!(a.session_id.as_bytes()[14], b'4');
+        assert_eq!(a.created, "2023-11-14T22:13:20.000Z");
+        assert_eq!(b.created, "2023-11-14T22:13:21.000Z");
+
+        a.message_count = 1;
+        b.message_count = 1;
+        update_entry(&dir, &a);
+        update_entry(&dir, &b);
+        let ids: Vec<String> = recent_entries(&dir)
+            .into_iter()
+            .map(|e| e.session_id)
+            .collect();
+        assert_eq!(ids, [b.session_id, a.session_id]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn new_ids_skip_existing_sessions() {
+        let dir = std::env::temp_dir().join("tapir_session_collide");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let old = set_source(Box::new(Seeded::new(3, 0)));
+        let first = create_entry(&dir, &dir).unwrap();
+        fs::write(session_path(&first), "").unwrap();
+        set_source(Box::new(Seeded::new(3, 0)));
+        let second = create_entry(&dir, &dir).unwrap();
+        assert_ne!(first.session_id, second.session_id);
+
+        // A source stuck on one value gives up instead of
+        // reusing the session
+        struct Stuck;
+        impl Source for Stuck {
+            fn random(&mut self) -> std::io::Result<[u8; 16]> {
+                Ok([0; 16])
+            }
+            fn now(&mut self) -> i64 {
+                0
+            }
+        }
+        set_source(Box::new(Stuck));
+        let stuck = create_entry(&dir, &dir).unwrap();
+        update_entry(&dir, &stuck);
+        let err = create_entry(&dir, &dir).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        set_source(old);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,18 +1,39 @@
 use std::sync::atomic::{AtomicBool, Ordering};
 
 static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static HUNG_UP: AtomicBool = AtomicBool::new(false);
+static IDLE: AtomicBool = AtomicBool::new(false);
+/// Starts set so the first look at the terminal size reads
+/// it.
+static RESIZED: AtomicBool = AtomicBool::new(true);
 
-/// Install a SIGINT handler that sets the `INTERRUPTED` flag.
+/// Install handlers for SIGINT, which sets the `INTERRUPTED`
+/// flag; SIGHUP, which also sets `HUNG_UP`; SIGALRM, which
+/// the editor's idle timer raises; and SIGWINCH, which sets
+/// `RESIZED`.
 ///
 /// Uses `sa_flags = 0` (no `SA_RESTART`) so that blocking
-/// `read()` calls return `EINTR` when the signal fires.
+/// `read()` calls return `EINTR` when a signal fires, except
+/// for SIGWINCH: a resize is no reason to stop reading.
 pub fn install_handler() {
-    unsafe {
-        let mut sa: libc::sigaction = std::mem::zeroed();
-        sa.sa_sigaction = handler as usize;
-        sa.sa_flags = 0; // no SA_RESTART
-        libc::sigemptyset(&mut sa.sa_mask);
-        libc::sigaction(libc::SIGINT, &sa, std::ptr::null_mut());
+    let handlers: [(libc::c_int, extern "C" fn(libc::c_int)); 4] = [
+        (libc::SIGINT, handler),
+        (libc::SIGHUP, hangup),
+        (libc::SIGALRM, alarm),
+        (libc::SIGWINCH, resize),
+    ];
+    for (sig, f) in handlers {
+        unsafe {
+            let mut sa: libc::sigaction = std::mem::zeroed();
+            sa.sa_sigaction = f as usize;
+            sa.sa_flags = if sig == libc::SIGWINCH {
+                libc::SA_RESTART
+            } else {
+                0 // no SA_RESTART
+            };
+            libc::sigemptyset(&mut sa.sa_mask);
+            libc::sigaction(sig, &sa, std::ptr::null_mut());
+        }
     }
 }
 
@@ -20,6 +41,29 @@ extern "C" fn handler(_sig: libc::c_int) {
     INTERRUPTED.store(true, Ordering::SeqCst);
 }
 
+/// The terminal is gone: stop what is running, and send
+/// output to /dev/null so winding down can't fail on a write.
+extern "C" fn hangup(_sig: libc::c_int) {
+    HUNG_UP.store(true, Ordering::SeqCst);
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    unsafe {
+        let fd = libc::open(c"/dev/null".as_ptr(), libc::O_WRONLY);
+        if fd >= 0 {
+            libc::dup2(fd, 1);
+            libc::dup2(fd, 2);
+            libc::close(fd);
+        }
+    }
+}
+
+extern "C" fn alarm(_sig: libc::c_int) {
+    IDLE.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn resize(_sig: libc::c_int) {
+    RESIZED.store(true, Ordering::SeqCst);
+}
+
 pub fn is_interrupted() -> bool {
     INTERRUPTED.load(Ordering::SeqCst)
 }
@@ -28,7 +72,28 @@ pub fn clear() {
     INTERRUPTED.store(false, Ordering::SeqCst);
 }
 
+/// Whether the controlling terminal hung up.
+pub fn hung_up() -> bool {
+    HUNG_UP.load(Ordering::SeqCst)
+}
+
+/// Whether the idle alarm fired since the last call.
+pub fn take_idle() -> bool {
+    IDLE.swap(false, Ordering::SeqCst)
+}
+
+/// Whether the terminal may have changed size since the
+/// last call.
+pub fn take_resized() -> bool {
+    RESIZED.swap(false, Ordering::SeqCst)
+}
+
 #[cfg(test)]
 pub(crate) fn set() {
     INTERRUPTED.store(true, Ordering::SeqCst);
 }
+
+#[cfg(test)]
+pub(crate) fn set_hung_up(on: bool) {
+    HUNG_UP.store(on, Ordering::SeqCst);
+}
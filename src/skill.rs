@@ -1,14 +1,96 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use serde::{Deserialize, Serialize};
+
 pub struct Skill {
     pub name: String,
     pub description: String,
     pub path: PathBuf,
+    pub origin: Origin,
+    /// Another skill with the same name from a higher
+    /// precedence origin wins; reachable only as
+    /// `/skill:<origin>:<name>`.
+    pub shadowed: bool,
+    /// Validation problems found while loading.
+    pub problems: Vec<String>,
+    /// A problem prevents using the skill (unparsable
+    /// frontmatter, missing name or description).
+    pub disabled: bool,
+}
+
+/// Where a skill was discovered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Origin {
+    /// Global: `~/.tapir/agent/skills`, `~/.agents/skills`.
+    User,
+    /// The project and its ancestors up to the git root.
+    Project,
+    /// Extra paths listed under `skills` in config.
+    Config,
+}
+
+/// Default precedence when config doesn't set one: global
+/// skills win over project ones, matching discovery order.
+pub const DEFAULT_PRECEDENCE: [Origin; 3] =
+    [Origin::User, Origin::Project, Origin::Config];
+
+impl Origin {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Origin::User => "user",
+            Origin::Project => "project",
+            Origin::Config => "config",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "user" => Some(Origin::User),
+            "project" => Some(Origin::Project),
+            "config" => Some(Origin::Config),
+            _ => None,
+        }
+    }
+}
+
+/// Build a full precedence list from config names. Unknown
+/// names warn; origins not mentioned keep default order.
+pub fn parse_precedence(names: &[String]) -> Vec<Origin> {
+    let mut order = Vec::new();
+    for name in names {
+        match Origin::parse(name) {
+            Some(o) if !order.contains(&o) => order.push(o),
+            Some(_) => {}
+            None => eprintln!(
+                "warning: skill_precedence: unknown origin '{name}' \
+                 (expected user, project, config)"
+            ),
+        }
+    }
+    for o in DEFAULT_PRECEDENCE {
+        if !order.contains(&o) {
+            order.push(o);
+        }
+    }
+    order
+}
+
+/// Resolve `name` or `origin:name` to a skill. A bare name
+/// picks the non-shadowed skill.
+pub fn find<'a>(skills: &'a [Skill], spec: &str) -> Option<&'a Skill> {
+    match spec.split_once(':') {
+        Some((origin, name)) => {
+            let origin = Origin::parse(origin)?;
+            skills.iter().find(|s| s.origin == origin && s.name == name)
+        }
+        None => skills.iter().find(|s| !s.shadowed && s.name == spec),
+    }
 }
 
 /// Parsed YAML frontmatter fields.
+#[derive(Debug)]
 #[cfg_attr(not(test), allow(dead_code))]
 struct Frontmatter {
     name: Option<String>,
@@ -16,22 +98,37 @@ struct Frontmatter {
     body_start: usize,
 }
 
+/// A frontmatter value in the supported YAML subset.
+#[derive(Debug, PartialEq)]
+enum Value {
+    Str(String),
+    List(Vec<String>),
+}
+
+fn has_frontmatter(content: &str) -> bool {
+    content.trim_start().starts_with("---")
+}
+
 /// Parse YAML frontmatter from a SKILL.md file's content.
-fn parse_frontmatter(content: &str) -> Option<Frontmatter> {
+/// Errors describe what is wrong, with file line numbers.
+fn parse_frontmatter(content: &str) -> Result<Frontmatter, String> {
     let content = content.trim_start();
     if !content.starts_with("---") {
-        return None;
+        return Err("missing frontmatter (file must start with ---)".into());
     }
     let after_open = &content[3..];
     let after_open = after_open
         .strip_prefix('\n')
-        .or_else(|| after_open.strip_prefix("\r\n"))?;
+        .or_else(|| after_open.strip_prefix("\r\n"))
+        .ok_or("opening --- must be on its own line")?;
 
     // Handle empty frontmatter (closing --- on next line)
     let (close, yaml_block) = if after_open.starts_with("---") {
         (0, "")
     } else {
-        let pos = after_open.find("\n---")?;
+        let pos = after_open
+            .find("\n---")
+            .ok_or("unterminated frontmatter (missing closing ---)")?;
         (pos + 1, &after_open[..pos])
     };
     let dash_start = content.len() - after_open.len() + close;
@@ -49,29 +146,248 @@ fn parse_frontmatter(content: &str) -> Option<Frontmatter> {
 
     let mut name = None;
     let mut description = None;
-    for line in yaml_block.lines() {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') {
-            continue;
-        }
-        if let Some((key, value)) = line.split_once(':') {
-            let key = key.trim();
-            let value = value.trim();
-            match key {
-                "name" => name = Some(value.to_string()),
-                "description" => description = Some(value.to_string()),
-                _ => {}
-            }
+    for (key, value) in parse_yaml(yaml_block)? {
+        let slot = match key.as_str() {
+            "name" => &mut name,
+            "description" => &mut description,
+            _ => continue,
+        };
+        match value {
+            Value::Str(s) => *slot = Some(s),
+            Value::List(_) => return Err(format!("{key} must be a string")),
         }
     }
 
-    Some(Frontmatter {
+    Ok(Frontmatter {
         name,
         description,
         body_start,
     })
 }
 
+/// Parse the YAML subset used in frontmatter: top-level
+/// `key: value` pairs with plain, single- or double-quoted
+/// scalars, `|`/`>` block scalars, indented continuation
+/// lines, `[a, b]` flow lists, and `- item` block lists.
+/// Nested mappings are kept as folded text.
+fn parse_yaml(block: &str) -> Result<Vec<(String, Value)>, String> {
+    let lines: Vec<&str> = block.lines().collect();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let raw = lines[i];
+        // Line 1 of the file is the opening ---
+        let n = i + 2;
+        i += 1;
+        let trimmed = raw.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if raw.starts_with([' ', '\t']) {
+            return Err(format!("line {n}: unexpected indentation"));
+        }
+        let Some((key, rest)) = raw.split_once(':') else {
+            return Err(format!("line {n}: expected `key: value`"));
+        };
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(format!("line {n}: empty key"));
+        }
+
+        // Indented lines after the key belong to its value
+        let start = i;
+        while i < lines.len()
+            && (lines[i].trim().is_empty() || lines[i].starts_with([' ', '\t']))
+        {
+            i += 1;
+        }
+        let mut nested = &lines[start..i];
+        while let Some((last, init)) = nested.split_last()
+            && last.trim().is_empty()
+        {
+            nested = init;
+        }
+
+        let value = parse_value(rest.trim(), nested, n)?;
+        out.push((key.to_string(), value));
+    }
+    Ok(out)
+}
+
+fn parse_value(rest: &str, nested: &[&str], n: usize) -> Result<Value, String> {
+    if let Some(style) = rest.strip_prefix(['|', '>']) {
+        if !style.is_empty() && style != "-" && style != "+" {
+            return Err(format!("line {n}: unsupported block indicator"));
+        }
+        return Ok(Value::Str(block_scalar(nested, rest.starts_with('>'))));
+    }
+
+    let items: Vec<&str> = nested
+        .iter()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .collect();
+
+    if rest.is_empty() {
+        if !items.is_empty()
+            && items.iter().all(|l| *l == "-" || l.starts_with("- "))
+        {
+            return items
+                .iter()
+                .map(|l| parse_scalar(l[1..].trim(), n))
+                .collect::<Result<_, _>>()
+                .map(Value::List);
+        }
+        return Ok(Value::Str(items.join(" ")));
+    }
+
+    if rest.starts_with(['[', '"', '\'']) && !items.is_empty() {
+        return Err(format!(
+            "line {n}: multi-line quoted values and lists \
+             are not supported"
+        ));
+    }
+    if let Some(inner) = rest.strip_prefix('[') {
+        return parse_flow_list(inner, n).map(Value::List);
+    }
+    let first = parse_scalar(rest, n)?;
+    if items.is_empty() {
+        return Ok(Value::Str(first));
+    }
+    // Plain multi-line scalar: continuation lines fold
+    let mut parts = vec![first];
+    parts.extend(items.iter().map(|l| strip_comment(l).to_string()));
+    Ok(Value::Str(parts.join(" ")))
+}
+
+/// Join the lines of a `|` (literal) or `>` (folded) block,
+/// removing the common indentation.
+fn block_scalar(lines: &[&str], folded: bool) -> String {
+    let indent = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let stripped: Vec<&str> = lines
+        .iter()
+        .map(|l| if l.len() >= indent { &l[indent..] } else { "" })
+        .collect();
+    if !folded {
+        return stripped.join("\n");
+    }
+    let mut out = String::new();
+    for line in stripped {
+        if line.trim().is_empty() {
+            out.push('\n');
+        } else {
+            if !out.is_empty() && !out.ends_with('\n') {
+                out.push(' ');
+            }
+            out.push_str(line.trim_end());
+        }
+    }
+    out
+}
+
+/// Parse a single scalar: quoted or plain (with trailing
+/// ` # comment` removed).
+fn parse_scalar(s: &str, n: usize) -> Result<String, String> {
+    let s = s.trim();
+    let (out, rest) = if let Some(body) = s.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = body.char_indices();
+        let end = loop {
+            match chars.next() {
+                Some((i, '"')) => break i + 1,
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, 'n')) => out.push('\n'),
+                    Some((_, 't')) => out.push('\t'),
+                    Some((_, c @ ('"' | '\\' | '/'))) => out.push(c),
+                    Some((_, c)) => {
+                        return Err(format!("line {n}: unknown escape \\{c}"));
+                    }
+                    None => break body.len() + 1,
+                },
+                Some((_, c)) => out.push(c),
+                None => return Err(format!("line {n}: unterminated string")),
+            }
+        };
+        (out, &body[end.min(body.len())..])
+    } else if let Some(body) = s.strip_prefix('\'') {
+        let mut out = String::new();
+        let mut chars = body.char_indices().peekable();
+        let end = loop {
+            match chars.next() {
+                Some((_, '\'')) if chars.peek().map(|c| c.1) == Some('\'') => {
+                    chars.next();
+                    out.push('\'');
+                }
+                Some((i, '\'')) => break i + 1,
+                Some((_, c)) => out.push(c),
+                None => return Err(format!("line {n}: unterminated string")),
+            }
+        };
+        (out, &body[end..])
+    } else {
+        return Ok(strip_comment(s).to_string());
+    };
+    let rest = rest.trim();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Err(format!("line {n}: unexpected text after string"));
+    }
+    Ok(out)
+}
+
+/// Parse the inside of a `[a, "b", c]` flow list (after `[`).
+fn parse_flow_list(inner: &str, n: usize) -> Result<Vec<String>, String> {
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    let mut closed = None;
+    for (i, ch) in inner.char_indices() {
+        match (quote, ch) {
+            (Some(q), c) if c == q => {
+                quote = None;
+                current.push(c);
+            }
+            (Some(_), c) => current.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(ch);
+                current.push(ch);
+            }
+            (None, ',') => {
+                items.push(parse_scalar(&current, n)?);
+                current.clear();
+            }
+            (None, ']') => {
+                closed = Some(i);
+                break;
+            }
+            (None, c) => current.push(c),
+        }
+    }
+    let Some(close) = closed else {
+        return Err(format!("line {n}: unclosed ["));
+    };
+    let tail = inner[close + 1..].trim();
+    if !tail.is_empty() && !tail.starts_with('#') {
+        return Err(format!("line {n}: unexpected text after ]"));
+    }
+    if !current.trim().is_empty() {
+        items.push(parse_scalar(&current, n)?);
+    }
+    Ok(items)
+}
+
+/// Remove a trailing ` # comment` from a plain scalar.
+fn strip_comment(s: &str) -> &str {
+    match s.find(" #") {
+        Some(i) => s[..i].trim_end(),
+        None => s.trim(),
+    }
+}
+
 fn validate_name(name: &str) -> std::result::Result<(), String> {
     if name.is_empty() || name.len() > 64 {
         return Err(format!("name must be 1-64 chars, got {}", name.len()));
@@ -93,31 +409,54 @@ fn validate_name(name: &str) -> std::result::Result<(), String> {
     Ok(())
 }
 
-fn load_skill_file(path: &Path) -> Option<Skill> {
+/// Load a skill file. Problems (bad YAML, missing fields,
+/// invalid name) are recorded on the skill rather than
+/// dropping it, so `/skills` can show them. Plain `.md` files
+/// without frontmatter are not skills and yield `None`.
+fn load_skill_file(path: &Path, origin: Origin) -> Option<Skill> {
     let content = fs::read_to_string(path).ok()?;
-    let fm = parse_frontmatter(&content)?;
+    let is_skill_md = path.file_name().is_some_and(|f| f == "SKILL.md");
+    if !is_skill_md && !has_frontmatter(&content) {
+        return None;
+    }
 
-    let name = fm.name?;
-    let description = fm.description.filter(|d| !d.is_empty())?;
+    let fallback_name = if is_skill_md {
+        path.parent().and_then(|p| p.file_name())
+    } else {
+        path.file_stem()
+    }
+    .map(|n| n.to_string_lossy().to_string())
+    .unwrap_or_default();
+
+    let mut problems = Vec::new();
+    let (name, description) = match parse_frontmatter(&content) {
+        Ok(fm) => {
+            let name = fm.name.unwrap_or_else(|| {
+                problems.push("missing name".to_string());
+                fallback_name.clone()
+            });
+            let description =
+                fm.description.filter(|d| !d.is_empty()).unwrap_or_else(|| {
+                    problems.push("missing description".to_string());
+                    String::new()
+                });
+            (name, description)
+        }
+        Err(e) => {
+            problems.push(e);
+            (fallback_name.clone(), String::new())
+        }
+    };
+    let disabled = !problems.is_empty();
 
     if let Err(e) = validate_name(&name) {
-        eprintln!("warning: {}: {e}", path.display());
+        problems.push(e);
     }
 
-    if path.file_name().map(|f| f == "SKILL.md").unwrap_or(false)
-        && let Some(parent_name) = path
-            .parent()
-            .and_then(|p| p.file_name())
-            .and_then(|n| n.to_str())
-        && parent_name != name
-    {
-        eprintln!(
-            "warning: {}: name '{}' doesn't \
-             match directory '{}'",
-            path.display(),
-            name,
-            parent_name,
-        );
+    if is_skill_md && !fallback_name.is_empty() && fallback_name != name {
+        problems.push(format!(
+            "name '{name}' doesn't match directory '{fallback_name}'"
+        ));
     }
 
     let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
@@ -126,48 +465,182 @@ fn load_skill_file(path: &Path) -> Option<Skill> {
         name,
         description,
         path,
+        origin,
+        shadowed: false,
+        problems,
+        disabled,
     })
 }
 
-fn load_from_dir(dir: &Path) -> Vec<Skill> {
+#[cfg(test)]
+fn load_from_dir(dir: &Path, origin: Origin) -> Vec<Skill> {
+    scan_dir(dir, origin).0
+}
+
+/// The skills in `dir`, and the mtime of everything looked at
+/// to find them: the directory, its subdirectories and their
+/// candidate files. Empty stamps mean `dir` doesn't exist.
+fn scan_dir(dir: &Path, origin: Origin) -> (Vec<Skill>, Vec<(PathBuf, u64)>) {
     let entries = match fs::read_dir(dir) {
         Ok(e) => e,
-        Err(_) => return Vec::new(),
+        Err(_) => return (Vec::new(), Vec::new()),
     };
 
     let mut skills = Vec::new();
+    let mut stamps: Vec<(PathBuf, u64)> = Vec::new();
+    let mut stamp = |path: &Path| {
+        if let Some(t) = mtime(path) {
+            stamps.push((path.to_path_buf(), t));
+        }
+    };
+    stamp(dir);
 
     for entry in entries.flatten() {
         let path = entry.path();
 
         if path.is_dir() {
+            stamp(&path);
             let skill_md = path.join("SKILL.md");
-            if skill_md.is_file()
-                && let Some(s) = load_skill_file(&skill_md)
-            {
-                skills.push(s);
+            if skill_md.is_file() {
+                stamp(&skill_md);
+                if let Some(s) = load_skill_file(&skill_md, origin) {
+                    skills.push(s);
+                }
             }
-        } else if path.is_file()
-            && path.extension().is_some_and(|e| e == "md")
-            && let Some(s) = load_skill_file(&path)
+        } else if path.is_file() && path.extension().is_some_and(|e| e == "md")
         {
-            skills.push(s);
+            stamp(&path);
+            if let Some(s) = load_skill_file(&path, origin) {
+                skills.push(s);
+            }
         }
     }
 
     skills.sort_by(|a, b| a.name.cmp(&b.name));
-    skills
+    (skills, stamps)
+}
+
+fn mtime(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    let since = modified.duration_since(std::time::UNIX_EPOCH).ok()?;
+    Some(since.as_nanos() as u64)
+}
+
+/// Skills per directory as last scanned, reused while nothing
+/// the scan looked at has a new mtime. Directory walks are
+/// slow on network filesystems.
+#[derive(Default, Serialize, Deserialize)]
+struct DiscoveryCache {
+    dirs: HashMap<PathBuf, CachedDir>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedDir {
+    stamps: Vec<(PathBuf, u64)>,
+    skills: Vec<CachedSkill>,
 }
 
-/// Discover skills from an ordered list of directories.
-/// First occurrence of a name wins; duplicates warn.
-fn discover_skills_from_dirs(dirs: &[PathBuf]) -> Vec<Skill> {
+#[derive(Serialize, Deserialize)]
+struct CachedSkill {
+    name: String,
+    description: String,
+    path: PathBuf,
+    problems: Vec<String>,
+    disabled: bool,
+}
+
+fn cache_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("skills-cache.json")
+}
+
+impl CachedDir {
+    fn new(stamps: Vec<(PathBuf, u64)>, skills: &[Skill]) -> Self {
+        let skills = skills
+            .iter()
+            .map(|s| CachedSkill {
+                name: s.name.clone(),
+                description: s.description.clone(),
+                path: s.path.clone(),
+                problems: s.problems.clone(),
+                disabled: s.disabled,
+            })
+            .collect();
+        CachedDir { stamps, skills }
+    }
+
+    fn is_fresh(&self) -> bool {
+        !self.stamps.is_empty()
+            && self.stamps.iter().all(|(p, t)| mtime(p) == Some(*t))
+    }
+
+    fn skills(&self, origin: Origin) -> Vec<Skill> {
+        self.skills
+            .iter()
+            .map(|s| Skill {
+                name: s.name.clone(),
+                description: s.description.clone(),
+                path: s.path.clone(),
+                origin,
+                shadowed: false,
+                problems: s.problems.clone(),
+                disabled: s.disabled,
+            })
+            .collect()
+    }
+}
+
+/// Discover skills from an ordered list of directories,
+/// scanned concurrently and through `cache_file` if given.
+/// Within one origin the first occurrence of a name wins and
+/// duplicates warn. Across origins all are kept, and every
+/// skill but the one from the highest `precedence` origin is
+/// marked shadowed.
+fn discover_skills_from_dirs(
+    dirs: &[(PathBuf, Origin)],
+    precedence: &[Origin],
+    cache_file: Option<&Path>,
+) -> Vec<Skill> {
+    let mut cache: DiscoveryCache = cache_file
+        .and_then(|f| fs::read_to_string(f).ok())
+        .and_then(|t| serde_json::from_str(&t).ok())
+        .unwrap_or_default();
+
+    // Each directory's skills, and its new cache entry if it
+    // had to be scanned
+    let scanned: Vec<(Vec<Skill>, Option<CachedDir>)> =
+        std::thread::scope(|s| {
+            let cache = &cache;
+            let handles: Vec<_> = dirs
+                .iter()
+                .map(|(dir, origin)| {
+                    s.spawn(move || match cache.dirs.get(dir) {
+                        Some(c) if c.is_fresh() => (c.skills(*origin), None),
+                        _ => {
+                            let (skills, stamps) = scan_dir(dir, *origin);
+                            let entry = CachedDir::new(stamps, &skills);
+                            (skills, Some(entry))
+                        }
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
     let mut seen = HashSet::new();
     let mut result = Vec::new();
-
-    for dir in dirs {
-        for skill in load_from_dir(dir) {
-            if seen.contains(&skill.name) {
+    let mut changed = false;
+
+    for ((dir, _), (skills, entry)) in dirs.iter().zip(scanned) {
+        if let Some(entry) = entry {
+            changed = true;
+            if entry.stamps.is_empty() {
+                cache.dirs.remove(dir);
+            } else {
+                cache.dirs.insert(dir.clone(), entry);
+            }
+        }
+        for skill in skills {
+            if !seen.insert((skill.name.clone(), skill.origin.as_str())) {
                 eprintln!(
                     "warning: duplicate skill '{}' \
                      in {}, skipping",
@@ -176,10 +649,37 @@ fn discover_skills_from_dirs(dirs: &[PathBuf]) -> Vec<Skill> {
                 );
                 continue;
             }
-            seen.insert(skill.name.clone());
             result.push(skill);
         }
     }
+    if changed
+        && let Some(file) = cache_file
+        && let Ok(json) = serde_json::to_string(&cache)
+    {
+        if let Some(parent) = file.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let _ = fs::write(file, json);
+    }
+
+    let rank = |o: Origin| {
+        precedence
+            .iter()
+            .position(|p| *p == o)
+            .unwrap_or(precedence.len())
+    };
+    // A broken skill never hides a working one of the same name
+    result.sort_by(|a, b| {
+        a.name
+            .cmp(&b.name)
+            .then(a.disabled.cmp(&b.disabled))
+            .then(rank(a.origin).cmp(&rank(b.origin)))
+    });
+    for i in 1..result.len() {
+        if result[i].name == result[i - 1].name {
+            result[i].shadowed = true;
+        }
+    }
 
     result
 }
@@ -195,21 +695,31 @@ fn git_root(dir: &Path) -> Option<PathBuf> {
     }
 }
 
+/// A `skills` directory from config, `~` expanded.
+pub fn config_dir(home: &Path, path_str: &str) -> PathBuf {
+    if path_str.starts_with('~') {
+        home.join(path_str.trim_start_matches("~/"))
+    } else {
+        PathBuf::from(path_str)
+    }
+}
+
 /// Main entry point: discover all skills from standard
 /// locations plus config paths.
 pub fn discover_skills(
     working_dir: &Path,
+    tapir_dirs: &crate::config::Dirs,
     config_paths: &[String],
+    precedence: &[Origin],
 ) -> Vec<Skill> {
-    let home = std::env::var("HOME").unwrap_or_else(|_| "/tmp".into());
-    let home = PathBuf::from(&home);
     let boundary = git_root(working_dir);
 
     let mut dirs = Vec::new();
 
     // 1. Global
-    dirs.push(home.join(".tapir").join("agent").join("skills"));
-    dirs.push(home.join(".agents").join("skills"));
+    let home = &tapir_dirs.home;
+    dirs.push((tapir_dirs.config.join("agent").join("skills"), Origin::User));
+    dirs.push((home.join(".agents").join("skills"), Origin::User));
 
     // 2. Ancestors (root-first), up to git root or fs root
     let ancestors: Vec<&Path> = working_dir.ancestors().skip(1).collect();
@@ -219,32 +729,28 @@ pub fn discover_skills(
         {
             continue;
         }
-        dirs.push(dir.join(".agents").join("skills"));
+        dirs.push((dir.join(".agents").join("skills"), Origin::Project));
     }
 
     // 3. Project
-    dirs.push(working_dir.join(".tapir").join("skills"));
-    dirs.push(working_dir.join(".agents").join("skills"));
+    dirs.push((working_dir.join(".tapir").join("skills"), Origin::Project));
+    dirs.push((working_dir.join(".agents").join("skills"), Origin::Project));
 
     // 4. Config paths
     for path_str in config_paths {
-        let p = if path_str.starts_with('~') {
-            home.join(path_str.trim_start_matches("~/"))
-        } else {
-            PathBuf::from(path_str)
-        };
-        dirs.push(p);
+        dirs.push((config_dir(home, path_str), Origin::Config));
     }
 
-    discover_skills_from_dirs(&dirs)
+    let cache = cache_path(&tapir_dirs.data);
+    discover_skills_from_dirs(&dirs, precedence, Some(&cache))
 }
 
 pub fn format_skills(skills: &[Skill]) -> String {
-    if skills.is_empty() {
+    if skills.iter().all(|s| s.shadowed || s.disabled) {
         return String::new();
     }
     let mut out = String::from("<available-skills>\n");
-    for skill in skills {
+    for skill in skills.iter().filter(|s| !s.shadowed && !s.disabled) {
         out.push_str(&format!(
             "<skill name=\"{}\" path=\"{}\">\n\
              {}\n</skill>\n",
@@ -257,6 +763,64 @@ pub fn format_skills(skills: &[Skill]) -> String {
     out
 }
 
+/// Who triggered a skill.
+#[derive(Clone, Copy)]
+pub enum Invoker {
+    /// `/skill:name`
+    User,
+    /// The model read the skill file.
+    Model,
+}
+
+/// Usage counters for one skill, keyed by path in the stats
+/// file.
+#[derive(Default, Serialize, Deserialize)]
+pub struct SkillUsage {
+    pub name: String,
+    #[serde(default)]
+    pub user: u32,
+    #[serde(default)]
+    pub model: u32,
+    #[serde(default)]
+    pub last_used: String,
+}
+
+pub fn stats_path(tapir_dir: &Path) -> PathBuf {
+    tapir_dir.join("skills-stats.json")
+}
+
+pub fn load_stats(path: &Path) -> HashMap<String, SkillUsage> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|t| serde_json::from_str(&t).ok())
+        .unwrap_or_default()
+}
+
+/// Bump the counter for `skill` and persist.
+pub fn record_use(stats_file: &Path, skill: &Skill, by: Invoker) {
+    let mut stats = load_stats(stats_file);
+    let entry = stats
+        .entry(skill.path.to_string_lossy().to_string())
+        .or_default();
+    entry.name = skill.name.clone();
+    match by {
+        Invoker::User => entry.user += 1,
+        Invoker::Model => entry.model += 1,
+    }
+    entry.last_used = crate::session::iso_now();
+    if let Ok(json) = serde_json::to_string_pretty(&stats) {
+        let _ = fs::write(stats_file, json);
+    }
+}
+
+/// The skill whose file is `path`, if any. Used to notice the
+/// model loading a skill through read_file.
+pub fn by_path<'a>(skills: &'a [Skill], path: &Path) -> Option<&'a Skill> {
+    skills.iter().find(|s| {
+        s.path == path || s.path.canonicalize().is_ok_and(|p| p == path)
+    })
+}
+
 /// Extract the body of a SKILL.md after the frontmatter.
 pub fn skill_body(content: &str) -> &str {
     let trimmed = content.trim_start();
@@ -310,7 +874,80 @@ mod tests {
 
     #[test]
     fn parse_frontmatter_missing_fences() {
-        assert!(parse_frontmatter("no fences here").is_none());
+        assert!(parse_frontmatter("no fences here").is_err());
+    }
+
+    #[test]
+    fn parse_frontmatter_unterminated() {
+        let err = parse_frontmatter("---\nname: x\n").unwrap_err();
+        assert!(err.contains("unterminated"), "got: {err}");
+    }
+
+    #[test]
+    fn parse_frontmatter_quoted_strings() {
+        let content = "---\nname: \"my-skill\"\n\
+                       description: 'It''s: quoted' # note\n---\n";
+        let fm = parse_frontmatter(content).unwrap();
+        assert_eq!(fm.name.as_deref(), Some("my-skill"));
+        assert_eq!(fm.description.as_deref(), Some("It's: quoted"));
+    }
+
+    #[test]
+    fn parse_frontmatter_multiline_descriptions() {
+        let folded = "---\nname: x\ndescription: >\n  Line one\n  \
+                      line two\n---\n";
+        let fm = parse_frontmatter(folded).unwrap();
+        assert_eq!(fm.description.as_deref(), Some("Line one line two"));
+
+        let literal = "---\nname: x\ndescription: |\n  a\n  b\n---\n";
+        let fm = parse_frontmatter(literal).unwrap();
+        assert_eq!(fm.description.as_deref(), Some("a\nb"));
+
+        let plain = "---\nname: x\ndescription: starts here\n  \
+                     and continues\n---\n";
+        let fm = parse_frontmatter(plain).unwrap();
+        assert_eq!(
+            fm.description.as_deref(),
+            Some("starts here and continues")
+        );
+    }
+
+    #[test]
+    fn parse_yaml_lists() {
+        let fields =
+            parse_yaml("tools: [read, \"bash, safe\"]\ntags:\n  - a\n  - 'b'")
+                .unwrap();
+        assert_eq!(
+            fields[0],
+            (
+                "tools".into(),
+                Value::List(vec!["read".into(), "bash, safe".into()])
+            )
+        );
+        assert_eq!(
+            fields[1],
+            ("tags".into(), Value::List(vec!["a".into(), "b".into()]))
+        );
+    }
+
+    #[test]
+    fn parse_yaml_errors_report_line() {
+        assert_eq!(
+            parse_yaml("name: x\njust text").unwrap_err(),
+            "line 3: expected `key: value`"
+        );
+        assert!(
+            parse_yaml("a: \"open")
+                .unwrap_err()
+                .contains("unterminated")
+        );
+        assert!(parse_yaml("a: [x, y").unwrap_err().contains("unclosed"));
+    }
+
+    #[test]
+    fn parse_frontmatter_rejects_list_description() {
+        let content = "---\nname: x\ndescription: [a]\n---\n";
+        assert!(parse_frontmatter(content).is_err());
     }
 
     #[test]
@@ -394,7 +1031,7 @@ mod tests {
         )
         .unwrap();
 
-        let skills = load_from_dir(&dir);
+        let skills = load_from_dir(&dir, Origin::User);
         assert_eq!(skills.len(), 1);
         assert_eq!(skills[0].name, "my-skill");
         assert_eq!(skills[0].description, "A test skill");
@@ -411,14 +1048,14 @@ mod tests {
         )
         .unwrap();
 
-        let skills = load_from_dir(&dir);
+        let skills = load_from_dir(&dir, Origin::User);
         assert_eq!(skills.len(), 1);
         assert_eq!(skills[0].name, "quick-tool");
         std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn load_from_dir_skips_missing_description() {
+    fn load_from_dir_flags_missing_description() {
         let dir = tempdir("nodesc");
         let skill_dir = dir.join("no-desc");
         std::fs::create_dir_all(&skill_dir).unwrap();
@@ -428,8 +1065,31 @@ mod tests {
         )
         .unwrap();
 
-        let skills = load_from_dir(&dir);
-        assert!(skills.is_empty());
+        let skills = load_from_dir(&dir, Origin::User);
+        assert_eq!(skills.len(), 1);
+        assert!(skills[0].disabled);
+        assert_eq!(skills[0].problems, vec!["missing description"]);
+        assert_eq!(format_skills(&skills), "");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_from_dir_flags_unparsable_yaml() {
+        let dir = tempdir("badyaml");
+        let skill_dir = dir.join("bad");
+        std::fs::create_dir_all(&skill_dir).unwrap();
+        std::fs::write(
+            skill_dir.join("SKILL.md"),
+            "---\nname: bad\ndescription: \"oops\n---\nBody",
+        )
+        .unwrap();
+        std::fs::write(dir.join("README.md"), "# not a skill").unwrap();
+
+        let skills = load_from_dir(&dir, Origin::User);
+        assert_eq!(skills.len(), 1);
+        assert_eq!(skills[0].name, "bad");
+        assert!(skills[0].disabled);
+        assert!(skills[0].problems[0].contains("line 3"));
         std::fs::remove_dir_all(&dir).unwrap();
     }
 
@@ -445,16 +1105,18 @@ mod tests {
         )
         .unwrap();
 
-        let skills = load_from_dir(&dir);
+        let skills = load_from_dir(&dir, Origin::User);
         assert_eq!(skills.len(), 1);
         assert_eq!(skills[0].name, "right-name");
+        assert!(!skills[0].disabled);
+        assert!(skills[0].problems[0].contains("doesn't match"));
         std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
     fn load_from_dir_empty() {
         let dir = tempdir("empty_dir");
-        let skills = load_from_dir(&dir);
+        let skills = load_from_dir(&dir, Origin::User);
         assert!(skills.is_empty());
         std::fs::remove_dir_all(&dir).unwrap();
     }
@@ -462,7 +1124,7 @@ mod tests {
     #[test]
     fn load_from_dir_nonexistent() {
         let dir = PathBuf::from("/tmp/tapir_skill_nonexistent");
-        let skills = load_from_dir(&dir);
+        let skills = load_from_dir(&dir, Origin::User);
         assert!(skills.is_empty());
     }
 
@@ -477,6 +1139,10 @@ mod tests {
             name: "test-skill".into(),
             description: "Does testing".into(),
             path: PathBuf::from("/tmp/test/SKILL.md"),
+            origin: Origin::User,
+            shadowed: false,
+            problems: Vec::new(),
+            disabled: false,
         }];
         let xml = format_skills(&skills);
         assert!(xml.contains("<available-skills>"));
@@ -511,7 +1177,11 @@ mod tests {
         )
         .unwrap();
 
-        let skills = discover_skills_from_dirs(&[dir1.clone(), dir2.clone()]);
+        let skills = discover_skills_from_dirs(
+            &[(dir1.clone(), Origin::User), (dir2.clone(), Origin::User)],
+            &DEFAULT_PRECEDENCE,
+            None,
+        );
         assert_eq!(skills.len(), 1);
         assert_eq!(skills[0].description, "First");
 
@@ -542,10 +1212,179 @@ mod tests {
         )
         .unwrap();
 
-        let skills = discover_skills_from_dirs(&[dir1.clone(), dir2.clone()]);
+        let skills = discover_skills_from_dirs(
+            &[
+                (dir1.clone(), Origin::User),
+                (dir2.clone(), Origin::Project),
+            ],
+            &DEFAULT_PRECEDENCE,
+            None,
+        );
         assert_eq!(skills.len(), 2);
+        assert!(skills.iter().all(|s| !s.shadowed));
 
         std::fs::remove_dir_all(&dir1).unwrap();
         std::fs::remove_dir_all(&dir2).unwrap();
     }
+
+    fn write_skill(dir: &Path, name: &str, desc: &str) {
+        let d = dir.join(name);
+        std::fs::create_dir_all(&d).unwrap();
+        std::fs::write(
+            d.join("SKILL.md"),
+            format!("---\nname: {name}\ndescription: {desc}\n---\n"),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn discover_reuses_cache_until_mtimes_change() {
+        let dir = tempdir("cache");
+        let skills_dir = dir.join("skills");
+        let cache = dir.join("cache.json");
+        write_skill(&skills_dir, "deploy", "First");
+        let dirs = [(skills_dir.clone(), Origin::User)];
+        let discover = || {
+            discover_skills_from_dirs(&dirs, &DEFAULT_PRECEDENCE, Some(&cache))
+        };
+        assert_eq!(discover()[0].description, "First");
+        assert!(cache.exists());
+
+        // Same mtime: the cached entry wins
+        let file = skills_dir.join("deploy/SKILL.md");
+        let modified = fs::metadata(&file).unwrap().modified().unwrap();
+        write_skill(&skills_dir, "deploy", "Second");
+        fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_eq!(discover()[0].description, "First");
+
+        // A new skill changes the directory's mtime
+        write_skill(&skills_dir, "build", "Build it");
+        let names: Vec<_> = discover().into_iter().map(|s| s.name).collect();
+        assert_eq!(names, ["build", "deploy"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn discover_shadows_across_origins() {
+        let user = tempdir("shadow_user");
+        let project = tempdir("shadow_project");
+        write_skill(&user, "deploy", "Global deploy");
+        write_skill(&project, "deploy", "Project deploy");
+        let dirs = [
+            (user.clone(), Origin::User),
+            (project.clone(), Origin::Project),
+        ];
+
+        let skills =
+            discover_skills_from_dirs(&dirs, &DEFAULT_PRECEDENCE, None);
+        assert_eq!(skills.len(), 2);
+        assert_eq!(
+            find(&skills, "deploy").unwrap().description,
+            "Global deploy"
+        );
+        assert_eq!(
+            find(&skills, "project:deploy").unwrap().description,
+            "Project deploy"
+        );
+        assert!(format_skills(&skills).contains("Global deploy"));
+        assert!(!format_skills(&skills).contains("Project deploy"));
+
+        let precedence = parse_precedence(&["project".into()]);
+        let skills = discover_skills_from_dirs(&dirs, &precedence, None);
+        assert_eq!(
+            find(&skills, "deploy").unwrap().description,
+            "Project deploy"
+        );
+        assert_eq!(
+            find(&skills, "user:deploy").unwrap().description,
+            "Global deploy"
+        );
+
+        std::fs::remove_dir_all(&user).unwrap();
+        std::fs::remove_dir_all(&project).unwrap();
+    }
+
+    #[test]
+    fn broken_skills_do_not_shadow() {
+        let user = tempdir("broken_user");
+        let project = tempdir("broken_project");
+        write_skill(&user, "deploy", "Global deploy");
+        write_skill(&project, "deploy", "");
+        let dirs = [
+            (user.clone(), Origin::User),
+            (project.clone(), Origin::Project),
+        ];
+
+        let precedence = parse_precedence(&["project".into()]);
+        let skills = discover_skills_from_dirs(&dirs, &precedence, None);
+        assert_eq!(
+            find(&skills, "deploy").unwrap().description,
+            "Global deploy"
+        );
+        assert!(find(&skills, "project:deploy").unwrap().disabled);
+        assert!(format_skills(&skills).contains("Global deploy"));
+
+        std::fs::remove_dir_all(&user).unwrap();
+        std::fs::remove_dir_all(&project).unwrap();
+    }
+
+    #[test]
+    fn find_rejects_unknown_origin() {
+        let skills = vec![Skill {
+            name: "x".into(),
+            description: "d".into(),
+            path: PathBuf::from("/tmp/x.md"),
+            origin: Origin::User,
+            shadowed: false,
+            problems: Vec::new(),
+            disabled: false,
+        }];
+        assert!(find(&skills, "x").is_some());
+        assert!(find(&skills, "team:x").is_none());
+        assert!(find(&skills, "project:x").is_none());
+    }
+
+    #[test]
+    fn parse_precedence_fills_missing_origins() {
+        assert_eq!(
+            parse_precedence(&["config".into(), "bogus".into()]),
+            vec![Origin::Config, Origin::User, Origin::Project]
+        );
+    }
+
+    #[test]
+    fn record_use_counts_by_invoker() {
+        let dir = tempdir("stats");
+        let file = stats_path(&dir);
+        let skill = Skill {
+            name: "deploy".into(),
+            description: "d".into(),
+            path: dir.join("deploy/SKILL.md"),
+            origin: Origin::Project,
+            shadowed: false,
+            problems: Vec::new(),
+            disabled: false,
+        };
+        record_use(&file, &skill, Invoker::User);
+        record_use(&file, &skill, Invoker::Model);
+        record_use(&file, &skill, Invoker::Model);
+
+        let stats = load_stats(&file);
+        let usage = &stats[&skill.path.to_string_lossy().to_string()];
+        assert_eq!(usage.name, "deploy");
+        assert_eq!(usage.user, 1);
+        assert_eq!(usage.model, 2);
+        assert!(!usage.last_used.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_stats_missing_file_is_empty() {
+        assert!(load_stats(Path::new("/tmp/tapir_no_stats.json")).is_empty());
+    }
 }
@@ -31,6 +31,9 @@ pub enum SseEvent {
     MessageDelta {
         stop_reason: StopReason,
         output_tokens: u32,
+        /// Old tool results the server cleared from this
+        /// request (context-management beta).
+        cleared_tool_uses: u32,
     },
     MessageStop,
     Ping,
@@ -53,29 +56,40 @@ pub enum Delta {
 
 // -- Reader --
 
+/// Longest line, and longest event, accepted from the stream.
+/// Real events are a few KB; anything near this is a broken
+/// proxy.
+const MAX_LINE: usize = 8 << 20;
+
 pub struct SseReader {
     reader: Box<dyn BufRead>,
+    /// Event type read before the previous event's blank line,
+    /// when a proxy dropped it.
+    next_type: Option<String>,
 }
 
 impl SseReader {
     pub fn new(reader: Box<dyn BufRead>) -> Self {
-        Self { reader }
+        Self {
+            reader,
+            next_type: None,
+        }
     }
 
     /// Read the next SSE event.
     ///
     /// Returns `Ok(None)` on stream end or interruption.
     pub fn next_event(&mut self) -> crate::error::Result<Option<SseEvent>> {
-        let mut event_type = String::new();
+        let mut event_type = self.next_type.take().unwrap_or_default();
         let mut data = String::new();
+        let mut buf = Vec::new();
 
         loop {
             if signal::is_interrupted() {
                 return Ok(None);
             }
 
-            let mut line = String::new();
-            let n = match self.reader.read_line(&mut line) {
+            let n = match self.read_line(&mut buf) {
                 Ok(n) => n,
                 Err(e) if e.kind() == io::ErrorKind::Interrupted => {
                     if signal::is_interrupted() {
@@ -87,30 +101,116 @@ impl SseReader {
             };
 
             if n == 0 {
-                // EOF
-                return Ok(None);
+                // EOF; a last event may lack its blank line
+                if data.is_empty() {
+                    return Ok(None);
+                }
+                return dispatch(&event_type, &data).map(Some);
             }
 
-            let line = line.trim_end_matches('\n').trim_end_matches('\r');
+            let text = String::from_utf8_lossy(&buf);
+            let line = text.trim_end_matches('\n').trim_end_matches('\r');
 
             if line.is_empty() {
                 // Blank line = dispatch event
+                buf.clear();
                 if data.is_empty() {
                     continue;
                 }
-                let evt = parse_event(&event_type, &data)?;
-                return Ok(Some(evt));
+                return dispatch(&event_type, &data).map(Some);
             }
 
-            if let Some(val) = line.strip_prefix("event: ") {
+            if let Some(val) = field(line, "event") {
+                if !data.is_empty() {
+                    // No blank line before this event
+                    self.next_type = Some(val.to_string());
+                    return dispatch(&event_type, &data).map(Some);
+                }
                 event_type = val.to_string();
-            } else if let Some(val) = line.strip_prefix("data: ") {
+            } else if let Some(val) = field(line, "data") {
+                if data.len() + val.len() > MAX_LINE {
+                    return Err(too_long().into());
+                }
                 if !data.is_empty() {
                     data.push('\n');
                 }
                 data.push_str(val);
             }
             // Ignore other fields (id:, retry:, comments)
+            buf.clear();
+        }
+    }
+
+    /// Append one line, newline included, to `buf`; returns
+    /// the bytes read, 0 at EOF. Unlike `read_until`, EINTR is
+    /// returned so Ctrl-C is noticed, and a partial line stays
+    /// in `buf` for the retry.
+    fn read_line(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let start = buf.len();
+        loop {
+            let chunk = self.reader.fill_buf()?;
+            if chunk.is_empty() {
+                return Ok(buf.len() - start);
+            }
+            let (n, done) = match chunk.iter().position(|&b| b == b'\n') {
+                Some(i) => (i + 1, true),
+                None => (chunk.len(), false),
+            };
+            if buf.len() + n > MAX_LINE {
+                buf.clear();
+                self.skip_line(done, n)?;
+                return Err(too_long());
+            }
+            buf.extend_from_slice(&chunk[..n]);
+            self.reader.consume(n);
+            if done {
+                return Ok(buf.len() - start);
+            }
+        }
+    }
+
+    /// Drop the rest of an oversized line, so the next read
+    /// starts at the following one.
+    fn skip_line(&mut self, mut done: bool, mut n: usize) -> io::Result<()> {
+        loop {
+            self.reader.consume(n);
+            if done {
+                return Ok(());
+            }
+            let chunk = self.reader.fill_buf()?;
+            if chunk.is_empty() {
+                return Ok(());
+            }
+            (n, done) = match chunk.iter().position(|&b| b == b'\n') {
+                Some(i) => (i + 1, true),
+                None => (chunk.len(), false),
+            };
+        }
+    }
+}
+
+/// Value of `name:` in `line`, minus one optional space.
+fn field<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let val = line.strip_prefix(name)?.strip_prefix(':')?;
+    Some(val.strip_prefix(' ').unwrap_or(val))
+}
+
+fn too_long() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("event stream line longer than {MAX_LINE} bytes"),
+    )
+}
+
+/// Feed arbitrary bytes through the reader until it runs
+/// out. Errors are expected; panics and hangs are bugs. Used
+/// by the cargo-fuzz target in `fuzz/`.
+#[cfg(any(test, fuzzing))]
+pub fn fuzz(data: &[u8]) {
+    let mut reader = SseReader::new(Box::new(io::Cursor::new(data.to_vec())));
+    for _ in 0..=data.len() {
+        if let Ok(None) = reader.next_event() {
+            break;
         }
     }
 }
@@ -187,6 +287,20 @@ struct RawContentBlockStop {
 struct RawMessageDelta {
     delta: RawMessageDeltaInner,
     usage: RawUsage,
+    #[serde(default)]
+    context_management: Option<RawContextManagement>,
+}
+
+#[derive(Deserialize)]
+struct RawContextManagement {
+    #[serde(default)]
+    applied_edits: Vec<RawAppliedEdit>,
+}
+
+#[derive(Deserialize)]
+struct RawAppliedEdit {
+    #[serde(default)]
+    cleared_tool_uses: u32,
 }
 
 #[derive(Deserialize)]
@@ -196,6 +310,22 @@ struct RawMessageDeltaInner {
 
 // -- Parsing --
 
+/// Parse an event, taking its type from the data's `type`
+/// field when the `event:` line is missing.
+fn dispatch(event_type: &str, data: &str) -> crate::error::Result<SseEvent> {
+    #[derive(Deserialize)]
+    struct Typed {
+        #[serde(rename = "type")]
+        kind: String,
+    }
+    if event_type.is_empty()
+        && let Ok(t) = serde_json::from_str::<Typed>(data)
+    {
+        return parse_event(&t.kind, data);
+    }
+    parse_event(event_type, data)
+}
+
 fn parse_event(event_type: &str, data: &str) -> crate::error::Result<SseEvent> {
     match event_type {
         "message_start" => {
@@ -244,9 +374,13 @@ fn parse_event(event_type: &str, data: &str) -> crate::error::Result<SseEvent> {
         }
         "message_delta" => {
             let raw: RawMessageDelta = serde_json::from_str(data)?;
+            let cleared_tool_uses = raw.context_management.map_or(0, |cm| {
+                cm.applied_edits.iter().map(|e| e.cleared_tool_uses).sum()
+            });
             Ok(SseEvent::MessageDelta {
                 stop_reason: raw.delta.stop_reason,
                 output_tokens: raw.usage.output_tokens,
+                cleared_tool_uses,
             })
         }
         "message_stop" => Ok(SseEvent::MessageStop),
@@ -254,3 +388,70 @@ fn parse_event(event_type: &str, data: &str) -> crate::error::Result<SseEvent> {
         _ => Ok(SseEvent::Ping), // ignore unknown events
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(stream: &[u8]) -> Vec<String> {
+        let mut reader =
+            SseReader::new(Box::new(io::Cursor::new(stream.to_vec())));
+        let mut out = Vec::new();
+        loop {
+            match reader.next_event() {
+                Ok(Some(SseEvent::ContentBlockDelta {
+                    delta: Delta::Text(t),
+                    ..
+                })) => out.push(t),
+                Ok(Some(e)) => out.push(format!("{e:?}")),
+                Ok(None) => return out,
+                Err(e) => out.push(format!("error: {e}")),
+            }
+        }
+    }
+
+    fn text_delta(text: &str) -> String {
+        format!(
+            "{{\"type\":\"content_block_delta\",\"index\":0,\
+             \"delta\":{{\"type\":\"text_delta\",\"text\":\"{text}\"}}}}"
+        )
+    }
+
+    #[test]
+    fn tolerates_sloppy_framing() {
+        let a = text_delta("a");
+        let b = text_delta("b");
+        let c = text_delta("c");
+        // data before event, no space after the colon, a
+        // missing blank line, no event line, and no final
+        // blank line
+        let stream = format!(
+            "data: {a}\nevent: content_block_delta\n\n\
+             event:content_block_delta\ndata:{b}\n\
+             event: ping\ndata: {{}}\n\n\
+             data: {c}\n\n\
+             event: message_stop\ndata: {{}}"
+        );
+        assert_eq!(
+            events(stream.as_bytes()),
+            ["a", "b", "Ping", "c", "MessageStop"]
+        );
+    }
+
+    #[test]
+    fn survives_bad_bytes_and_huge_lines() {
+        let mut stream = b"event: content_block_delta\ndata: ".to_vec();
+        stream.extend_from_slice(text_delta("caf\u{e9}").as_bytes());
+        // Split a UTF-8 sequence and leave it invalid
+        stream.extend_from_slice(b"\n\nevent: x\ndata: \xc3\n\n");
+        stream.extend_from_slice(b"data: ");
+        stream.resize(stream.len() + MAX_LINE + 10, b'x');
+        stream.extend_from_slice(b"\n\nevent: message_stop\ndata: {}\n\n");
+        let out = events(&stream);
+        assert_eq!(out[0], "caf\u{e9}");
+        assert_eq!(out[1], "Ping");
+        assert!(out[2].contains("longer than"), "{}", out[2]);
+        assert_eq!(out[3], "MessageStop");
+        fuzz(&stream[..1000]);
+    }
+}
@@ -1,17 +1,107 @@
 use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 use crate::config::Config;
+use crate::display::{Markdown, Wrap};
 use crate::error::Result;
 use crate::sse::{BlockStart, Delta, SseEvent};
+use crate::theme::{self, Style};
 use crate::timer::ThinkingTimer;
 use crate::types::{ContentBlock, Request, StopReason, Usage};
-use crate::{api, signal};
+use crate::{api, signal, tool};
 
 pub struct StreamResult {
     pub content: Vec<ContentBlock>,
     pub stop_reason: StopReason,
     pub usage: Usage,
     pub interrupted: bool,
+    /// Tool results the server cleared before answering.
+    pub cleared_tool_uses: u32,
+    pub timing: Timing,
+}
+
+/// How long a response took to arrive.
+#[derive(Clone, Copy, Default)]
+pub struct Timing {
+    /// From sending the request to the first content delta.
+    pub first_token: Option<Duration>,
+    pub total: Duration,
+}
+
+impl Timing {
+    /// Time spent generating, after the first token.
+    fn generating(&self) -> Option<Duration> {
+        let busy = self.total.checked_sub(self.first_token?)?;
+        (busy >= Duration::from_millis(10)).then_some(busy)
+    }
+
+    pub fn tokens_per_sec(&self, output_tokens: u32) -> Option<f64> {
+        Some(output_tokens as f64 / self.generating()?.as_secs_f64())
+    }
+
+    /// `ttft=0.82s total=4.10s 52 tok/s`, for the per-turn line.
+    pub fn describe(&self, output_tokens: u32) -> String {
+        let mut out = String::new();
+        if let Some(t) = self.first_token {
+            out.push_str(&format!("ttft={:.2}s ", t.as_secs_f64()));
+        }
+        out.push_str(&format!("total={:.2}s", self.total.as_secs_f64()));
+        if let Some(rate) = self.tokens_per_sec(output_tokens) {
+            out.push_str(&format!(" {rate:.0} tok/s"));
+        }
+        out
+    }
+}
+
+/// Response timing summed over a session's turns.
+#[derive(Default)]
+pub struct LatencyStats {
+    turns: u32,
+    first_token_turns: u32,
+    first_token: Duration,
+    total: Duration,
+    output_tokens: u64,
+    generating: Duration,
+}
+
+impl LatencyStats {
+    pub fn add(&mut self, timing: &Timing, output_tokens: u32) {
+        self.turns += 1;
+        self.total += timing.total;
+        if let Some(t) = timing.first_token {
+            self.first_token_turns += 1;
+            self.first_token += t;
+        }
+        if let Some(busy) = timing.generating() {
+            self.output_tokens += output_tokens as u64;
+            self.generating += busy;
+        }
+    }
+
+    /// Averages per turn and the overall output rate, or
+    /// `None` before the first response.
+    pub fn summary(&self) -> Option<String> {
+        if self.turns == 0 {
+            return None;
+        }
+        let mut out = format!(
+            "avg turn {:.2}s",
+            self.total.as_secs_f64() / self.turns as f64
+        );
+        if self.first_token_turns > 0 {
+            let avg =
+                self.first_token.as_secs_f64() / self.first_token_turns as f64;
+            out.push_str(&format!(", first token {avg:.2}s"));
+        }
+        if !self.generating.is_zero() {
+            let rate =
+                self.output_tokens as f64 / self.generating.as_secs_f64();
+            out.push_str(&format!(", {rate:.0} tok/s"));
+        }
+        out.push_str(&format!(" over {} turns", self.turns));
+        Some(out)
+    }
 }
 
 enum BlockState {
@@ -20,7 +110,11 @@ enum BlockState {
     /// Accumulating a thinking block.
     Thinking { thinking: String, signature: String },
     /// Accumulating a text block.
-    Text { buf: String, at_line_start: bool, first_line: bool },
+    Text {
+        buf: String,
+        markdown: Markdown,
+        wrap: Wrap,
+    },
     /// Accumulating a tool-use block.
     ToolUse {
         id: String,
@@ -34,6 +128,8 @@ pub fn stream_response(
     request: &Request<'_>,
 ) -> Result<StreamResult> {
     signal::clear();
+    let started = Instant::now();
+    let mut first_token = None;
     let timer = ThinkingTimer::start();
     let mut timer = Some(timer);
 
@@ -43,9 +139,18 @@ pub fn stream_response(
     let mut usage = Usage::default();
     let mut stop_reason = StopReason::EndTurn;
     let mut interrupted = false;
+    let mut cleared_tool_uses = 0;
     let mut block = BlockState::Idle;
 
     let mut stdout = io::stdout();
+    // Escape codes only for a terminal, not a pipe
+    let tty = unsafe { libc::isatty(1) } == 1;
+    let theme = (config.markdown && tty).then(crate::theme::current);
+    let width: fn() -> Option<usize> = if tty {
+        crate::readline::terminal_width
+    } else {
+        || None
+    };
 
     loop {
         let event = reader.next_event()?;
@@ -60,14 +165,14 @@ pub fn stream_response(
                     interrupted = true;
                     if let BlockState::Text {
                         ref buf,
-                        at_line_start,
-                        ..
+                        ref mut markdown,
+                        ref mut wrap,
                     } = block
                         && !buf.is_empty()
                     {
-                        if !at_line_start {
-                            let _ = writeln!(stdout);
-                        }
+                        let tail =
+                            wrap.push(&markdown.finish()) + &wrap.finish();
+                        let _ = write!(stdout, "{tail}");
                         content.push(ContentBlock::Text { text: buf.clone() });
                     }
                     eprintln!("\n* interrupted");
@@ -97,8 +202,11 @@ pub fn stream_response(
                     },
                     BlockStart::Text => BlockState::Text {
                         buf: String::new(),
-                        at_line_start: true,
-                        first_line: true,
+                        markdown: Markdown::new(
+                            theme.clone(),
+                            config.highlight,
+                        ),
+                        wrap: Wrap::new(width),
                     },
                     BlockStart::ToolUse { id, name } => BlockState::ToolUse {
                         id,
@@ -111,6 +219,7 @@ pub fn stream_response(
                 if let Some(t) = timer.take() {
                     t.stop();
                 }
+                first_token.get_or_insert_with(|| started.elapsed());
                 match (&mut block, delta) {
                     (
                         BlockState::Thinking { thinking, .. },
@@ -127,28 +236,14 @@ pub fn stream_response(
                     (
                         BlockState::Text {
                             buf,
-                            at_line_start,
-                            first_line,
+                            markdown,
+                            wrap,
                         },
                         Delta::Text(s),
                     ) => {
                         buf.push_str(&s);
-                        for ch in s.chars() {
-                            if *at_line_start {
-                                let prefix = if *first_line {
-                                    "< "
-                                } else {
-                                    "  "
-                                };
-                                let _ = write!(stdout, "{prefix}");
-                                *at_line_start = false;
-                            }
-                            let _ = write!(stdout, "{ch}");
-                            if ch == '\n' {
-                                *at_line_start = true;
-                                *first_line = false;
-                            }
-                        }
+                        let _ =
+                            write!(stdout, "{}", wrap.push(&markdown.push(&s)));
                         let _ = stdout.flush();
                     }
                     (BlockState::ToolUse { json, .. }, Delta::InputJson(s)) => {
@@ -171,11 +266,13 @@ pub fn stream_response(
                         });
                     }
                     BlockState::Text {
-                        buf, at_line_start, ..
+                        buf,
+                        mut markdown,
+                        mut wrap,
                     } => {
-                        if !at_line_start {
-                            let _ = writeln!(stdout);
-                        }
+                        let tail =
+                            wrap.push(&markdown.finish()) + &wrap.finish();
+                        let _ = write!(stdout, "{tail}");
                         content.push(ContentBlock::Text { text: buf });
                     }
                     BlockState::ToolUse { id, name, json } => {
@@ -183,7 +280,7 @@ pub fn stream_response(
                             serde_json::from_str(&json).unwrap_or(
                                 serde_json::Value::Object(Default::default()),
                             );
-                        print_tool_call(&name, &input);
+                        print_tool_call(&name, &input, config);
                         content.push(ContentBlock::ToolUse { id, name, input });
                     }
                     BlockState::Idle => {}
@@ -192,9 +289,11 @@ pub fn stream_response(
             SseEvent::MessageDelta {
                 stop_reason: reason,
                 output_tokens,
+                cleared_tool_uses: cleared,
             } => {
                 stop_reason = reason;
                 usage.output_tokens = output_tokens;
+                cleared_tool_uses = cleared;
             }
             SseEvent::MessageStop => break,
             SseEvent::Ping => {}
@@ -206,6 +305,11 @@ pub fn stream_response(
         stop_reason,
         usage,
         interrupted,
+        cleared_tool_uses,
+        timing: Timing {
+            first_token,
+            total: started.elapsed(),
+        },
     })
 }
 
@@ -230,23 +334,162 @@ pub(crate) fn tool_call_header(
             let cmd = input["command"].as_str().unwrap_or("?");
             format!("bash: {cmd}")
         }
+        "stat" => {
+            let path = input["path"].as_str().unwrap_or("?");
+            format!("stat: {path}")
+        }
+        "delete_file" => {
+            let path = input["path"].as_str().unwrap_or("?");
+            format!("delete: {path}")
+        }
+        "memory" => {
+            let cmd = input["command"].as_str().unwrap_or("?");
+            let path = input["path"]
+                .as_str()
+                .or_else(|| input["old_path"].as_str())
+                .unwrap_or("");
+            format!("memory {cmd}: {path}")
+        }
         _ => name.to_string(),
     }
 }
 
-fn print_tool_call(name: &str, input: &serde_json::Value) {
+/// Print a tool call as it arrives; an edit shows as a hunk
+/// of the file it changes.
+fn print_tool_call(name: &str, input: &serde_json::Value, config: &Config) {
     let header = tool_call_header(name, input);
     eprintln!("* {header}");
-    if name == "edit_file" {
-        if let Some(old) = input["old_string"].as_str() {
-            for line in old.lines() {
-                eprintln!("- {line}");
-            }
-        }
-        if let Some(new) = input["new_string"].as_str() {
-            for line in new.lines() {
-                eprintln!("+ {line}");
-            }
-        }
+    if name != "edit_file" {
+        return;
+    }
+    let old = input["old_string"].as_str().unwrap_or("");
+    let new = input["new_string"].as_str().unwrap_or("");
+    if let Some(hunk) =
+        edit_hunk_header(&config.working_dir, &config.policy, input)
+    {
+        eprintln!("{}", theme::paint(Style::Hunk, &hunk));
+    }
+    for line in old.lines() {
+        eprintln!("{}", theme::paint(Style::Removed, &format!("-{line}")));
+    }
+    for line in new.lines() {
+        eprintln!("{}", theme::paint(Style::Added, &format!("+{line}")));
+    }
+}
+
+/// Largest file `edit_hunk_header` reads.
+const HUNK_MAX_BYTES: u64 = 4 << 20;
+
+/// `@@ -l,n +l,m @@` for an `edit_file` call, placed where
+/// `old_string` is in the file now. None when it isn't there
+/// word for word, as when the edit will fuzzy-match, and for
+/// anything the tool itself could not read without asking:
+/// the call has not been checked yet.
+fn edit_hunk_header(
+    working_dir: &Path,
+    policy: &tool::Policy,
+    input: &serde_json::Value,
+) -> Option<String> {
+    let path = tool::safe_path(working_dir, input["path"].as_str()?).ok()?;
+    let meta = std::fs::metadata(&path).ok()?;
+    if !meta.is_file()
+        || meta.len() > HUNK_MAX_BYTES
+        || policy.hidden(working_dir, &path)
+    {
+        return None;
+    }
+    let old = input["old_string"].as_str().filter(|s| !s.is_empty())?;
+    let new = input["new_string"].as_str().unwrap_or("");
+    let content = std::fs::read_to_string(path).ok()?;
+    let at = content.find(old)?;
+    let line = content[..at].matches('\n').count() + 1;
+    Some(format!(
+        "@@ -{line},{} +{line},{} @@",
+        old.lines().count(),
+        new.lines().count()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_hunks_point_at_the_old_text() {
+        let dir = std::env::temp_dir().join("tapir_edit_hunk");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.rs"), "fn a() {}\n\nfn b() {\n}\n").unwrap();
+        let mut policy = tool::Policy::default();
+        let input = serde_json::json!({
+            "path": "a.rs",
+            "old_string": "fn b() {\n}",
+            "new_string": "fn b() {\n    a();\n}",
+        });
+        assert_eq!(
+            edit_hunk_header(&dir, &policy, &input).as_deref(),
+            Some("@@ -3,2 +3,3 @@")
+        );
+        let input = serde_json::json!({
+            "path": "a.rs",
+            "old_string": "fn c() {}",
+            "new_string": "",
+        });
+        assert_eq!(edit_hunk_header(&dir, &policy, &input), None);
+
+        let hunk = |path: &str, policy: &tool::Policy| {
+            let input = serde_json::json!({
+                "path": path,
+                "old_string": "fn a() {}",
+                "new_string": "",
+            });
+            edit_hunk_header(&dir, policy, &input)
+        };
+        assert!(hunk("a.rs", &policy).is_some());
+        let outside = std::env::temp_dir().join("tapir_edit_hunk_out.rs");
+        std::fs::write(&outside, "fn a() {}\n").unwrap();
+        assert_eq!(hunk("../tapir_edit_hunk_out.rs", &policy), None);
+        assert_eq!(hunk(outside.to_str().unwrap(), &policy), None);
+        std::fs::remove_file(&outside).unwrap();
+        std::fs::create_dir_all(dir.join("d.rs")).unwrap();
+        assert_eq!(hunk("d.rs", &policy), None);
+        policy.rules.push(tool::PathRule {
+            path: "a.rs".into(),
+            read: Some(tool::Access::Prompt),
+            write: None,
+        });
+        assert_eq!(hunk("a.rs", &policy), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn latency_stats_average_turns() {
+        let ms = Duration::from_millis;
+        let mut stats = LatencyStats::default();
+        assert!(stats.summary().is_none());
+        let turn = Timing {
+            first_token: Some(ms(500)),
+            total: ms(2500),
+        };
+        assert_eq!(turn.describe(100), "ttft=0.50s total=2.50s 50 tok/s");
+        stats.add(&turn, 100);
+        stats.add(
+            &Timing {
+                first_token: Some(ms(1500)),
+                total: ms(3500),
+            },
+            300,
+        );
+        // Interrupted before any output
+        stats.add(
+            &Timing {
+                first_token: None,
+                total: ms(3000),
+            },
+            0,
+        );
+        assert_eq!(
+            stats.summary().unwrap(),
+            "avg turn 3.00s, first token 1.00s, 100 tok/s over 3 turns"
+        );
     }
 }
@@ -0,0 +1,419 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+/// OTLP/HTTP export, from `telemetry` in config. Off unless
+/// the key is present.
+#[derive(Clone, Deserialize)]
+pub struct TelemetryConfig {
+    /// Collector base URL; `/v1/metrics` and `/v1/traces` are
+    /// appended. Defaults to `$OTEL_EXPORTER_OTLP_ENDPOINT`,
+    /// then `http://localhost:4318`.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// Extra request headers, e.g. for collector auth.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+}
+
+fn default_service_name() -> String {
+    "tapir".to_string()
+}
+
+const EXPORT_TIMEOUT: u64 = 5;
+
+/// Counters and finished spans, fed from `events::emit`.
+pub struct Telemetry {
+    endpoint: String,
+    headers: HashMap<String, String>,
+    service_name: String,
+    start_nanos: u64,
+    /// Current session id without dashes, used as trace id.
+    trace_id: String,
+    model: String,
+    /// Counter name and sorted attributes to the running sum.
+    counters: BTreeMap<Series, f64>,
+    spans: Vec<Value>,
+}
+
+/// A counter name and its sorted attributes.
+type Series = (&'static str, Vec<(&'static str, String)>);
+
+static STATE: Mutex<Option<Telemetry>> = Mutex::new(None);
+
+/// Start collecting if `config` enables telemetry.
+pub fn init(config: Option<&TelemetryConfig>) {
+    let Some(config) = config else {
+        return;
+    };
+    let endpoint = config
+        .endpoint
+        .clone()
+        .or_else(|| std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok())
+        .unwrap_or_else(|| "http://localhost:4318".to_string());
+    if let Ok(mut state) = STATE.lock() {
+        *state = Some(Telemetry::new(config, endpoint, now_nanos()));
+    }
+}
+
+/// Count an event; called for every `events::emit`. Each
+/// finished turn is exported in the background.
+pub fn record(event: &str, fields: &Value) {
+    let Ok(mut state) = STATE.lock() else {
+        return;
+    };
+    let Some(t) = state.as_mut() else {
+        return;
+    };
+    t.record(event, fields, now_nanos());
+    if event == "turn_end" {
+        let batch = t.batch(now_nanos());
+        std::thread::spawn(move || batch.send());
+    }
+}
+
+/// Export what is left, waiting for the collector.
+pub fn shutdown() {
+    let batch = match STATE.lock() {
+        Ok(mut state) => state.take().map(|mut t| t.batch(now_nanos())),
+        Err(_) => None,
+    };
+    if let Some(batch) = batch {
+        batch.send();
+    }
+}
+
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+/// Request bodies for one export.
+struct Batch {
+    endpoint: String,
+    headers: HashMap<String, String>,
+    metrics: Value,
+    traces: Option<Value>,
+}
+
+impl Batch {
+    fn send(self) {
+        let mut bodies = vec![("metrics", self.metrics)];
+        bodies.extend(self.traces.map(|t| ("traces", t)));
+        for (kind, body) in bodies {
+            let url = format!("{}/v1/{kind}", self.endpoint);
+            let mut request = minreq::post(url)
+                .with_header("Content-Type", "application/json")
+                .with_body(body.to_string())
+                .with_timeout(EXPORT_TIMEOUT);
+            for (name, value) in &self.headers {
+                request = request.with_header(name, value);
+            }
+            // Telemetry must never get in the way of the session
+            let _ = request.send();
+        }
+    }
+}
+
+impl Telemetry {
+    fn new(config: &TelemetryConfig, endpoint: String, now: u64) -> Self {
+        Telemetry {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            headers: config.headers.clone(),
+            service_name: config.service_name.clone(),
+            start_nanos: now,
+            trace_id: random_hex(16),
+            model: String::new(),
+            counters: BTreeMap::new(),
+            spans: Vec::new(),
+        }
+    }
+
+    fn add(
+        &mut self,
+        name: &'static str,
+        attrs: &[(&'static str, &str)],
+        n: f64,
+    ) {
+        let mut attrs: Vec<_> =
+            attrs.iter().map(|(k, v)| (*k, v.to_string())).collect();
+        attrs.sort();
+        *self.counters.entry((name, attrs)).or_default() += n;
+    }
+
+    fn record(&mut self, event: &str, fields: &Value, now: u64) {
+        let str_field = |key: &str| fields[key].as_str().unwrap_or("");
+        let num = |key: &str| fields[key].as_f64().unwrap_or(0.0);
+        match event {
+            "session_start" => {
+                let id = str_field("session_id").replace('-', "");
+                if id.len() == 32 {
+                    self.trace_id = id;
+                }
+                self.model = str_field("model").to_string();
+            }
+            "model_switch" => {
+                self.model = str_field("to").to_string();
+            }
+            "turn_end" => {
+                // Set per turn by `@name:` overrides
+                let model = fields["model"]
+                    .as_str()
+                    .map_or_else(|| self.model.clone(), str::to_string);
+                let m = [("model", model.as_str())];
+                self.add("tapir.requests", &m, 1.0);
+                for (kind, key) in [
+                    ("input", "input_tokens"),
+                    ("output", "output_tokens"),
+                    ("cache_read", "cache_read_tokens"),
+                    ("cache_write", "cache_write_tokens"),
+                ] {
+                    let attrs = [("model", model.as_str()), ("type", kind)];
+                    self.add("tapir.tokens", &attrs, num(key));
+                }
+                self.add("tapir.cost", &m, num("cost"));
+                let mut attrs = vec![
+                    attr("model", &model),
+                    attr("stop_reason", &fields["stop_reason"]),
+                ];
+                for key in ["input_tokens", "output_tokens", "ttft_ms"] {
+                    if !fields[key].is_null() {
+                        attrs.push(attr(key, &fields[key]));
+                    }
+                }
+                self.span("turn", now, num("duration_ms"), attrs, false);
+            }
+            "tool" => {
+                let name = str_field("name");
+                let failed = fields["is_error"].as_bool().unwrap_or(false);
+                let error = if failed { "true" } else { "false" };
+                let attrs = [("tool", name), ("error", error)];
+                self.add("tapir.tool.invocations", &attrs, 1.0);
+                if failed {
+                    self.add("tapir.errors", &[("kind", "tool")], 1.0);
+                }
+                let attrs = vec![attr("tool", &fields["name"])];
+                let span = format!("tool {name}");
+                self.span(&span, now, num("duration_ms"), attrs, failed);
+            }
+            "retry" => self.add("tapir.errors", &[("kind", "api_retry")], 1.0),
+            "error" => self.add("tapir.errors", &[("kind", "fatal")], 1.0),
+            _ => {}
+        }
+    }
+
+    /// A span that ended at `end` after `duration_ms`.
+    fn span(
+        &mut self,
+        name: &str,
+        end: u64,
+        duration_ms: f64,
+        attributes: Vec<Value>,
+        failed: bool,
+    ) {
+        let start = end.saturating_sub((duration_ms * 1e6) as u64);
+        self.spans.push(json!({
+            "traceId": self.trace_id,
+            "spanId": random_hex(8),
+            "name": name,
+            "kind": 1,
+            "startTimeUnixNano": start.to_string(),
+            "endTimeUnixNano": end.to_string(),
+            "attributes": attributes,
+            "status": {"code": if failed { 2 } else { 1 }},
+        }));
+    }
+
+    /// Cumulative counters so far and the spans since the last
+    /// batch, as OTLP/JSON bodies.
+    fn batch(&mut self, now: u64) -> Batch {
+        let resource = json!({
+            "attributes": [attr("service.name", &self.service_name)],
+        });
+        let scope =
+            json!({"name": "tapir", "version": env!("CARGO_PKG_VERSION")});
+
+        let mut by_name: BTreeMap<&str, Vec<Value>> = BTreeMap::new();
+        for ((name, attrs), value) in &self.counters {
+            let attributes: Vec<Value> =
+                attrs.iter().map(|(k, v)| attr(k, v)).collect();
+            by_name.entry(name).or_default().push(json!({
+                "attributes": attributes,
+                "startTimeUnixNano": self.start_nanos.to_string(),
+                "timeUnixNano": now.to_string(),
+                "asDouble": value,
+            }));
+        }
+        let metrics: Vec<Value> = by_name
+            .into_iter()
+            .map(|(name, points)| {
+                json!({
+                    "name": name,
+                    "unit": metric_unit(name),
+                    "sum": {
+                        "dataPoints": points,
+                        // Cumulative
+                        "aggregationTemporality": 2,
+                        "isMonotonic": true,
+                    },
+                })
+            })
+            .collect();
+        let metrics = json!({"resourceMetrics": [{
+            "resource": resource,
+            "scopeMetrics": [{"scope": scope, "metrics": metrics}],
+        }]});
+
+        let spans = std::mem::take(&mut self.spans);
+        let traces = (!spans.is_empty()).then(|| {
+            json!({"resourceSpans": [{
+                "resource": resource,
+                "scopeSpans": [{"scope": scope, "spans": spans}],
+            }]})
+        });
+        Batch {
+            endpoint: self.endpoint.clone(),
+            headers: self.headers.clone(),
+            metrics,
+            traces,
+        }
+    }
+}
+
+fn metric_unit(name: &str) -> &'static str {
+    match name {
+        "tapir.tokens" => "{token}",
+        "tapir.cost" => "USD",
+        _ => "1",
+    }
+}
+
+/// An OTLP key/value attribute.
+fn attr(key: &str, value: impl Into<AttrValue>) -> Value {
+    let value = match value.into() {
+        AttrValue::Str(s) => json!({"stringValue": s}),
+        AttrValue::Json(v) => match v {
+            Value::Number(n) if n.is_u64() || n.is_i64() => {
+                json!({"intValue": n.to_string()})
+            }
+            Value::Number(n) => json!({"doubleValue": n}),
+            Value::Bool(b) => json!({"boolValue": b}),
+            Value::String(s) => json!({"stringValue": s}),
+            other => json!({"stringValue": other.to_string()}),
+        },
+    };
+    json!({"key": key, "value": value})
+}
+
+enum AttrValue {
+    Str(String),
+    Json(Value),
+}
+
+impl From<&str> for AttrValue {
+    fn from(s: &str) -> Self {
+        AttrValue::Str(s.to_string())
+    }
+}
+
+impl From<&String> for AttrValue {
+    fn from(s: &String) -> Self {
+        AttrValue::Str(s.clone())
+    }
+}
+
+impl From<&Value> for AttrValue {
+    fn from(v: &Value) -> Self {
+        AttrValue::Json(v.clone())
+    }
+}
+
+/// `bytes` random bytes as lowercase hex.
+fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    if let Ok(mut f) = std::fs::File::open("/dev/urandom") {
+        let _ = f.read_exact(&mut buf);
+    }
+    buf.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turns_and_tools_become_otlp() {
+        let config: TelemetryConfig = serde_json::from_str("{}").unwrap();
+        let mut t =
+            Telemetry::new(&config, "http://collector:4318/".into(), 1000);
+        t.record(
+            "session_start",
+            &json!({
+                "session_id": "0123abcd-0000-4000-8000-00000000beef",
+                "model": "claude-sonnet-4-5",
+            }),
+            2000,
+        );
+        for _ in 0..2 {
+            t.record(
+                "turn_end",
+                &json!({
+                    "duration_ms": 1,
+                    "stop_reason": "end_turn",
+                    "input_tokens": 100,
+                    "output_tokens": 10,
+                    "cost": 0.5,
+                }),
+                5_000_000,
+            );
+        }
+        t.record(
+            "tool",
+            &json!({"name": "bash", "duration_ms": 2, "is_error": true}),
+            9_000_000,
+        );
+
+        let batch = t.batch(10_000_000);
+        assert_eq!(batch.endpoint, "http://collector:4318");
+        let metrics =
+            &batch.metrics["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+        let find = |name: &str| {
+            metrics
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|m| m["name"] == name)
+                .unwrap()["sum"]["dataPoints"]
+                .clone()
+        };
+        assert_eq!(find("tapir.requests")[0]["asDouble"], 2.0);
+        assert_eq!(find("tapir.cost")[0]["asDouble"], 1.0);
+        assert_eq!(find("tapir.errors")[0]["asDouble"], 1.0);
+        let input = find("tapir.tokens")
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|p| p["attributes"][1]["value"]["stringValue"] == "input")
+            .unwrap()["asDouble"]
+            .clone();
+        assert_eq!(input, 200.0);
+
+        let spans = batch.traces.unwrap()["resourceSpans"][0]["scopeSpans"][0]
+            ["spans"]
+            .clone();
+        assert_eq!(spans.as_array().unwrap().len(), 3);
+        assert_eq!(spans[0]["traceId"], "0123abcd00004000800000000000beef");
+        assert_eq!(spans[0]["startTimeUnixNano"], "4000000");
+        assert_eq!(spans[2]["name"], "tool bash");
+        assert_eq!(spans[2]["status"]["code"], 2);
+        // Spans go out once
+        assert!(t.batch(11_000_000).traces.is_none());
+    }
+}
@@ -0,0 +1,229 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, FileConfig};
+use crate::types::{Content, ContentBlock, Message, Role};
+
+/// Directory holding the expected requests.
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/golden")
+}
+
+/// A throwaway home and project under the temp dir. The
+/// project is `<root>/project`, the home `<root>/home`.
+pub struct Fixture {
+    pub root: PathBuf,
+}
+
+impl Fixture {
+    pub fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("tapir_golden_{name}"));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("project")).unwrap();
+        fs::create_dir_all(root.join("home")).unwrap();
+        Fixture { root }
+    }
+
+    /// Write `text` to `path`, relative to the fixture root.
+    pub fn file(&self, path: &str, text: &str) -> &Self {
+        let path = self.root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+        self
+    }
+
+    /// Load the config the way `Config::load` would, from
+    /// `config_json` and no environment besides an API key.
+    /// The environment block is fixed so the date and host
+    /// don't leak in.
+    pub fn config(&self, config_json: &str) -> Config {
+        self.config_from(serde_json::from_str(config_json).unwrap())
+    }
+
+    /// Like `config`, from an already parsed file.
+    pub fn config_from(&self, file_cfg: FileConfig) -> Config {
+        let mut config = crate::config::test_config(&self.root, file_cfg);
+        config.environment =
+            crate::context::format_environment("test", "2026-01-01", None);
+        config
+    }
+
+    /// The URL, headers and body of the next request for
+    /// `messages`, as pretty JSON with the fixture root
+    /// replaced by `$ROOT`.
+    pub fn render(&self, config: &mut Config, messages: &[Message]) -> String {
+        config.ensure_full_prompt();
+        let tools = crate::tool::definitions(config.memory);
+        let request = crate::agent::turn_request(config, messages, &tools);
+        let headers: serde_json::Map<String, serde_json::Value> =
+            crate::api::headers(config)
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.into()))
+                .collect();
+        let value = serde_json::json!({
+            "url": config.model_api_url(),
+            "headers": headers,
+            "body": request,
+        });
+        let mut text = serde_json::to_string_pretty(&value).unwrap();
+        text.push('\n');
+        text.replace(self.root.to_str().unwrap(), "$ROOT")
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Compare `actual` with `testdata/golden/<name>.json`. With
+/// `TAPIR_UPDATE_GOLDEN=1` the file is rewritten instead.
+pub fn assert_golden(name: &str, actual: &str) {
+    let path = golden_dir().join(format!("{name}.json"));
+    if std::env::var_os("TAPIR_UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(golden_dir()).unwrap();
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!("missing {}; run with TAPIR_UPDATE_GOLDEN=1", path.display())
+    });
+    if expected == actual {
+        return;
+    }
+    let line = expected
+        .lines()
+        .zip(actual.lines())
+        .position(|(e, a)| e != a)
+        .unwrap_or_else(|| {
+            expected.lines().count().min(actual.lines().count())
+        });
+    panic!(
+        "{name}: request differs from {} at line {}\n\
+         expected: {}\n  actual: {}\n\
+         rerun with TAPIR_UPDATE_GOLDEN=1 if the change is intended",
+        path.display(),
+        line + 1,
+        expected.lines().nth(line).unwrap_or("<end>"),
+        actual.lines().nth(line).unwrap_or("<end>"),
+    );
+}
+
+/// Time `f`, which processes `input_bytes` bytes, and print
+/// the mean per run and the throughput. Used by the ignored
+/// `bench_*` tests:
+/// `cargo test --release bench_ -- --ignored --nocapture`.
+pub fn bench(name: &str, input_bytes: usize, mut f: impl FnMut()) {
+    f();
+    let start = Instant::now();
+    let mut runs = 0;
+    while runs < 5 || start.elapsed() < Duration::from_millis(500) {
+        f();
+        runs += 1;
+    }
+    let per = start.elapsed() / runs;
+    let mb_s = input_bytes as f64 / per.as_secs_f64() / 1e6;
+    eprintln!("{name:<28} {per:>12.2?} {mb_s:>8.0} MB/s");
+}
+
+/// `lines` lines of mixed ASCII and Unicode text, about 80
+/// bytes each.
+pub fn sample_text(lines: usize) -> String {
+    let mut out = String::with_capacity(lines * 80);
+    for i in 0..lines {
+        if i % 10 == 0 {
+            out.push_str(
+                "\t\u{201C}quoted\u{201D}  text \u{2014} with  gaps\n",
+            );
+        } else {
+            out.push_str(&format!(
+                "    let value_{i} = compute(\"{i}\", &mut state); // step\n"
+            ));
+        }
+    }
+    out
+}
+
+pub fn user(text: &str) -> Message {
+    Message {
+        role: Role::User,
+        content: Content::Text(text.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn golden_default() {
+        let fx = Fixture::new("default");
+        let mut config = fx.config("{}");
+        let out = fx.render(&mut config, &[user("hello")]);
+        assert_golden("default", &out);
+    }
+
+    #[test]
+    fn golden_project() {
+        let fx = Fixture::new("project");
+        fx.file("AGENTS.md", "Root rules.")
+            .file("home/.tapir/agent/AGENTS.md", "Global rules.")
+            .file("home/.tapir/agent/APPEND_SYSTEM.md", "Global extra.")
+            .file("project/AGENTS.md", "Project rules.")
+            .file("project/.tapir/APPEND_SYSTEM.md", "Project extra.")
+            .file(
+                "project/Cargo.toml",
+                "[package]\nname = \"demo\"\nversion = \"0.2.0\"\n",
+            )
+            .file(
+                "project/.tapir/skills/review/SKILL.md",
+                "---\nname: review\ndescription: Review a diff\n---\nBody",
+            )
+            .file(
+                "home/.tapir/agent/skills/review/SKILL.md",
+                "---\nname: review\ndescription: Shadowed\n---\nBody",
+            );
+        let mut config = fx.config(
+            r#"{
+                "model": "claude-sonnet-4-5",
+                "thinking_budget": 4000,
+                "response_language": "Portuguese",
+                "verbosity": "terse",
+                "memory": true,
+                "context_management": {"keep_tool_uses": 5}
+            }"#,
+        );
+        let out = fx.render(&mut config, &[user("review my change")]);
+        assert_golden("project", &out);
+    }
+
+    #[test]
+    fn golden_compacted() {
+        let fx = Fixture::new("compacted");
+        let mut config = fx.config(r#"{"betas": []}"#);
+        let mut messages: Vec<Message> =
+            crate::agent::summary_messages("The user is adding a parser.")
+                .into();
+        messages.push(user("run the tests"));
+        messages.push(Message {
+            role: Role::Assistant,
+            content: Content::Blocks(vec![ContentBlock::ToolUse {
+                id: "toolu_1".into(),
+                name: "bash".into(),
+                input: serde_json::json!({"command": "cargo test"}),
+            }]),
+        });
+        messages.push(Message {
+            role: Role::User,
+            content: Content::Blocks(vec![ContentBlock::ToolResult {
+                tool_use_id: "toolu_1".into(),
+                content: "test result: ok".into(),
+                is_error: None,
+            }]),
+        });
+        let out = fx.render(&mut config, &messages);
+        assert_golden("compacted", &out);
+    }
+}
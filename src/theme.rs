@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// What a piece of text is on screen; the theme picks its
+/// look.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Style {
+    Bold,
+    Dim,
+    /// A top-level markdown heading.
+    Title,
+    Heading,
+    /// Inline code, and code blocks in unknown languages.
+    Code,
+    Keyword,
+    String,
+    Comment,
+    Number,
+    Added,
+    Removed,
+    /// A diff's `@@` lines.
+    Hunk,
+    /// The highlighted entry of a picker.
+    Selected,
+}
+
+/// Each style's name in the `colors` config and its SGR
+/// parameters in the dark and light palettes.
+const STYLES: &[(Style, &str, &str, &str)] = &[
+    (Style::Bold, "bold", "1", "1"),
+    (Style::Dim, "dim", "2", "2"),
+    (Style::Title, "title", "1;4", "1;4"),
+    (Style::Heading, "heading", "1", "1"),
+    (Style::Code, "code", "36", "34"),
+    (Style::Keyword, "keyword", "35", "35"),
+    (Style::String, "string", "32", "32"),
+    (Style::Comment, "comment", "2", "2"),
+    (Style::Number, "number", "33", "38;5;94"),
+    (Style::Added, "added", "32", "32"),
+    (Style::Removed, "removed", "31", "31"),
+    (Style::Hunk, "hunk", "36", "34"),
+    (Style::Selected, "selected", "7", "7"),
+];
+
+/// Names `theme` accepts.
+pub const THEMES: &[&str] = &["dark", "light", "none"];
+
+/// SGR parameters per style, empty for unstyled text.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Theme {
+    codes: Vec<String>,
+}
+
+impl Theme {
+    /// The `name` palette with `colors` overriding styles by
+    /// name, like `{"keyword": "1;34"}`.
+    pub fn new(
+        name: &str,
+        colors: &HashMap<String, String>,
+    ) -> Result<Theme, String> {
+        let mut theme = match name {
+            "dark" | "" => Theme::palette(false),
+            "light" => Theme::palette(true),
+            "none" => Theme::plain(),
+            _ => {
+                return Err(format!(
+                    "no theme `{name}`, use one of {}",
+                    THEMES.join(", ")
+                ));
+            }
+        };
+        for (key, code) in colors {
+            let Some(i) = STYLES.iter().position(|(_, n, _, _)| n == key)
+            else {
+                return Err(format!("no style `{key}` in colors"));
+            };
+            if !code.chars().all(|c| c.is_ascii_digit() || c == ';') {
+                return Err(format!(
+                    "colors.{key}: `{code}` is not an SGR code like `1;34`"
+                ));
+            }
+            theme.codes[i] = code.clone();
+        }
+        Ok(theme)
+    }
+
+    fn palette(light: bool) -> Theme {
+        let codes = STYLES
+            .iter()
+            .map(|(_, _, dark, lit)| if light { lit } else { dark })
+            .map(|code| code.to_string())
+            .collect();
+        Theme { codes }
+    }
+
+    pub fn dark() -> Theme {
+        Theme::palette(false)
+    }
+
+    /// No styling at all.
+    pub fn plain() -> Theme {
+        Theme {
+            codes: vec![String::new(); STYLES.len()],
+        }
+    }
+
+    /// This theme, or `plain` when `NO_COLOR` is set or
+    /// stderr, where tapir talks to the user, is no terminal.
+    pub fn for_terminal(self) -> Theme {
+        let no_color =
+            std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        if no_color || unsafe { libc::isatty(2) } != 1 {
+            return Theme::plain();
+        }
+        self
+    }
+
+    fn code(&self, style: Style) -> &str {
+        let i = STYLES.iter().position(|(s, ..)| *s == style).unwrap_or(0);
+        &self.codes[i]
+    }
+
+    /// The sequence turning `style` on, or nothing when it
+    /// has no look.
+    pub fn start(&self, style: Style) -> String {
+        match self.code(style) {
+            "" => String::new(),
+            code => format!("\x1b[{code}m"),
+        }
+    }
+
+    /// The sequence turning every style off, or nothing when
+    /// the theme styles nothing.
+    pub fn reset(&self) -> &'static str {
+        if self.codes.iter().all(String::is_empty) {
+            ""
+        } else {
+            "\x1b[0m"
+        }
+    }
+
+    /// `text` in `style`.
+    pub fn paint(&self, style: Style, text: &str) -> String {
+        match self.code(style) {
+            "" => text.to_string(),
+            code => format!("\x1b[{code}m{text}\x1b[0m"),
+        }
+    }
+}
+
+fn current_lock() -> &'static RwLock<Arc<Theme>> {
+    static CURRENT: std::sync::OnceLock<RwLock<Arc<Theme>>> =
+        std::sync::OnceLock::new();
+    CURRENT.get_or_init(|| RwLock::new(Arc::new(Theme::dark().for_terminal())))
+}
+
+/// The theme in use.
+pub fn current() -> Arc<Theme> {
+    current_lock()
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// Use `theme` from now on.
+pub fn set(theme: Theme) {
+    *current_lock().write().unwrap_or_else(|e| e.into_inner()) =
+        Arc::new(theme);
+}
+
+/// `text` in `style` of the current theme.
+pub fn paint(style: Style, text: &str) -> String {
+    current().paint(style, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palettes_and_overrides() {
+        let colors =
+            HashMap::from([("keyword".to_string(), "1;34".to_string())]);
+        let theme = Theme::new("light", &colors).unwrap();
+        assert_eq!(theme.paint(Style::Keyword, "fn"), "\x1b[1;34mfn\x1b[0m");
+        assert_eq!(theme.start(Style::Code), "\x1b[34m");
+        assert_eq!(Theme::dark().start(Style::Code), "\x1b[36m");
+
+        let plain = Theme::new("none", &HashMap::new()).unwrap();
+        assert_eq!(plain.paint(Style::Bold, "x"), "x");
+        assert_eq!(plain.reset(), "");
+
+        let bad = HashMap::from([("keyword".to_string(), "red".to_string())]);
+        assert!(Theme::new("dark", &bad).is_err());
+        assert!(Theme::new("solarized", &HashMap::new()).is_err());
+    }
+}
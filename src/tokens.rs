@@ -0,0 +1,95 @@
+use crate::types::Request;
+
+/// Rough token count of `text`, modeled on BPE tokenizers:
+/// short ASCII words are a token each and longer ones one per
+/// six characters, runs of punctuation and newlines a token
+/// per two, other characters a token each, and spaces only as
+/// indentation. Real tokenizers differ per model; `scale`
+/// corrects for that.
+pub fn estimate(text: &str, scale: f64) -> u32 {
+    let mut tokens = 0usize;
+    let (mut word, mut symbols, mut spaces) = (0usize, 0usize, 0usize);
+    for ch in text.chars() {
+        let is_word = ch.is_ascii_alphanumeric();
+        let is_symbol = ch.is_ascii() && !is_word && ch != ' ' && ch != '\t';
+        if !is_word {
+            tokens += word.div_ceil(6);
+            word = 0;
+        }
+        if !is_symbol {
+            tokens += symbols.div_ceil(2);
+            symbols = 0;
+        }
+        if ch == ' ' || ch == '\t' {
+            spaces += 1;
+            continue;
+        }
+        // A single space merges into what follows
+        tokens += spaces.saturating_sub(1).div_ceil(4);
+        spaces = 0;
+        if is_word {
+            word += 1;
+        } else if is_symbol {
+            symbols += 1;
+        } else {
+            tokens += 1;
+        }
+    }
+    tokens += word.div_ceil(6)
+        + symbols.div_ceil(2)
+        + spaces.saturating_sub(1).div_ceil(4);
+    (tokens as f64 * scale).round() as u32
+}
+
+/// Estimated input tokens of `request` as the API would count
+/// them, from its JSON body.
+pub fn estimate_request(request: &Request<'_>, scale: f64) -> u32 {
+    let json = serde_json::to_string(request).unwrap_or_default();
+    estimate(&json, scale)
+}
+
+/// Correction factor for later estimates, from what the API
+/// actually counted. Clamped so one odd request can't skew
+/// it far.
+pub fn calibrate(actual: u32, estimated: u32) -> f64 {
+    if actual == 0 || estimated == 0 {
+        return 1.0;
+    }
+    (actual as f64 / estimated as f64).clamp(0.25, 4.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_are_in_range() {
+        // Typical BPE counts for these strings, within 30%
+        let cases = [
+            ("The quick brown fox jumps over the lazy dog.", 10),
+            ("fn main() {\n    println!(\"hello\");\n}\n", 13),
+            ("日本語のテキスト", 8),
+        ];
+        for (text, actual) in cases {
+            let est = estimate(text, 1.0) as f64;
+            let ratio = est / actual as f64;
+            assert!((0.7..=1.3).contains(&ratio), "{text:?}: {est}");
+        }
+        assert_eq!(estimate("", 1.0), 0);
+        assert_eq!(estimate("word word", 2.0), 4);
+        assert_eq!(estimate("            x", 1.0), 4);
+        assert_eq!(calibrate(150, 100), 1.5);
+        assert_eq!(calibrate(1, 1000), 0.25);
+    }
+
+    #[test]
+    fn calibrates_on_the_cached_prompt_too() {
+        let usage = crate::types::Usage {
+            input_tokens: 10,
+            cache_read_input_tokens: 900,
+            cache_creation_input_tokens: 90,
+            ..Default::default()
+        };
+        assert_eq!(calibrate(usage.prompt_tokens(), 1000), 1.0);
+    }
+}
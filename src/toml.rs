@@ -0,0 +1,525 @@
+//! A small TOML reader producing `serde_json::Value`, enough
+//! for manifests and config files: tables, arrays of tables,
+//! dotted keys, inline tables, arrays, all four string forms,
+//! integers, floats and booleans. Dates are kept as strings.
+
+use serde_json::{Map, Value};
+
+/// Parse a TOML document. Errors carry a 1-based line number.
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut p = Parser {
+        s: text.as_bytes(),
+        pos: 0,
+        line: 1,
+    };
+    let mut root = Map::new();
+    // Path of the table that `key = value` lines go into
+    let mut current: Vec<String> = Vec::new();
+
+    loop {
+        p.skip_ws_comments_newlines();
+        if p.eof() {
+            break;
+        }
+        if p.peek() == Some(b'[') {
+            let array = p.s.get(p.pos + 1) == Some(&b'[');
+            p.pos += if array { 2 } else { 1 };
+            p.skip_ws();
+            let path = p.key_path()?;
+            p.skip_ws();
+            if !p.eat(b']') || (array && !p.eat(b']')) {
+                return Err(p.err("expected ] after table name"));
+            }
+            p.end_of_line()?;
+            if array {
+                let parent = table_at(&mut root, &path[..path.len() - 1])
+                    .map_err(|e| p.err(&e))?;
+                let last = path.last().unwrap().clone();
+                let entry = parent
+                    .entry(last)
+                    .or_insert_with(|| Value::Array(Vec::new()));
+                match entry {
+                    Value::Array(items) => {
+                        items.push(Value::Object(Map::new()))
+                    }
+                    _ => return Err(p.err("key is not an array of tables")),
+                }
+            } else {
+                table_at(&mut root, &path).map_err(|e| p.err(&e))?;
+            }
+            current = path;
+            continue;
+        }
+
+        let path = p.key_path()?;
+        p.skip_ws();
+        if !p.eat(b'=') {
+            return Err(p.err("expected = after key"));
+        }
+        p.skip_ws();
+        let value = p.value()?;
+        let table = table_at(&mut root, &current).map_err(|e| p.err(&e))?;
+        insert(table, &path, value).map_err(|e| p.err(&e))?;
+        p.end_of_line()?;
+    }
+    Ok(Value::Object(root))
+}
+
+/// The table at `path`, creating missing ones. An array of
+/// tables resolves to its last element.
+fn table_at<'a>(
+    root: &'a mut Map<String, Value>,
+    path: &[String],
+) -> Result<&'a mut Map<String, Value>, String> {
+    let mut table = root;
+    for key in path {
+        let entry = table
+            .entry(key.clone())
+            .or_insert_with(|| Value::Object(Map::new()));
+        table = match entry {
+            Value::Object(m) => m,
+            Value::Array(items) => match items.last_mut() {
+                Some(Value::Object(m)) => m,
+                _ => return Err(format!("{key} is not a table")),
+            },
+            _ => return Err(format!("{key} is not a table")),
+        };
+    }
+    Ok(table)
+}
+
+fn insert(
+    table: &mut Map<String, Value>,
+    path: &[String],
+    value: Value,
+) -> Result<(), String> {
+    let (last, parents) = path.split_last().unwrap();
+    let table = table_at(table, parents)?;
+    if table.contains_key(last) {
+        return Err(format!("duplicate key {last}"));
+    }
+    table.insert(last.clone(), value);
+    Ok(())
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn err(&self, msg: &str) -> String {
+        format!("line {}: {msg}", self.line)
+    }
+
+    fn eof(&self) -> bool {
+        self.pos >= self.s.len()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_ws(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some(b'#') {
+            while !matches!(self.peek(), None | Some(b'\n')) {
+                self.pos += 1;
+            }
+        }
+    }
+
+    fn skip_ws_comments_newlines(&mut self) {
+        loop {
+            self.skip_ws();
+            self.skip_comment();
+            match self.peek() {
+                Some(b'\n') => {
+                    self.pos += 1;
+                    self.line += 1;
+                }
+                Some(b'\r') => self.pos += 1,
+                _ => break,
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), String> {
+        self.skip_ws();
+        self.skip_comment();
+        self.eat(b'\r');
+        match self.peek() {
+            None => Ok(()),
+            Some(b'\n') => {
+                self.pos += 1;
+                self.line += 1;
+                Ok(())
+            }
+            Some(_) => Err(self.err("unexpected text after value")),
+        }
+    }
+
+    fn key_path(&mut self) -> Result<Vec<String>, String> {
+        let mut path = vec![self.key()?];
+        loop {
+            self.skip_ws();
+            if !self.eat(b'.') {
+                return Ok(path);
+            }
+            self.skip_ws();
+            path.push(self.key()?);
+        }
+    }
+
+    fn key(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(b'"') => self.basic_string(),
+            Some(b'\'') => self.literal_string(),
+            _ => {
+                let start = self.pos;
+                while matches!(
+                    self.peek(),
+                    Some(b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_' | b'-')
+                ) {
+                    self.pos += 1;
+                }
+                if start == self.pos {
+                    return Err(self.err("expected a key"));
+                }
+                Ok(self.text(start, self.pos))
+            }
+        }
+    }
+
+    fn text(&self, start: usize, end: usize) -> String {
+        String::from_utf8_lossy(&self.s[start..end]).into_owned()
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some(b'"') => self.basic_string().map(Value::String),
+            Some(b'\'') => self.literal_string().map(Value::String),
+            Some(b'[') => self.array(),
+            Some(b'{') => self.inline_table(),
+            Some(_) => self.bare_value(),
+            None => Err(self.err("expected a value")),
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        loop {
+            self.skip_ws_comments_newlines();
+            if self.eat(b']') {
+                return Ok(Value::Array(items));
+            }
+            items.push(self.value()?);
+            self.skip_ws_comments_newlines();
+            if self.eat(b']') {
+                return Ok(Value::Array(items));
+            }
+            if !self.eat(b',') {
+                return Err(self.err("expected , or ] in array"));
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Value, String> {
+        self.pos += 1;
+        let mut table = Map::new();
+        self.skip_ws();
+        if self.eat(b'}') {
+            return Ok(Value::Object(table));
+        }
+        loop {
+            self.skip_ws();
+            let path = self.key_path()?;
+            self.skip_ws();
+            if !self.eat(b'=') {
+                return Err(self.err("expected = in inline table"));
+            }
+            self.skip_ws();
+            let value = self.value()?;
+            insert(&mut table, &path, value).map_err(|e| self.err(&e))?;
+            self.skip_ws();
+            if self.eat(b'}') {
+                return Ok(Value::Object(table));
+            }
+            if !self.eat(b',') {
+                return Err(self.err("expected , or } in inline table"));
+            }
+        }
+    }
+
+    /// Numbers, booleans and dates.
+    fn bare_value(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if matches!(c, b',' | b']' | b'}' | b'\n' | b'\r' | b'#') {
+                break;
+            }
+            // A space ends the value unless it separates the
+            // date and time of a datetime
+            if c == b' '
+                && !(self.pos - start == 10
+                    && self.s.get(self.pos + 1).is_some_and(u8::is_ascii_digit))
+            {
+                break;
+            }
+            self.pos += 1;
+        }
+        let raw = self.text(start, self.pos);
+        let raw = raw.trim_end();
+        match raw {
+            "true" => return Ok(Value::Bool(true)),
+            "false" => return Ok(Value::Bool(false)),
+            "inf" | "+inf" | "-inf" | "nan" | "+nan" | "-nan" => {
+                return Err(self.err("inf/nan are not supported"));
+            }
+            _ => {}
+        }
+        let digits = raw.replace('_', "");
+        let int = if let Some(hex) = digits.strip_prefix("0x") {
+            i64::from_str_radix(hex, 16).ok()
+        } else if let Some(oct) = digits.strip_prefix("0o") {
+            i64::from_str_radix(oct, 8).ok()
+        } else if let Some(bin) = digits.strip_prefix("0b") {
+            i64::from_str_radix(bin, 2).ok()
+        } else {
+            digits.parse::<i64>().ok()
+        };
+        if let Some(n) = int {
+            return Ok(Value::from(n));
+        }
+        if let Ok(f) = digits.parse::<f64>()
+            && let Some(n) = serde_json::Number::from_f64(f)
+        {
+            return Ok(Value::Number(n));
+        }
+        let is_date = raw.len() >= 8
+            && raw.bytes().next().is_some_and(|b| b.is_ascii_digit())
+            && raw.contains(['-', ':']);
+        if is_date {
+            return Ok(Value::String(raw.to_string()));
+        }
+        Err(self.err(&format!("invalid value: {raw}")))
+    }
+
+    fn basic_string(&mut self) -> Result<String, String> {
+        let multi = self.s[self.pos..].starts_with(b"\"\"\"");
+        self.pos += if multi { 3 } else { 1 };
+        if multi {
+            self.skip_first_newline();
+        }
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(self.err("unterminated string"));
+            };
+            if multi && self.s[self.pos..].starts_with(b"\"\"\"") {
+                self.pos += 3;
+                // Up to two quotes may end the content
+                for _ in 0..2 {
+                    if !self.eat(b'"') {
+                        break;
+                    }
+                    out.push(b'"');
+                }
+                break;
+            }
+            if !multi && c == b'"' {
+                self.pos += 1;
+                break;
+            }
+            if c == b'\n' {
+                if !multi {
+                    return Err(self.err("newline in string"));
+                }
+                self.line += 1;
+            }
+            self.pos += 1;
+            if c != b'\\' {
+                out.push(c);
+                continue;
+            }
+            let Some(e) = self.peek() else {
+                return Err(self.err("unterminated string"));
+            };
+            self.pos += 1;
+            match e {
+                b'n' => out.push(b'\n'),
+                b't' => out.push(b'\t'),
+                b'r' => out.push(b'\r'),
+                b'b' => out.push(8),
+                b'f' => out.push(12),
+                b'"' => out.push(b'"'),
+                b'\\' => out.push(b'\\'),
+                b'u' | b'U' => {
+                    let len = if e == b'u' { 4 } else { 8 };
+                    let hex = self
+                        .s
+                        .get(self.pos..self.pos + len)
+                        .ok_or_else(|| self.err("short unicode escape"))?;
+                    let code =
+                        u32::from_str_radix(&String::from_utf8_lossy(hex), 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| self.err("bad unicode escape"))?;
+                    self.pos += len;
+                    let mut buf = [0u8; 4];
+                    out.extend_from_slice(
+                        code.encode_utf8(&mut buf).as_bytes(),
+                    );
+                }
+                b' ' | b'\t' | b'\r' | b'\n' if multi => {
+                    // Line-ending backslash trims to the next
+                    // non-whitespace character
+                    self.pos -= 1;
+                    while let Some(c) = self.peek() {
+                        match c {
+                            b'\n' => self.line += 1,
+                            b' ' | b'\t' | b'\r' => {}
+                            _ => break,
+                        }
+                        self.pos += 1;
+                    }
+                }
+                _ => return Err(self.err("bad escape in string")),
+            }
+        }
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    fn literal_string(&mut self) -> Result<String, String> {
+        let multi = self.s[self.pos..].starts_with(b"'''");
+        let delim: &[u8] = if multi { b"'''" } else { b"'" };
+        self.pos += delim.len();
+        if multi {
+            self.skip_first_newline();
+        }
+        let start = self.pos;
+        loop {
+            if self.eof() {
+                return Err(self.err("unterminated string"));
+            }
+            if self.s[self.pos..].starts_with(delim) {
+                let mut end = self.pos;
+                self.pos += delim.len();
+                if multi {
+                    while self.peek() == Some(b'\'') {
+                        self.pos += 1;
+                        end += 1;
+                    }
+                }
+                return Ok(self.text(start, end));
+            }
+            match self.s[self.pos] {
+                b'\n' if !multi => {
+                    return Err(self.err("newline in string"));
+                }
+                b'\n' => self.line += 1,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn skip_first_newline(&mut self) {
+        if self.s[self.pos..].starts_with(b"\r\n") {
+            self.pos += 2;
+            self.line += 1;
+        } else if self.eat(b'\n') {
+            self.line += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn tables_and_scalars() {
+        let v = parse(
+            "title = \"x\" # note\n\
+             [package]\n\
+             name = 'tapir'\n\
+             version = \"0.1.0\"\n\
+             edition = 2024\n\
+             ratio = 0.5\n\
+             publish = false\n\
+             [profile.release]\n\
+             lto = true\n",
+        )
+        .unwrap();
+        assert_eq!(v["title"], "x");
+        assert_eq!(v["package"]["name"], "tapir");
+        assert_eq!(v["package"]["edition"], 2024);
+        assert_eq!(v["package"]["ratio"], 0.5);
+        assert_eq!(v["package"]["publish"], false);
+        assert_eq!(v["profile"]["release"]["lto"], true);
+    }
+
+    #[test]
+    fn arrays_inline_tables_and_dotted_keys() {
+        let v = parse(
+            "deps = [\n  \"a>=1\", # first\n  'b',\n]\n\
+             serde = { version = \"1\", features = [\"derive\"] }\n\
+             a.b.c = 1\n\
+             [[bin]]\nname = \"one\"\n[[bin]]\nname = \"two\"\n",
+        )
+        .unwrap();
+        assert_eq!(v["deps"], json!(["a>=1", "b"]));
+        assert_eq!(v["serde"]["features"], json!(["derive"]));
+        assert_eq!(v["a"]["b"]["c"], 1);
+        assert_eq!(v["bin"][1]["name"], "two");
+    }
+
+    #[test]
+    fn strings() {
+        let v = parse(
+            "a = \"tab\\tq\\\"\\u00e9\"\n\
+             b = \"\"\"\nline1\nline2\"\"\"\n\
+             c = '''\nC:\\raw\\'''\n\
+             d = \"\"\"one \\\n    two\"\"\"\n\
+             when = 1979-05-27T07:32:00Z\n",
+        )
+        .unwrap();
+        assert_eq!(v["a"], "tab\tq\"é");
+        assert_eq!(v["b"], "line1\nline2");
+        assert_eq!(v["c"], "C:\\raw\\");
+        assert_eq!(v["d"], "one two");
+        assert_eq!(v["when"], "1979-05-27T07:32:00Z");
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        assert_eq!(
+            parse("a = 1\nb = \n").unwrap_err(),
+            "line 2: invalid value: "
+        );
+        assert_eq!(
+            parse("a = 1\na = 2\n").unwrap_err(),
+            "line 2: duplicate key a"
+        );
+        assert!(parse("[x\n").unwrap_err().starts_with("line 1:"));
+        assert!(parse("s = \"open\n").is_err());
+    }
+}
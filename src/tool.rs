@@ -1,11 +1,22 @@
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write as _;
 use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Read};
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use std::sync::mpsc;
-use std::time::Duration;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Mutex, mpsc};
+use std::time::{Duration, SystemTime};
 
+use serde::Deserialize;
+
+use crate::checkpoint::Checkpoints;
 use crate::error::{Error, Result};
+use crate::i18n::tr;
 use crate::signal;
+use crate::trash::Trash;
 use crate::types::{CacheControl, ToolDef};
 use crate::util::{
     edit_diff, normalize_for_match, truncate_head, truncate_line, truncate_tail,
@@ -67,9 +78,165 @@ pub fn safe_path_for_write(working_dir: &Path, path: &str) -> Result<PathBuf> {
     Ok(parent_canonical.join(filename))
 }
 
-pub fn definitions() -> Vec<ToolDef> {
+/// What a file looked like when the model last saw it.
+#[derive(Clone, Copy, PartialEq)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: u64,
+}
+
+fn content_hash(content: &[u8]) -> u64 {
+    let mut h = DefaultHasher::new();
+    content.hash(&mut h);
+    h.finish()
+}
+
+/// Per-session record of file contents the model has seen, so
+/// edits to files changed on disk since the last read can be
+/// refused instead of silently clobbering someone else's work.
+#[derive(Default)]
+pub struct FileTracker {
+    stamps: Mutex<HashMap<PathBuf, FileStamp>>,
+    /// Files read or written, most recently touched last.
+    recent: Mutex<Vec<PathBuf>>,
+    /// Content of each written file before its first write
+    /// this session (`None` if it did not exist).
+    originals: Mutex<BTreeMap<PathBuf, Option<Vec<u8>>>>,
+    checkpoints: Option<Checkpoints>,
+    /// Where deleted files go; without one they are unlinked.
+    trash: Option<Trash>,
+}
+
+impl FileTracker {
+    /// A tracker that also snapshots every file before it is
+    /// written.
+    pub fn with_checkpoints(checkpoints: Checkpoints) -> Self {
+        FileTracker {
+            checkpoints: Some(checkpoints),
+            ..Default::default()
+        }
+    }
+
+    pub fn checkpoints(&self) -> Option<&Checkpoints> {
+        self.checkpoints.as_ref()
+    }
+
+    /// Move deleted files to `trash` instead of unlinking them.
+    pub fn with_trash(mut self, trash: Trash) -> Self {
+        self.trash = Some(trash);
+        self
+    }
+
+    pub fn trash(&self) -> Option<&Trash> {
+        self.trash.as_ref()
+    }
+
+    /// Remember `content` as the model's view of `path`.
+    fn record(&self, path: &Path, content: &[u8]) {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        let stamp = FileStamp {
+            modified,
+            len: content.len() as u64,
+            hash: content_hash(content),
+        };
+        if let Ok(mut stamps) = self.stamps.lock() {
+            stamps.insert(path.to_path_buf(), stamp);
+        }
+        if let Ok(mut recent) = self.recent.lock() {
+            recent.retain(|p| p != path);
+            recent.push(path.to_path_buf());
+        }
+    }
+
+    /// Files read or written this session, most recent first.
+    pub fn recent(&self) -> Vec<PathBuf> {
+        self.recent
+            .lock()
+            .map(|r| r.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Save what `path` holds now: the first time as its
+    /// session-start content, and always as a checkpoint.
+    fn before_write(&self, path: &Path) -> Result<()> {
+        if let Ok(mut originals) = self.originals.lock() {
+            originals
+                .entry(path.to_path_buf())
+                .or_insert_with(|| fs::read(path).ok());
+        }
+        if let Some(cp) = &self.checkpoints {
+            cp.snapshot(path)?;
+        }
+        Ok(())
+    }
+
+    /// Write `content` to `path` for the user, checkpointed
+    /// and tracked like a tool write.
+    pub fn write(&self, path: &Path, content: &[u8]) -> Result<()> {
+        self.before_write(path)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, content)?;
+        self.record(path, content);
+        Ok(())
+    }
+
+    /// Files written this session with their original content.
+    pub fn originals(&self) -> Vec<(PathBuf, Option<Vec<u8>>)> {
+        self.originals
+            .lock()
+            .map(|o| o.iter().map(|(p, c)| (p.clone(), c.clone())).collect())
+            .unwrap_or_default()
+    }
+
+    /// Fail if `path` was read earlier and has changed since.
+    /// Files never read (or since deleted) pass.
+    fn check_fresh(
+        &self,
+        name: &str,
+        path: &Path,
+        display: &str,
+    ) -> Result<()> {
+        let Some(stamp) =
+            self.stamps.lock().ok().and_then(|s| s.get(path).copied())
+        else {
+            return Ok(());
+        };
+        let Ok(meta) = fs::metadata(path) else {
+            return Ok(());
+        };
+        if meta.len() == stamp.len && meta.modified().ok() == stamp.modified {
+            return Ok(());
+        }
+        // mtime or size moved; only content counts
+        let current = fs::read(path)?;
+        if current.len() as u64 == stamp.len
+            && content_hash(&current) == stamp.hash
+        {
+            return Ok(());
+        }
+        Err(Error::Tool {
+            name: name.to_string(),
+            message: format!(
+                "{display} changed on disk since it was last read; \
+                 re-read it before editing"
+            ),
+        })
+    }
+}
+
+/// Tools that only look, the ones offered while
+/// `/plan-refactor` drafts a plan.
+pub const READ_ONLY: &[&str] = &["read_file", "ls", "find", "grep", "stat"];
+
+/// Tool definitions sent with each request. `memory` adds
+/// the API's memory tool, served by [`crate::memory`].
+pub fn definitions(memory: bool) -> Vec<ToolDef> {
     let mut tools = vec![
         ToolDef {
+            kind: None,
             name: "read_file".to_string(),
             description: "Read the contents of a file. \
                  Supports offset and limit for \
@@ -101,6 +268,7 @@ pub fn definitions() -> Vec<ToolDef> {
             cache_control: None,
         },
         ToolDef {
+            kind: None,
             name: "write_file".to_string(),
             description: "Write content to a file, creating it \
                  if it doesn't exist"
@@ -124,6 +292,7 @@ pub fn definitions() -> Vec<ToolDef> {
             cache_control: None,
         },
         ToolDef {
+            kind: None,
             name: "edit_file".to_string(),
             description: "Edit a file by replacing a string \
                  match with new content. The old_string \
@@ -160,6 +329,7 @@ pub fn definitions() -> Vec<ToolDef> {
             cache_control: None,
         },
         ToolDef {
+            kind: None,
             name: "bash".to_string(),
             description: "Run a shell command".to_string(),
             input_schema: serde_json::json!({
@@ -181,10 +351,14 @@ pub fn definitions() -> Vec<ToolDef> {
             cache_control: None,
         },
         ToolDef {
+            kind: None,
             name: "ls".to_string(),
             description: "List directory contents, sorted \
                  alphabetically. Directories have a \
-                 trailing /."
+                 trailing /. In a git repository, untracked \
+                 entries are marked ? and gitignored ones \
+                 (build output, dependencies) are hidden \
+                 unless ignored is set, then marked *."
                 .to_string(),
             input_schema: serde_json::json!({
                 "type": "object",
@@ -194,12 +368,19 @@ pub fn definitions() -> Vec<ToolDef> {
                         "description":
                             "Directory to list \
                              (default: working directory)"
+                    },
+                    "ignored": {
+                        "type": "boolean",
+                        "description":
+                            "Also list gitignored entries \
+                             (default: false)"
                     }
                 }
             }),
             cache_control: None,
         },
         ToolDef {
+            kind: None,
             name: "find".to_string(),
             description: "Find files matching a glob pattern \
                  using fd. Returns up to 1000 results."
@@ -225,6 +406,7 @@ pub fn definitions() -> Vec<ToolDef> {
             cache_control: None,
         },
         ToolDef {
+            kind: None,
             name: "grep".to_string(),
             description: "Search file contents using ripgrep. \
                  Returns matching lines with file paths \
@@ -255,7 +437,65 @@ pub fn definitions() -> Vec<ToolDef> {
             }),
             cache_control: None,
         },
+        ToolDef {
+            kind: None,
+            name: "delete_file".to_string(),
+            description: "Delete a file, or a directory with \
+                 recursive set. Deleted paths go to a trash the \
+                 user can restore from."
+                .to_string(),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description":
+                            "File or directory to delete"
+                    },
+                    "recursive": {
+                        "type": "boolean",
+                        "description":
+                            "Required to delete a directory \
+                             (default: false)"
+                    }
+                },
+                "required": ["path"]
+            }),
+            cache_control: None,
+        },
+        ToolDef {
+            kind: None,
+            name: "stat".to_string(),
+            description: "Show size, line count, last modified \
+                 time and language of a file. Given a directory \
+                 or glob pattern, aggregate over the matching \
+                 files instead. Use it to decide whether a file \
+                 needs an offset/limit read."
+                .to_string(),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description":
+                            "File, directory or glob \
+                             (e.g. \"src/**/*.rs\")"
+                    }
+                },
+                "required": ["path"]
+            }),
+            cache_control: None,
+        },
     ];
+    if memory {
+        tools.push(ToolDef {
+            kind: Some(crate::memory::TOOL_TYPE),
+            name: "memory".to_string(),
+            description: String::new(),
+            input_schema: serde_json::Value::Null,
+            cache_control: None,
+        });
+    }
 
     // Tag last tool with cache_control for prompt
     // caching (tools + system prompt cached together)
@@ -266,27 +506,399 @@ pub fn definitions() -> Vec<ToolDef> {
     tools
 }
 
+/// The file a write_file/edit_file call targets, resolved
+/// against the working directory. `None` for other tools.
+pub fn edited_path(
+    working_dir: &Path,
+    name: &str,
+    input: &serde_json::Value,
+) -> Option<PathBuf> {
+    match name {
+        "write_file" | "edit_file" => {
+            safe_path(working_dir, input["path"].as_str()?).ok()
+        }
+        _ => None,
+    }
+}
+
+/// What tools may do without asking, from the user's config.
+pub struct Policy {
+    /// write_file content larger than this needs confirmation
+    /// (0: no limit).
+    pub max_write_bytes: usize,
+    /// Shrinking an existing file by more than this percent
+    /// with write_file needs confirmation (0: no limit).
+    pub max_shrink_pct: u8,
+    /// Per-path overrides, first match wins. Not applied to
+    /// bash, which can reach any path.
+    pub rules: Vec<PathRule>,
+    /// Globs (as in `rules`) for files that are never written
+    /// without asking, unless a rule covers them.
+    pub protected: Vec<String>,
+    /// Protected files the user allowed this session.
+    approved: Mutex<HashSet<PathBuf>>,
+    /// Asks the user; true means go ahead.
+    pub confirm: fn(&str) -> bool,
+}
+
+/// `{"path": "secrets/**", "read": "deny"}` in the `sandbox`
+/// config list.
+#[derive(Clone, Deserialize)]
+pub struct PathRule {
+    /// Glob relative to the working directory. Without a `/`
+    /// it matches file names at any depth.
+    pub path: String,
+    #[serde(default)]
+    pub read: Option<Access>,
+    /// `allow` also skips the write_file size checks.
+    #[serde(default)]
+    pub write: Option<Access>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Allow,
+    Prompt,
+    Deny,
+}
+
+/// Files smaller than this may shrink freely.
+const SHRINK_MIN_BYTES: u64 = 1024;
+
+/// Default `protected_files`: secrets, lockfiles and git
+/// internals.
+pub const DEFAULT_PROTECTED: &[&str] = &[
+    ".env",
+    ".env.*",
+    "*.pem",
+    "*.key",
+    "*.lock",
+    "package-lock.json",
+    "pnpm-lock.yaml",
+    "go.sum",
+    ".git/**",
+];
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            max_write_bytes: 200_000,
+            max_shrink_pct: 50,
+            rules: Vec::new(),
+            protected: DEFAULT_PROTECTED
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+            approved: Mutex::new(HashSet::new()),
+            confirm: crate::readline::confirm,
+        }
+    }
+}
+
+impl Policy {
+    /// Access the first matching rule gives to `path` for
+    /// reading or writing, `None` if no rule applies.
+    fn access(
+        &self,
+        working_dir: &Path,
+        path: &Path,
+        write: bool,
+    ) -> Option<Access> {
+        if self.rules.is_empty() {
+            return None;
+        }
+        let rel = relative(working_dir, path)?;
+        self.rules
+            .iter()
+            .filter(|r| rule_matches(&r.path, &rel))
+            .find_map(|r| if write { r.write } else { r.read })
+    }
+
+    /// Whether searches and directory walks should leave out
+    /// `path`: anything not freely readable.
+    pub(crate) fn hidden(&self, working_dir: &Path, path: &Path) -> bool {
+        matches!(
+            self.access(working_dir, path, false),
+            Some(Access::Deny | Access::Prompt)
+        )
+    }
+
+    pub(crate) fn check_read(
+        &self,
+        name: &str,
+        working_dir: &Path,
+        path: &Path,
+        display: &str,
+    ) -> Result<()> {
+        match self.access(working_dir, path, false) {
+            Some(Access::Deny) => Err(Error::Security(format!(
+                "reading {display} is denied by a sandbox rule"
+            ))),
+            Some(Access::Prompt) => self.ask(
+                name,
+                question(
+                    "{name} reads {display}",
+                    name,
+                    &[("display", &display)],
+                ),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Ask before a write rules say to prompt for, and before a
+    /// write_file (`new_len` set) that is very large or would
+    /// cut most of an existing file, which is usually a
+    /// truncated full-file rewrite.
+    pub(crate) fn check_write(
+        &self,
+        name: &str,
+        working_dir: &Path,
+        path: &Path,
+        display: &str,
+        new_len: Option<usize>,
+    ) -> Result<()> {
+        match self.access(working_dir, path, true) {
+            Some(Access::Deny) => {
+                return Err(Error::Security(format!(
+                    "writing {display} is denied by a sandbox rule"
+                )));
+            }
+            Some(Access::Prompt) => {
+                return self.ask(
+                    name,
+                    question(
+                        "{name} changes {display}",
+                        name,
+                        &[("display", &display)],
+                    ),
+                );
+            }
+            Some(Access::Allow) => return Ok(()),
+            None => {}
+        }
+        self.check_protected(name, working_dir, path, display)?;
+        let Some(new_len) = new_len else {
+            return Ok(());
+        };
+        let old_len = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        let limit = self.max_write_bytes;
+        let keep_pct = 100 - self.max_shrink_pct.min(100) as u64;
+        let question = if limit > 0 && new_len > limit {
+            question(
+                "{name} {display}: {new_len} bytes exceeds the {limit} byte limit",
+                name,
+                &[
+                    ("display", &display),
+                    ("new_len", &new_len),
+                    ("limit", &limit),
+                ],
+            )
+        } else if self.max_shrink_pct > 0
+            && old_len >= SHRINK_MIN_BYTES
+            && (new_len as u64) * 100 < old_len * keep_pct
+        {
+            let pct = 100 - (new_len as u64 * 100 / old_len);
+            question(
+                "{name} {display}: shrinks the file from {old_len} to \
+                 {new_len} bytes (-{pct}%)",
+                name,
+                &[
+                    ("display", &display),
+                    ("old_len", &old_len),
+                    ("new_len", &new_len),
+                    ("pct", &pct),
+                ],
+            )
+        } else {
+            return Ok(());
+        };
+        let asked = question.0.clone();
+        self.ask(name, question).map_err(|_| Error::Tool {
+            name: name.to_string(),
+            message: format!(
+                "{asked}; the user declined. Use edit_file for \
+                 targeted changes instead of rewriting the file"
+            ),
+        })
+    }
+
+    /// Ask once per file before changing a protected file.
+    fn check_protected(
+        &self,
+        name: &str,
+        working_dir: &Path,
+        path: &Path,
+        display: &str,
+    ) -> Result<()> {
+        let Some(rel) = relative(working_dir, path) else {
+            return Ok(());
+        };
+        if !self.protected.iter().any(|p| rule_matches(p, &rel)) {
+            return Ok(());
+        }
+        let mut approved =
+            self.approved.lock().unwrap_or_else(|e| e.into_inner());
+        if approved.contains(path) {
+            return Ok(());
+        }
+        self.ask(
+            name,
+            question(
+                "{name} changes protected file {display}",
+                name,
+                &[("display", &display)],
+            ),
+        )?;
+        approved.insert(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Ask the user `shown`, a translation of `question`,
+    /// which the model is told when they decline.
+    fn ask(
+        &self,
+        name: &str,
+        (question, shown): (String, String),
+    ) -> Result<()> {
+        crate::notify::title("waiting for permission");
+        crate::notify::alert("waiting for permission");
+        let allowed =
+            (self.confirm)(&tr!("{question}. Allow? [y/N] ", question = shown));
+        crate::notify::title(&format!("running {name}"));
+        if allowed {
+            return Ok(());
+        }
+        Err(Error::Tool {
+            name: name.to_string(),
+            message: format!("{question}; the user declined"),
+        })
+    }
+}
+
+/// An approval question for tool `name`: `template` filled
+/// in English for the model and translated for the user.
+fn question(
+    template: &'static str,
+    name: &str,
+    args: &[(&str, &dyn std::fmt::Display)],
+) -> (String, String) {
+    let mut args = args.to_vec();
+    args.push(("name", &name));
+    (
+        crate::i18n::fill(template, &args),
+        crate::i18n::fill(crate::i18n::t(template), &args),
+    )
+}
+
+/// `path` relative to the working directory. Tool paths are
+/// canonical; rg and fd output may not be.
+fn relative(working_dir: &Path, path: &Path) -> Option<String> {
+    let wd = working_dir.canonicalize().ok()?;
+    let rel = path
+        .strip_prefix(&wd)
+        .or_else(|_| path.strip_prefix(working_dir))
+        .ok()?;
+    Some(rel.to_string_lossy().into_owned())
+}
+
+/// Match a rule glob against a path relative to the working
+/// directory. `dir/**` also covers `dir` itself.
+fn rule_matches(pattern: &str, rel: &str) -> bool {
+    if !pattern.contains('/') {
+        return rel.split('/').any(|part| glob_match(pattern, part));
+    }
+    let pattern = pattern.trim_start_matches("./");
+    glob_match(pattern, rel) || pattern.strip_suffix("/**") == Some(rel)
+}
+
+/// `safe_path` for tools that read, plus the sandbox rules.
+fn resolve_read(
+    working_dir: &Path,
+    policy: &Policy,
+    name: &str,
+    path: &str,
+) -> Result<PathBuf> {
+    let resolved = safe_path(working_dir, path)?;
+    policy.check_read(name, working_dir, &resolved, path)?;
+    Ok(resolved)
+}
+
 const READ_MAX_LINES: usize = 2000;
 const READ_MAX_BYTES: usize = 50_000;
+const FIND_MAX_RESULTS: usize = 1000;
 const BASH_MAX_LINES: usize = 1000;
 const BASH_MAX_BYTES: usize = 30_000;
 const LS_MAX_ENTRIES: usize = 500;
 const LS_MAX_BYTES: usize = 30_000;
 const GREP_LINE_MAX_CHARS: usize = 500;
+const STAT_MAX_FILES: usize = 10_000;
+const STAT_TOP_FILES: usize = 10;
 
+/// Run tool `name`. A panic inside the tool comes back as
+/// its error rather than unwinding into the caller, so one
+/// buggy tool fails its call instead of the session.
 pub fn execute(
     working_dir: &Path,
+    files: &FileTracker,
+    policy: &Policy,
+    name: &str,
+    input: &serde_json::Value,
+) -> Result<String> {
+    catching(name, || dispatch(working_dir, files, policy, name, input))
+}
+
+/// `run`'s result, or a tool error for `name` when it
+/// panics.
+fn catching<T>(name: &str, run: impl FnOnce() -> Result<T>) -> Result<T> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(run)).unwrap_or_else(
+        |payload| {
+            Err(Error::Tool {
+                name: name.to_string(),
+                message: format!("panicked: {}", panic_message(&*payload)),
+            })
+        },
+    )
+}
+
+/// The message a panic was raised with.
+pub fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause")
+}
+
+fn dispatch(
+    working_dir: &Path,
+    files: &FileTracker,
+    policy: &Policy,
     name: &str,
     input: &serde_json::Value,
 ) -> Result<String> {
     match name {
-        "read_file" => exec_read_file(working_dir, name, input),
-        "write_file" => exec_write_file(working_dir, name, input),
-        "edit_file" => exec_edit_file(working_dir, name, input),
-        "bash" => exec_bash(working_dir, name, input),
-        "ls" => exec_ls(working_dir, name, input),
-        "find" => exec_find(working_dir, name, input),
-        "grep" => exec_grep(working_dir, name, input),
+        "read_file" => exec_read_file(working_dir, files, policy, name, input),
+        "write_file" => {
+            exec_write_file(working_dir, files, policy, name, input)
+        }
+        "edit_file" => exec_edit_file(working_dir, files, policy, name, input),
+        "bash" => exec_bash(working_dir, files, policy, name, input),
+        "ls" => exec_ls(working_dir, policy, name, input),
+        "find" => exec_find(working_dir, policy, name, input),
+        "grep" => exec_grep(working_dir, policy, name, input),
+        "stat" => exec_stat(working_dir, policy, name, input),
+        "delete_file" => {
+            exec_delete_file(working_dir, files, policy, name, input)
+        }
+        "memory" => {
+            crate::memory::execute(&crate::memory::dir(working_dir), input)
+                .map_err(|message| Error::Tool {
+                    name: name.to_string(),
+                    message,
+                })
+        }
         _ => Err(Error::Tool {
             name: name.to_string(),
             message: "unknown tool".to_string(),
@@ -296,6 +908,8 @@ pub fn execute(
 
 fn exec_read_file(
     working_dir: &Path,
+    files: &FileTracker,
+    policy: &Policy,
     name: &str,
     input: &serde_json::Value,
 ) -> Result<String> {
@@ -306,8 +920,9 @@ fn exec_read_file(
     let offset = input["offset"].as_u64().map(|v| v as usize);
     let limit = input["limit"].as_u64().map(|v| v as usize);
 
-    let resolved = safe_path(working_dir, path)?;
+    let resolved = resolve_read(working_dir, policy, name, path)?;
     let content = fs::read_to_string(&resolved)?;
+    files.record(&resolved, content.as_bytes());
     let total_lines = content.lines().count();
 
     // Apply offset/limit
@@ -344,6 +959,8 @@ fn exec_read_file(
 
 fn exec_write_file(
     working_dir: &Path,
+    files: &FileTracker,
+    policy: &Policy,
     name: &str,
     input: &serde_json::Value,
 ) -> Result<String> {
@@ -356,15 +973,27 @@ fn exec_write_file(
         message: "missing content".to_string(),
     })?;
     let resolved = safe_path_for_write(working_dir, path)?;
+    files.check_fresh(name, &resolved, path)?;
+    policy.check_write(
+        name,
+        working_dir,
+        &resolved,
+        path,
+        Some(content.len()),
+    )?;
+    files.before_write(&resolved)?;
     if let Some(parent) = resolved.parent() {
         fs::create_dir_all(parent)?;
     }
     fs::write(&resolved, content)?;
+    files.record(&resolved, content.as_bytes());
     Ok(format!("Wrote {} bytes to {}", content.len(), path))
 }
 
 fn exec_edit_file(
     working_dir: &Path,
+    files: &FileTracker,
+    policy: &Policy,
     name: &str,
     input: &serde_json::Value,
 ) -> Result<String> {
@@ -381,13 +1010,17 @@ fn exec_edit_file(
         message: "missing new_string".to_string(),
     })?;
     let resolved = safe_path(working_dir, path)?;
+    files.check_fresh(name, &resolved, path)?;
+    policy.check_write(name, working_dir, &resolved, path, None)?;
     let content = fs::read_to_string(&resolved)?;
 
     // Try exact match first
     let count = content.matches(old).count();
     if count == 1 {
         let updated = content.replacen(old, new, 1);
+        files.before_write(&resolved)?;
         fs::write(&resolved, &updated)?;
+        files.record(&resolved, updated.as_bytes());
         let diff = edit_diff(path, &content, old, new);
         return Ok(format!("Edited {path}\n{diff}"));
     }
@@ -414,7 +1047,9 @@ fn exec_edit_file(
             let old_end = (orig_pos + old.len()).min(content.len());
             let orig_old = &content[orig_pos..old_end];
             let diff = edit_diff(path, &content, orig_old, new);
+            files.before_write(&resolved)?;
             fs::write(&resolved, &updated)?;
+            files.record(&resolved, updated.as_bytes());
             Ok(format!("Edited {path} (fuzzy match)\n{diff}"))
         }
         None => Err(Error::Tool {
@@ -495,6 +1130,8 @@ fn map_norm_offset_to_original(original: &str, norm_offset: usize) -> usize {
 
 fn exec_bash(
     working_dir: &Path,
+    files: &FileTracker,
+    policy: &Policy,
     name: &str,
     input: &serde_json::Value,
 ) -> Result<String> {
@@ -502,6 +1139,11 @@ fn exec_bash(
         name: name.to_string(),
         message: "missing command".to_string(),
     })?;
+    if files.trash.is_some()
+        && let Some(rm) = parse_plain_rm(command)
+    {
+        return run_rm(working_dir, files, policy, &rm);
+    }
     let timeout_secs = input["timeout"].as_u64().unwrap_or(120).clamp(1, 600);
     let output = run_bash(working_dir, command, timeout_secs)?;
 
@@ -514,13 +1156,173 @@ fn exec_bash(
     }
 }
 
+fn exec_delete_file(
+    working_dir: &Path,
+    files: &FileTracker,
+    policy: &Policy,
+    name: &str,
+    input: &serde_json::Value,
+) -> Result<String> {
+    let path = input["path"].as_str().ok_or_else(|| Error::Tool {
+        name: name.to_string(),
+        message: "missing path".to_string(),
+    })?;
+    let recursive = input["recursive"].as_bool().unwrap_or(false);
+    delete_path(working_dir, files, policy, name, path, recursive)
+}
+
+/// Move `path` to the session trash (or unlink it without
+/// one). Directories need `recursive`.
+fn delete_path(
+    working_dir: &Path,
+    files: &FileTracker,
+    policy: &Policy,
+    name: &str,
+    path: &str,
+    recursive: bool,
+) -> Result<String> {
+    let fail = |message: String| Error::Tool {
+        name: name.to_string(),
+        message,
+    };
+    // Resolve the parent only, so a symlink is deleted
+    // rather than its target
+    let resolved = safe_path_for_write(working_dir, path)?;
+    let meta = resolved
+        .symlink_metadata()
+        .map_err(|e| fail(format!("cannot remove {path}: {e}")))?;
+    if meta.is_dir() && !recursive {
+        return Err(fail(format!("{path} is a directory; set recursive")));
+    }
+    policy.check_write(name, working_dir, &resolved, path, None)?;
+    if meta.is_dir() {
+        check_tree(working_dir, policy, name, &resolved, path)?;
+    }
+    match files.trash() {
+        Some(trash) => {
+            let entry = trash.put(&resolved).map_err(|e| {
+                fail(format!("cannot move {path} to trash: {e}"))
+            })?;
+            Ok(format!("Moved {path} to trash (item {})", entry.id))
+        }
+        None => {
+            let removed = if meta.is_dir() {
+                fs::remove_dir_all(&resolved)
+            } else {
+                fs::remove_file(&resolved)
+            };
+            removed.map_err(|e| fail(format!("cannot remove {path}: {e}")))?;
+            Ok(format!("Deleted {path}"))
+        }
+    }
+}
+
+/// Run the write checks on everything under `dir` before it
+/// is deleted, so a sandbox rule or `protected_files` entry
+/// for a file inside still applies. Symlinks are checked but
+/// not followed.
+fn check_tree(
+    working_dir: &Path,
+    policy: &Policy,
+    name: &str,
+    dir: &Path,
+    display: &str,
+) -> Result<()> {
+    let entries = fs::read_dir(dir).map_err(|e| Error::Tool {
+        name: name.to_string(),
+        message: format!("cannot read {display}: {e}"),
+    })?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let shown =
+            format!("{display}/{}", entry.file_name().to_string_lossy());
+        policy.check_write(name, working_dir, &path, &shown, None)?;
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            check_tree(working_dir, policy, name, &path, &shown)?;
+        }
+    }
+    Ok(())
+}
+
+/// A bash `rm` simple enough to run through the trash.
+#[derive(Debug, PartialEq)]
+struct PlainRm {
+    recursive: bool,
+    force: bool,
+    paths: Vec<String>,
+}
+
+/// Recognize `rm [-rfv] path...` with no shell syntax (globs,
+/// quoting, variables, chaining). Anything else runs as is.
+fn parse_plain_rm(command: &str) -> Option<PlainRm> {
+    if command.contains(|c: char| ";|&<>$`()*?[]{}~'\"\\#\n".contains(c)) {
+        return None;
+    }
+    let mut words = command.split_whitespace();
+    if words.next()? != "rm" {
+        return None;
+    }
+    let mut rm = PlainRm {
+        recursive: false,
+        force: false,
+        paths: Vec::new(),
+    };
+    let mut flags_done = false;
+    for word in words {
+        if !flags_done && word == "--" {
+            flags_done = true;
+        } else if !flags_done && word.len() > 1 && word.starts_with('-') {
+            for c in word[1..].chars() {
+                match c {
+                    'r' | 'R' => rm.recursive = true,
+                    'f' => rm.force = true,
+                    'v' => {}
+                    _ => return None,
+                }
+            }
+        } else {
+            rm.paths.push(word.to_string());
+        }
+    }
+    (!rm.paths.is_empty()).then_some(rm)
+}
+
+fn run_rm(
+    working_dir: &Path,
+    files: &FileTracker,
+    policy: &Policy,
+    rm: &PlainRm,
+) -> Result<String> {
+    let mut out = Vec::new();
+    for path in &rm.paths {
+        let missing = safe_path_for_write(working_dir, path)
+            .is_ok_and(|p| p.symlink_metadata().is_err());
+        if missing && rm.force {
+            continue;
+        }
+        match delete_path(
+            working_dir,
+            files,
+            policy,
+            "bash",
+            path,
+            rm.recursive,
+        ) {
+            Ok(msg) => out.push(msg),
+            Err(e) => out.push(format!("rm: {e}")),
+        }
+    }
+    Ok(out.join("\n"))
+}
+
 fn exec_ls(
     working_dir: &Path,
+    policy: &Policy,
     name: &str,
     input: &serde_json::Value,
 ) -> Result<String> {
     let dir = if let Some(p) = input["path"].as_str() {
-        safe_path(working_dir, p)?
+        resolve_read(working_dir, policy, name, p)?
     } else {
         working_dir.to_path_buf()
     };
@@ -551,6 +1353,26 @@ fn exec_ls(
     // Sort case-insensitively
     entries.sort_by_key(|a| a.to_lowercase());
 
+    let show_ignored = input["ignored"].as_bool().unwrap_or(false);
+    let mut hidden: Vec<String> = Vec::new();
+    if let Some(git) = git_marks(&dir) {
+        let mut marked: Vec<String> = Vec::new();
+        for entry in entries {
+            if git.ignored.contains(&entry) {
+                if show_ignored {
+                    marked.push(format!("{entry}*"));
+                } else {
+                    hidden.push(entry);
+                }
+            } else if git.untracked.contains(&entry) {
+                marked.push(format!("{entry}?"));
+            } else {
+                marked.push(entry);
+            }
+        }
+        entries = marked;
+    }
+
     // Limit entries
     let total = entries.len();
     let mut output = String::new();
@@ -568,6 +1390,13 @@ fn exec_ls(
         bytes += entry.len() + 1;
     }
 
+    if !hidden.is_empty() {
+        let names = truncate_line(&hidden.join(", "), 200);
+        output.push_str(&format!(
+            "({} gitignored hidden: {names}; set ignored to list)\n",
+            hidden.len()
+        ));
+    }
     if output.is_empty() {
         output.push_str("(empty directory)");
     }
@@ -575,8 +1404,49 @@ fn exec_ls(
     Ok(output)
 }
 
+/// Entries directly in a directory that git ignores or
+/// doesn't track, named like `ls` prints them (`dir/`).
+struct GitMarks {
+    ignored: HashSet<String>,
+    untracked: HashSet<String>,
+}
+
+/// `None` outside a git work tree.
+fn git_marks(dir: &Path) -> Option<GitMarks> {
+    let ls_files = |extra: &[&str]| -> Option<HashSet<String>> {
+        let out = Command::new("git")
+            .args(["ls-files", "-z", "--others", "--exclude-standard"])
+            .arg("--directory")
+            .args(extra)
+            .args(["--", "."])
+            .current_dir(dir)
+            .stderr(std::process::Stdio::null())
+            .output()
+            .ok()?;
+        if !out.status.success() {
+            return None;
+        }
+        // Paths are relative to `dir`; only direct entries
+        // count, not untracked files deeper in tracked dirs
+        Some(
+            String::from_utf8_lossy(&out.stdout)
+                .split('\0')
+                .filter(|p| {
+                    !p.is_empty() && !p.trim_end_matches('/').contains('/')
+                })
+                .map(str::to_string)
+                .collect(),
+        )
+    };
+    Some(GitMarks {
+        ignored: ls_files(&["--ignored"])?,
+        untracked: ls_files(&[])?,
+    })
+}
+
 fn exec_find(
     working_dir: &Path,
+    policy: &Policy,
     name: &str,
     input: &serde_json::Value,
 ) -> Result<String> {
@@ -586,64 +1456,393 @@ fn exec_find(
     })?;
 
     let search_dir = if let Some(p) = input["path"].as_str() {
-        safe_path(working_dir, p)?
+        resolve_read(working_dir, policy, name, p)?
     } else {
         working_dir.to_path_buf()
     };
 
-    let result = Command::new("fd")
+    let spawned = Command::new("fd")
         .arg("--glob")
         .arg(pattern)
-        .arg("--max-results")
-        .arg("1000")
         .current_dir(&search_dir)
-        .output();
-
-    match result {
-        Ok(output) => {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            if !output.status.success() && !stderr.is_empty() {
-                return Ok(format!("stderr: {stderr}"));
-            }
-            if stdout.is_empty() {
-                return Ok("No files found matching pattern.".to_string());
-            }
-            let (out, _) =
-                truncate_head(&stdout, READ_MAX_LINES, READ_MAX_BYTES);
-            Ok(out)
-        }
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-            Ok("Error: fd not found. Install it: \
+            return Ok("Error: fd not found. Install it: \
                  https://github.com/sharkdp/fd"
-                .to_string())
+                .to_string());
         }
-        Err(e) => Err(Error::Tool {
-            name: name.to_string(),
-            message: format!("failed to run fd: {e}"),
-        }),
+        Err(e) => {
+            return Err(Error::Tool {
+                name: name.to_string(),
+                message: format!("failed to run fd: {e}"),
+            });
+        }
+    };
+    let stderr = child.stderr.take().map(|mut err| {
+        std::thread::spawn(move || {
+            let mut text = String::new();
+            let _ = err.read_to_string(&mut text);
+            text
+        })
+    });
+
+    let mut out = String::new();
+    let mut count = 0;
+    let stopped = read_lines(&mut child, |line| {
+        let line = line.trim_end_matches('\n');
+        if line.is_empty() || policy.hidden(working_dir, &search_dir.join(line))
+        {
+            return true;
+        }
+        if count == FIND_MAX_RESULTS || out.len() + line.len() >= READ_MAX_BYTES
+        {
+            return false;
+        }
+        out.push_str(line);
+        out.push('\n');
+        count += 1;
+        true
+    });
+    let status = child.wait();
+    let stderr = stderr.and_then(|t| t.join().ok()).unwrap_or_default();
+
+    if !stopped && status.is_ok_and(|s| !s.success()) && !stderr.is_empty() {
+        return Ok(format!("stderr: {stderr}"));
+    }
+    if out.is_empty() {
+        return Ok("No files found matching pattern.".to_string());
     }
+    if stopped {
+        let _ = write!(
+            out,
+            "... (stopped at {count} files; narrow the pattern or path \
+             for more)"
+        );
+    }
+    Ok(out)
 }
 
-fn exec_grep(
+/// Feed `child`'s stdout to `f` line by line until it returns
+/// `false` or the user interrupts, then kill the child rather
+/// than let it finish walking the tree. Returns whether it was
+/// stopped early; the caller still has to wait for it.
+fn read_lines(child: &mut Child, mut f: impl FnMut(&str) -> bool) -> bool {
+    let Some(stdout) = child.stdout.take() else {
+        return false;
+    };
+    let mut reader = BufReader::new(stdout);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf) {
+            Ok(0) | Err(_) => return false,
+            Ok(_) => {}
+        }
+        if signal::is_interrupted() || !f(&String::from_utf8_lossy(&buf)) {
+            let _ = child.kill();
+            return true;
+        }
+    }
+}
+
+struct FileStats {
+    path: String,
+    size: u64,
+    /// `None` for binary files.
+    lines: Option<usize>,
+    modified: Option<i64>,
+    language: String,
+}
+
+fn exec_stat(
     working_dir: &Path,
+    policy: &Policy,
     name: &str,
     input: &serde_json::Value,
 ) -> Result<String> {
-    let pattern = input["pattern"].as_str().ok_or_else(|| Error::Tool {
+    let path = input["path"].as_str().ok_or_else(|| Error::Tool {
         name: name.to_string(),
-        message: "missing pattern".to_string(),
+        message: "missing path".to_string(),
     })?;
 
-    let ctx = input["context"].as_u64().unwrap_or(2);
+    if !path.contains(['*', '?', '[']) {
+        let resolved = resolve_read(working_dir, policy, name, path)?;
+        if !resolved.is_dir() {
+            let stats = file_stats(&resolved, path)?;
+            return Ok(format_file_stats(&stats));
+        }
+    }
 
-    let search_path = if let Some(p) = input["path"].as_str() {
-        safe_path(working_dir, p)?
+    // Directory or glob: walk from the literal prefix
+    let (base, pattern) = split_glob(path);
+    let base_dir =
+        safe_path(working_dir, if base.is_empty() { "." } else { &base })?;
+    let mut found = Vec::new();
+    let mut truncated = false;
+    walk_files(&base_dir, &mut |p| {
+        if policy.hidden(working_dir, p) {
+            return true;
+        }
+        if found.len() >= STAT_MAX_FILES {
+            truncated = true;
+            return false;
+        }
+        let rel = p.strip_prefix(&base_dir).unwrap_or(p);
+        let rel_str = rel.to_string_lossy();
+        let matches = match &pattern {
+            None => true,
+            Some(pat) if pat.contains('/') => glob_match(pat, &rel_str),
+            Some(pat) => rel
+                .file_name()
+                .is_some_and(|n| glob_match(pat, &n.to_string_lossy())),
+        };
+        if matches {
+            let display = p.strip_prefix(working_dir).unwrap_or(p);
+            if let Ok(stats) = file_stats(p, &display.to_string_lossy()) {
+                found.push(stats);
+            }
+        }
+        true
+    });
+    if found.is_empty() {
+        return Ok(format!("No files match {path}"));
+    }
+    let mut out = format_aggregate(path, &mut found);
+    if truncated {
+        out.push_str(&format!("\n(stopped after {STAT_MAX_FILES} files)"));
+    }
+    Ok(out)
+}
+
+fn file_stats(path: &Path, display: &str) -> Result<FileStats> {
+    let meta = fs::metadata(path)?;
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64);
+    let data = fs::read(path)?;
+    let binary = data[..data.len().min(8192)].contains(&0);
+    let lines = if binary {
+        None
+    } else {
+        let newlines = data.iter().filter(|&&b| b == b'\n').count();
+        Some(newlines + usize::from(!data.is_empty() && !data.ends_with(b"\n")))
+    };
+    Ok(FileStats {
+        path: display.to_string(),
+        size: meta.len(),
+        lines,
+        modified,
+        language: language_name(path),
+    })
+}
+
+fn format_file_stats(s: &FileStats) -> String {
+    let lines = match s.lines {
+        Some(n) => n.to_string(),
+        None => "(binary)".to_string(),
+    };
+    let modified = s
+        .modified
+        .map(crate::session::iso_time)
+        .unwrap_or_else(|| "unknown".to_string());
+    format!(
+        "path: {}\nsize: {} bytes\nlines: {lines}\nmodified: {modified}\n\
+         language: {}",
+        s.path, s.size, s.language,
+    )
+}
+
+fn format_aggregate(path: &str, files: &mut [FileStats]) -> String {
+    let size: u64 = files.iter().map(|f| f.size).sum();
+    let lines: usize = files.iter().filter_map(|f| f.lines).sum();
+    let mut out = format!(
+        "{path}: {} files, {size} bytes, {lines} lines\n",
+        files.len()
+    );
+
+    let mut by_lang: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for f in files.iter() {
+        let e = by_lang.entry(&f.language).or_default();
+        e.0 += 1;
+        e.1 += f.lines.unwrap_or(0);
+    }
+    out.push_str("by language:\n");
+    for (lang, (count, lines)) in &by_lang {
+        out.push_str(&format!("  {lang}: {count} files, {lines} lines\n"));
+    }
+
+    files.sort_by(|a, b| b.lines.cmp(&a.lines).then(b.size.cmp(&a.size)));
+    out.push_str("largest:\n");
+    for f in files.iter().take(STAT_TOP_FILES) {
+        let lines = f
+            .lines
+            .map(|n| format!("{n} lines"))
+            .unwrap_or_else(|| "binary".to_string());
+        out.push_str(&format!("  {}  {lines}, {} bytes\n", f.path, f.size));
+    }
+    out.truncate(out.trim_end().len());
+    out
+}
+
+/// Split `src/**/*.rs` into the literal directory `src` and
+/// the pattern `**/*.rs`. A plain path has no pattern.
+fn split_glob(path: &str) -> (String, Option<String>) {
+    let parts: Vec<&str> = path.split('/').collect();
+    match parts.iter().position(|p| p.contains(['*', '?', '['])) {
+        Some(i) => (parts[..i].join("/"), Some(parts[i..].join("/"))),
+        None => (path.to_string(), None),
+    }
+}
+
+/// Visit regular files under `dir`, skipping hidden entries
+/// and build/dependency directories. Stops when `visit`
+/// returns false.
+pub(crate) fn walk_files(
+    dir: &Path,
+    visit: &mut dyn FnMut(&Path) -> bool,
+) -> bool {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return true;
+    };
+    let mut entries: Vec<_> = read_dir.flatten().collect();
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') {
+            continue;
+        }
+        let Ok(ft) = entry.file_type() else {
+            continue;
+        };
+        let path = entry.path();
+        if ft.is_dir() {
+            if matches!(name.as_ref(), "target" | "node_modules") {
+                continue;
+            }
+            if !walk_files(&path, visit) {
+                return false;
+            }
+        } else if ft.is_file() && !visit(&path) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Match `text` against a glob: `*` and `?` stay within one
+/// path component, `**` spans any number, `[abc]` is a class.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    glob_match_at(&p, &t)
+}
+
+fn glob_match_at(p: &[char], t: &[char]) -> bool {
+    match p.first() {
+        None => t.is_empty(),
+        Some('*') if p.get(1) == Some(&'*') && p.get(2) == Some(&'/') => {
+            // `**/` matches zero or more whole directories
+            (0..=t.len()).any(|i| {
+                (i == 0 || t[i - 1] == '/') && glob_match_at(&p[3..], &t[i..])
+            })
+        }
+        Some('*') if p.get(1) == Some(&'*') => {
+            (0..=t.len()).any(|i| glob_match_at(&p[2..], &t[i..]))
+        }
+        Some('*') => (0..=t.len())
+            .take_while(|&i| i == 0 || t[i - 1] != '/')
+            .any(|i| glob_match_at(&p[1..], &t[i..])),
+        Some('?') => {
+            !t.is_empty() && t[0] != '/' && glob_match_at(&p[1..], &t[1..])
+        }
+        Some('[') => {
+            let Some(close) = p.iter().position(|&c| c == ']') else {
+                return t.first() == Some(&'[')
+                    && glob_match_at(&p[1..], &t[1..]);
+            };
+            let Some(&c) = t.first() else {
+                return false;
+            };
+            let class = &p[1..close];
+            let (negate, class) = match class.first() {
+                Some('!' | '^') => (true, &class[1..]),
+                _ => (false, class),
+            };
+            let mut hit = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == '-' {
+                    hit |= (class[i]..=class[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    hit |= class[i] == c;
+                    i += 1;
+                }
+            }
+            hit != negate && glob_match_at(&p[close + 1..], &t[1..])
+        }
+        Some(&c) => t.first() == Some(&c) && glob_match_at(&p[1..], &t[1..]),
+    }
+}
+
+/// Human-readable language for a file, from its extension.
+fn language_name(path: &Path) -> String {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let name = match ext.as_str() {
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "ts" | "tsx" => "typescript",
+        "jsx" => "javascript",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hpp" => "c++",
+        "go" => "go",
+        "rb" => "ruby",
+        "java" => "java",
+        "sh" | "bash" => "shell",
+        "md" => "markdown",
+        "json" => "json",
+        "toml" => "toml",
+        "yaml" | "yml" => "yaml",
+        "html" | "htm" => "html",
+        "css" => "css",
+        "" => "unknown",
+        other => other,
+    };
+    name.to_string()
+}
+
+fn exec_grep(
+    working_dir: &Path,
+    policy: &Policy,
+    name: &str,
+    input: &serde_json::Value,
+) -> Result<String> {
+    let pattern = input["pattern"].as_str().ok_or_else(|| Error::Tool {
+        name: name.to_string(),
+        message: "missing pattern".to_string(),
+    })?;
+
+    let ctx = input["context"].as_u64().unwrap_or(2);
+
+    let search_path = if let Some(p) = input["path"].as_str() {
+        resolve_read(working_dir, policy, name, p)?
     } else {
         working_dir.to_path_buf()
     };
 
-    let result = Command::new("rg")
+    let spawned = Command::new("rg")
         .arg("--json")
         .arg("--max-count")
         .arg("100")
@@ -652,84 +1851,173 @@ fn exec_grep(
         .arg(pattern)
         .arg(&search_path)
         .current_dir(working_dir)
-        .output();
-
-    match result {
-        Ok(output) => {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            if stdout.is_empty() {
-                return Ok("No matches found.".to_string());
-            }
-            Ok(format_rg_json(&stdout, working_dir))
-        }
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-            Ok("Error: rg (ripgrep) not found. Install it: \
+            return Ok("Error: rg (ripgrep) not found. Install it: \
                  https://github.com/BurntSushi/ripgrep"
-                .to_string())
+                .to_string());
         }
-        Err(e) => Err(Error::Tool {
-            name: name.to_string(),
-            message: format!("failed to run rg: {e}"),
-        }),
-    }
+        Err(e) => {
+            return Err(Error::Tool {
+                name: name.to_string(),
+                message: format!("failed to run rg: {e}"),
+            });
+        }
+    };
+
+    // Format as rg writes, and stop it once the output is full
+    let hidden = |p: &Path| policy.hidden(working_dir, p);
+    let mut formatter = RgFormatter::new(working_dir, &hidden);
+    read_lines(&mut child, |line| formatter.push(line));
+    let _ = child.wait();
+    Ok(formatter.finish())
 }
 
-/// Parse ripgrep JSON output into a compact, readable
-/// format: `path\n  line_num:text`
-fn format_rg_json(json_output: &str, working_dir: &Path) -> String {
-    let mut output = String::new();
-    let mut current_path: Option<String> = None;
-    let wd = working_dir.to_string_lossy();
+/// One line of `rg --json`, borrowing from it where no
+/// unescaping is needed. Only match and context lines are
+/// read; other fields are skipped unparsed.
+#[derive(Deserialize)]
+struct RgLine<'a> {
+    #[serde(rename = "type", borrow)]
+    kind: Cow<'a, str>,
+    #[serde(default, borrow)]
+    data: Option<RgData<'a>>,
+}
+
+#[derive(Deserialize)]
+struct RgData<'a> {
+    #[serde(default, borrow)]
+    path: RgText<'a>,
+    #[serde(default, borrow)]
+    lines: RgText<'a>,
+    #[serde(default)]
+    line_number: Option<u64>,
+}
 
+#[derive(Default, Deserialize)]
+struct RgText<'a> {
+    /// Missing when rg reports non-UTF-8 as `bytes`.
+    #[serde(default, borrow)]
+    text: Option<Cow<'a, str>>,
+}
+
+/// Parse ripgrep JSON output into a compact, readable
+/// format: `path\n  line_num:text`. Files `hidden` accepts
+/// are left out.
+#[cfg(test)]
+fn format_rg_json(
+    json_output: &str,
+    working_dir: &Path,
+    hidden: &dyn Fn(&Path) -> bool,
+) -> String {
+    let mut formatter = RgFormatter::new(working_dir, hidden);
     for line in json_output.lines() {
-        let Ok(obj) = serde_json::from_str::<serde_json::Value>(line) else {
-            continue;
-        };
+        if !formatter.push(line) {
+            break;
+        }
+    }
+    formatter.finish()
+}
 
-        let msg_type = obj["type"].as_str().unwrap_or("");
-
-        match msg_type {
-            "match" | "context" => {
-                let data = &obj["data"];
-                let path_text = data["path"]["text"].as_str().unwrap_or("");
-                let line_number = data["line_number"].as_u64().unwrap_or(0);
-                let text = data["lines"]["text"]
-                    .as_str()
-                    .unwrap_or("")
-                    .trim_end_matches('\n');
-                let text = truncate_line(text, GREP_LINE_MAX_CHARS);
-
-                // Strip working dir prefix for display
-                let display_path = path_text
-                    .strip_prefix(&*wd)
-                    .map_or(path_text, |p| p.strip_prefix('/').unwrap_or(p));
-
-                // Print path header on change
-                let path_changed =
-                    current_path.as_deref() != Some(display_path);
-                if path_changed {
-                    if current_path.is_some() {
-                        output.push('\n');
-                    }
-                    output.push_str(display_path);
-                    output.push('\n');
-                    current_path = Some(display_path.to_string());
-                }
+/// Incremental `rg --json` formatting, one line at a time,
+/// up to the read limits.
+struct RgFormatter<'a> {
+    output: String,
+    lines: usize,
+    current_path: Option<String>,
+    wd: String,
+    hidden: &'a dyn Fn(&Path) -> bool,
+    full: bool,
+}
 
-                let sep = if msg_type == "match" { ':' } else { '-' };
-                output.push_str(&format!("  {line_number}{sep}{text}\n"));
-            }
-            _ => {}
+impl<'a> RgFormatter<'a> {
+    fn new(working_dir: &Path, hidden: &'a dyn Fn(&Path) -> bool) -> Self {
+        RgFormatter {
+            output: String::new(),
+            lines: 0,
+            current_path: None,
+            wd: working_dir.to_string_lossy().into_owned(),
+            hidden,
+            full: false,
         }
     }
 
-    if output.is_empty() {
-        return "No matches found.".to_string();
+    /// Add one line of rg output. Returns `false` once the
+    /// output is full and the rest can be dropped.
+    fn push(&mut self, line: &str) -> bool {
+        let Ok(RgLine {
+            kind,
+            data: Some(data),
+        }) = serde_json::from_str::<RgLine>(line)
+        else {
+            return true;
+        };
+        if kind != "match" && kind != "context" {
+            return true;
+        }
+        let path_text = data.path.text.as_deref().unwrap_or("");
+        if (self.hidden)(Path::new(path_text)) {
+            return true;
+        }
+        let line_number = data.line_number.unwrap_or(0);
+        let text = data
+            .lines
+            .text
+            .as_deref()
+            .unwrap_or("")
+            .trim_end_matches('\n');
+        let text = truncate_line(text, GREP_LINE_MAX_CHARS);
+
+        // Strip working dir prefix for display
+        let display_path = path_text
+            .strip_prefix(&*self.wd)
+            .map_or(path_text, |p| p.strip_prefix('/').unwrap_or(p));
+
+        let before = (self.output.len(), self.lines);
+        // Print path header on change
+        if self.current_path.as_deref() != Some(display_path) {
+            if self.current_path.is_some() {
+                self.output.push('\n');
+                self.lines += 1;
+            }
+            self.output.push_str(display_path);
+            self.output.push('\n');
+            self.lines += 1;
+            self.current_path = Some(display_path.to_string());
+        }
+        let sep = if kind == "match" { ':' } else { '-' };
+        let _ = writeln!(self.output, "  {line_number}{sep}{text}");
+        self.lines += 1;
+
+        if self.lines > READ_MAX_LINES || self.output.len() > READ_MAX_BYTES {
+            self.output.truncate(before.0);
+            self.lines = before.1;
+            self.full = true;
+            return false;
+        }
+        true
     }
 
-    // Apply truncation
-    let (out, _) = truncate_head(&output, READ_MAX_LINES, READ_MAX_BYTES);
-    out
+    fn finish(self) -> String {
+        if self.output.is_empty() {
+            return "No matches found.".to_string();
+        }
+        let mut out = self.output;
+        if self.full {
+            let _ = write!(
+                out,
+                "... (stopped at {} lines; narrow the pattern or path \
+                 for more)",
+                self.lines
+            );
+        }
+        out
+    }
 }
 
 #[cfg(target_os = "openbsd")]
@@ -774,6 +2062,17 @@ pub fn run_bash(
     command: &str,
     timeout_secs: u64,
 ) -> Result<String> {
+    run_command(working_dir, command, timeout_secs).map(|(out, _)| out)
+}
+
+/// Run `command` in the shell and return its formatted output
+/// and whether it exited successfully (timeouts count as
+/// failure).
+fn run_command(
+    working_dir: &Path,
+    command: &str,
+    timeout_secs: u64,
+) -> Result<(String, bool)> {
     let child = shell_command()
         .arg("-c")
         .arg(command)
@@ -793,7 +2092,10 @@ pub fn run_bash(
     let start = std::time::Instant::now();
     loop {
         match rx.recv_timeout(Duration::from_millis(200)) {
-            Ok(result) => return Ok(format_output(&result?)),
+            Ok(result) => {
+                let output = result?;
+                return Ok((format_output(&output), output.status.success()));
+            }
             Err(mpsc::RecvTimeoutError::Timeout) => {
                 if signal::is_interrupted() {
                     unsafe {
@@ -819,12 +2121,15 @@ pub fn run_bash(
                                 "(timed out after \
                                  {timeout_secs}s)"
                             ));
-                            return Ok(text);
+                            return Ok((text, false));
                         }
                         _ => {
-                            return Ok(format!(
-                                "(timed out after \
-                                 {timeout_secs}s)"
+                            return Ok((
+                                format!(
+                                    "(timed out after \
+                                     {timeout_secs}s)"
+                                ),
+                                false,
                             ));
                         }
                     }
@@ -840,6 +2145,45 @@ pub fn run_bash(
     }
 }
 
+const HOOK_TIMEOUT: u64 = 60;
+
+/// Run the configured post-edit command for `path`'s extension.
+/// `{path}` in the command is replaced by the quoted file path.
+/// Returns feedback for the model when the command fails or
+/// rewrites the file; `None` when there is nothing to report.
+pub fn run_post_edit_hook(
+    working_dir: &Path,
+    hooks: &HashMap<String, String>,
+    path: &Path,
+) -> Option<String> {
+    let ext = path.extension()?.to_str()?;
+    let template = hooks.get(ext)?;
+    let quoted = shell_quote(&path.to_string_lossy());
+    let command = template.replace("{path}", &quoted);
+
+    let before = fs::read(path).ok();
+    let (output, ok) = match run_command(working_dir, &command, HOOK_TIMEOUT) {
+        Ok(r) => r,
+        Err(e) => return Some(format!("post-edit hook `{command}`: {e}")),
+    };
+    if !ok {
+        let (output, _) = truncate_tail(&output, 100, 10_000);
+        return Some(format!("post-edit hook `{command}` failed:\n{output}"));
+    }
+    if fs::read(path).ok() != before {
+        return Some(format!(
+            "post-edit hook `{command}` rewrote the file; \
+             re-read it before further edits"
+        ));
+    }
+    None
+}
+
+/// Quote `s` for safe interpolation into a POSIX shell command.
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -896,6 +2240,22 @@ mod tests {
         fs::remove_dir_all(&dir).unwrap();
     }
 
+    #[test]
+    fn panics_become_tool_errors() {
+        // The default hook prints the panic; the hook is
+        // process-wide, so leave it to the other tests
+        let result: Result<()> = catching("edit_file", || {
+            let lines = ["only"];
+            panic!("index out of bounds: {}", lines.len() + 1)
+        });
+        let Err(Error::Tool { name, message }) = result else {
+            panic!("expected a tool error");
+        };
+        assert_eq!(name, "edit_file");
+        assert_eq!(message, "panicked: index out of bounds: 2");
+        assert_eq!(catching("ls", || Ok(1)).ok(), Some(1));
+    }
+
     #[test]
     fn test_bash_timeout() {
         use crate::signal;
@@ -904,6 +2264,8 @@ mod tests {
         let dir = std::env::temp_dir();
         let result = execute(
             &dir,
+            &FileTracker::default(),
+            &Policy::default(),
             "bash",
             &serde_json::json!({
                 "command": "sleep 60",
@@ -922,6 +2284,8 @@ mod tests {
         let dir = std::env::temp_dir();
         let result = execute(
             &dir,
+            &FileTracker::default(),
+            &Policy::default(),
             "bash",
             &serde_json::json!({
                 "command": "echo hello",
@@ -935,8 +2299,13 @@ mod tests {
     #[test]
     fn test_bash_default_timeout() {
         let dir = std::env::temp_dir();
-        let result =
-            execute(&dir, "bash", &serde_json::json!({ "command": "echo ok" }));
+        let result = execute(
+            &dir,
+            &FileTracker::default(),
+            &Policy::default(),
+            "bash",
+            &serde_json::json!({ "command": "echo ok" }),
+        );
         assert!(result.unwrap().contains("ok"));
     }
 
@@ -973,6 +2342,8 @@ mod tests {
 
         let result = execute(
             &dir,
+            &FileTracker::default(),
+            &Policy::default(),
             "read_file",
             &serde_json::json!({
                 "path": "test.txt",
@@ -998,6 +2369,8 @@ mod tests {
 
         let result = execute(
             &dir,
+            &FileTracker::default(),
+            &Policy::default(),
             "read_file",
             &serde_json::json!({ "path": "test.txt" }),
         );
@@ -1017,6 +2390,8 @@ mod tests {
 
         let result = execute(
             &dir,
+            &FileTracker::default(),
+            &Policy::default(),
             "edit_file",
             &serde_json::json!({
                 "path": "test.txt",
@@ -1036,6 +2411,57 @@ mod tests {
         fs::remove_dir_all(&dir).unwrap();
     }
 
+    #[test]
+    fn test_originals_keep_first_content() {
+        let dir = std::env::temp_dir().join("tapir_originals");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "one\n").unwrap();
+        let files = FileTracker::default();
+        for content in ["two\n", "three\n"] {
+            execute(
+                &dir,
+                &files,
+                &Policy::default(),
+                "write_file",
+                &serde_json::json!({"path": "a.txt", "content": content}),
+            )
+            .unwrap();
+        }
+        execute(
+            &dir,
+            &files,
+            &Policy::default(),
+            "write_file",
+            &serde_json::json!({"path": "new.txt", "content": "x"}),
+        )
+        .unwrap();
+
+        let originals = files.originals();
+        assert_eq!(originals.len(), 2);
+        assert_eq!(originals[0].1.as_deref(), Some(&b"one\n"[..]));
+        assert_eq!(originals[1].1, None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_edits_record_nothing() {
+        let dir = std::env::temp_dir().join("tapir_edit_failed");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "x\nx\n").unwrap();
+        let files = FileTracker::default();
+        for old in ["x", "missing"] {
+            let edit = serde_json::json!({
+                "path": "a.txt",
+                "old_string": old,
+                "new_string": "y"
+            });
+            execute(&dir, &files, &Policy::default(), "edit_file", &edit)
+                .unwrap_err();
+        }
+        assert!(files.originals().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn test_edit_file_fuzzy_match() {
         let dir = std::env::temp_dir().join("tapir_edit_fuzzy");
@@ -1046,6 +2472,8 @@ mod tests {
 
         let result = execute(
             &dir,
+            &FileTracker::default(),
+            &Policy::default(),
             "edit_file",
             &serde_json::json!({
                 "path": "test.txt",
@@ -1065,6 +2493,389 @@ mod tests {
         fs::remove_dir_all(&dir).unwrap();
     }
 
+    #[test]
+    fn test_edit_file_refuses_stale_read() {
+        let dir = std::env::temp_dir().join("tapir_edit_stale");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("test.txt");
+        fs::write(&file, "one\n").unwrap();
+        let files = FileTracker::default();
+        let read = serde_json::json!({ "path": "test.txt" });
+        let edit = serde_json::json!({
+            "path": "test.txt",
+            "old_string": "one",
+            "new_string": "two"
+        });
+
+        execute(&dir, &files, &Policy::default(), "read_file", &read).unwrap();
+        // Someone else edits the file
+        fs::write(&file, "one\nextra\n").unwrap();
+        let err = execute(&dir, &files, &Policy::default(), "edit_file", &edit)
+            .unwrap_err();
+        assert!(err.to_string().contains("changed on disk"), "got: {err}");
+        let write = serde_json::json!({ "path": "test.txt", "content": "x" });
+        assert!(
+            execute(&dir, &files, &Policy::default(), "write_file", &write)
+                .is_err()
+        );
+
+        // Re-reading clears the guard; our own edits keep it fresh
+        execute(&dir, &files, &Policy::default(), "read_file", &read).unwrap();
+        execute(&dir, &files, &Policy::default(), "edit_file", &edit).unwrap();
+        let edit2 = serde_json::json!({
+            "path": "test.txt",
+            "old_string": "extra",
+            "new_string": "more"
+        });
+        execute(&dir, &files, &Policy::default(), "edit_file", &edit2).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "two\nmore\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_file_unread_file_allowed() {
+        let dir = std::env::temp_dir().join("tapir_write_unread");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "old").unwrap();
+        let files = FileTracker::default();
+        let write = serde_json::json!({ "path": "a.txt", "content": "new" });
+        assert!(
+            execute(&dir, &files, &Policy::default(), "write_file", &write)
+                .is_ok()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_plain_rm() {
+        assert_eq!(
+            parse_plain_rm("rm -rf build  out.txt"),
+            Some(PlainRm {
+                recursive: true,
+                force: true,
+                paths: vec!["build".into(), "out.txt".into()],
+            })
+        );
+        assert_eq!(parse_plain_rm("rm -- -x").unwrap().paths, vec!["-x"]);
+        assert_eq!(parse_plain_rm("rm"), None);
+        assert_eq!(parse_plain_rm("rm *.o"), None);
+        assert_eq!(parse_plain_rm("rm a && ls"), None);
+        assert_eq!(parse_plain_rm("rm -i a"), None);
+        assert_eq!(parse_plain_rm("rmdir a"), None);
+    }
+
+    #[test]
+    fn test_delete_moves_to_trash() {
+        let dir = std::env::temp_dir().join("tapir_delete_trash");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("d")).unwrap();
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        fs::write(dir.join("d/b.txt"), "b").unwrap();
+        let files = FileTracker::default().with_trash(Trash::new(
+            &dir.join(".home"),
+            "s1",
+            u64::MAX,
+        ));
+        let policy = Policy::default();
+
+        let del = serde_json::json!({"path": "d"});
+        assert!(execute(&dir, &files, &policy, "delete_file", &del).is_err());
+        let del = serde_json::json!({"path": "d", "recursive": true});
+        execute(&dir, &files, &policy, "delete_file", &del).unwrap();
+        let rm = serde_json::json!({"command": "rm -f a.txt missing"});
+        let out = execute(&dir, &files, &policy, "bash", &rm).unwrap();
+        assert_eq!(out, "Moved a.txt to trash (item 2)");
+        assert!(!dir.join("a.txt").exists() && !dir.join("d").exists());
+
+        let trash = files.trash().unwrap();
+        assert_eq!(trash.list().len(), 2);
+        trash.restore(1).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("d/b.txt")).unwrap(), "b");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rm_asks_for_protected_files_inside() {
+        let dir = std::env::temp_dir().join("tapir_rm_protected");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("certs")).unwrap();
+        fs::write(dir.join("certs/server.pem"), "key").unwrap();
+        let files = FileTracker::default().with_trash(Trash::new(
+            &dir.join(".home"),
+            "s1",
+            u64::MAX,
+        ));
+        let rm = serde_json::json!({"command": "rm -r certs"});
+
+        let deny = Policy {
+            confirm: |_| false,
+            ..Policy::default()
+        };
+        let out = execute(&dir, &files, &deny, "bash", &rm).unwrap();
+        assert!(out.contains("protected file certs/server.pem"), "{out}");
+        assert!(dir.join("certs/server.pem").exists());
+
+        let allow = Policy {
+            confirm: |_| true,
+            ..Policy::default()
+        };
+        execute(&dir, &files, &allow, "bash", &rm).unwrap();
+        assert!(!dir.join("certs").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_delete_checks_what_the_directory_holds() {
+        let dir = std::env::temp_dir().join("tapir_delete_tree");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("d/deep")).unwrap();
+        fs::write(dir.join("d/a.txt"), "a").unwrap();
+        fs::write(dir.join("d/deep/secret.txt"), "s").unwrap();
+        let files = FileTracker::default();
+        let mut policy = Policy {
+            rules: vec![PathRule {
+                path: "secret.txt".into(),
+                read: None,
+                write: Some(Access::Deny),
+            }],
+            ..Policy::default()
+        };
+
+        let del = serde_json::json!({"path": "d", "recursive": true});
+        let err =
+            execute(&dir, &files, &policy, "delete_file", &del).unwrap_err();
+        assert!(err.to_string().contains("d/deep/secret.txt"), "{err}");
+        assert!(dir.join("d/a.txt").exists());
+
+        policy.rules.clear();
+        execute(&dir, &files, &policy, "delete_file", &del).unwrap();
+        assert!(!dir.join("d").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_file_guard() {
+        let dir = std::env::temp_dir().join("tapir_write_guard");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "x".repeat(4000)).unwrap();
+        let files = FileTracker::default();
+        let deny = Policy {
+            max_write_bytes: 100,
+            confirm: |_| false,
+            ..Policy::default()
+        };
+
+        // Too large
+        let big =
+            serde_json::json!({"path": "b.txt", "content": "y".repeat(101)});
+        let err = execute(&dir, &files, &deny, "write_file", &big).unwrap_err();
+        assert!(err.to_string().contains("exceeds"), "{err}");
+        assert!(!dir.join("b.txt").exists());
+
+        // Shrinks 4000 -> 10 bytes
+        let small =
+            serde_json::json!({"path": "a.txt", "content": "short text"});
+        let err =
+            execute(&dir, &files, &deny, "write_file", &small).unwrap_err();
+        assert!(err.to_string().contains("edit_file"), "{err}");
+        assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap().len(), 4000);
+
+        let allow = Policy {
+            confirm: |_| true,
+            ..deny
+        };
+        execute(&dir, &files, &allow, "write_file", &small).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("a.txt")).unwrap(),
+            "short text"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_protected_files() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static ASKED: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join("tapir_protected");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::create_dir_all(dir.join("certs")).unwrap();
+        fs::write(dir.join("Cargo.lock"), "old").unwrap();
+        let files = FileTracker::default();
+        let write =
+            |path: &str| serde_json::json!({"path": path, "content": "new"});
+
+        let deny = Policy {
+            confirm: |_| false,
+            ..Policy::default()
+        };
+        for path in [".env", "certs/../Cargo.lock", ".git/config"] {
+            let err = execute(&dir, &files, &deny, "write_file", &write(path))
+                .unwrap_err();
+            assert!(err.to_string().contains("protected"), "{err}");
+        }
+        execute(&dir, &files, &deny, "write_file", &write("a.txt")).unwrap();
+
+        // Approval sticks for the file
+        let allow = Policy {
+            confirm: |_| {
+                ASKED.fetch_add(1, Ordering::SeqCst);
+                true
+            },
+            ..Policy::default()
+        };
+        for _ in 0..2 {
+            execute(&dir, &files, &allow, "write_file", &write(".env"))
+                .unwrap();
+        }
+        assert_eq!(ASKED.load(Ordering::SeqCst), 1);
+
+        // A sandbox rule takes precedence
+        let ruled = Policy {
+            rules: vec![PathRule {
+                path: "*.lock".to_string(),
+                read: None,
+                write: Some(Access::Allow),
+            }],
+            ..deny
+        };
+        execute(&dir, &files, &ruled, "write_file", &write("Cargo.lock"))
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stat_file() {
+        let dir = std::env::temp_dir().join("tapir_stat_file");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.rs"), "fn main() {}\n\nfoo").unwrap();
+        let out = execute(
+            &dir,
+            &FileTracker::default(),
+            &Policy::default(),
+            "stat",
+            &serde_json::json!({"path": "a.rs"}),
+        )
+        .unwrap();
+        assert!(out.contains("size: 17 bytes"), "{out}");
+        assert!(out.contains("lines: 3"));
+        assert!(out.contains("language: rust"));
+        assert!(out.contains("modified: 20"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stat_glob_aggregate() {
+        let dir = std::env::temp_dir().join("tapir_stat_glob");
+        fs::create_dir_all(dir.join("src/sub")).unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join("src/a.rs"), "1\n2\n").unwrap();
+        fs::write(dir.join("src/sub/b.rs"), "1\n2\n3\n").unwrap();
+        fs::write(dir.join("src/c.txt"), "x\n").unwrap();
+        fs::write(dir.join(".git/d.rs"), "x\n").unwrap();
+        let files = FileTracker::default();
+        let run = |path: &str| {
+            execute(
+                &dir,
+                &files,
+                &Policy::default(),
+                "stat",
+                &serde_json::json!({"path": path}),
+            )
+            .unwrap()
+        };
+
+        let out = run("src/**/*.rs");
+        assert!(out.starts_with("src/**/*.rs: 2 files, 10 bytes, 5 lines"));
+        assert!(out.contains("rust: 2 files, 5 lines"));
+        assert!(out.contains("src/sub/b.rs  3 lines"));
+        assert!(run("*.rs").contains("2 files"));
+        assert!(run("src").contains("3 files"));
+        assert!(run("*.go").contains("No files match"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sandbox_rules() {
+        let dir = std::env::temp_dir().join("tapir_sandbox_rules");
+        let _ = fs::remove_dir_all(&dir);
+        for sub in ["docs", "migrations", "secrets"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        fs::write(dir.join("secrets/key.txt"), "k").unwrap();
+        fs::write(dir.join("migrations/001.sql"), "create").unwrap();
+        fs::write(dir.join("id.pem"), "p").unwrap();
+        let rule = |path: &str, read, write| PathRule {
+            path: path.to_string(),
+            read,
+            write,
+        };
+        let policy = Policy {
+            max_write_bytes: 10,
+            rules: vec![
+                rule("docs/**", None, Some(Access::Allow)),
+                rule("migrations/**", None, Some(Access::Prompt)),
+                rule("secrets/**", Some(Access::Deny), Some(Access::Deny)),
+                rule("*.pem", Some(Access::Deny), None),
+            ],
+            confirm: |_| false,
+            ..Policy::default()
+        };
+        let files = FileTracker::default();
+        let run = |name: &str, input: serde_json::Value| {
+            execute(&dir, &files, &policy, name, &input)
+        };
+
+        let err =
+            run("read_file", serde_json::json!({"path": "secrets/key.txt"}))
+                .unwrap_err();
+        assert!(err.to_string().contains("denied"), "{err}");
+        assert!(run("ls", serde_json::json!({"path": "secrets"})).is_err());
+        assert!(
+            run("read_file", serde_json::json!({"path": "id.pem"})).is_err()
+        );
+        assert!(
+            run(
+                "write_file",
+                serde_json::json!({"path": "secrets/new", "content": "x"})
+            )
+            .is_err()
+        );
+
+        // Over max_write_bytes, but docs/** is allowed outright
+        let long = "long enough content";
+        run(
+            "write_file",
+            serde_json::json!({"path": "docs/a.md", "content": long}),
+        )
+        .unwrap();
+        let err = run(
+            "edit_file",
+            serde_json::json!({"path": "migrations/001.sql",
+                "old_string": "create", "new_string": "drop"}),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("declined"), "{err}");
+
+        let out = run("stat", serde_json::json!({"path": "."})).unwrap();
+        assert!(out.contains("2 files"), "{out}");
+        assert!(!out.contains("key.txt") && !out.contains("id.pem"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.rs", "main.rs"));
+        assert!(!glob_match("*.rs", "src/main.rs"));
+        assert!(glob_match("**/*.rs", "main.rs"));
+        assert!(glob_match("**/*.rs", "a/b/main.rs"));
+        assert!(glob_match("src/**", "src/a/b"));
+        assert!(glob_match("test_?.[ch]", "test_1.h"));
+        assert!(!glob_match("test_?.[!ch]", "test_1.h"));
+        assert!(glob_match("[a-c]x", "bx"));
+    }
+
     #[test]
     fn test_ls_basic() {
         let dir = std::env::temp_dir().join("tapir_ls");
@@ -1074,7 +2885,13 @@ mod tests {
         fs::write(dir.join("b.txt"), "").unwrap();
         fs::create_dir_all(dir.join("subdir")).unwrap();
 
-        let result = execute(&dir, "ls", &serde_json::json!({}));
+        let result = execute(
+            &dir,
+            &FileTracker::default(),
+            &Policy::default(),
+            "ls",
+            &serde_json::json!({}),
+        );
         let output = result.unwrap();
         assert!(output.contains("a.txt"));
         assert!(output.contains("b.txt"));
@@ -1083,6 +2900,49 @@ mod tests {
         fs::remove_dir_all(&dir).unwrap();
     }
 
+    #[test]
+    fn test_ls_git_marks() {
+        let dir = std::env::temp_dir().join("tapir_ls_git");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("target/debug")).unwrap();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join(".gitignore"), "target/\n").unwrap();
+        fs::write(dir.join("src/main.rs"), "").unwrap();
+        fs::write(dir.join("src/new.rs"), "").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .args(args)
+                .current_dir(&dir)
+                .output()
+                .unwrap()
+        };
+        git(&["init", "-q"]);
+        git(&["add", ".gitignore", "src/main.rs"]);
+
+        let ls = |input: serde_json::Value| {
+            execute(
+                &dir,
+                &FileTracker::default(),
+                &Policy::default(),
+                "ls",
+                &input,
+            )
+            .unwrap()
+        };
+        let out = ls(serde_json::json!({}));
+        assert_eq!(
+            out,
+            ".git/\n.gitignore\nnotes.txt?\nsrc/\n\
+             (1 gitignored hidden: target/; set ignored to list)\n"
+        );
+        let out = ls(serde_json::json!({"ignored": true}));
+        assert!(out.contains("target/*\n"), "{out}");
+        let out = ls(serde_json::json!({"path": "src"}));
+        assert_eq!(out, "main.rs\nnew.rs?\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn test_fuzzy_replace_whitespace() {
         let content = "hello   world";
@@ -1104,11 +2964,141 @@ mod tests {
         assert_eq!(result, None);
     }
 
+    fn hooks(ext: &str, cmd: &str) -> HashMap<String, String> {
+        HashMap::from([(ext.to_string(), cmd.to_string())])
+    }
+
+    #[test]
+    fn test_post_edit_hook_reports_failure() {
+        let dir = std::env::temp_dir().join("tapir_hook_fail");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.txt");
+        fs::write(&file, "x").unwrap();
+
+        let out = run_post_edit_hook(
+            &dir,
+            &hooks("txt", "echo bad {path} >&2; exit 3"),
+            &file,
+        )
+        .unwrap();
+        assert!(out.contains("failed"), "got: {out}");
+        assert!(out.contains("a.txt"));
+        assert!(out.contains("exit code: 3"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_post_edit_hook_silent_on_success() {
+        let dir = std::env::temp_dir().join("tapir_hook_ok");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.txt");
+        fs::write(&file, "x").unwrap();
+
+        assert!(
+            run_post_edit_hook(&dir, &hooks("txt", "true"), &file).is_none()
+        );
+        assert!(
+            run_post_edit_hook(&dir, &hooks("rs", "false"), &file).is_none()
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_post_edit_hook_notes_rewrite() {
+        let dir = std::env::temp_dir().join("tapir_hook_rewrite");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("my file.txt");
+        fs::write(&file, "x").unwrap();
+
+        let out =
+            run_post_edit_hook(&dir, &hooks("txt", "echo y > {path}"), &file)
+                .unwrap();
+        assert!(out.contains("rewrote"), "got: {out}");
+        assert_eq!(fs::read_to_string(&file).unwrap(), "y\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_format_rg_json() {
+        let mut json = String::new();
+        for i in 0..20_000 {
+            let kind = if i % 3 == 0 { "context" } else { "match" };
+            json.push_str(&format!(
+                "{{\"type\":\"{kind}\",\"data\":{{\"path\":{{\"text\":\
+                 \"/w/src/file_{}.rs\"}},\"lines\":{{\"text\":\
+                 \"    let x_{i} = \\\"needle\\\"; // \\u00e9t\u{e9}\\n\"}},\
+                 \"line_number\":{i},\"absolute_offset\":{},\
+                 \"submatches\":[{{\"match\":{{\"text\":\"needle\"}},\
+                 \"start\":13,\"end\":19}}]}}}}\n",
+                i / 50,
+                i * 40,
+            ));
+            if i % 50 == 49 {
+                json.push_str(
+                    "{\"type\":\"end\",\"data\":{\"path\":{\"text\":\
+                     \"x\"},\"stats\":{}}}\n",
+                );
+            }
+        }
+        let wd = Path::new("/w");
+        let out = format_rg_json(&json, wd, &|_| false);
+        assert!(
+            out.starts_with(
+                "src/file_0.rs\n  0-    let x_0 = \"needle\"; // ét"
+            )
+        );
+        crate::testing::bench("format_rg_json", json.len(), || {
+            std::hint::black_box(format_rg_json(&json, wd, &|_| false));
+        });
+    }
+
+    #[test]
+    fn read_lines_kills_the_child_when_done() {
+        let mut child =
+            Command::new("yes").stdout(Stdio::piped()).spawn().unwrap();
+        let mut seen = 0;
+        let stopped = read_lines(&mut child, |line| {
+            assert_eq!(line, "y\n");
+            seen += 1;
+            seen < 3
+        });
+        assert!(stopped);
+        assert_eq!(seen, 3);
+        assert!(!child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn rg_formatter_stops_when_full() {
+        let line = r#"{"type":"match","data":{"path":{"text":"/w/a.rs"},"lines":{"text":"hit\n"},"line_number":7}}"#;
+        let hidden = |_: &Path| false;
+        let mut formatter = RgFormatter::new(Path::new("/w"), &hidden);
+        let pushed = (0..READ_MAX_LINES * 2)
+            .take_while(|_| formatter.push(line))
+            .count();
+        assert_eq!(pushed, READ_MAX_LINES - 1);
+        let out = formatter.finish();
+        assert!(out.starts_with("a.rs\n  7:hit\n"));
+        assert!(out.ends_with(&format!(
+            "... (stopped at {READ_MAX_LINES} lines; \
+             narrow the pattern or path for more)"
+        )));
+    }
+
     #[test]
     fn test_format_rg_json() {
         let json = r#"{"type":"match","data":{"path":{"text":"/tmp/test.rs"},"lines":{"text":"fn main() {\n"},"line_number":1}}"#;
         let wd = Path::new("/tmp");
-        let result = format_rg_json(json, wd);
+        let result = format_rg_json(json, wd, &|_| false);
         assert!(result.contains("test.rs"));
         assert!(result.contains("1:fn main()"));
     }
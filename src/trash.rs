@@ -0,0 +1,257 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+/// Files and directories deleted by tools, moved to
+/// `~/.tapir/trash/<session>/` so they can be restored. Each
+/// item is stored as `<id>` next to an `index.jsonl`.
+pub struct Trash {
+    root: PathBuf,
+    dir: PathBuf,
+    /// Total bytes kept across all sessions; the oldest items
+    /// go first once it is exceeded.
+    quota: u64,
+    lock: Mutex<()>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: u32,
+    /// Where the item was deleted from.
+    pub path: PathBuf,
+    pub size: u64,
+    pub time: String,
+}
+
+impl Trash {
+    pub fn new(tapir_dir: &Path, session_id: &str, quota: u64) -> Self {
+        let root = tapir_dir.join("trash");
+        Trash {
+            dir: root.join(session_id),
+            root,
+            quota,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Move `path` into the trash, then evict old items if the
+    /// quota is exceeded.
+    pub fn put(&self, path: &Path) -> io::Result<Entry> {
+        let _guard = self.lock.lock();
+        fs::create_dir_all(&self.dir)?;
+        let entries = load(&self.dir);
+        let entry = Entry {
+            id: entries.last().map_or(1, |e| e.id + 1),
+            path: path.to_path_buf(),
+            size: disk_size(path),
+            time: crate::session::iso_now(),
+        };
+        move_path(path, &item_path(&self.dir, entry.id))?;
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join("index.jsonl"))?;
+        writeln!(f, "{}", serde_json::to_string(&entry)?)?;
+        self.enforce_quota(&entry);
+        Ok(entry)
+    }
+
+    /// Items deleted in this session, oldest first.
+    pub fn list(&self) -> Vec<Entry> {
+        let _guard = self.lock.lock();
+        load(&self.dir)
+    }
+
+    /// Move item `id` back to where it was deleted from. Fails
+    /// rather than overwrite something created there since.
+    pub fn restore(&self, id: u32) -> io::Result<PathBuf> {
+        let _guard = self.lock.lock();
+        let mut entries = load(&self.dir);
+        let pos = entries.iter().position(|e| e.id == id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no item {id}"))
+        })?;
+        let entry = &entries[pos];
+        if entry.path.symlink_metadata().is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists", entry.path.display()),
+            ));
+        }
+        if let Some(parent) = entry.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        move_path(&item_path(&self.dir, id), &entry.path)?;
+        let entry = entries.remove(pos);
+        save(&self.dir, &entries)?;
+        Ok(entry.path)
+    }
+
+    /// Drop the oldest items of every session until the total
+    /// fits the quota. `keep` (the item just added) is never
+    /// dropped.
+    fn enforce_quota(&self, keep: &Entry) {
+        let Ok(read_dir) = fs::read_dir(&self.root) else {
+            return;
+        };
+        let mut all: Vec<(PathBuf, Entry)> = Vec::new();
+        for dir in read_dir.flatten().map(|e| e.path()) {
+            all.extend(load(&dir).into_iter().map(|e| (dir.clone(), e)));
+        }
+        let mut total: u64 = all.iter().map(|(_, e)| e.size).sum();
+        if total <= self.quota {
+            return;
+        }
+        // Oldest first; on ties, other sessions before this one
+        all.sort_by_key(|(dir, e)| (e.time.clone(), *dir == self.dir));
+        let mut evicted: Vec<(PathBuf, u32)> = Vec::new();
+        for (dir, e) in &all {
+            if total <= self.quota {
+                break;
+            }
+            if *dir == self.dir && e.id == keep.id {
+                continue;
+            }
+            let item = item_path(dir, e.id);
+            let _ = if item.is_dir() {
+                fs::remove_dir_all(&item)
+            } else {
+                fs::remove_file(&item)
+            };
+            total = total.saturating_sub(e.size);
+            evicted.push((dir.clone(), e.id));
+        }
+        let mut dirs: Vec<&PathBuf> = evicted.iter().map(|(d, _)| d).collect();
+        dirs.sort();
+        dirs.dedup();
+        for dir in dirs {
+            let mut entries = load(dir);
+            entries.retain(|e| !evicted.contains(&(dir.clone(), e.id)));
+            if entries.is_empty() {
+                let _ = fs::remove_dir_all(dir);
+            } else {
+                let _ = save(dir, &entries);
+            }
+        }
+    }
+}
+
+fn item_path(dir: &Path, id: u32) -> PathBuf {
+    dir.join(format!("{id:05}"))
+}
+
+fn load(dir: &Path) -> Vec<Entry> {
+    let Ok(text) = fs::read_to_string(dir.join("index.jsonl")) else {
+        return Vec::new();
+    };
+    text.lines()
+        .filter_map(|l| serde_json::from_str(l).ok())
+        .collect()
+}
+
+fn save(dir: &Path, entries: &[Entry]) -> io::Result<()> {
+    let mut out = String::new();
+    for e in entries {
+        out.push_str(&serde_json::to_string(e)?);
+        out.push('\n');
+    }
+    fs::write(dir.join("index.jsonl"), out)
+}
+
+/// Rename, or copy and remove when `to` is on another
+/// filesystem.
+fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+            copy_all(from, to)?;
+            if from.is_dir() {
+                fs::remove_dir_all(from)
+            } else {
+                fs::remove_file(from)
+            }
+        }
+        other => other,
+    }
+}
+
+fn copy_all(from: &Path, to: &Path) -> io::Result<()> {
+    if !from.is_dir() {
+        return fs::copy(from, to).map(|_| ());
+    }
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        copy_all(&entry.path(), &to.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+fn disk_size(path: &Path) -> u64 {
+    let Ok(meta) = path.symlink_metadata() else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    fs::read_dir(path)
+        .map(|rd| rd.flatten().map(|e| disk_size(&e.path())).sum())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tempdir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tapir_trash_{name}"));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn put_and_restore() {
+        let dir = tempdir("restore");
+        let trash = Trash::new(&dir.join("home"), "s1", u64::MAX);
+        let file = dir.join("a.txt");
+        fs::write(&file, "hello").unwrap();
+        let sub = dir.join("sub");
+        fs::create_dir_all(sub.join("x")).unwrap();
+        fs::write(sub.join("x/b.txt"), "b").unwrap();
+
+        let a = trash.put(&file).unwrap();
+        let s = trash.put(&sub).unwrap();
+        assert!(!file.exists() && !sub.exists());
+        assert_eq!(trash.list().len(), 2);
+        assert_eq!(s.size, 1);
+
+        fs::write(&file, "new").unwrap();
+        assert!(trash.restore(a.id).is_err());
+        fs::remove_file(&file).unwrap();
+        assert_eq!(trash.restore(a.id).unwrap(), file);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "hello");
+        trash.restore(s.id).unwrap();
+        assert_eq!(fs::read_to_string(sub.join("x/b.txt")).unwrap(), "b");
+        assert!(trash.list().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn quota_evicts_oldest_across_sessions() {
+        let dir = tempdir("quota");
+        let home = dir.join("home");
+        let old = Trash::new(&home, "s1", 10);
+        let cur = Trash::new(&home, "s2", 10);
+        for (trash, name) in [(&old, "a"), (&cur, "b"), (&cur, "c")] {
+            let file = dir.join(name);
+            fs::write(&file, "12345").unwrap();
+            trash.put(&file).unwrap();
+        }
+        assert!(old.list().is_empty());
+        assert!(!home.join("trash/s1").exists());
+        assert_eq!(cur.list().len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
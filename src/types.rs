@@ -8,8 +8,18 @@ pub struct Request<'a> {
     pub thinking: Option<ThinkingConfig>,
     pub system: Vec<SystemBlock<'a>>,
     pub messages: &'a [Message],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    pub stop_sequences: &'a [String],
     pub tools: &'a [ToolDef],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
     pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_management: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Serialize)]
@@ -74,6 +84,16 @@ pub struct Message {
     pub content: Content,
 }
 
+/// A user annotation from `/note`. Kept in the session file
+/// and exports, never sent to the API.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+    /// Number of messages before the note.
+    pub at: usize,
+    pub text: String,
+    pub time: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Content {
@@ -129,7 +149,17 @@ pub struct Usage {
     pub cache_read_input_tokens: u32,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+impl Usage {
+    /// The whole prompt: `input_tokens` leaves out what was
+    /// read from or written to the cache.
+    pub fn prompt_tokens(&self) -> u32 {
+        self.input_tokens
+            + self.cache_read_input_tokens
+            + self.cache_creation_input_tokens
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub enum StopReason {
     #[serde(rename = "end_turn")]
     EndTurn,
@@ -143,8 +173,14 @@ pub enum StopReason {
 
 #[derive(Debug, Clone, Serialize)]
 pub struct ToolDef {
+    /// Set for tools the API defines, which carry no
+    /// description or schema.
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<&'static str>,
     pub name: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub description: String,
+    #[serde(skip_serializing_if = "serde_json::Value::is_null")]
     pub input_schema: serde_json::Value,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cache_control: Option<CacheControl>,
@@ -162,3 +198,26 @@ pub struct ApiErrorDetail {
     pub kind: String,
     pub message: String,
 }
+
+/// A page of the models endpoint.
+#[derive(Debug, Deserialize)]
+pub struct ModelList {
+    pub data: Vec<ListedModel>,
+    #[serde(default)]
+    pub has_more: bool,
+    #[serde(default)]
+    pub last_id: Option<String>,
+}
+
+/// One model from the models endpoint. Limits are optional:
+/// not every provider reports them, or names them alike.
+#[derive(Debug, Deserialize)]
+pub struct ListedModel {
+    pub id: String,
+    #[serde(default)]
+    pub display_name: String,
+    #[serde(default, alias = "context_window", alias = "context_length")]
+    pub max_input_tokens: Option<u32>,
+    #[serde(default, alias = "max_output_tokens")]
+    pub max_tokens: Option<u32>,
+}
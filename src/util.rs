@@ -7,6 +7,17 @@ pub fn truncate(s: &str, max: usize) -> String {
     }
 }
 
+/// Short relative age like `5m ago` for a duration in seconds.
+pub fn format_age(secs: i64) -> String {
+    let secs = secs.max(0);
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
 /// Keep the first `max_lines` lines and `max_bytes` bytes.
 /// Returns the (possibly truncated) string and whether
 /// truncation occurred.
@@ -58,17 +69,30 @@ pub fn truncate_tail(
         }
     }
 
-    // Walk backwards from end to find start position
-    let lines: Vec<&str> = s.lines().collect();
-    let total_lines = lines.len();
+    // Count and search bytes rather than collecting lines;
+    // this runs on every tool result
+    let bytes = s.as_bytes();
+    let newlines = bytes.iter().filter(|&&b| b == b'\n').count();
+    let total_lines =
+        newlines + usize::from(!s.ends_with('\n') && !s.is_empty());
     let keep = total_lines.min(max_lines);
     let start_line = total_lines - keep;
 
-    // Find byte offset of start_line
-    let mut byte_offset = 0;
-    for line in &lines[..start_line] {
-        byte_offset += line.len() + 1; // +1 for '\n'
-    }
+    // Byte offset of start_line: just past the newline ending
+    // the line before it, found from the end
+    let byte_offset = match start_line {
+        0 => 0,
+        n => bytes
+            .iter()
+            .rposition({
+                let mut left = newlines - n + 1;
+                move |&b| {
+                    left -= usize::from(b == b'\n');
+                    left == 0
+                }
+            })
+            .map_or(0, |i| i + 1),
+    };
 
     // Also enforce max_bytes from the end
     let byte_start = if s.len() > max_bytes {
@@ -122,12 +146,68 @@ pub fn floor_char_boundary(s: &str, i: usize) -> usize {
     pos
 }
 
+/// Up to `max` one-line excerpts of `text` around matches of
+/// `query` (ASCII case-insensitive), with whitespace collapsed.
+pub fn snippets(text: &str, query: &str, max: usize) -> Vec<String> {
+    const BEFORE: usize = 40;
+    const AFTER: usize = 60;
+    let mut out = Vec::new();
+    if query.is_empty() {
+        return out;
+    }
+    let lower = text.to_ascii_lowercase();
+    let query = query.to_ascii_lowercase();
+    let mut from = 0;
+    while out.len() < max
+        && let Some(n) = lower[from..].find(&query)
+    {
+        let at = from + n;
+        let start = floor_char_boundary(text, at.saturating_sub(BEFORE));
+        let end = ceil_char_boundary(text, at + query.len() + AFTER);
+        let body = text[start..end].split_whitespace().collect::<Vec<_>>();
+        let mut line = body.join(" ");
+        if start > 0 {
+            line.insert_str(0, "...");
+        }
+        if end < text.len() {
+            line.push_str("...");
+        }
+        out.push(line);
+        from = end;
+    }
+    out
+}
+
 /// Normalize a string for fuzzy matching: collapse
 /// whitespace, replace smart quotes and unicode dashes.
 pub fn normalize_for_match(s: &str) -> String {
     let mut out = String::with_capacity(s.len());
     let mut prev_ws = false;
-    for ch in s.chars() {
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        // Handle runs of plain ASCII, or of ASCII whitespace,
+        // at once
+        let is_ws = |b: u8| (b as char).is_whitespace();
+        let ws = is_ws(bytes[i]);
+        let run = bytes[i..]
+            .iter()
+            .position(|&b| !b.is_ascii() || is_ws(b) != ws)
+            .unwrap_or(bytes.len() - i);
+        if run > 0 {
+            if !ws {
+                out.push_str(&s[i..i + run]);
+            } else if !prev_ws && !out.is_empty() {
+                out.push(' ');
+            }
+            prev_ws = ws;
+            i += run;
+            continue;
+        }
+        let Some(ch) = s[i..].chars().next() else {
+            break;
+        };
+        i += ch.len_utf8();
         let mapped = match ch {
             // Smart single quotes → ASCII
             '\u{2018}' | '\u{2019}' => '\'',
@@ -154,6 +234,80 @@ pub fn normalize_for_match(s: &str) -> String {
     out
 }
 
+/// The candidate nearest to `word` by edit distance, if it is
+/// close enough to be a likely typo.
+pub fn closest<'a>(
+    word: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let max = (word.chars().count() / 3).max(2);
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(word, c), c))
+        .filter(|(d, _)| *d <= max)
+        .min()
+        .map(|(_, c)| c)
+}
+
+/// Levenshtein distance in chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let next = (diag + usize::from(ca != *cb))
+                .min(row[j] + 1)
+                .min(row[j + 1] + 1);
+            diag = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
+
+/// Replace each `{{name}}` in `text` with its value in
+/// `vars`. Unknown names are left as written and returned, in
+/// order of appearance.
+pub fn interpolate(
+    text: &str,
+    vars: &std::collections::BTreeMap<String, String>,
+) -> (String, Vec<String>) {
+    let mut out = String::with_capacity(text.len());
+    let mut unknown = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+        let name = after[..end].trim();
+        match vars.get(name) {
+            Some(value) if is_var_name(name) => out.push_str(value),
+            _ => {
+                if is_var_name(name) && !unknown.iter().any(|u| u == name) {
+                    unknown.push(name.to_string());
+                }
+                out.push_str(&rest[start..start + 2 + end + 2]);
+            }
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    (out, unknown)
+}
+
+/// Letters, digits, `_`, `-` and `.`, as `/set` accepts.
+pub fn is_var_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
 /// Generate a unified-style diff for a single-region edit.
 /// Shows the edit location with 3 lines of context.
 pub fn edit_diff(
@@ -217,9 +371,189 @@ pub fn edit_diff(
     out
 }
 
+/// Largest middle section (after common prefix/suffix are
+/// trimmed) that gets a real LCS; beyond this the whole
+/// section is shown as replaced.
+const DIFF_MAX_CELLS: usize = 4_000_000;
+
+/// Unified diff of two whole files with 3 lines of context.
+/// Empty when they are equal.
+pub fn line_diff(path: &str, old: &str, new: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let ops = diff_ops(&a, &b);
+    if ops.iter().all(|op| matches!(op, DiffOp::Same(_))) {
+        return String::new();
+    }
+
+    let ctx = 3;
+    let mut out = format!("--- a/{path}\n+++ b/{path}\n");
+    let mut i = 0;
+    while i < ops.len() {
+        if matches!(ops[i], DiffOp::Same(_)) {
+            i += 1;
+            continue;
+        }
+        // Grow the hunk until a run of >2*ctx unchanged lines
+        let start = i.saturating_sub(ctx);
+        let mut end = i;
+        let mut same_run = 0;
+        while end < ops.len() {
+            if matches!(ops[end], DiffOp::Same(_)) {
+                same_run += 1;
+                if same_run > 2 * ctx {
+                    break;
+                }
+            } else {
+                same_run = 0;
+            }
+            end += 1;
+        }
+        let trailing = ops[i..end]
+            .iter()
+            .rev()
+            .take_while(|o| matches!(o, DiffOp::Same(_)))
+            .count();
+        let end = end - trailing + ctx.min(trailing);
+
+        let (mut a_line, mut b_line) = (1, 1);
+        for op in &ops[..start] {
+            match op {
+                DiffOp::Same(_) => {
+                    a_line += 1;
+                    b_line += 1;
+                }
+                DiffOp::Del(_) => a_line += 1,
+                DiffOp::Add(_) => b_line += 1,
+            }
+        }
+        let hunk = &ops[start..end];
+        let a_len =
+            hunk.iter().filter(|o| !matches!(o, DiffOp::Add(_))).count();
+        let b_len =
+            hunk.iter().filter(|o| !matches!(o, DiffOp::Del(_))).count();
+        out.push_str(&format!(
+            "@@ -{},{a_len} +{},{b_len} @@\n",
+            if a_len == 0 { a_line - 1 } else { a_line },
+            if b_len == 0 { b_line - 1 } else { b_line },
+        ));
+        for op in hunk {
+            let (mark, line) = match op {
+                DiffOp::Same(l) => (' ', l),
+                DiffOp::Del(l) => ('-', l),
+                DiffOp::Add(l) => ('+', l),
+            };
+            out.push_str(&format!("{mark}{line}\n"));
+        }
+        i = end;
+    }
+    out
+}
+
+enum DiffOp<'a> {
+    Same(&'a str),
+    Del(&'a str),
+    Add(&'a str),
+}
+
+fn diff_ops<'a>(a: &[&'a str], b: &[&'a str]) -> Vec<DiffOp<'a>> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (am, bm) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut ops: Vec<DiffOp> =
+        a[..prefix].iter().map(|l| DiffOp::Same(l)).collect();
+    if am.len() * bm.len() > DIFF_MAX_CELLS {
+        ops.extend(am.iter().map(|l| DiffOp::Del(l)));
+        ops.extend(bm.iter().map(|l| DiffOp::Add(l)));
+    } else {
+        // lcs[i][j] = LCS length of am[i..] and bm[j..]
+        let w = bm.len() + 1;
+        let mut lcs = vec![0u32; (am.len() + 1) * w];
+        for i in (0..am.len()).rev() {
+            for j in (0..bm.len()).rev() {
+                lcs[i * w + j] = if am[i] == bm[j] {
+                    lcs[(i + 1) * w + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * w + j].max(lcs[i * w + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < am.len() || j < bm.len() {
+            if i < am.len() && j < bm.len() && am[i] == bm[j] {
+                ops.push(DiffOp::Same(am[i]));
+                i += 1;
+                j += 1;
+            } else if i < am.len()
+                && (j == bm.len() || lcs[(i + 1) * w + j] >= lcs[i * w + j + 1])
+            {
+                ops.push(DiffOp::Del(am[i]));
+                i += 1;
+            } else {
+                ops.push(DiffOp::Add(bm[j]));
+                j += 1;
+            }
+        }
+    }
+    ops.extend(a[a.len() - suffix..].iter().map(|l| DiffOp::Same(l)));
+    ops
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::{bench, sample_text};
+    use std::hint::black_box;
+
+    #[test]
+    #[ignore]
+    fn bench_text_utils() {
+        let text = sample_text(50_000);
+        let n = text.len();
+        bench("truncate_head", n, || {
+            black_box(truncate_head(black_box(&text), 2000, 50_000));
+        });
+        bench("truncate_tail", n, || {
+            black_box(truncate_tail(black_box(&text), 1000, 30_000));
+        });
+        bench("truncate_tail (lines only)", n, || {
+            black_box(truncate_tail(black_box(&text), 1000, usize::MAX));
+        });
+        bench("normalize_for_match", n, || {
+            black_box(normalize_for_match(black_box(&text)));
+        });
+    }
+
+    #[test]
+    fn test_snippets() {
+        let text = format!(
+            "{}Fixed the AUTH bug\nin login{}",
+            "x ".repeat(30),
+            "y".repeat(80)
+        );
+        let s = snippets(&text, "auth", 3);
+        assert_eq!(s.len(), 1);
+        assert!(s[0].starts_with("...x x"), "{}", s[0]);
+        assert!(s[0].contains("Fixed the AUTH bug in login"));
+        assert!(s[0].ends_with("y..."));
+        assert_eq!(snippets("a b a b a", "a", 2).len(), 1);
+        assert_eq!(snippets("ab", "", 3).len(), 0);
+    }
+
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(-5), "just now");
+        assert_eq!(format_age(59), "just now");
+        assert_eq!(format_age(150), "2m ago");
+        assert_eq!(format_age(7200), "2h ago");
+        assert_eq!(format_age(3 * 86400 + 5), "3d ago");
+    }
 
     #[test]
     fn test_truncate_short() {
@@ -277,6 +611,14 @@ mod tests {
         assert!(out.starts_with("abcdefghij\n"));
     }
 
+    #[test]
+    fn test_truncate_tail_crlf_and_no_final_newline() {
+        let (out, _) = truncate_tail("a\r\nb\r\nc\r\nd", 2, 1000);
+        assert_eq!(out, "... (4 lines, 10 bytes total)\nc\r\nd");
+        let (out, _) = truncate_tail("\n\nx\n", 1, 1000);
+        assert_eq!(out, "... (3 lines, 4 bytes total)\nx\n");
+    }
+
     #[test]
     fn test_truncate_tail_no_truncation() {
         let (out, truncated) = truncate_tail("a\nb\n", 10, 1000);
@@ -335,6 +677,12 @@ mod tests {
         assert_eq!(normalize_for_match(input), "a-b-c");
     }
 
+    #[test]
+    fn test_normalize_for_match_mixed_runs() {
+        let s = " a\u{a0}\u{a0} b\t\u{2014}\u{201C}c\u{201D}  \n";
+        assert_eq!(normalize_for_match(s), "a b -\"c\"");
+    }
+
     #[test]
     fn test_normalize_for_match_whitespace() {
         let input = "  hello   world  ";
@@ -349,4 +697,53 @@ mod tests {
         assert!(diff.contains("+line3a"));
         assert!(diff.contains("--- test.rs"));
     }
+
+    #[test]
+    fn test_line_diff_equal_is_empty() {
+        assert_eq!(line_diff("a", "x\ny\n", "x\ny\n"), "");
+    }
+
+    #[test]
+    fn test_line_diff_hunks() {
+        let old: String = (1..=20).map(|i| format!("l{i}\n")).collect();
+        let new = old.replace("\nl2\n", "\ntwo\n").replace("l18\n", "");
+        let diff = line_diff("f.txt", &old, &new);
+        assert!(diff.starts_with("--- a/f.txt\n+++ b/f.txt\n"));
+        assert!(
+            diff.contains("@@ -1,5 +1,5 @@\n l1\n-l2\n+two\n l3\n l4\n l5\n@@")
+        );
+        assert!(diff.contains("@@ -15,6 +15,5 @@\n"));
+        assert!(diff.contains("-l18\n"));
+        assert!(!diff.contains(" l10\n"));
+    }
+
+    #[test]
+    fn test_line_diff_new_file() {
+        let diff = line_diff("n", "", "a\nb\n");
+        assert!(diff.contains("@@ -0,0 +1,2 @@\n+a\n+b\n"));
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let vars: std::collections::BTreeMap<String, String> =
+            [("ticket".to_string(), "PROJ-123".to_string())].into();
+        let (out, unknown) = interpolate(
+            "fix {{ticket}} see {{ ticket }}, {{nope}} {{a b}} {{x",
+            &vars,
+        );
+        assert_eq!(out, "fix PROJ-123 see PROJ-123, {{nope}} {{a b}} {{x");
+        assert_eq!(unknown, ["nope"]);
+        assert_eq!(interpolate("no vars", &vars).0, "no vars");
+        assert!(is_var_name("build.dir"));
+        assert!(!is_var_name("a=b"));
+    }
+
+    #[test]
+    fn test_closest() {
+        let keys = ["thinking_budget", "max_tokens", "model"];
+        assert_eq!(closest("thiking_budget", keys), Some("thinking_budget"));
+        assert_eq!(closest("modle", keys), Some("model"));
+        assert_eq!(closest("colour", keys), None);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
 }
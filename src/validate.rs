@@ -0,0 +1,265 @@
+use crate::types::{Content, ContentBlock, Message, Role};
+
+/// Fix what the API would reject with an opaque 400, usually
+/// left behind by an interrupt: empty content, consecutive
+/// same-role messages, tool calls without results and results
+/// without calls. Valid conversations are left untouched.
+/// Returns one line per repair, naming the message index in
+/// the original list.
+pub fn repair(messages: &mut Vec<Message>) -> Vec<String> {
+    let mut notes = Vec::new();
+    let mut out: Vec<Message> = Vec::with_capacity(messages.len());
+    // Tool calls of the last assistant message still unanswered
+    let mut pending: Vec<String> = Vec::new();
+
+    for (i, msg) in std::mem::take(messages).into_iter().enumerate() {
+        let was_text = matches!(msg.content, Content::Text(_));
+        let mut blocks = match msg.content {
+            Content::Text(text) => vec![ContentBlock::Text { text }],
+            Content::Blocks(blocks) => blocks,
+        };
+        let before = blocks.len();
+        blocks.retain(|b| {
+            !matches!(b, ContentBlock::Text { text } if text.trim().is_empty())
+        });
+        if blocks.len() < before && !blocks.is_empty() {
+            notes.push(format!("message {i}: dropped empty text"));
+        }
+
+        if msg.role == Role::User {
+            blocks.retain(|b| match b {
+                ContentBlock::ToolResult { tool_use_id, .. } => {
+                    let Some(pos) =
+                        pending.iter().position(|p| p == tool_use_id)
+                    else {
+                        notes.push(format!(
+                            "message {i}: dropped tool_result {tool_use_id} \
+                             with no matching tool_use"
+                        ));
+                        return false;
+                    };
+                    pending.remove(pos);
+                    true
+                }
+                _ => true,
+            });
+        }
+        if !pending.is_empty() {
+            notes.push(format!(
+                "message {i}: added missing tool_result for {}",
+                pending.join(", ")
+            ));
+            let missing = cancelled(&mut pending);
+            if msg.role == Role::User {
+                blocks.splice(0..0, missing);
+            } else {
+                out.push(Message {
+                    role: Role::User,
+                    content: Content::Blocks(missing),
+                });
+            }
+        }
+
+        if blocks.is_empty() {
+            notes.push(format!("message {i}: dropped empty message"));
+            continue;
+        }
+        if out.is_empty() && msg.role == Role::Assistant {
+            notes.push(format!(
+                "message {i}: dropped leading assistant message"
+            ));
+            continue;
+        }
+        if msg.role == Role::Assistant {
+            pending = blocks
+                .iter()
+                .filter_map(|b| match b {
+                    ContentBlock::ToolUse { id, .. } => Some(id.clone()),
+                    _ => None,
+                })
+                .collect();
+        }
+
+        if let Some(last) = out.last_mut()
+            && last.role == msg.role
+        {
+            notes.push(format!("message {i}: merged into the previous one"));
+            let prev = match std::mem::replace(
+                &mut last.content,
+                Content::Blocks(Vec::new()),
+            ) {
+                Content::Text(text) => vec![ContentBlock::Text { text }],
+                Content::Blocks(prev) => prev,
+            };
+            let mut merged = prev;
+            merged.extend(blocks);
+            // Tool results have to lead a user message
+            merged
+                .sort_by_key(|b| !matches!(b, ContentBlock::ToolResult { .. }));
+            last.content = Content::Blocks(merged);
+            continue;
+        }
+
+        let content = match blocks.as_slice() {
+            [ContentBlock::Text { text }] if was_text => {
+                Content::Text(text.clone())
+            }
+            _ => Content::Blocks(blocks),
+        };
+        out.push(Message {
+            role: msg.role,
+            content,
+        });
+    }
+
+    if !pending.is_empty() {
+        notes.push(format!(
+            "end: added missing tool_result for {}",
+            pending.join(", ")
+        ));
+        out.push(Message {
+            role: Role::User,
+            content: Content::Blocks(cancelled(&mut pending)),
+        });
+    }
+    *messages = out;
+    notes
+}
+
+fn cancelled(ids: &mut Vec<String>) -> Vec<ContentBlock> {
+    ids.drain(..)
+        .map(|id| ContentBlock::ToolResult {
+            tool_use_id: id,
+            content: "(cancelled)".to_string(),
+            is_error: Some(true),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(role: Role, text: &str) -> Message {
+        Message {
+            role,
+            content: Content::Text(text.to_string()),
+        }
+    }
+
+    fn call(id: &str) -> Message {
+        Message {
+            role: Role::Assistant,
+            content: Content::Blocks(vec![
+                ContentBlock::Text {
+                    text: String::new(),
+                },
+                ContentBlock::ToolUse {
+                    id: id.to_string(),
+                    name: "bash".to_string(),
+                    input: serde_json::json!({}),
+                },
+            ]),
+        }
+    }
+
+    fn result(id: &str) -> ContentBlock {
+        ContentBlock::ToolResult {
+            tool_use_id: id.to_string(),
+            content: "ok".to_string(),
+            is_error: None,
+        }
+    }
+
+    fn shape(messages: &[Message]) -> Vec<String> {
+        messages
+            .iter()
+            .map(|m| {
+                let role = if m.role == Role::User { "u" } else { "a" };
+                let parts: Vec<String> = match &m.content {
+                    Content::Text(t) => vec![t.clone()],
+                    Content::Blocks(b) => b
+                        .iter()
+                        .map(|b| match b {
+                            ContentBlock::Text { text } => text.clone(),
+                            ContentBlock::ToolUse { id, .. } => {
+                                format!("use:{id}")
+                            }
+                            ContentBlock::ToolResult {
+                                tool_use_id,
+                                content,
+                                ..
+                            } => format!("{content}:{tool_use_id}"),
+                            ContentBlock::Thinking { .. } => "think".into(),
+                        })
+                        .collect(),
+                };
+                format!("{role}[{}]", parts.join(" "))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn valid_conversation_is_untouched() {
+        let mut msgs = vec![
+            text(Role::User, "hi"),
+            Message {
+                role: Role::Assistant,
+                content: Content::Blocks(vec![ContentBlock::ToolUse {
+                    id: "a".into(),
+                    name: "bash".into(),
+                    input: serde_json::json!({}),
+                }]),
+            },
+            Message {
+                role: Role::User,
+                content: Content::Blocks(vec![result("a")]),
+            },
+            text(Role::Assistant, "done"),
+        ];
+        assert!(repair(&mut msgs).is_empty());
+        assert_eq!(shape(&msgs), ["u[hi]", "a[use:a]", "u[ok:a]", "a[done]"]);
+    }
+
+    #[test]
+    fn interrupted_conversation_is_repaired() {
+        let mut msgs = vec![
+            text(Role::Assistant, "stray"),
+            text(Role::User, "first"),
+            call("a"),
+            text(Role::User, "second"),
+            text(Role::User, ""),
+            text(Role::User, "third"),
+            Message {
+                role: Role::User,
+                content: Content::Blocks(vec![result("zzz")]),
+            },
+            call("b"),
+        ];
+        let notes = repair(&mut msgs);
+        assert_eq!(
+            shape(&msgs),
+            [
+                "u[first]",
+                "a[use:a]",
+                "u[(cancelled):a second third]",
+                "a[use:b]",
+                "u[(cancelled):b]",
+            ]
+        );
+        assert_eq!(
+            notes,
+            [
+                "message 0: dropped leading assistant message",
+                "message 2: dropped empty text",
+                "message 3: added missing tool_result for a",
+                "message 4: dropped empty message",
+                "message 5: merged into the previous one",
+                "message 6: dropped tool_result zzz with no matching tool_use",
+                "message 6: dropped empty message",
+                "message 7: dropped empty text",
+                "end: added missing tool_result for b",
+            ]
+        );
+    }
+}
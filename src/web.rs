@@ -0,0 +1,260 @@
+use std::io::Read;
+
+/// Give up on pages slower than this, in seconds.
+const FETCH_TIMEOUT: u64 = 20;
+
+/// Bytes read from a page; the rest is dropped.
+const MAX_PAGE_BYTES: u64 = 2 * 1024 * 1024;
+
+/// Elements whose content is never text worth reading.
+const SKIPPED: &[&str] = &["script", "style", "noscript", "svg", "template"];
+
+/// Elements that start a new line in the extracted text.
+const BLOCKS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "br",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "title",
+    "tr",
+    "ul",
+];
+
+/// The readable text of the page at `url`: HTML reduced with
+/// `html_to_text`, other text types as sent.
+pub fn fetch_text(url: &str) -> Result<String, String> {
+    let response = minreq::get(url)
+        .with_header("User-Agent", concat!("tapir/", env!("CARGO_PKG_VERSION")))
+        .with_header("Accept", "text/html, text/plain;q=0.9, */*;q=0.5")
+        .with_timeout(FETCH_TIMEOUT)
+        .send_lazy()
+        .map_err(|e| e.to_string())?;
+    if !(200..300).contains(&response.status_code) {
+        return Err(format!(
+            "HTTP {} {}",
+            response.status_code, response.reason_phrase
+        ));
+    }
+    let kind = response
+        .headers
+        .get("content-type")
+        .cloned()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let textual = kind.is_empty()
+        || kind.starts_with("text/")
+        || kind.contains("json")
+        || kind.contains("xml");
+    if !textual {
+        return Err(format!("not text ({kind})"));
+    }
+    let mut body = Vec::new();
+    // ResponseLazy is also an Iterator, hence the spelled-out
+    // trait
+    Read::take(response, MAX_PAGE_BYTES)
+        .read_to_end(&mut body)
+        .map_err(|e| e.to_string())?;
+    let body = String::from_utf8_lossy(&body);
+    if kind.contains("html") || (kind.is_empty() && body.contains("<html")) {
+        Ok(html_to_text(&body))
+    } else {
+        Ok(body.into_owned())
+    }
+}
+
+/// Text of an HTML document: tags dropped, scripts and styles
+/// skipped, block elements on their own lines, entities
+/// decoded and runs of blank space collapsed.
+pub fn html_to_text(html: &str) -> String {
+    let mut raw = String::new();
+    let mut rest = html;
+    let mut pre = false;
+    while let Some(lt) = rest.find('<') {
+        push_text(&mut raw, &rest[..lt], pre);
+        rest = &rest[lt..];
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map_or("", |end| &rest[end + 3..]);
+            continue;
+        }
+        let Some(gt) = rest.find('>') else {
+            rest = "";
+            break;
+        };
+        let tag = &rest[1..gt];
+        rest = &rest[gt + 1..];
+        let closing = tag.starts_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if !closing && SKIPPED.contains(&name.as_str()) {
+            let end = format!("</{name}");
+            rest = match find_ignore_case(rest, &end) {
+                Some(i) => {
+                    rest[i..].find('>').map_or("", |j| &rest[i + j + 1..])
+                }
+                None => "",
+            };
+            continue;
+        }
+        if name == "pre" {
+            pre = !closing;
+        }
+        if BLOCKS.contains(&name.as_str()) && !raw.ends_with('\n') {
+            raw.push('\n');
+        } else if name == "td" || name == "th" {
+            push_space(&mut raw);
+        }
+    }
+    push_text(&mut raw, rest, pre);
+
+    let mut out = String::new();
+    let mut blank = 0;
+    for line in raw.lines() {
+        let line = line.trim_end();
+        if line.trim().is_empty() {
+            blank += 1;
+            continue;
+        }
+        if !out.is_empty() {
+            out.push_str(if blank > 0 { "\n\n" } else { "\n" });
+        }
+        out.push_str(line);
+        blank = 0;
+    }
+    out
+}
+
+/// Append a text node. Outside `<pre>`, line breaks in the
+/// source are only spaces.
+fn push_text(raw: &mut String, text: &str, pre: bool) {
+    let text = decode_entities(text);
+    if pre {
+        raw.push_str(&text);
+        return;
+    }
+    let words: Vec<&str> = text.split_whitespace().collect();
+    if words.is_empty() {
+        if !text.is_empty() {
+            push_space(raw);
+        }
+        return;
+    }
+    if text.starts_with(char::is_whitespace) {
+        push_space(raw);
+    }
+    raw.push_str(&words.join(" "));
+    if text.ends_with(char::is_whitespace) {
+        push_space(raw);
+    }
+}
+
+/// A space, unless at the start of a line or after another.
+fn push_space(raw: &mut String) {
+    if !raw.is_empty() && !raw.ends_with(char::is_whitespace) {
+        raw.push(' ');
+    }
+}
+
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    let haystack = haystack.as_bytes();
+    let needle = needle.as_bytes();
+    haystack
+        .windows(needle.len())
+        .position(|w| w.eq_ignore_ascii_case(needle))
+}
+
+/// Replace the common named entities and numeric references.
+fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let end = rest[1..].find(';').map(|i| i + 1).filter(|&i| i <= 10);
+        let decoded = end.and_then(|end| {
+            let name = &rest[1..end];
+            let c = match name {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" | "#39" => '\'',
+                "nbsp" => ' ',
+                _ => {
+                    let code = if let Some(hex) = name
+                        .strip_prefix("#x")
+                        .or_else(|| name.strip_prefix("#X"))
+                    {
+                        u32::from_str_radix(hex, 16).ok()
+                    } else {
+                        name.strip_prefix('#').and_then(|d| d.parse().ok())
+                    };
+                    char::from_u32(code?)?
+                }
+            };
+            Some((c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_becomes_readable_text() {
+        let html = "<html><head><title>Issue #4</title>\
+                    <style>p { color: red }</style></head>\
+                    <body><script>var x = '<p>';</script>\
+                    <h1>Crash &amp; burn</h1><!-- hidden -->\
+                    <p>Steps:\n   run <code>tapir</code>&nbsp;twice</p>\
+                    <pre>fn main() {\n    x();\n}</pre>\
+                    <ul><li>one</li><li>two &#8212; &#x41;</li></ul>\
+                    <table><tr><td>a</td><td>b</td></tr></table>\
+                    <p>AT&T &unknown; x</p></body></html>";
+        assert_eq!(
+            html_to_text(html),
+            "Issue #4\nCrash & burn\nSteps: run tapir twice\n\
+             fn main() {\n    x();\n}\none\n\
+             two \u{2014} A\na b\nAT&T &unknown; x"
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// A git worktree tapir runs in for `--worktree <branch>`,
+/// so parallel instances on one repo don't share a checkout.
+pub struct Worktree {
+    pub path: PathBuf,
+    /// Top level of the main checkout.
+    pub root: PathBuf,
+}
+
+impl Worktree {
+    /// Check out `branch` (created from HEAD if missing) in
+    /// `<repo>/.tapir/worktrees/<branch>`. Reuses the worktree
+    /// if it exists.
+    pub fn create(cwd: &Path, branch: &str) -> Result<Self, String> {
+        let root =
+            PathBuf::from(git(cwd, &["rev-parse", "--show-toplevel"])?.trim());
+        let path = root.join(".tapir/worktrees").join(branch.replace('/', "-"));
+
+        if !path.exists() {
+            let path_str = path.to_string_lossy();
+            let exists = git(
+                &root,
+                &[
+                    "rev-parse",
+                    "--verify",
+                    "--quiet",
+                    &format!("refs/heads/{branch}"),
+                ],
+            )
+            .is_ok();
+            if exists {
+                git(&root, &["worktree", "add", &path_str, branch])?;
+            } else {
+                git(&root, &["worktree", "add", "-b", branch, &path_str])?;
+            }
+        }
+        Ok(Worktree { path, root })
+    }
+
+    /// Remove the worktree if it has no uncommitted changes;
+    /// otherwise leave it for the user. The branch is kept
+    /// either way.
+    pub fn cleanup(self) {
+        match git(&self.path, &["status", "--porcelain"]) {
+            Ok(status) if status.trim().is_empty() => {
+                let path = self.path.to_string_lossy();
+                match git(&self.root, &["worktree", "remove", &path]) {
+                    Ok(_) => eprintln!("* removed worktree {path}"),
+                    Err(e) => eprintln!("* worktree {path} kept: {e}"),
+                }
+            }
+            _ => eprintln!(
+                "* worktree kept at {} (uncommitted changes)",
+                self.path.display()
+            ),
+        }
+    }
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String, String> {
+    let out = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("git: {e}"))?;
+    if !out.status.success() {
+        let err = String::from_utf8_lossy(&out.stderr).trim().to_string();
+        return Err(format!("git {}: {err}", args.join(" ")));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn create_and_cleanup() {
+        let repo = std::env::temp_dir().join("tapir_worktree_repo");
+        let _ = fs::remove_dir_all(&repo);
+        fs::create_dir_all(&repo).unwrap();
+        let repo = repo.canonicalize().unwrap();
+        for args in [
+            &["init", "-q", "-b", "main"][..],
+            &[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "-q",
+                "--allow-empty",
+                "-m",
+                "init",
+            ][..],
+        ] {
+            git(&repo, args).unwrap();
+        }
+
+        let wt = Worktree::create(&repo, "feature/x").unwrap();
+        assert_eq!(wt.path, repo.join(".tapir/worktrees/feature-x"));
+        assert_eq!(
+            git(&wt.path, &["branch", "--show-current"]).unwrap().trim(),
+            "feature/x"
+        );
+        let path = wt.path.clone();
+        wt.cleanup();
+        assert!(!path.exists());
+        assert!(git(&repo, &["rev-parse", "--verify", "feature/x"]).is_ok());
+        fs::remove_dir_all(&repo).unwrap();
+    }
+}